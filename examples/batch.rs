@@ -0,0 +1,103 @@
+use cytube_generator::batch::{album_items, audio_files_in, batch_items, chapter_items, media_files_in, run_batch};
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::failure::{error_json, USAGE_EXIT_CODE};
+use cytube_generator::plan::CliRunner;
+use cytube_generator::playlist::PLAYLIST_FILENAME;
+use cytube_generator::transcode::TranscodeOptions;
+use std::path::PathBuf;
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} [--strip-metadata] [--split-chapters] [--error-format text|json] <output root> <URL prefix> <input directory, files or cue sheets...>", argv0);
+    eprintln!("--split-chapters makes an output of each chapter of files that have them, titled after it");
+    eprintln!("set CYTUBE_CHANNEL (and CYTUBE_SERVER, CYTUBE_USER, CYTUBE_PASSWORD) to queue the results");
+    eprintln!("exit codes: 1 other, 2 usage, 3 probe, 4 encode, 5 upload, 6 validation (each when nothing worked), 7 when some of it did");
+    std::process::exit(USAGE_EXIT_CODE);
+}
+
+fn main() {
+    let mut args: Vec<_> = std::env::args_os().collect();
+    let strip_metadata = args.iter().any(|x| x == "--strip-metadata");
+    args.retain(|x| x != "--strip-metadata");
+    let split_chapters = args.iter().any(|x| x == "--split-chapters");
+    args.retain(|x| x != "--split-chapters");
+    let json_errors = match args.iter().position(|x| x == "--error-format") {
+        Some(i) => {
+            let end = (i + 2).min(args.len());
+            let format: Vec<_> = args.drain(i..end).collect();
+            match format.get(1).and_then(|x| x.to_str()) {
+                Some("json") => true,
+                Some("text") => false,
+                _ => usage(&args[0].to_string_lossy()),
+            }
+        },
+        None => false,
+    };
+    let mut args = args.into_iter();
+    let argv0 = args.next().unwrap(); // skip argv0
+    if args.len() < 3 {
+        usage(&argv0.to_string_lossy());
+    }
+    let output_root = PathBuf::from(args.next().unwrap());
+    let url_prefix = args.next().unwrap().to_string_lossy().into_owned();
+    let mut inputs = Vec::new();
+    let mut albums = Vec::new();
+    for arg in args {
+        let path = PathBuf::from(arg);
+        if path.is_dir() {
+            let files = media_files_in(&path).expect("error listing input directory");
+            // a directory with nothing but music in it is an album
+            if files.is_empty() && !audio_files_in(&path).expect("error listing input directory").is_empty() {
+                albums.push(path);
+            }
+            inputs.extend(files);
+        } else {
+            inputs.push(path);
+        }
+    }
+
+    let mut options = TranscodeOptions {
+        preferred_language: Some("eng".into()),
+        ..Default::default()
+    };
+    let config = default_config_path().map(|path| load_config(&path).expect("error reading config file")).unwrap_or_default();
+    config.apply(&mut options);
+    options.strip_metadata |= strip_metadata;
+
+    let mut items = batch_items(inputs, &output_root, &url_prefix).expect("error reading cue sheet");
+    if split_chapters {
+        items = chapter_items(items);
+    }
+    for album in albums {
+        items.extend(album_items(&album, &output_root, &url_prefix).expect("error reading album"));
+    }
+    let result = run_batch(&items, &options, &CliRunner);
+    for (input, e) in &result.failures {
+        match json_errors {
+            true => eprintln!("{}", error_json(input, e)),
+            false => eprintln!("{} failed: {}", input.display(), e),
+        }
+    }
+    std::fs::create_dir_all(&output_root).expect("error creating the output root");
+    result.playlist.write(&output_root.join(PLAYLIST_FILENAME)).expect("error writing playlist");
+    eprintln!("{} of {} done, playlist in {}", result.playlist.entries.len(), items.len(), output_root.join(PLAYLIST_FILENAME).display());
+
+    if let Ok(channel) = std::env::var("CYTUBE_CHANNEL") {
+        #[cfg(feature = "channel")]
+        {
+            let channel = cytube_generator::channel::Channel {
+                server: std::env::var("CYTUBE_SERVER").unwrap_or_else(|_| "https://cytu.be".to_string()),
+                channel,
+                username: std::env::var("CYTUBE_USER").ok(),
+                password: std::env::var("CYTUBE_PASSWORD").ok(),
+            };
+            channel.queue(&result.playlist.urls()).expect("error queueing on cytube");
+            eprintln!("queued on {}", channel.channel);
+        }
+        #[cfg(not(feature = "channel"))]
+        panic!("built without the channel feature, can't queue on {}", channel);
+    }
+
+    if let Some(failure) = result.failure() {
+        std::process::exit(failure.exit_code());
+    }
+}
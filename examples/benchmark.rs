@@ -0,0 +1,54 @@
+use cytube_generator::benchmark::{Benchmark, BenchmarkResults};
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::encoder::VideoEncoder;
+use cytube_generator::transcode::TranscodeOptions;
+use std::path::PathBuf;
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} [--sample <file>] [--start <seconds>] [--length <seconds>] [--height <pixels>] [--encoders <svt-av1,x264,...>] [--config <file>] [--out <file>]", argv0);
+    eprintln!("encodes a short clip with each encoder and saves how fast they were and how good they looked, for [auto_encoder] in the config file");
+    eprintln!("without --sample the clip is a test pattern.  encoders: {}", VideoEncoder::ALL.map(|x| serde_json::to_value(x).unwrap().as_str().unwrap().to_string()).join(", "));
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let mut benchmark = Benchmark::default();
+    let mut config_path = None;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&argv0));
+        match arg.as_str() {
+            "--sample" => benchmark.sample = Some(PathBuf::from(value())),
+            "--start" => benchmark.start = value().parse().expect("start must be a number"),
+            "--length" => benchmark.length = value().parse().expect("length must be a number"),
+            "--height" => benchmark.height = value().parse().expect("height must be a number"),
+            "--encoders" => benchmark.encoders = value().split(',')
+                .map(|x| serde_json::from_value(x.into()).unwrap_or_else(|_| usage(&argv0)))
+                .collect(),
+            "--config" => config_path = Some(PathBuf::from(value())),
+            "--out" => out = Some(PathBuf::from(value())),
+            _ => usage(&argv0),
+        }
+    }
+
+    // the encoders get the config file's settings (CRF, H.264 constraints...), same as a real transcode
+    let mut options = TranscodeOptions::default();
+    let config = config_path.or_else(default_config_path).map(|path| load_config(&path).expect("error reading config")).unwrap_or_default();
+    config.apply(&mut options);
+
+    let results = benchmark.run(&options).expect("benchmark failed");
+    for score in &results.scores {
+        println!("{:>10}  {:6.2}x realtime  {:6} kbps  SSIM {:.4}", score.encoder.ffmpeg_name(), score.speed, score.kbps, score.ssim);
+    }
+    for (encoder, why) in &results.unavailable {
+        println!("{:>10}  unavailable: {}", encoder.ffmpeg_name(), why);
+    }
+    let Some(path) = out.or_else(BenchmarkResults::default_path) else {
+        eprintln!("nowhere to save the results; give --out");
+        std::process::exit(1);
+    };
+    results.save(&path).expect("error saving results");
+    println!("saved to {}", path.display());
+}
@@ -0,0 +1,49 @@
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::daemon::{run_daemon, DaemonOptions};
+use cytube_generator::jobs::JobStore;
+use cytube_generator::size_model::SizeModel;
+use std::sync::Arc;
+
+fn main() {
+    let mut args = std::env::args_os();
+    let argv0 = args.next().unwrap(); // skip argv0
+    if !(1..=3).contains(&args.len()) {
+        eprintln!("usage: {} <output root> [listen address] [job database]", argv0.to_string_lossy());
+        eprintln!("set CYTUBE_API_TOKEN to require a bearer token, and CYTUBE_DISK_BUDGET to limit how many bytes jobs write at once");
+        std::process::exit(2);
+    }
+    let mut options = DaemonOptions {
+        output_root: args.next().unwrap().into(),
+        api_token: std::env::var("CYTUBE_API_TOKEN").ok(),
+        disk_budget: std::env::var("CYTUBE_DISK_BUDGET").ok().map(|x| x.parse().expect("CYTUBE_DISK_BUDGET must be a number of bytes")),
+        ..Default::default()
+    };
+    if let Some(addr) = args.next() {
+        options.addr = addr.to_string_lossy().parse().expect("listen address must look like 127.0.0.1:8081");
+    }
+    if let Some(path) = default_config_path() {
+        let config = load_config(&path).expect("error reading config file");
+        config.apply(&mut options.transcode);
+        options.notifiers = config.notifiers();
+        options.uploader = config.upload.as_ref().map(|x| x.uploader());
+        options.upload_throttle = config.upload_throttle();
+        options.tenants = config.tenants();
+        options.device_limits = config.device_limits;
+        options.quotas = config.quotas;
+    }
+
+    if let Some(path) = SizeModel::default_path() {
+        options.transcode.size_model = Some(Arc::new(SizeModel::load(&path).expect("error reading the size model")));
+    }
+
+    let store: Arc<dyn JobStore> = match args.next() {
+        #[cfg(feature = "sqlite")]
+        Some(path) => Arc::new(cytube_generator::jobs::SqliteJobStore::open(path.as_ref()).expect("error opening job database")),
+        #[cfg(not(feature = "sqlite"))]
+        Some(_) => panic!("built without the sqlite feature"),
+        None => Arc::new(cytube_generator::jobs::MemoryJobStore::default()),
+    };
+
+    eprintln!("listening on http://{}", options.addr);
+    run_daemon(store, options).expect("daemon error");
+}
@@ -0,0 +1,147 @@
+use cytube_generator::daemon::{DaemonClient, JobView};
+use cytube_generator::jobs::JobStatus;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+const HELP: &str = "j/k or arrows: pick a job   p: pause/resume   c: cancel   +/-: priority   q: quit";
+
+// the terminal with keys coming through one at a time and not echoed, until it's dropped.  stty
+// rather than a terminal library: it's all that's needed, and it's everywhere
+struct RawTerminal {
+    saved: String,
+}
+
+impl RawTerminal {
+    fn new() -> std::io::Result<Self> {
+        let saved = Command::new("stty").arg("-g").stdin(Stdio::inherit()).output()?;
+        let saved = String::from_utf8_lossy(&saved.stdout).trim().to_string();
+        // -isig so ^C comes through as a key, and quitting puts the terminal back
+        Command::new("stty").args(["-icanon", "-echo", "-isig", "min", "1"]).stdin(Stdio::inherit()).status()?;
+        print!("\x1b[?25l");
+        Ok(RawTerminal {saved})
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = Command::new("stty").arg(&self.saved).stdin(Stdio::inherit()).status();
+        println!("\x1b[?25h");
+    }
+}
+
+fn bar(progress: f64, width: usize) -> String {
+    let filled = ((progress * width as f64).round() as usize).min(width);
+    format!("[{}{}]", "#".repeat(filled), ".".repeat(width - filled))
+}
+
+fn duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    match seconds >= 3600 {
+        true => format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60),
+        false => format!("{}:{:02}", seconds / 60, seconds % 60),
+    }
+}
+
+// running first, then what's queued in the order it'll run, then the last few that finished
+fn order(mut jobs: Vec<JobView>) -> Vec<JobView> {
+    let rank = |x: &JobView| match x.job.status {
+        JobStatus::Running => 0,
+        JobStatus::Queued => 1,
+        _ => 2,
+    };
+    jobs.sort_by(|a, b| rank(a).cmp(&rank(b))
+        .then_with(|| match rank(a) {
+            2 => b.job.updated_at.cmp(&a.job.updated_at),
+            _ => b.job.priority.cmp(&a.job.priority).then(a.job.id.cmp(&b.job.id)),
+        }));
+    let unfinished = jobs.iter().filter(|x| !x.job.status.is_finished()).count();
+    jobs.truncate(unfinished + 5);
+    jobs
+}
+
+fn render(url: &str, jobs: &Result<Vec<JobView>, String>, selected: usize, message: &str) {
+    let mut out = String::from("\x1b[H\x1b[2J");
+    out.push_str(&format!("{}\r\n{}\r\n\r\n", url, HELP));
+    match jobs {
+        Err(e) => out.push_str(&format!("can't reach the daemon: {}\r\n", e)),
+        Ok(jobs) if jobs.is_empty() => out.push_str("no jobs\r\n"),
+        Ok(jobs) => {
+            out.push_str(&format!("  {:>5}  {:<9}  {:>4}  {:<27}  {:>6}  {:>8}  input\r\n", "id", "status", "prio", "progress", "speed", "eta"));
+            for (i, view) in jobs.iter().enumerate() {
+                let status = match view.paused {
+                    true => "paused".to_string(),
+                    false => view.job.status.to_string(),
+                };
+                let progress = view.progress.map_or(String::new(), |x| format!("{} {:>3.0}%", bar(x, 20), x * 100.0));
+                let speed = view.speed.map_or(String::new(), |x| format!("{:.1}x", x));
+                let eta = view.eta.filter(|_| !view.paused).map_or(String::new(), duration);
+                out.push_str(&format!("{} {:>5}  {:<9}  {:>4}  {:<27}  {:>6}  {:>8}  {}\r\n",
+                    if i == selected { ">" } else { " " },
+                    view.job.id, status, view.job.priority, progress, speed, eta, view.job.spec.input));
+            }
+        },
+    }
+    out.push_str(&format!("\r\n{}", message));
+    print!("{}", out);
+    let _ = std::io::stdout().flush();
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let url = args.next().unwrap_or_else(|| "http://127.0.0.1:8081".to_string());
+    if url.starts_with("--") || args.next().is_some() {
+        eprintln!("usage: {} [daemon URL]", argv0);
+        eprintln!("set CYTUBE_API_TOKEN if the daemon wants one");
+        std::process::exit(2);
+    }
+    let client = DaemonClient {url: url.clone(), api_token: std::env::var("CYTUBE_API_TOKEN").ok()};
+
+    let (keys, key_events) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 16];
+        while let Ok(n @ 1..) = std::io::stdin().read(&mut buf) {
+            if buf[..n].iter().any(|x| keys.send(*x).is_err()) {
+                break;
+            }
+        }
+    });
+
+    let _terminal = RawTerminal::new().expect("couldn't set up the terminal");
+    let mut selected = 0;
+    let mut message = String::new();
+    loop {
+        let jobs = client.jobs().map(order).map_err(|e| e.to_string());
+        let count = jobs.as_ref().map_or(0, |x| x.len());
+        selected = selected.min(count.saturating_sub(1));
+        render(&url, &jobs, selected, &message);
+
+        // redraw every second, or as soon as a key's pressed
+        let key = match key_events.recv_timeout(Duration::from_secs(1)) {
+            Ok(key) => key,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let job = jobs.as_ref().ok().and_then(|x| x.get(selected));
+        let result = match (key, job) {
+            (b'q' | 3, _) => break,
+            (b'j', _) => { selected = (selected + 1).min(count.saturating_sub(1)); Ok(()) },
+            (b'k', _) => { selected = selected.saturating_sub(1); Ok(()) },
+            // arrow keys are ESC [ A and ESC [ B; the ESC and [ are just ignored
+            (b'B', _) => { selected = (selected + 1).min(count.saturating_sub(1)); Ok(()) },
+            (b'A', _) => { selected = selected.saturating_sub(1); Ok(()) },
+            (b'p', Some(view)) if view.paused => client.resume(view.job.id),
+            (b'p', Some(view)) => client.pause(view.job.id),
+            (b'c', Some(view)) => client.cancel(view.job.id),
+            (b'+' | b'=', Some(view)) => client.set_priority(view.job.id, view.job.priority + 1),
+            (b'-', Some(view)) => client.set_priority(view.job.id, view.job.priority - 1),
+            _ => Ok(()),
+        };
+        message = match result {
+            Ok(()) => String::new(),
+            Err(e) => e.to_string(),
+        };
+    }
+}
@@ -0,0 +1,77 @@
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::cytube_structs::CytubeVideo;
+use cytube_generator::ffprobe::ffprobe;
+use cytube_generator::manifest::{read_manifest, read_manifest_from};
+use cytube_generator::manifest_diff::diff;
+use cytube_generator::transcode::{plan, TranscodeOptions};
+use std::path::{Path, PathBuf};
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} [--json] <old manifest or output directory> <new manifest or output directory>", argv0);
+    eprintln!("       {} [--json] [--config <file>] --plan <input file> <output directory>", argv0);
+    eprintln!("says what's different between two manifests, or between the one in an output directory and what transcoding the input again would make");
+    eprintln!("a plan's bitrates are guesses, so with --plan they're expected to differ.  exits 0 if nothing's different, 1 if something is, 2 if there's trouble");
+    std::process::exit(2);
+}
+
+// a manifest file, or the one in an output directory
+fn read(path: &Path) -> CytubeVideo {
+    let manifest = match path.is_dir() {
+        true => read_manifest(path),
+        false => read_manifest_from(path),
+    };
+    manifest.unwrap_or_else(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        std::process::exit(2);
+    })
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let mut json = false;
+    let mut plan_input = None;
+    let mut config_path = None;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--plan" => plan_input = Some(PathBuf::from(args.next().unwrap_or_else(|| usage(&argv0)))),
+            "--config" => config_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage(&argv0)))),
+            x if x.starts_with("--") => usage(&argv0),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let (old, new) = match (plan_input, paths.as_slice()) {
+        (None, [old, new]) => (read(old), read(new)),
+        (Some(input), [outputdir]) => {
+            let old = read(outputdir);
+            // planned with the prefix the old one's served from, so the URLs only differ if the
+            // filenames do
+            let prefix = old.sources.first().and_then(|x| x.url.rsplit_once('/')).map(|(prefix, _)| format!("{}/", prefix)).unwrap_or_default();
+            let mut options = TranscodeOptions::default();
+            let config = config_path.or_else(default_config_path).map(|path| load_config(&path).expect("error reading config")).unwrap_or_default();
+            config.apply(&mut options);
+            let probe = ffprobe(&input).unwrap_or_else(|e| {
+                eprintln!("{}: {}", input.display(), e);
+                std::process::exit(2);
+            });
+            let new = plan(&input, &probe, outputdir, &prefix, &options).manifest;
+            (old, new)
+        },
+        _ => usage(&argv0),
+    };
+
+    let changes = diff(&old, &new);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+    } else {
+        for change in &changes {
+            println!("{}", change);
+        }
+    }
+    if !changes.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -1,37 +1,282 @@
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::distributed::{Scheduler, Worker};
+use cytube_generator::failure::{error_json, Failure, USAGE_EXIT_CODE};
 use cytube_generator::ffprobe::ffprobe;
-use cytube_generator::transcode::remux;
-use std::path::Path;
-use std::os::unix::process::CommandExt;
-use serde_json::to_writer;
-use std::fs::{OpenOptions, create_dir};
+use cytube_generator::labels::LabelLanguage;
+use cytube_generator::manifest::{write_manifest_to, MANIFEST_FILENAME};
+use cytube_generator::plan::{CliRunner, DeviceLimits, LimitedRunner};
+use cytube_generator::size_model::SizeModel;
+use cytube_generator::track_select::{choose_program, keep_program, select_tracks};
+use cytube_generator::transcode::{plan, plan_preview, DefaultSubtitles, TranscodeOptions};
+use std::path::{Path, PathBuf};
+use std::fs::create_dir;
+use std::sync::Arc;
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} [--strip-metadata] [--select-tracks] [--program <id>] [--audio-langs <jpn,eng,...>] [--sub-langs <eng,...>] [--no-subs] [--default-subs none|forced|preferred|source] [--renditions <720,480,...>] [--preview <30s>] [--storyboard] [--waveform] [--skip-markers] [--preview-page] [--label-language native|english|<languages.json>] [--label-template <template>] [--threads <n>] [--cpus <list>] [--workers <host[=slots],...>] [--config <file>] [--manifest <file>] [--error-format text|json] <input file> <output directory> <URL prefix> [parallel segments]", argv0);
+    eprintln!("if the config file says to upload outputs, give the directory to upload them into instead of the URL prefix");
+    eprintln!("--select-tracks asks which audio and subtitle tracks to keep before starting");
+    eprintln!("--program picks a program (a channel) out of a broadcast capture with more than one, by id; the default's the first with video in it");
+    eprintln!("--audio-langs keeps only audio tracks in those languages (and ones with no language), --sub-langs the same for subtitles");
+    eprintln!("--default-subs picks a subtitle track to show without the viewer turning it on");
+    eprintln!("--renditions also encodes smaller versions of the video, by height");
+    eprintln!("--storyboard also makes thumbnail sprite sheets and a storyboard.vtt for seek previews");
+    eprintln!("--waveform writes waveform peaks (audiowaveform's JSON) next to each audio output");
+    eprintln!("--skip-markers writes skip.json, with the times of chapters named like an opening, credits or a preview");
+    eprintln!("--preview-page writes preview.html, which plays the outputs, to check them in a browser before they go on the channel");
+    eprintln!("--label-language names the languages in track labels in their own language (the default), English, or any language CLDR's languages.json has names in");
+    eprintln!("--label-template says what goes in track labels, out of {{lang}}, {{title}}, {{codec}}, {{channels}} and {{bitrate}}, e.g. \"{{lang}} – {{codec}} {{channels}} ({{title}})\"");
+    eprintln!("--threads limits how many threads encoding gets; --cpus keeps ffmpeg on those CPUs (taskset's list format, e.g. 4-11)");
+    eprintln!("--workers encodes the video in segments on those machines over ssh (localhost is this one), as many at once on each as its slots, 1 if not given; the segments default to the total slots");
+    eprintln!("--preview encodes just that long a sample (in seconds, or minutes with an m), with the same settings as the whole thing");
+    eprintln!("--config - reads the config from stdin; --manifest - writes the manifest to stdout instead of its URL");
+    eprintln!("--error-format json prints errors as JSON.  exit codes: 1 other, 2 usage, 3 probe, 4 encode, 5 upload, 6 validation");
+    std::process::exit(USAGE_EXIT_CODE);
+}
+
+// "30", "30s" or "2m", in seconds
+fn parse_length(s: &str) -> Option<f64> {
+    let length = match s.strip_suffix('m') {
+        Some(minutes) => minutes.parse::<f64>().ok()? * 60.0,
+        None => s.strip_suffix('s').unwrap_or(s).parse().ok()?,
+    };
+    (length > 0.0).then_some(length)
+}
+
+// "host" or "host=slots", where localhost is this machine rather than ssh to it
+fn parse_worker(s: &str) -> Option<Worker> {
+    let (host, slots) = match s.rsplit_once('=') {
+        Some((host, slots)) => (host, slots.parse().ok().filter(|x| *x > 0)?),
+        None => (s, 1),
+    };
+    match host {
+        "" => None,
+        "localhost" => Some(Worker::Local {slots}),
+        _ => Some(Worker::Ssh {destination: host.to_string(), slots}),
+    }
+}
+
+// prints `e` the way --error-format says to, and exits with the code for how it failed
+fn fail(json: bool, input: &Path, e: std::io::Error) -> ! {
+    match json {
+        true => eprintln!("{}", error_json(input, &e)),
+        false => eprintln!("{}: {}", input.display(), e),
+    }
+    std::process::exit(Failure::of(&e).exit_code())
+}
 
 fn main() {
     let mut args = std::env::args_os();
-    let argv0 = args.next().unwrap(); // skip argv0
-    if args.len() != 4 {
-        eprintln!("usage: {} <input file> <output directory> <URL prefix>", argv0.to_string_lossy());
+    let argv0 = args.next().unwrap().to_string_lossy().into_owned(); // skip argv0
+    let mut strip_metadata = false;
+    let mut config_path = None;
+    let mut manifest_path = None;
+    let mut json_errors = false;
+    let mut select = false;
+    let mut program = None;
+    let mut audio_languages = None;
+    let mut subtitle_languages = None;
+    let mut no_subtitles = false;
+    let mut default_subtitles = None;
+    let mut renditions = None;
+    let mut preview = None;
+    let mut storyboard = false;
+    let mut waveform = false;
+    let mut skip_markers = false;
+    let mut preview_page = false;
+    let mut label_language = None;
+    let mut label_template = None;
+    let mut threads = None;
+    let mut cpus = None;
+    let mut workers = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--strip-metadata") => strip_metadata = true,
+            Some("--select-tracks") => select = true,
+            Some("--program") => program = Some(args.next().and_then(|x| x.to_str()?.parse().ok()).unwrap_or_else(|| usage(&argv0))),
+            Some("--audio-langs") => match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => audio_languages = Some(x.split(',').map(|x| x.trim().into()).collect()),
+                None => usage(&argv0),
+            },
+            Some("--sub-langs") => match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => subtitle_languages = Some(x.split(',').map(|x| x.trim().into()).collect()),
+                None => usage(&argv0),
+            },
+            Some("--no-subs") => no_subtitles = true,
+            Some("--default-subs") => default_subtitles = match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some("none") => Some(DefaultSubtitles::None),
+                Some("forced") => Some(DefaultSubtitles::Forced),
+                Some("preferred") => Some(DefaultSubtitles::Preferred),
+                Some("source") => Some(DefaultSubtitles::Source),
+                _ => usage(&argv0),
+            },
+            Some("--renditions") => match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => renditions = Some(x.split(',').map(|x| x.trim().parse().unwrap_or_else(|_| usage(&argv0))).collect()),
+                None => usage(&argv0),
+            },
+            Some("--storyboard") => storyboard = true,
+            Some("--waveform") => waveform = true,
+            Some("--skip-markers") => skip_markers = true,
+            Some("--preview-page") => preview_page = true,
+            Some("--label-template") => label_template = match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => Some(x.to_string()),
+                None => usage(&argv0),
+            },
+            Some("--label-language") => label_language = match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => Some(LabelLanguage::parse(x).unwrap_or_else(|e| fail(json_errors, Path::new(x), Failure::Validation.wrap(e)))),
+                None => usage(&argv0),
+            },
+            Some("--threads") => threads = match args.next().as_ref().and_then(|x| x.to_str()).and_then(|x| x.parse().ok()) {
+                Some(threads) => Some(threads),
+                None => usage(&argv0),
+            },
+            Some("--cpus") => cpus = match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => Some(x.to_string()),
+                None => usage(&argv0),
+            },
+            Some("--workers") => workers = match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => Some(x.split(',').map(|x| parse_worker(x.trim()).unwrap_or_else(|| usage(&argv0))).collect()),
+                None => usage(&argv0),
+            },
+            Some("--preview") => preview = match args.next().as_ref().and_then(|x| x.to_str()).and_then(parse_length) {
+                Some(length) => Some(length),
+                None => usage(&argv0),
+            },
+            Some("--config") => config_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage(&argv0)))),
+            Some("--manifest") => manifest_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage(&argv0)))),
+            Some("--error-format") => match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some("json") => json_errors = true,
+                Some("text") => json_errors = false,
+                _ => usage(&argv0),
+            },
+            Some(x) if x.starts_with("--") => usage(&argv0),
+            _ => positional.push(arg),
+        }
     }
+    if !(3..=4).contains(&positional.len()) {
+        usage(&argv0);
+    }
+    let mut args = positional.into_iter();
     let file = args.next().unwrap();
     let outputdir = args.next().unwrap();
     let urlprefix = args.next().unwrap();
+    let scheduler = workers.map(Scheduler::new);
+    let parallel_segments = args.next().map_or(scheduler.as_ref().map_or(0, Scheduler::slots), |x| x.to_string_lossy().parse().unwrap_or_else(|_| usage(&argv0)));
     
     let file = Path::new(&file);
     let outputdir = Path::new(&outputdir);
-    let urlprefix = urlprefix.to_string_lossy();
+    let urlprefix = urlprefix.to_string_lossy().into_owned();
 
-    let ffprobe = ffprobe(file).expect("ffprobe error");
-    let (mut command, cytube_data) = remux(file, &ffprobe, outputdir, &urlprefix, Some("eng".into()));
+    let mut ffprobe = ffprobe(file).unwrap_or_else(|e| fail(json_errors, file, Failure::Probe.wrap(e)));
+    let mut options = TranscodeOptions {
+        preferred_language: Some("eng".into()),
+        parallel_segments,
+        ..Default::default()
+    };
+    let config = config_path.or_else(default_config_path).map(|path| load_config(&path).unwrap_or_else(|e| fail(json_errors, &path, Failure::Validation.wrap(e)))).unwrap_or_default();
+    config.apply(&mut options);
+    options.strip_metadata |= strip_metadata;
+    if audio_languages.is_some() {
+        options.audio_languages = audio_languages;
+    }
+    if subtitle_languages.is_some() {
+        options.subtitle_languages = subtitle_languages;
+    }
+    options.no_subtitles |= no_subtitles;
+    options.skip_markers |= skip_markers;
+    options.preview_page |= preview_page;
+    if let Some(label_language) = label_language {
+        options.label_language = label_language;
+    }
+    if label_template.is_some() {
+        options.label_template = label_template;
+    }
+    if let Some(default_subtitles) = default_subtitles {
+        options.default_subtitles = default_subtitles;
+    }
+    if let Some(renditions) = renditions {
+        options.renditions = renditions;
+    }
+    if threads.is_some() {
+        options.threads = threads;
+    }
+    if cpus.is_some() {
+        options.cpu_affinity = cpus;
+    }
+    if storyboard && options.storyboard.is_none() {
+        options.storyboard = Some(Default::default());
+    }
+    if waveform && options.waveform.is_none() {
+        options.waveform = Some(Default::default());
+    }
+    let urlprefix = match &config.upload {
+        Some(upload) => {
+            let upload = cytube_generator::upload::Upload {uploader: upload.uploader(), remote_dir: urlprefix, throttle: config.upload_throttle()};
+            options.upload = Some(upload.clone());
+            upload.url_prefix()
+        },
+        None => urlprefix,
+    };
+    if let Some(path) = SizeModel::default_path() {
+        options.size_model = Some(Arc::new(SizeModel::load(&path).unwrap_or_else(|e| fail(json_errors, &path, e))));
+    }
+    if let Err(e) = choose_program(&ffprobe, program) {
+        fail(json_errors, file, Failure::Validation.wrap(e));
+    }
+    options.program = program;
+    // before picking tracks, so the ones in other programs aren't on offer
+    ffprobe = keep_program(&ffprobe, program);
+    if select {
+        // the prompt goes to stderr, like everything else that isn't the result
+        ffprobe = select_tracks(&ffprobe, std::io::stdin().lock(), std::io::stderr())
+            .unwrap_or_else(|e| fail(json_errors, file, e));
+    }
+    let plan = match preview {
+        Some(length) => plan_preview(file, &ffprobe, outputdir, &urlprefix, &options, length),
+        None => plan(file, &ffprobe, outputdir, &urlprefix, &options),
+    };
 
     if let Err(e) = create_dir(outputdir) {
         if e.kind() != std::io::ErrorKind::AlreadyExists {
-            panic!("error creating the output directory: {}", e);
+            fail(json_errors, outputdir, e);
         }
     }
 
+    eprintln!("{}", plan.command);
+    eprintln!("expecting about {} MB of output", plan.predicted_bytes / 1_000_000);
+    // keeps parallel segments from asking a GPU for more sessions than it has
+    let limits = DeviceLimits::new(config.device_limits.clone());
+    let runner = LimitedRunner {inner: &CliRunner, limits: &limits};
+    let result = match &scheduler {
+        Some(scheduler) => plan.execute_with(&runner, scheduler),
+        None => plan.execute(&runner),
+    };
+    #[cfg(feature = "notify")]
     {
-        let f = OpenOptions::new().write(true).create(true).truncate(true).open(outputdir.join("manifest.json")).expect("could not open JSON file for writing");
-        to_writer(f, &cytube_data).expect("error serializing data");
+        use cytube_generator::notify::{notify_all, JobEvent};
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let event = JobEvent::new(&file.to_string_lossy(), &urlprefix, result.as_ref().map_err(|_| error.as_deref().unwrap_or("")));
+        notify_all(&config.notifiers(), &event);
+    }
+    let manifest = result.unwrap_or_else(|e| fail(json_errors, file, e));
+    // the manifest needs signing too, if everything else does
+    let manifest_url = format!("{}{}", urlprefix, MANIFEST_FILENAME);
+    let manifest_url = match &options.signed_urls {
+        Some(signed_urls) => signed_urls.sign(&manifest_url).unwrap_or_else(|e| fail(json_errors, file, e)),
+        None => manifest_url,
+    };
+    // stdout only ever gets one thing, so this can go at the end of a pipeline
+    match manifest_path {
+        Some(path) if path.as_os_str() == "-" => {
+            eprintln!("{}", manifest_url);
+            serde_json::to_writer(std::io::stdout().lock(), &manifest).unwrap_or_else(|e| fail(json_errors, file, e.into()));
+            println!();
+        },
+        Some(path) => {
+            write_manifest_to(&path, &manifest).unwrap_or_else(|e| fail(json_errors, &path, e));
+            println!("{}", manifest_url);
+        },
+        None => println!("{}", manifest_url),
     }
-
-    command.exec();
 }
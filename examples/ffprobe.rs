@@ -1,5 +1,5 @@
 use cytube_generator::ffprobe::ffprobe;
 
 fn main() {
-    dbg!(ffprobe(std::path::Path::new("test.mkv")));
+    dbg!(ffprobe(std::path::Path::new("test.mkv")).expect("ffprobe error"));
 }
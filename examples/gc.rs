@@ -0,0 +1,35 @@
+use cytube_generator::gc::{collect_garbage, parse_protected, GcPolicy};
+use std::time::Duration;
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} <output root> [--max-age-days N] [--max-size-gb N] [--protect <file of URLs>] [--dry-run]", argv0);
+    eprintln!("the protect file can be whatever cytube's \"Retrieve playlist links\" gives you");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let Some(root) = args.next() else { usage(&argv0) };
+    let mut policy = GcPolicy::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&argv0));
+        match arg.as_str() {
+            "--max-age-days" => policy.max_age = Some(Duration::from_secs(value().parse::<u64>().expect("days must be a number") * 24 * 60 * 60)),
+            "--max-size-gb" => policy.max_bytes = Some((value().parse::<f64>().expect("size must be a number") * 1e9) as u64),
+            "--protect" => policy.protected.extend(parse_protected(&std::fs::read_to_string(value()).expect("error reading protect file"))),
+            "--dry-run" => policy.dry_run = true,
+            _ => usage(&argv0),
+        }
+    }
+    if policy.max_age.is_none() && policy.max_bytes.is_none() {
+        eprintln!("nothing to do without --max-age-days or --max-size-gb");
+        std::process::exit(2);
+    }
+
+    let report = collect_garbage(root.as_ref(), &policy).expect("gc failed");
+    for output in &report.removed {
+        println!("{}{}", if policy.dry_run { "would remove " } else { "removed " }, output.dir.display());
+    }
+    println!("{} outputs, {} MB; {} protected outputs kept", report.removed.len(), report.freed / 1_000_000, report.protected);
+}
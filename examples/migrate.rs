@@ -0,0 +1,28 @@
+use cytube_generator::cytube_structs::SCHEMA_VERSION;
+use cytube_generator::migrate::migrate_manifest;
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let dirs: Vec<String> = args.collect();
+    if dirs.is_empty() || dirs.iter().any(|x| x.starts_with("--")) {
+        eprintln!("usage: {} <output directory>...", argv0);
+        eprintln!("brings the manifests in those directories up to version {} of the format, rewriting the ones that were older", SCHEMA_VERSION);
+        std::process::exit(2);
+    }
+
+    let mut failed = false;
+    for dir in &dirs {
+        match migrate_manifest(dir.as_ref()) {
+            Ok(version) if version < SCHEMA_VERSION => println!("{}: upgraded from version {}", dir, version),
+            Ok(_) => {},
+            Err(e) => {
+                eprintln!("{}: {}", dir, e);
+                failed = true;
+            },
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,39 @@
+use cytube_generator::preflight::{preflight, DEFAULT_ORIGIN};
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} <manifest URL> [--origin https://cytu.be]", argv0);
+    eprintln!("checks the manifest and everything in it will load in Cytube's player");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let mut manifest_url = None;
+    let mut origin = DEFAULT_ORIGIN.to_string();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => origin = args.next().unwrap_or_else(|| usage(&argv0)),
+            _ if arg.starts_with("--") || manifest_url.is_some() => usage(&argv0),
+            _ => manifest_url = Some(arg),
+        }
+    }
+    let Some(manifest_url) = manifest_url else { usage(&argv0) };
+
+    let reports = preflight(&manifest_url, &origin).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    for report in &reports {
+        match report.problems.is_empty() {
+            true => println!("ok    {} ({})", report.url, report.content_type),
+            false => println!("FAIL  {} ({})", report.url, report.content_type),
+        }
+        for problem in &report.problems {
+            println!("        {}", problem);
+        }
+    }
+    if reports.iter().any(|x| !x.problems.is_empty()) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,80 @@
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::failure::{Failure, USAGE_EXIT_CODE};
+use cytube_generator::ffprobe::ffprobe;
+use cytube_generator::track_select::describe;
+use cytube_generator::transcode::{explain, TranscodeOptions};
+use std::path::PathBuf;
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} [--json] [--config <file>] [--audio-langs <jpn,eng,...>] [--sub-langs <eng,...>] [--no-subs] <input file>", argv0);
+    eprintln!("prints the input's tracks, and whether each would be copied, transcoded or dropped, and why");
+    std::process::exit(USAGE_EXIT_CODE);
+}
+
+fn main() {
+    let mut args = std::env::args_os();
+    let argv0 = args.next().unwrap().to_string_lossy().into_owned(); // skip argv0
+    let mut json = false;
+    let mut config_path = None;
+    let mut audio_languages = None;
+    let mut subtitle_languages = None;
+    let mut no_subtitles = false;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--json") => json = true,
+            Some("--config") => config_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage(&argv0)))),
+            Some("--audio-langs") => match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => audio_languages = Some(x.split(',').map(|x| x.trim().into()).collect()),
+                None => usage(&argv0),
+            },
+            Some("--sub-langs") => match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => subtitle_languages = Some(x.split(',').map(|x| x.trim().into()).collect()),
+                None => usage(&argv0),
+            },
+            Some("--no-subs") => no_subtitles = true,
+            Some(x) if x.starts_with("--") => usage(&argv0),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 1 {
+        usage(&argv0);
+    }
+    let file = PathBuf::from(positional.pop().unwrap());
+
+    // the same options extract would use
+    let mut options = TranscodeOptions {
+        preferred_language: Some("eng".into()),
+        ..Default::default()
+    };
+    let config = config_path.or_else(default_config_path).map(|path| load_config(&path).expect("error reading config file")).unwrap_or_default();
+    config.apply(&mut options);
+    if audio_languages.is_some() {
+        options.audio_languages = audio_languages;
+    }
+    if subtitle_languages.is_some() {
+        options.subtitle_languages = subtitle_languages;
+    }
+    options.no_subtitles |= no_subtitles;
+
+    let probe = ffprobe(&file).unwrap_or_else(|e| {
+        eprintln!("{}: {}", file.display(), e);
+        std::process::exit(Failure::Probe.exit_code())
+    });
+    let verdicts = explain(&probe, &options);
+    if json {
+        println!("{}", serde_json::json!({"probe": probe, "verdicts": verdicts}));
+        return;
+    }
+
+    println!("{}", file.display());
+    if let Some(title) = &probe.title {
+        println!("title: {}", title);
+    }
+    println!("{:.1}s, {} kbps, for the {} browser profile", probe.duration, probe.bitrate, options.target);
+    println!();
+    for (track, verdict) in probe.tracks.iter().zip(&verdicts) {
+        println!("{}", describe(track));
+        println!("       -> {:?}: {}", verdict.action, verdict.reason);
+    }
+}
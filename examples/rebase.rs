@@ -0,0 +1,40 @@
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::manifest::{rebase_manifest, PREFIX_PLACEHOLDER};
+use cytube_generator::signing::resign_manifest;
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} <URL prefix> <output directory>...", argv0);
+    eprintln!("puts the URL prefix into manifests written with {} as theirs, and signs them if the config file's [signed_urls] says to", PREFIX_PLACEHOLDER);
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let prefix = args.next().filter(|x| !x.starts_with("--")).unwrap_or_else(|| usage(&argv0));
+    let dirs: Vec<String> = args.collect();
+    if dirs.is_empty() || dirs.iter().any(|x| x.starts_with("--")) {
+        usage(&argv0);
+    }
+
+    let config = default_config_path().map(|path| load_config(&path).expect("error reading config file")).unwrap_or_default();
+    let signed_urls = config.signed_urls.as_ref().map(|x| x.signed_urls());
+
+    let mut failed = false;
+    for dir in &dirs {
+        let result = rebase_manifest(dir.as_ref(), &prefix).and_then(|manifest| match &signed_urls {
+            Some(signed_urls) => resign_manifest(dir.as_ref(), signed_urls),
+            None => Ok(manifest),
+        });
+        match result {
+            Ok(manifest) => println!("{}: {} is now served from {}", dir, manifest.title, prefix),
+            Err(e) => {
+                eprintln!("{}: {}", dir, e);
+                failed = true;
+            },
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,53 @@
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::signing::resign_manifest;
+use std::time::Duration;
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} <output directory>... [--expires-days N]", argv0);
+    eprintln!("signs the URLs in each directory's manifest again, as the config file's [signed_urls] says to");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap(); // skip argv0
+    let mut dirs = Vec::new();
+    let mut expires_in = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--expires-days" => {
+                let days = args.next().unwrap_or_else(|| usage(&argv0)).parse::<u64>().expect("days must be a number");
+                expires_in = Some(Duration::from_secs(days * 24 * 60 * 60));
+            },
+            _ if arg.starts_with("--") => usage(&argv0),
+            _ => dirs.push(arg),
+        }
+    }
+    if dirs.is_empty() {
+        usage(&argv0);
+    }
+
+    let config = default_config_path().map(|path| load_config(&path).expect("error reading config file")).unwrap_or_default();
+    let Some(signed_urls) = &config.signed_urls else {
+        eprintln!("the config file has no [signed_urls] table");
+        std::process::exit(1);
+    };
+    let mut signed_urls = signed_urls.signed_urls();
+    if let Some(expires_in) = expires_in {
+        signed_urls.expires_in = expires_in;
+    }
+
+    let mut failed = false;
+    for dir in &dirs {
+        match resign_manifest(dir.as_ref(), &signed_urls) {
+            Ok(manifest) => println!("{}: re-signed {}", dir, manifest.title),
+            Err(e) => {
+                eprintln!("{}: {}", dir, e);
+                failed = true;
+            },
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
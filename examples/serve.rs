@@ -0,0 +1,24 @@
+use cytube_generator::serve::{serve, ServeOptions, Tls};
+use std::path::Path;
+
+fn main() {
+    let mut args = std::env::args_os();
+    let argv0 = args.next().unwrap(); // skip argv0
+    if ![1, 2, 4].contains(&args.len()) {
+        eprintln!("usage: {} <output directory> [listen address [cert.pem key.pem]]", argv0.to_string_lossy());
+        std::process::exit(2);
+    }
+    let dir = args.next().unwrap();
+    let mut options = ServeOptions::default();
+    if let Some(addr) = args.next() {
+        options.addr = addr.to_string_lossy().parse().expect("listen address must look like 0.0.0.0:8080");
+    }
+    if let (Some(cert), Some(key)) = (args.next(), args.next()) {
+        options.tls = Some(Tls::Files {cert: cert.into(), key: key.into()});
+    }
+
+    let scheme = if options.tls.is_some() { "https" } else { "http" };
+    eprintln!("serving {} on {}://{}", Path::new(&dir).display(), scheme, options.addr);
+    eprintln!("to try a manifest out in cytube's player: {}://{}/player?manifest=<path to manifest.json>[&local]", scheme, options.addr);
+    serve(Path::new(&dir), &options).expect("server error");
+}
@@ -0,0 +1,81 @@
+use cytube_generator::config::{default_config_path, load_config};
+use cytube_generator::failure::{Failure, USAGE_EXIT_CODE};
+use cytube_generator::ffprobe::ffprobe;
+use cytube_generator::manifest::read_manifest;
+use cytube_generator::plan::CliRunner;
+use cytube_generator::subtitles::{add_text_tracks, extract_subtitles, url_prefix_of};
+use cytube_generator::transcode::{DefaultSubtitles, TranscodeOptions};
+use std::path::{Path, PathBuf};
+
+fn usage(argv0: &str) -> ! {
+    eprintln!("usage: {} [--merge] [--sub-langs <eng,...>] [--default-subs none|forced|preferred|source] [--config <file>] <input file> <output directory> [URL prefix]", argv0);
+    eprintln!("converts just the subtitles of the input to VTT files in an output directory that's already there, and prints their textTracks");
+    eprintln!("--merge adds them to the directory's manifest instead.  the URL prefix defaults to the manifest's");
+    std::process::exit(USAGE_EXIT_CODE);
+}
+
+fn main() {
+    let mut args = std::env::args_os();
+    let argv0 = args.next().unwrap().to_string_lossy().into_owned(); // skip argv0
+    let mut merge = false;
+    let mut config_path = None;
+    let mut options = TranscodeOptions {
+        preferred_language: Some("eng".into()),
+        ..Default::default()
+    };
+    let mut subtitle_languages = None;
+    let mut default_subtitles = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--merge") => merge = true,
+            Some("--config") => config_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage(&argv0)))),
+            Some("--sub-langs") => match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some(x) => subtitle_languages = Some(x.split(',').map(|x| x.trim().into()).collect()),
+                None => usage(&argv0),
+            },
+            Some("--default-subs") => default_subtitles = match args.next().as_ref().and_then(|x| x.to_str()) {
+                Some("none") => Some(DefaultSubtitles::None),
+                Some("forced") => Some(DefaultSubtitles::Forced),
+                Some("preferred") => Some(DefaultSubtitles::Preferred),
+                Some("source") => Some(DefaultSubtitles::Source),
+                _ => usage(&argv0),
+            },
+            Some(x) if x.starts_with("--") => usage(&argv0),
+            _ => positional.push(arg),
+        }
+    }
+    if !(2..=3).contains(&positional.len()) {
+        usage(&argv0);
+    }
+    let mut positional = positional.into_iter();
+    let file = PathBuf::from(positional.next().unwrap());
+    let outputdir = PathBuf::from(positional.next().unwrap());
+    let fail = |path: &Path, e: std::io::Error| -> ! {
+        eprintln!("{}: {}", path.display(), e);
+        std::process::exit(Failure::of(&e).exit_code())
+    };
+
+    let config = config_path.or_else(default_config_path).map(|path| load_config(&path).unwrap_or_else(|e| fail(&path, e))).unwrap_or_default();
+    config.apply(&mut options);
+    if subtitle_languages.is_some() {
+        options.subtitle_languages = subtitle_languages;
+    }
+    if let Some(default_subtitles) = default_subtitles {
+        options.default_subtitles = default_subtitles;
+    }
+    let url_prefix = match positional.next() {
+        Some(prefix) => prefix.to_string_lossy().into_owned(),
+        None => read_manifest(&outputdir).ok().as_ref().and_then(url_prefix_of)
+            .unwrap_or_else(|| fail(&outputdir, std::io::Error::other("no manifest to take the URL prefix from; give one"))),
+    };
+
+    let probe = ffprobe(&file).unwrap_or_else(|e| fail(&file, Failure::Probe.wrap(e)));
+    let text_tracks = extract_subtitles(&file, &probe, &outputdir, &url_prefix, &options, &CliRunner).unwrap_or_else(|e| fail(&file, e));
+    if merge {
+        let manifest = add_text_tracks(&outputdir, &text_tracks, options.signed_urls.as_ref()).unwrap_or_else(|e| fail(&outputdir, e));
+        eprintln!("{}: added {} subtitle tracks, {} in all", outputdir.display(), text_tracks.len(), manifest.text_tracks.len());
+    } else {
+        println!("{}", serde_json::json!({"textTracks": text_tracks}));
+    }
+}
@@ -0,0 +1,230 @@
+// Transcoding a whole bunch of files in one go, e.g. a season of a show.  Files are taken in
+// natural order ("Episode 2" before "Episode 10"), each gets its own subdirectory of the output
+// root named after it, and the whole lot comes out as a playlist in that same order.
+//
+// A cue sheet counts as an input too: each of its tracks becomes an item of its own, in
+// `<output root>/<cue sheet name>/<track number>`.  So does a directory of music (see
+// `album_items`), which is put in album order going by its tags rather than by filename.  Files
+// with chapters can be split at them the same way (see `chapter_items`).
+
+use crate::chapters::{plan_chapter, split_points};
+use crate::cue::{plan_track, read_cue, CueTrack};
+use crate::failure::Failure;
+use crate::ffprobe::{ffprobe, Chapter};
+use crate::plan::Runner;
+use crate::playlist::Playlist;
+use crate::transcode::{plan, TranscodeOptions};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+// what counts as media when going through a directory.  season folders tend to have subtitles,
+// .nfo files and cover art lying around too.
+const MEDIA_EXTENSIONS: [&str; 13] = ["avi", "flv", "m2ts", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "ogv", "ts", "webm", "wmv"];
+const AUDIO_EXTENSIONS: [&str; 13] = ["aac", "aiff", "alac", "ape", "flac", "m4a", "mp3", "oga", "ogg", "opus", "wav", "wma", "wv"];
+
+/// One file in a batch, and where it goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchItem {
+    pub input: PathBuf,
+    pub outputdir: PathBuf,
+    pub url_prefix: String,
+    /// Set if this is one track of a cue sheet, which gets cut out of `input`.
+    pub track: Option<CueTrack>,
+    /// Set if this is one chapter of `input`, which gets cut out of it.
+    pub chapter: Option<Chapter>,
+}
+
+#[derive(Debug, Default)]
+pub struct BatchResult {
+    /// Everything that worked, in order.
+    pub playlist: Playlist,
+    pub failures: Vec<(PathBuf, std::io::Error)>,
+}
+
+impl BatchResult {
+    /// How the batch as a whole went: `None` if everything worked, `Partial` if only some of it
+    /// did, and if none of it did, whatever the first failure was.
+    pub fn failure(&self) -> Option<Failure> {
+        let (_, first) = self.failures.first()?;
+        match self.playlist.entries.is_empty() {
+            true => Some(Failure::of(first)),
+            false => Some(Failure::Partial),
+        }
+    }
+}
+
+/// Compares names the way people number things: runs of digits compare as numbers.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_digits = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_digits = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (a_number, b_number) = (a[..a_digits].trim_start_matches('0'), b[..b_digits].trim_start_matches('0'));
+            let ordering = a_number.len().cmp(&b_number.len()).then_with(|| a_number.cmp(b_number));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            (a, b) = (&a[a_digits..], &b[b_digits..]);
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+fn is_cue(path: &Path) -> bool {
+    path.extension().is_some_and(|x| x.eq_ignore_ascii_case("cue"))
+}
+
+/// Every media file directly in `dir`, going by extension, and every cue sheet.  Files a cue sheet
+/// covers are left out, since they'll get split up by way of the cue sheet.
+pub fn media_files_in(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    files_in(dir, &MEDIA_EXTENSIONS)
+}
+
+/// Same as `media_files_in`, but for music.
+pub fn audio_files_in(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    files_in(dir, &AUDIO_EXTENSIONS)
+}
+
+fn files_in(dir: &Path, extensions: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut covered = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_media = path.extension().is_some_and(|x| extensions.contains(&x.to_string_lossy().to_ascii_lowercase().as_str()));
+        if path.is_file() && is_cue(&path) {
+            covered.extend(read_cue(&path)?.tracks.into_iter().map(|x| x.file));
+            files.push(path);
+        } else if path.is_file() && is_media {
+            files.push(path);
+        }
+    }
+    files.retain(|x| !covered.contains(x));
+    Ok(files)
+}
+
+/// Sorts `inputs` naturally by filename and works out where each one goes under `output_root`,
+/// which is served from `url_prefix`.  Cue sheets are read and split into their tracks.
+pub fn batch_items(mut inputs: Vec<PathBuf>, output_root: &Path, url_prefix: &str) -> std::io::Result<Vec<BatchItem>> {
+    let name = |x: &Path| x.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    inputs.sort_by(|a, b| natural_cmp(&name(a), &name(b)));
+    let mut items = Vec::new();
+    for input in inputs {
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        // most of what's in a filename is fine in a URL path, but not all of it
+        let prefix = format!("{}{}/", url_prefix, encode_path_segment(&stem));
+        if is_cue(&input) {
+            for track in read_cue(&input)?.tracks {
+                let number = format!("{:02}", track.number);
+                items.push(BatchItem {
+                    input: track.file.clone(),
+                    outputdir: output_root.join(&stem).join(&number),
+                    url_prefix: format!("{}{}/", prefix, number),
+                    track: Some(track),
+                    chapter: None,
+                });
+            }
+        } else {
+            items.push(BatchItem {outputdir: output_root.join(&stem), url_prefix: prefix, input, track: None, chapter: None});
+        }
+    }
+    Ok(items)
+}
+
+/// The music files in `dir` as an album, each track going in `<output root>/<dir name>/<track
+/// number>`.  They're in disc and track order going by their tags, or by filename if they're not
+/// tagged.  Cue sheets in there are split up as usual.
+pub fn album_items(dir: &Path, output_root: &Path, url_prefix: &str) -> std::io::Result<Vec<BatchItem>> {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let (output_root, url_prefix) = (output_root.join(&name), format!("{}{}/", url_prefix, encode_path_segment(&name)));
+    let (cues, files): (Vec<PathBuf>, Vec<PathBuf>) = audio_files_in(dir)?.into_iter().partition(|x| is_cue(x));
+
+    let mut tracks = Vec::new();
+    for file in files {
+        let probe = ffprobe(&file)?;
+        tracks.push(((probe.disc_number.unwrap_or(1), probe.track_number.unwrap_or(u32::MAX)), file));
+    }
+    tracks.sort_by(|(a, a_file), (b, b_file)| {
+        a.cmp(b).then_with(|| natural_cmp(&a_file.to_string_lossy(), &b_file.to_string_lossy()))
+    });
+    let mut items: Vec<BatchItem> = tracks.into_iter().enumerate().map(|(i, (_, input))| {
+        let number = format!("{:02}", i + 1);
+        BatchItem {
+            outputdir: output_root.join(&number),
+            url_prefix: format!("{}{}/", url_prefix, number),
+            input,
+            track: None,
+            chapter: None,
+        }
+    }).collect();
+    items.extend(batch_items(cues, &output_root, &url_prefix)?);
+    Ok(items)
+}
+
+/// `items` with every file that has chapters split into one item per chapter, in
+/// `<outputdir>/<chapter number>`.  Files that won't probe are left as they are, to fail when
+/// they're transcoded.
+pub fn chapter_items(items: Vec<BatchItem>) -> Vec<BatchItem> {
+    let mut split = Vec::new();
+    for item in items {
+        let chapters = match &item.track {
+            Some(_) => Vec::new(),
+            None => ffprobe(&item.input).map(|x| split_points(&x)).unwrap_or_default(),
+        };
+        if chapters.is_empty() {
+            split.push(item);
+            continue;
+        }
+        for (i, chapter) in chapters.into_iter().enumerate() {
+            let number = format!("{:02}", i + 1);
+            split.push(BatchItem {
+                input: item.input.clone(),
+                outputdir: item.outputdir.join(&number),
+                url_prefix: format!("{}{}/", item.url_prefix, number),
+                track: None,
+                chapter: Some(chapter),
+            });
+        }
+    }
+    split
+}
+
+fn encode_path_segment(s: &str) -> String {
+    let mut encoded = String::new();
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Transcodes every item in order.  One failing doesn't stop the rest; it's just left out of the
+/// playlist.
+pub fn run_batch(items: &[BatchItem], options: &TranscodeOptions, runner: &dyn Runner) -> BatchResult {
+    let mut result = BatchResult::default();
+    for item in items {
+        let transcoded = ffprobe(&item.input).map_err(|e| Failure::Probe.wrap(e)).and_then(|probe| {
+            let plan = match (&item.track, &item.chapter) {
+                (Some(track), _) => plan_track(track, &probe, &item.outputdir, &item.url_prefix, options),
+                (None, Some(chapter)) => plan_chapter(&item.input, chapter, &probe, &item.outputdir, &item.url_prefix, options),
+                (None, None) => plan(&item.input, &probe, &item.outputdir, &item.url_prefix, options),
+            };
+            plan.execute(runner)
+        });
+        match transcoded {
+            Ok(manifest) => result.playlist.push(&manifest, &item.url_prefix),
+            Err(e) => result.failures.push((item.input.clone(), e)),
+        }
+    }
+    result
+}
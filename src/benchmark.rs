@@ -0,0 +1,227 @@
+// Timing each video encoder on this machine, once, so the fastest one that's good enough can be
+// picked without anyone having to know which of them the GPU (or ffmpeg build) has.
+//
+// A benchmark encodes the same short clip with each encoder, from a lossless reference made
+// first so decoding the sample doesn't count against anyone, and scores the result's SSIM against
+// the reference.  Encoders this ffmpeg doesn't have, or that have no device to run on, fail, and
+// are recorded as unavailable.  The results are saved (`default_path`) and read back by the config
+// file's `[auto_encoder]`, which has `TranscodeOptions::encoder` pick from them.
+
+use crate::compat::BrowserProfile;
+use crate::encoder::VideoEncoder;
+use crate::ffprobe::{Track, TrackType};
+use crate::invocation::FfmpegInvocation;
+use crate::transcode::TranscodeOptions;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// What to benchmark, and on what.
+#[derive(Debug, Clone)]
+pub struct Benchmark {
+    /// A file to take the clip from.  `None` uses ffmpeg's `testsrc2` pattern with some noise on
+    /// it, which is quick but flatters every encoder a bit.
+    pub sample: Option<PathBuf>,
+    /// Where in `sample` the clip starts, in seconds.
+    pub start: f32,
+    /// How long the clip is, in seconds.
+    pub length: f32,
+    /// The clip's height.  Encoders' speeds don't scale evenly with resolution, so this should be
+    /// what most inputs are.
+    pub height: u16,
+    pub encoders: Vec<VideoEncoder>,
+    /// Where to put the reference and the encodes while they're being scored.
+    pub scratch_dir: PathBuf,
+}
+
+impl Default for Benchmark {
+    fn default() -> Self {
+        Benchmark {
+            sample: None,
+            start: 0.0,
+            length: 10.0,
+            height: 1080,
+            encoders: VideoEncoder::ALL.to_vec(),
+            scratch_dir: std::env::temp_dir().join("cytube-generator-benchmark"),
+        }
+    }
+}
+
+/// How one encoder did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderScore {
+    pub encoder: VideoEncoder,
+    /// Seconds of video encoded per second.  Over 1 is faster than real time.
+    pub speed: f32,
+    pub kbps: u64,
+    /// Against the reference, 0 to 1.  Above about 0.98 is hard to tell apart from it.
+    pub ssim: f32,
+}
+
+/// Everything a benchmark found.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkResults {
+    /// The clip's height.
+    pub height: u16,
+    /// Fastest first.
+    pub scores: Vec<EncoderScore>,
+    /// The encoders that couldn't run, and the last thing ffmpeg said about it.
+    pub unavailable: Vec<(VideoEncoder, String)>,
+}
+
+// the last line ffmpeg printed, which is usually why it failed
+fn last_line(stderr: &[u8]) -> String {
+    String::from_utf8_lossy(stderr).lines().rev().find(|x| !x.trim().is_empty()).unwrap_or("").trim().to_string()
+}
+
+// runs `command`, failing with the last thing it printed
+fn run(command: &FfmpegInvocation) -> std::io::Result<Vec<u8>> {
+    let output = command.to_command().output()?;
+    match output.status.success() {
+        true => Ok(output.stderr),
+        false => Err(std::io::Error::other(last_line(&output.stderr))),
+    }
+}
+
+// "... SSIM Y:0.98 U:0.99 V:0.99 All:0.985123 (18.3)"
+fn parse_ssim(stderr: &[u8]) -> Option<f32> {
+    let stderr = String::from_utf8_lossy(stderr);
+    let line = stderr.lines().rev().find(|x| x.contains("SSIM "))?;
+    line.split_once("All:")?.1.split_whitespace().next()?.parse().ok()
+}
+
+impl Benchmark {
+    fn reference_path(&self) -> PathBuf {
+        self.scratch_dir.join("reference.mkv")
+    }
+
+    // makes the lossless clip everything's encoded from and scored against
+    fn make_reference(&self) -> std::io::Result<()> {
+        let mut command = FfmpegInvocation::new();
+        command.global_arg("-hide_banner").global_arg("-y");
+        match &self.sample {
+            Some(sample) => {
+                command.args(["-ss", self.start.to_string().as_str(), "-t", self.length.to_string().as_str()]);
+                command.input(sample);
+                command.args(["-map", "0:v:0", "-vf", format!("scale=-2:{}", self.height).as_str()]);
+            },
+            None => {
+                command.args(["-f", "lavfi"]);
+                command.input(format!("testsrc2=size={}x{}:rate=24000/1001:duration={},noise=alls=12:allf=t",
+                    self.height as u32 * 16 / 9, self.height, self.length));
+            },
+        }
+        command.args(["-c:v", "ffv1", "-pix_fmt", "yuv420p"]);
+        command.output(self.reference_path());
+        run(&command).map(|_| ())
+    }
+
+    fn score(&self, encoder: VideoEncoder, options: &TranscodeOptions) -> std::io::Result<EncoderScore> {
+        let output = self.scratch_dir.join(format!("{}.mkv", encoder.ffmpeg_name()));
+        let video = Track {
+            kind: TrackType::Video, codec: "ffv1".to_string(), scanline_count: Some(self.height), pix_fmt: Some("yuv420p".to_string()),
+            ..Default::default()
+        };
+        let mut command = FfmpegInvocation::new();
+        command.global_arg("-hide_banner").global_arg("-y");
+        for arg in options.encoder_device_args(encoder) {
+            command.global_arg(arg);
+        }
+        command.input(self.reference_path());
+        if let Some(upload) = options.encoder_upload(encoder) {
+            command.arg("-vf").arg(upload.to_string());
+        }
+        command.args(options.encoder_args_for(encoder, &video));
+        command.output(&output);
+        let started = Instant::now();
+        run(&command)?;
+        let elapsed = started.elapsed().as_secs_f32();
+
+        let mut compare = FfmpegInvocation::new();
+        compare.global_arg("-hide_banner").global_arg("-filter_complex").global_arg("[0:v][1:v]ssim");
+        compare.input(&output);
+        compare.input(self.reference_path());
+        compare.args(["-f", "null"]);
+        compare.output("-");
+        let ssim = parse_ssim(&run(&compare)?).ok_or_else(|| std::io::Error::other("ffmpeg didn't print an SSIM"))?;
+        let bytes = std::fs::metadata(&output)?.len();
+        let _ = std::fs::remove_file(&output);
+        Ok(EncoderScore {
+            encoder,
+            speed: self.length / elapsed.max(0.001),
+            kbps: (bytes as f32 * 8.0 / 1000.0 / self.length) as u64,
+            ssim,
+        })
+    }
+
+    /// Encodes the clip with each encoder, using `options`' settings for them (CRF, H.264
+    /// constraints, the VA-API device), one at a time so they don't slow each other down.
+    pub fn run(&self, options: &TranscodeOptions) -> std::io::Result<BenchmarkResults> {
+        std::fs::create_dir_all(&self.scratch_dir)?;
+        self.make_reference()?;
+        let mut results = BenchmarkResults {height: self.height, ..Default::default()};
+        for encoder in &self.encoders {
+            match self.score(*encoder, options) {
+                Ok(score) => results.scores.push(score),
+                Err(e) => results.unavailable.push((*encoder, e.to_string())),
+            }
+        }
+        let _ = std::fs::remove_dir_all(&self.scratch_dir);
+        results.scores.sort_by(|a, b| b.speed.total_cmp(&a.speed));
+        Ok(results)
+    }
+}
+
+impl BenchmarkResults {
+    /// `$XDG_STATE_HOME/cytube-generator/encoder-benchmark.json`, falling back on `~/.local/state`.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("state"),
+        };
+        Some(dir.join("cytube-generator").join("encoder-benchmark.json"))
+    }
+
+    /// Reads the results saved at `path`, or `None` if there aren't any.
+    pub fn load(path: &Path) -> std::io::Result<Option<Self>> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::File::create(&tmp)?.write_all(&serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// The fastest encoder that scored at least `min_ssim` and makes something `target` can play.
+    pub fn fastest(&self, min_ssim: f32, target: BrowserProfile) -> Option<VideoEncoder> {
+        self.scores.iter()
+            .filter(|x| x.ssim >= min_ssim && target.allows_video_codec(x.encoder.codec()))
+            .max_by(|a, b| a.speed.total_cmp(&b.speed))
+            .map(|x| x.encoder)
+    }
+}
+
+/// Picking the encoder from a benchmark's results instead of `fallback_encoder`.  The
+/// `[auto_encoder]` table in the config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoEncoder {
+    pub results: BenchmarkResults,
+    /// The lowest SSIM an encoder can have scored and still be picked.
+    pub min_ssim: f32,
+}
+
+impl AutoEncoder {
+    /// What to encode to for `target`, or `None` if nothing that was benchmarked is good enough.
+    pub fn choose(&self, target: BrowserProfile) -> Option<VideoEncoder> {
+        self.results.fastest(self.min_ssim, target)
+    }
+}
@@ -0,0 +1,77 @@
+// Putting ffmpeg in a cgroup (v2, so Linux only) with a memory limit and a CPU weight, so a
+// runaway encode gets killed by the OOM killer inside its cgroup instead of taking the rest of the
+// machine (the channel, the web server) down with it.
+//
+// There's nothing to set up beforehand and no daemon to ask: ffmpeg's run through `sh`, which
+// makes the cgroup if it isn't there, writes the limits, moves itself in and then execs ffmpeg, so
+// ffmpeg's inside from its first allocation.  That needs write access to the cgroup's parent,
+// which for a service means `Delegate=yes` in its unit (and the parent being its own cgroup, e.g.
+// `/sys/fs/cgroup/system.slice/cytube-generator.service`).  If the limits can't be set, ffmpeg
+// isn't run at all.
+
+use crate::invocation::shell_quote;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// The cgroup every ffmpeg goes in, and its limits.  The `[cgroup]` table in the config file.
+/// The limits are on all of them together, since they share the one cgroup.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cgroup {
+    /// Made if it isn't there.
+    pub path: PathBuf,
+    /// `memory.max`, e.g. `"4G"`.  Past it, the kernel reclaims what it can and then kills one of
+    /// the ffmpegs.
+    pub memory_max: Option<String>,
+    /// `cpu.weight`, 1 to 10000.  Everything else gets 100, so 20 means ffmpeg only gets a fifth
+    /// of what anything else that wants the CPU gets, but all of it when nothing does.
+    pub cpu_weight: Option<u16>,
+    /// `cpuset.cpus`, in the same format as `TranscodeOptions::cpu_affinity`.  Unlike
+    /// `taskset`, ffmpeg can't undo it.
+    pub cpus: Option<String>,
+}
+
+impl Default for Cgroup {
+    fn default() -> Self {
+        Cgroup {path: PathBuf::from("/sys/fs/cgroup/cytube-generator"), memory_max: None, cpu_weight: None, cpus: None}
+    }
+}
+
+impl Cgroup {
+    // the controllers the limits need, and what to write to each file
+    fn settings(&self) -> (Vec<&str>, Vec<(&str, String)>) {
+        let mut controllers = Vec::new();
+        let mut files = Vec::new();
+        if let Some(memory_max) = &self.memory_max {
+            controllers.push("+memory");
+            files.push(("memory.max", memory_max.clone()));
+        }
+        if let Some(cpu_weight) = self.cpu_weight {
+            controllers.push("+cpu");
+            files.push(("cpu.weight", cpu_weight.to_string()));
+        }
+        if let Some(cpus) = &self.cpus {
+            controllers.push("+cpuset");
+            files.push(("cpuset.cpus", cpus.clone()));
+        }
+        (controllers, files)
+    }
+
+    /// What to run ffmpeg through to put it in the cgroup.  See `FfmpegInvocation::launcher`.
+    pub fn launcher(&self) -> Vec<OsString> {
+        let (controllers, files) = self.settings();
+        // $0 is the cgroup, and "$@" the command to run in it
+        let mut script = String::from("mkdir -p \"$0\"");
+        if !controllers.is_empty() {
+            // the parent has to hand the controllers down first.  if it can't, writing the
+            // limits below fails, which is the error worth seeing
+            script.push_str(&format!(" && {{ echo {} > \"$(dirname \"$0\")/cgroup.subtree_control\" 2>/dev/null; true; }}", shell_quote(&controllers.join(" "))));
+        }
+        for (file, value) in files {
+            script.push_str(&format!(" && echo {} > \"$0/{}\"", shell_quote(&value), file));
+        }
+        script.push_str(" && echo $$ > \"$0/cgroup.procs\" && exec \"$@\"");
+        vec!["sh".into(), "-c".into(), script.into(), self.path.clone().into()]
+    }
+}
@@ -0,0 +1,133 @@
+// Queueing things on a Cytube channel.  Cytube has no HTTP API for this; everything goes through
+// socket.io, which we speak just enough of, over its HTTP long-polling transport (Engine.IO v4),
+// to log in, join the channel and queue custom media.
+//
+// Packets, as far as we care: a poll response is Engine.IO packets separated by 0x1e.  "0{...}"
+// is the handshake, "2" is a ping we have to answer with "3", and "4" is a message, which is a
+// Socket.IO packet: "40" is connected, "42[...]" is an event, `[name, data]`.
+
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+// how long to wait for Cytube to accept or reject each item
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channel {
+    /// e.g. `https://cytu.be`
+    pub server: String,
+    pub channel: String,
+    /// Who to log in as.  Without one, you're a guest, and most channels won't let guests queue.
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+fn io_error(e: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::other(e.to_string())
+}
+
+struct Connection {
+    agent: ureq::Agent,
+    url: String,
+    // events that arrived while waiting for something else
+    backlog: Vec<(String, Value)>,
+}
+
+impl Connection {
+    fn open(server: &str) -> std::io::Result<Self> {
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(60)).build();
+        let base = format!("{}/socket.io/?EIO=4&transport=polling", server.trim_end_matches('/'));
+        let handshake = agent.get(&base).call().map_err(io_error)?.into_string()?;
+        let handshake: Value = handshake.strip_prefix('0')
+            .and_then(|x| serde_json::from_str(x).ok())
+            .ok_or_else(|| io_error(format!("unexpected socket.io handshake: {}", handshake)))?;
+        let sid = handshake["sid"].as_str().ok_or_else(|| io_error("socket.io handshake without a sid"))?;
+        let connection = Connection {agent, url: format!("{}&sid={}", base, sid), backlog: Vec::new()};
+        connection.send(&["40".to_string()])?;
+        Ok(connection)
+    }
+
+    fn send(&self, packets: &[String]) -> std::io::Result<()> {
+        self.agent.post(&self.url)
+            .set("Content-Type", "text/plain;charset=UTF-8")
+            .send_string(&packets.join("\x1e"))
+            .map_err(io_error)?;
+        Ok(())
+    }
+
+    fn emit(&self, event: &str, data: Value) -> std::io::Result<()> {
+        self.send(&[format!("42{}", json!([event, data]))])
+    }
+
+    // one long poll's worth of events
+    fn poll(&mut self) -> std::io::Result<Vec<(String, Value)>> {
+        let body = self.agent.get(&self.url).call().map_err(io_error)?.into_string()?;
+        let mut events = Vec::new();
+        for packet in body.split('\x1e') {
+            if packet == "2" {
+                self.send(&["3".to_string()])?;
+            } else if packet.starts_with('1') {
+                return Err(io_error("cytube closed the connection"));
+            } else if let Some(event) = packet.strip_prefix("42") {
+                if let Ok(Value::Array(event)) = serde_json::from_str::<Value>(event) {
+                    let name = event.first().and_then(Value::as_str).unwrap_or_default().to_owned();
+                    events.push((name, event.get(1).cloned().unwrap_or(Value::Null)));
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    // waits for the first event `pick` says yes to
+    fn wait_for<T>(&mut self, timeout: Duration, mut pick: impl FnMut(&str, &Value) -> Option<T>) -> std::io::Result<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(i) = self.backlog.iter().position(|(name, data)| pick(name, data).is_some()) {
+                let (name, data) = self.backlog.remove(i);
+                return Ok(pick(&name, &data).unwrap());
+            }
+            if Instant::now() > deadline {
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out waiting for cytube"));
+            }
+            let events = self.poll()?;
+            self.backlog.extend(events);
+        }
+    }
+}
+
+impl Channel {
+    // where the channel's socket.io server is; it's not necessarily the web server
+    fn socket_server(&self) -> std::io::Result<String> {
+        let url = format!("{}/socketconfig/{}.json", self.server.trim_end_matches('/'), self.channel);
+        let config: Value = ureq::get(&url).call().map_err(io_error)?.into_json()?;
+        let servers = config["servers"].as_array().cloned().unwrap_or_default();
+        servers.iter().find(|x| x["secure"] == true).or(servers.first())
+            .and_then(|x| x["url"].as_str())
+            .map(str::to_owned)
+            .ok_or_else(|| io_error(format!("no socket.io servers in {}", url)))
+    }
+
+    /// Adds `urls` (manifest URLs, as custom media) to the end of the playlist, in order.  Each one
+    /// is waited on before the next is sent, so they end up in the order given even though Cytube
+    /// fetches manifests in the background.
+    pub fn queue(&self, urls: &[String]) -> std::io::Result<()> {
+        let mut connection = Connection::open(&self.socket_server()?)?;
+        connection.emit("joinChannel", json!({"name": self.channel}))?;
+        if let Some(username) = &self.username {
+            connection.emit("login", json!({"name": username, "pw": self.password.as_deref().unwrap_or("")}))?;
+            let login = connection.wait_for(QUEUE_TIMEOUT, |name, data| (name == "login").then(|| data.clone()))?;
+            if login["success"] != true {
+                return Err(io_error(format!("couldn't log in to cytube: {}", login["error"].as_str().unwrap_or("no reason given"))));
+            }
+        }
+        for url in urls {
+            connection.emit("queue", json!({"id": url, "type": "cm", "pos": "end", "temp": false}))?;
+            connection.wait_for(QUEUE_TIMEOUT, |name, data| match name {
+                "queue" if data["item"]["media"]["id"] == url.as_str() => Some(Ok(())),
+                "queueFail" => Some(Err(io_error(format!("cytube wouldn't queue {}: {}", url, data["msg"].as_str().unwrap_or("no reason given"))))),
+                _ => None,
+            })??;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+// Splitting a file at its chapter marks, for files that are really several videos in one: a
+// concert film with a chapter per song, or an anthology disc with one per short.  Each chapter gets
+// cut out into its own output with its own manifest, titled after the chapter, the same way the
+// tracks of a cue sheet are (see `cue`).
+//
+// Chapters named like an opening, credits or a preview of the next episode can also be written
+// out as skip markers (`skip.json`, next to the manifest), for a bot to announce or skip them.
+
+use crate::ffprobe::{Chapter, FFprobeResult};
+use crate::plan::TranscodePlan;
+use crate::transcode::{plan_cut, Cut, TranscodeOptions};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The chapters to split `ffprobe`'s file into, with the untitled ones called "Chapter 01" and so
+/// on.  Empty if there's fewer than two, since there's nothing to split then.  Chapters with no
+/// length (some muxers put one at the very end) are left out.
+pub fn split_points(ffprobe: &FFprobeResult) -> Vec<Chapter> {
+    let chapters: Vec<Chapter> = ffprobe.chapters.iter()
+        .filter(|x| x.end > x.start)
+        .enumerate()
+        .map(|(i, x)| Chapter {
+            title: Some(x.title.clone().unwrap_or_else(|| format!("Chapter {:02}", i + 1))),
+            ..x.clone()
+        })
+        .collect();
+    match chapters.len() {
+        0 | 1 => Vec::new(),
+        _ => chapters,
+    }
+}
+
+/// Plans cutting `chapter` out of `media_file`, which `ffprobe` is the probe of.  The manifest
+/// (and the output's title tag) get the chapter's title, and the duration is the chapter's.
+pub fn plan_chapter(media_file: &Path, chapter: &Chapter, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, options: &TranscodeOptions) -> TranscodePlan {
+    let mut probe = ffprobe.clone();
+    if let Some(title) = &chapter.title {
+        probe.title = Some(title.clone());
+        probe.tags.insert("title".to_string(), title.clone());
+    }
+    // the last chapter runs to the end of the file, in case its end was rounded down
+    let last = ffprobe.chapters.iter().rev().find(|x| x.end > x.start).is_some_and(|x| x.start == chapter.start);
+    let cut = Cut {start: chapter.start, length: (!last).then_some(chapter.end - chapter.start)};
+    plan_cut(media_file, &probe, outputdir, url_prefix, options, cut)
+}
+
+/// Where skip markers go, next to the manifest.
+pub const SKIP_MARKERS_FILENAME: &str = "skip.json";
+
+/// What a stretch that can be skipped is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipKind {
+    /// The opening, or the theme song.
+    Intro,
+    /// "Previously on..."
+    Recap,
+    /// The ending, or the credits.
+    Credits,
+    /// The preview of the next episode.
+    Preview,
+}
+
+/// A chapter that can be skipped, going by its name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkipMarker {
+    pub kind: SkipKind,
+    /// In seconds from the start of the output.
+    pub start: f64,
+    pub end: f64,
+    /// The chapter's name, as it was.
+    pub title: String,
+}
+
+// chapter names, tidied up by `normalize`, that mean each kind of skippable chapter.  anime
+// releases say OP and ED; western ones, intro and credits
+const SKIP_NAMES: [(SkipKind, &[&str]); 4] = [
+    (SkipKind::Intro, &["op", "opening", "opening song", "opening theme", "opening credits", "intro", "theme song"]),
+    (SkipKind::Recap, &["recap", "previously", "previously on"]),
+    (SkipKind::Credits, &["ed", "ending", "ending song", "ending theme", "credits", "end credits", "closing credits", "outro"]),
+    (SkipKind::Preview, &["preview", "next episode", "next episode preview", "next time", "next"]),
+];
+
+// lower case, punctuation and numbers ("OP1", "Opening 2") gone, one space between words
+fn normalize(title: &str) -> String {
+    let title = title.to_lowercase().replace(|c: char| !c.is_alphanumeric(), " ");
+    let words: Vec<&str> = title.split_whitespace()
+        .map(|x| x.trim_end_matches(|c: char| c.is_ascii_digit()))
+        .filter(|x| !x.is_empty())
+        .collect();
+    words.join(" ")
+}
+
+/// The skippable chapters in `chapters`, going by their names.  "OP", "Opening 2", "ED",
+/// "Credits", "Preview" and the like are; anything not named like one isn't.
+pub fn skip_markers(chapters: &[Chapter]) -> Vec<SkipMarker> {
+    chapters.iter().filter(|x| x.end > x.start).filter_map(|chapter| {
+        let title = chapter.title.as_ref()?;
+        let normalized = normalize(title);
+        let (kind, _) = SKIP_NAMES.iter().find(|(_, names)| names.contains(&normalized.as_str()))?;
+        Some(SkipMarker {kind: *kind, start: chapter.start, end: chapter.end, title: title.clone()})
+    }).collect()
+}
+
+/// Writes `markers` to `dir` as JSON (an array of them).
+pub fn write_skip_markers(dir: &Path, markers: &[SkipMarker]) -> std::io::Result<()> {
+    std::fs::write(dir.join(SKIP_MARKERS_FILENAME), serde_json::to_vec(markers)?)
+}
@@ -0,0 +1,69 @@
+// Cutting clips out of a longer input, for highlight reels and filler between scheduled shows.
+// Each range gets an output and a manifest of its own, made the same way a whole file would be
+// (so what's copied and what's transcoded is decided the same way too), in `<outputdir>/<clip
+// number>`.
+//
+// A copied video can only start on a keyframe, so ffmpeg keeps the frames from the one before the
+// range's start and writes an edit list telling the player to skip them.  Browsers go by the edit
+// list, so the clip still plays from where it was asked to.  Either way, the manifest's duration
+// is measured from the clip that came out (see `manifest::finalize_manifest`), not worked out from
+// the range.
+
+use crate::cytube_structs::CytubeVideo;
+use crate::failure::Failure;
+use crate::ffprobe::{ffprobe, FFprobeResult};
+use crate::plan::{Runner, TranscodePlan};
+use crate::transcode::{plan_cut, Cut, TranscodeOptions};
+use std::path::Path;
+
+/// A stretch of the input to make a clip of, in seconds from its start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipRange {
+    pub start: f64,
+    /// `None` runs to the end of the input.
+    pub end: Option<f64>,
+    /// What to title the clip's manifest.  Without one it's titled like the whole input would be.
+    pub title: Option<String>,
+}
+
+impl ClipRange {
+    fn check(&self, duration: f64) -> std::io::Result<()> {
+        let end = self.end.unwrap_or(duration);
+        if self.start < 0.0 || self.start >= duration || end <= self.start {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!(
+                "can't clip {}s to {}s out of something {}s long", self.start, end, duration)));
+        }
+        Ok(())
+    }
+}
+
+/// Plans a clip of each of `ranges` out of `input`, which `ffprobe` is the probe of, the `n`th
+/// (from 1) going in `<outputdir>/<n>` (two digits, at least) under `<url_prefix><n>/`.  Fails if
+/// any of the ranges isn't inside the input.
+pub fn plan_clips(input: &Path, ffprobe: &FFprobeResult, ranges: &[ClipRange], outputdir: &Path, url_prefix: &str, options: &TranscodeOptions) -> std::io::Result<Vec<TranscodePlan>> {
+    let mut plans = Vec::new();
+    for (i, range) in ranges.iter().enumerate() {
+        range.check(ffprobe.duration)?;
+        let mut probe = ffprobe.clone();
+        if let Some(title) = &range.title {
+            probe.title = Some(title.clone());
+            probe.tags.insert("title".to_string(), title.clone());
+        }
+        // a range that goes past the end is cut off there, so the manifest doesn't say it's longer
+        // than it is
+        let length = range.end.filter(|x| *x < ffprobe.duration).map(|x| x - range.start);
+        let number = format!("{:02}", i + 1);
+        let cut = Cut {start: range.start, length};
+        plans.push(plan_cut(input, &probe, &outputdir.join(&number), &format!("{}{}/", url_prefix, number), options, cut));
+    }
+    Ok(plans)
+}
+
+/// Probes `input`, then makes a clip of each of `ranges` (see `plan_clips`) one after another, and
+/// returns their manifests in the same order.  Stops at the first one that fails; the ones before
+/// it are left where they are.
+pub fn clip(input: &Path, ranges: &[ClipRange], outputdir: &Path, url_prefix: &str, options: &TranscodeOptions, runner: &dyn Runner) -> std::io::Result<Vec<CytubeVideo>> {
+    let probe = ffprobe(input).map_err(|e| Failure::Probe.wrap(e))?;
+    let plans = plan_clips(input, &probe, ranges, outputdir, url_prefix, options)?;
+    plans.into_iter().map(|plan| plan.execute(runner)).collect()
+}
@@ -0,0 +1,328 @@
+// Which codecs can go in which containers without being transcoded, as far as Cytube and the
+// browsers playing its media are concerned.  `transcode` makes its decisions from these tables;
+// they're public so other tools can ask the same questions, and explain the answers.
+//
+// The tables themselves describe what current browsers play.  A `BrowserProfile` narrows them
+// down for audiences stuck on something older or pickier (mostly Safari).
+
+use crate::encoder::VideoEncoder;
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A container Cytube accepts for a video source.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum VideoContainer {
+    MP4, WEBM, OGG
+}
+
+/// The container a video stream in `video_codec` (as ffprobe names it) can be copied into, or
+/// `None` if browsers can't play it and it has to be transcoded.
+pub fn find_video_container(video_codec: &str) -> Option<VideoContainer> {
+    use VideoContainer::*;
+    match video_codec {
+        "av1" | "vp8" | "vp9" => Some(WEBM),
+        "h264" | // H.264
+        "hevc" | // H.265
+        "mpeg4"| // MP4V-ES
+        "mpeg2video"
+                 => Some(MP4),
+        "theora" => Some(OGG),
+        _ => None,
+    }
+}
+
+impl VideoContainer {
+    /// Audio codecs that can be copied into this container alongside the video.
+    pub fn get_acceptable_audio_codecs(&self) -> &'static [&'static str] {
+        use VideoContainer::*;
+        match self {
+            MP4  => &["aac", "alac", "flac", "opus", "mp3"],
+            WEBM => &["opus", "vorbis"],
+            OGG  => &["opus", "vorbis", "flac"],
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        use VideoContainer::*;
+        match self {
+            MP4  => "mp4",
+            WEBM => "webm",
+            OGG  => "ogv",
+        }
+    }
+    pub fn mimetype(&self) -> &'static str {
+        use VideoContainer::*;
+        match self {
+            MP4  => "video/mp4",
+            WEBM => "video/webm",
+            OGG  => "video/ogg",
+        }
+    }
+}
+
+/// A container Cytube accepts for a standalone audio track.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum AudioContainer {
+    #[serde(rename="m4a")]
+    M4A,
+    #[serde(rename="ogg")]
+    OGG,
+    // Every source I can find on the internet says that M4A files are just renamed MP4 files that
+    // only contain audio tracks.  However, when I ask ffmpeg to create an M4A file and an MP4 file
+    // with the exact same contents, I get two different files.  It puts the sequence "M4A_" in the
+    // file subtype of one but not the other.  Also it will refuse to put any codec besides AAC or
+    // ALAC in an M4A file.  To work around this, I'm producing "pseudo-M4A" files which are
+    // actually literally renamed MP4 files that only contain audio tracks.  My testing says
+    // browsers will still play them despite the header saying it's an ISO MP4 rather than an M4A.
+    // This allows me to embed audio codecs like MP3 that cytube would otherwise reject.
+    #[serde(rename="pseudo-m4a")]
+    PseudoM4A,
+}
+
+/// The container a standalone audio track in `audio_codec` can be copied into, or `None` if it has
+/// to be transcoded.
+pub fn find_audio_container(audio_codec: &str) -> Option<AudioContainer> {
+    // Now here's where things get wacky.
+    // Cytube doesn't support adding bare FLAC files, citing browser compatiblitity
+    // issues with the FLAC codec.
+    // Maybe the documentation is just old and Cytube hasn't been updated in a while,
+    // but caniuse.com tells a very different story: green lights across the board for
+    // any browser released in the last couple years, with a 95% compatibility rating.
+    // I should probably see about bugging the guys at Cytube to remove that
+    // restriction.
+    // In the meantime, however, just because we can't use the FLAC *container*
+    // doesn't mean that we can't play FLAC-encoded *audio*.
+    // You see, one of the container formats that Cytube *does* accept is Ogg, and
+    // there are three audio codecs (that browsers support) that can go inside an
+    // Ogg file: Vorbis, Opus, and FLAC.
+    // If we embed FLAC data inside an Ogg file, Cytube won't know the difference.  The
+    // entire point of the custom metadata files is that Cytube doesn't have to
+    // retrieve the files from the media host to run ffprobe on them.  It doesn't know
+    // about the codecs, only the container.  We just tell the server we have an
+    // Ogg file and it says "great" and ships it to the clients.
+    // The Cytube client (webpage) doesn't do any enforcement on its end.  As long as
+    // the browser can play it, it'll play ball.
+    // We can play FLAC files, we just can't *tell Cytube* we're playing FLAC files.
+    use AudioContainer::*;
+    match audio_codec {
+        "aac" | "alac" | "aac_latm" => Some(M4A),
+        "opus" | "vorbis" | "flac" => Some(OGG),
+        "mp3" => Some(PseudoM4A),
+        _ => None,
+    }
+}
+
+impl AudioContainer {
+    pub fn extension(&self) -> &'static str {
+        use AudioContainer::*;
+        match self {
+            OGG => "ogg",
+            M4A | PseudoM4A => "m4a",
+        }
+    }
+    pub fn mimetype(&self) -> &'static str {
+        use AudioContainer::*;
+        match self {
+            OGG => "audio/ogg",
+            M4A | PseudoM4A => "audio/mp4",
+        }
+    }
+    /// The `-f` an output in this needs, if ffmpeg wouldn't pick the right muxer from the
+    /// extension.  It takes `.m4a` for the ipod muxer, which has no tag for MP3.
+    pub fn muxer(&self) -> Option<&'static str> {
+        match self {
+            AudioContainer::PseudoM4A => Some("mp4"),
+            _ => None,
+        }
+    }
+    /// The content type for this as a manifest source, rather than an audio track.  Cytube only
+    /// takes a handful of types for sources and audio/mp4 isn't one of them, but an audio-only MP4
+    /// plays just fine as video/mp4.
+    pub fn source_mimetype(&self) -> &'static str {
+        use AudioContainer::*;
+        match self {
+            OGG => "audio/ogg",
+            M4A | PseudoM4A => "video/mp4",
+        }
+    }
+}
+
+/// Which browsers the output has to play in.  Narrows down what gets copied as opposed to
+/// transcoded, and what gets transcoded to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, strum::EnumString, strum::Display, Serialize, Deserialize)]
+#[strum(serialize_all="kebab-case")]
+#[serde(rename_all="kebab-case")]
+pub enum BrowserProfile {
+    /// Current Chrome, Firefox and Safari.  Anything in the tables above goes.
+    #[default]
+    Modern,
+    /// Also Safari from the last few years, which can't be trusted with AV1, VP8/VP9, Opus, Vorbis
+    /// or FLAC.  H.264 and HEVC with AAC, ALAC or MP3, all in MP4.
+    SafariConservative,
+    /// Anything that plays video at all: H.264 with AAC or MP3.
+    Legacy,
+}
+
+impl BrowserProfile {
+    pub fn allows_video_codec(&self, video_codec: &str) -> bool {
+        match self {
+            BrowserProfile::Modern => true,
+            BrowserProfile::SafariConservative => matches!(video_codec, "h264" | "hevc"),
+            BrowserProfile::Legacy => video_codec == "h264",
+        }
+    }
+
+    pub fn allows_audio_codec(&self, audio_codec: &str) -> bool {
+        match self {
+            BrowserProfile::Modern => true,
+            BrowserProfile::SafariConservative => matches!(audio_codec, "aac" | "alac" | "mp3"),
+            BrowserProfile::Legacy => matches!(audio_codec, "aac" | "mp3"),
+        }
+    }
+
+    /// `find_video_container`, for this profile.
+    pub fn video_container(&self, video_codec: &str) -> Option<VideoContainer> {
+        find_video_container(video_codec).filter(|_| self.allows_video_codec(video_codec))
+    }
+
+    /// `find_audio_container`, for this profile.
+    pub fn audio_container(&self, audio_codec: &str) -> Option<AudioContainer> {
+        find_audio_container(audio_codec).filter(|_| self.allows_audio_codec(audio_codec))
+    }
+
+    /// Whether `audio_codec` can be copied into `container` next to the video.
+    pub fn accepts_audio_in(&self, container: VideoContainer, audio_codec: &str) -> bool {
+        container.get_acceptable_audio_codecs().contains(&audio_codec) && self.allows_audio_codec(audio_codec)
+    }
+
+    /// `encoder` if this profile can play what it puts out, x264 otherwise.
+    pub fn fallback_encoder(&self, encoder: VideoEncoder) -> VideoEncoder {
+        if self.allows_video_codec(encoder.codec()) { encoder } else { VideoEncoder::X264 }
+    }
+
+    /// The container a file with this video and audio could be remuxed into without transcoding
+    /// anything, or why there isn't one.
+    pub fn check(&self, video_codec: &str, audio_codec: &str) -> Result<VideoContainer, Incompatibility> {
+        let container = self.video_container(video_codec).ok_or_else(|| Incompatibility::VideoCodec(video_codec.to_owned()))?;
+        if !self.accepts_audio_in(container, audio_codec) {
+            return Err(Incompatibility::AudioCodec {codec: audio_codec.to_owned(), container});
+        }
+        Ok(container)
+    }
+}
+
+/// An entry in one of `CodecPolicy`'s tables.  In the config file it's either the name of a
+/// container ("mp4", "webm", "ogg"; "m4a", "ogg", "pseudo-m4a" for standalone audio), or
+/// "transcode".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule<C> {
+    CopyInto(C),
+    Transcode,
+}
+
+impl<C: Copy> Rule<C> {
+    fn container(&self) -> Option<C> {
+        match self {
+            Rule::CopyInto(container) => Some(*container),
+            Rule::Transcode => None,
+        }
+    }
+}
+
+impl<'de, C: Deserialize<'de>> Deserialize<'de> for Rule<C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s == "transcode" {
+            return Ok(Rule::Transcode);
+        }
+        C::deserialize(s.into_deserializer()).map(Rule::CopyInto)
+    }
+}
+
+/// Site-specific overrides for the tables above.  Anything in here wins over both the built-in
+/// tables and the `BrowserProfile`; anything not in here falls through to them.
+///
+/// ```toml
+/// [codec_policy]
+/// video = { hevc = "transcode" }
+/// audio = { flac = "transcode" }
+/// allow_audio = { mp4 = ["flac"] }
+/// deny_audio = { mp4 = ["opus"] }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodecPolicy {
+    /// Video codec -> what to do with it.
+    pub video: HashMap<String, Rule<VideoContainer>>,
+    /// Audio codec -> what to do with it when it gets its own file (multi-language audio).
+    pub audio: HashMap<String, Rule<AudioContainer>>,
+    /// Extra audio codecs that can be copied alongside video in each container.
+    pub allow_audio: HashMap<VideoContainer, Vec<String>>,
+    /// Audio codecs that can't, even though the built-in table says they can.
+    pub deny_audio: HashMap<VideoContainer, Vec<String>>,
+}
+
+fn listed(table: &HashMap<VideoContainer, Vec<String>>, container: VideoContainer, codec: &str) -> bool {
+    table.get(&container).is_some_and(|codecs| codecs.iter().any(|x| x == codec))
+}
+
+impl CodecPolicy {
+    /// `BrowserProfile::video_container`, with the overrides applied.
+    pub fn video_container(&self, target: BrowserProfile, video_codec: &str) -> Option<VideoContainer> {
+        match self.video.get(video_codec) {
+            Some(rule) => rule.container(),
+            None => target.video_container(video_codec),
+        }
+    }
+
+    /// `BrowserProfile::audio_container`, with the overrides applied.
+    pub fn audio_container(&self, target: BrowserProfile, audio_codec: &str) -> Option<AudioContainer> {
+        match self.audio.get(audio_codec) {
+            Some(rule) => rule.container(),
+            None => target.audio_container(audio_codec),
+        }
+    }
+
+    /// `BrowserProfile::accepts_audio_in`, with the overrides applied.
+    pub fn accepts_audio_in(&self, target: BrowserProfile, container: VideoContainer, audio_codec: &str) -> bool {
+        if listed(&self.deny_audio, container, audio_codec) {
+            return false;
+        }
+        listed(&self.allow_audio, container, audio_codec) || target.accepts_audio_in(container, audio_codec)
+    }
+}
+
+/// Why a video/audio pair can't be copied as-is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    /// The target browsers can't play the video codec in any container Cytube accepts.
+    VideoCodec(String),
+    /// The video can be copied, but the audio codec can't go in the same container with it.
+    AudioCodec { codec: String, container: VideoContainer },
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Incompatibility::VideoCodec(codec) => write!(f, "{} video can't be played by the target browsers", codec),
+            Incompatibility::AudioCodec { codec, container } =>
+                write!(f, "{} audio can't go in {} alongside the video", codec, container.extension()),
+        }
+    }
+}
+
+impl std::error::Error for Incompatibility {}
+
+/// `BrowserProfile::check` for the default (modern) profile.
+pub fn check(video_codec: &str, audio_codec: &str) -> Result<VideoContainer, Incompatibility> {
+    BrowserProfile::Modern.check(video_codec, audio_codec)
+}
+
+/// `check`, without the explanation.
+pub fn can_copy(video_codec: &str, audio_codec: &str) -> Option<VideoContainer> {
+    check(video_codec, audio_codec).ok()
+}
@@ -0,0 +1,267 @@
+// The config file: settings a site operator wants applied to every run, in TOML.  Everything in
+// it is optional, and a missing file is the same as an empty one.
+
+use crate::benchmark::{AutoEncoder, BenchmarkResults};
+use crate::cgroup::Cgroup;
+use crate::compat::CodecPolicy;
+use crate::encoder::AudioPolicy;
+use crate::jobs::Quotas;
+use crate::labels::LabelLanguage;
+use crate::metadata::MetadataFile;
+#[cfg(feature = "daemon")]
+use crate::daemon::Tenant;
+#[cfg(feature = "notify")]
+use crate::notify::{Discord, Irc, Matrix, Notifier, Webhook};
+use crate::signing::SignedUrlsConfig;
+use crate::storyboard::Storyboard;
+use crate::transcode::{DefaultSubtitles, ReplayGainMode, TranscodeOptions};
+use crate::transcode_cache::TranscodeCache;
+use crate::upload::{Bandwidth, Throttle, UploadConfig, UploadLimits};
+use crate::waveform::Waveform;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub codec_policy: CodecPolicy,
+    /// `[audio]`: see `encoder::AudioPolicy`.
+    pub audio: AudioPolicy,
+    /// `[device_limits]`: how many ffmpegs can use each hardware device at once, e.g.
+    /// `"/dev/dri/renderD128" = 4` or `cuda = 3`.  See `plan::DeviceLimits`.
+    pub device_limits: HashMap<String, usize>,
+    /// See `TranscodeOptions::threads`.
+    pub threads: Option<u16>,
+    /// e.g. `"4-11"`.  See `TranscodeOptions::cpu_affinity`.
+    pub cpu_affinity: Option<String>,
+    /// `[cgroup]`: run ffmpeg in a cgroup with these limits.  See `cgroup::Cgroup`.
+    pub cgroup: Option<Cgroup>,
+    /// `[auto_encoder]`: pick the encoder from `benchmark` results.  See `AutoEncoderConfig`.
+    pub auto_encoder: Option<AutoEncoderConfig>,
+    /// `[tenants.<submitter>]` tables: where each submitter's outputs go in daemon mode.  See
+    /// `TenantConfig`.
+    pub tenants: HashMap<String, TenantConfig>,
+    /// `[quotas]`: how much each submitter gets to run in daemon mode.  See `jobs::Quotas`.
+    pub quotas: Quotas,
+    /// Directory to keep finished transcodes in and reuse them from.  See `transcode_cache`.
+    pub transcode_cache: Option<PathBuf>,
+    /// e.g. `"{title} S{season}E{episode}"`.  See `TranscodeOptions::title_template`.
+    pub title_template: Option<String>,
+    /// What language track labels name languages in: `"native"` (each in its own, the
+    /// default), `"english"`, or a CLDR `languages.json` for any other.  See `labels`.
+    pub label_language: Option<LabelLanguage>,
+    /// What goes in track labels, e.g. `"{lang} – {codec} {channels} ({title})"`.  See
+    /// `TranscodeOptions::label_template`.
+    pub label_template: Option<String>,
+    /// A JSON or CSV file of titles and such for particular files.  See `metadata::MetadataFile`.
+    pub metadata_file: Option<PathBuf>,
+    /// See `TranscodeOptions::gapless`.
+    pub gapless: bool,
+    /// `"track"` or `"album"`.  See `TranscodeOptions::replay_gain`.
+    pub replay_gain: Option<ReplayGainMode>,
+    /// e.g. `["jpn", "eng"]`.  See `TranscodeOptions::audio_languages`.
+    pub audio_languages: Option<Vec<String>>,
+    /// See `TranscodeOptions::subtitle_languages`.
+    pub subtitle_languages: Option<Vec<String>>,
+    /// See `TranscodeOptions::no_subtitles`.
+    pub no_subtitles: bool,
+    /// See `TranscodeOptions::closed_captions`.
+    pub closed_captions: bool,
+    /// See `TranscodeOptions::teletext`.
+    pub teletext: bool,
+    /// `"none"`, `"forced"`, `"preferred"` or `"source"`.  See `TranscodeOptions::default_subtitles`.
+    pub default_subtitles: Option<DefaultSubtitles>,
+    /// e.g. `[720, 480]`.  See `TranscodeOptions::renditions`.
+    pub renditions: Vec<u16>,
+    /// `[storyboard]`: make thumbnail sprite sheets for seek previews, laid out like this (an
+    /// empty table takes the defaults).  See `storyboard::Storyboard`.
+    pub storyboard: Option<Storyboard>,
+    /// `[waveform]`: write waveform peaks for the audio outputs.  See `waveform::Waveform`.
+    pub waveform: Option<Waveform>,
+    /// See `TranscodeOptions::skip_markers`.
+    pub skip_markers: bool,
+    /// See `TranscodeOptions::preview_page`.
+    pub preview_page: bool,
+    /// See `TranscodeOptions::copy_tags`.
+    pub copy_tags: Option<Vec<String>>,
+    /// See `TranscodeOptions::strip_metadata`.
+    pub strip_metadata: bool,
+    /// See `TranscodeOptions::deterministic`.
+    pub deterministic: bool,
+    /// `[signed_urls]`: how to sign the manifest's URLs, if they need it.  See
+    /// `signing::SignedUrlsConfig`.
+    pub signed_urls: Option<SignedUrlsConfig>,
+    /// `[upload]`: where to upload outputs to.  See `upload::UploadConfig`.
+    pub upload: Option<UploadConfig>,
+    /// `[upload_limits]`: a bandwidth limit for every upload put together, and when uploads can
+    /// happen.
+    pub upload_limits: UploadLimits,
+    /// `[[webhooks]]` tables, each POSTed to when a transcode finishes.
+    #[cfg(feature = "notify")]
+    pub webhooks: Vec<Webhook>,
+    /// `[[discord]]`, `[[matrix]]` and `[[irc]]` tables, for announcing finished transcodes in
+    /// chat.
+    #[cfg(feature = "notify")]
+    pub discord: Vec<Discord>,
+    #[cfg(feature = "notify")]
+    pub matrix: Vec<Matrix>,
+    #[cfg(feature = "notify")]
+    pub irc: Vec<Irc>,
+}
+
+/// The `[auto_encoder]` table.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoEncoderConfig {
+    /// See `benchmark::AutoEncoder::min_ssim`.
+    pub min_ssim: f32,
+    /// Where the benchmark's results are.  Defaults to `BenchmarkResults::default_path`.
+    pub results: Option<PathBuf>,
+}
+
+impl Default for AutoEncoderConfig {
+    fn default() -> Self {
+        AutoEncoderConfig {min_ssim: 0.95, results: None}
+    }
+}
+
+/// A `[tenants.<submitter>]` table.  See `daemon::Tenant`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    pub output_root: PathBuf,
+    pub url_prefix: Option<String>,
+    /// The same as the top-level `[upload]`.
+    pub upload: Option<UploadConfig>,
+}
+
+impl Config {
+    /// Copies everything the config file sets into `options`.
+    pub fn apply(&self, options: &mut TranscodeOptions) {
+        options.codec_policy = self.codec_policy.clone();
+        options.audio = self.audio.clone();
+        if self.title_template.is_some() {
+            options.title_template = self.title_template.clone();
+        }
+        if self.threads.is_some() {
+            options.threads = self.threads;
+        }
+        if self.cpu_affinity.is_some() {
+            options.cpu_affinity = self.cpu_affinity.clone();
+        }
+        if self.cgroup.is_some() {
+            options.cgroup = self.cgroup.clone();
+        }
+        options.gapless |= self.gapless;
+        options.strip_metadata |= self.strip_metadata;
+        options.deterministic |= self.deterministic;
+        if self.replay_gain.is_some() {
+            options.replay_gain = self.replay_gain;
+        }
+        if let Some(languages) = &self.audio_languages {
+            options.audio_languages = Some(languages.iter().map(|x| x.as_str().into()).collect());
+        }
+        if let Some(languages) = &self.subtitle_languages {
+            options.subtitle_languages = Some(languages.iter().map(|x| x.as_str().into()).collect());
+        }
+        options.no_subtitles |= self.no_subtitles;
+        options.teletext |= self.teletext;
+        options.closed_captions |= self.closed_captions;
+        options.skip_markers |= self.skip_markers;
+        options.preview_page |= self.preview_page;
+        if let Some(default_subtitles) = self.default_subtitles {
+            options.default_subtitles = default_subtitles;
+        }
+        if !self.renditions.is_empty() {
+            options.renditions = self.renditions.clone();
+        }
+        if self.storyboard.is_some() {
+            options.storyboard = self.storyboard.clone();
+        }
+        if self.waveform.is_some() {
+            options.waveform = self.waveform.clone();
+        }
+        if self.copy_tags.is_some() {
+            options.copy_tags = self.copy_tags.clone();
+        }
+        if let Some(signed_urls) = &self.signed_urls {
+            options.signed_urls = Some(signed_urls.signed_urls());
+        }
+        if let Some(label_language) = &self.label_language {
+            options.label_language = label_language.clone();
+        }
+        if self.label_template.is_some() {
+            options.label_template = self.label_template.clone();
+        }
+        if let Some(path) = &self.metadata_file {
+            options.metadata = Some(Arc::new(MetadataFile::new(path)));
+        }
+        if let Some(auto) = &self.auto_encoder {
+            // no results (or unreadable ones) means there's been no benchmark yet, and
+            // `fallback_encoder` it is
+            let results = auto.results.clone().or_else(BenchmarkResults::default_path)
+                .and_then(|path| BenchmarkResults::load(&path).ok().flatten());
+            options.auto_encoder = results.map(|results| Arc::new(AutoEncoder {results, min_ssim: auto.min_ssim}));
+        }
+        if let Some(dir) = &self.transcode_cache {
+            options.cache = Some(TranscodeCache::new(dir));
+        }
+    }
+
+    /// The limits on uploads, from `[upload_limits]` and `[upload]`.  Every call makes new ones,
+    /// so for the limits to hold across uploads they have to share the one `Throttle`.
+    pub fn upload_throttle(&self) -> Throttle {
+        let limits = [self.upload_limits.max_bytes_per_second, self.upload.as_ref().and_then(|x| x.max_bytes_per_second)];
+        Throttle {
+            bandwidth: limits.into_iter().flatten().map(|x| Arc::new(Bandwidth::new(x))).collect(),
+            window: self.upload_limits.window,
+        }
+    }
+
+    /// The `[tenants]`, for `DaemonOptions::tenants`.
+    #[cfg(feature = "daemon")]
+    pub fn tenants(&self) -> HashMap<String, Tenant> {
+        self.tenants.iter().map(|(name, x)| (name.clone(), Tenant {
+            output_root: x.output_root.clone(),
+            url_prefix: x.url_prefix.clone(),
+            uploader: x.upload.as_ref().map(|x| x.uploader()),
+        })).collect()
+    }
+
+    /// Everything the config file says to notify when a transcode finishes.
+    #[cfg(feature = "notify")]
+    pub fn notifiers(&self) -> Vec<Arc<dyn Notifier>> {
+        let mut notifiers = Vec::<Arc<dyn Notifier>>::new();
+        notifiers.extend(self.webhooks.iter().map(|x| Arc::new(x.clone()) as Arc<dyn Notifier>));
+        notifiers.extend(self.discord.iter().map(|x| Arc::new(x.clone()) as Arc<dyn Notifier>));
+        notifiers.extend(self.matrix.iter().map(|x| Arc::new(x.clone()) as Arc<dyn Notifier>));
+        notifiers.extend(self.irc.iter().map(|x| Arc::new(x.clone()) as Arc<dyn Notifier>));
+        notifiers
+    }
+}
+
+/// `$XDG_CONFIG_HOME/cytube-generator/config.toml`, falling back on `~/.config` like XDG says to.
+pub fn default_config_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("cytube-generator").join("config.toml"))
+}
+
+/// Reads the config file at `path`, or stdin if it's `-`.  If there's no file there, you get the
+/// defaults.
+pub fn load_config(path: &Path) -> std::io::Result<Config> {
+    let text = match path.as_os_str() == "-" {
+        true => std::io::read_to_string(std::io::stdin()),
+        false => std::fs::read_to_string(path),
+    };
+    let text = match text {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e),
+    };
+    toml::from_str(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
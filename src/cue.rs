@@ -0,0 +1,213 @@
+// Cue sheets, for album rips that are one big FLAC (or whatever) plus a .cue saying where each
+// track starts.  Every track gets cut out into its own output with its own manifest, so they can
+// be queued one by one.
+//
+// Only the parts of the format that matter for that are read: FILE, TRACK, TITLE, PERFORMER and
+// INDEX.  Times are MM:SS:FF, with 75 frames to the second (it's from CDs).
+
+use crate::ffprobe::FFprobeResult;
+use crate::plan::TranscodePlan;
+use crate::transcode::{plan_cut, Cut, TranscodeOptions};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueSheet {
+    /// The album.
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    /// The file the track is in.  Relative to the cue sheet, unless it came from `read_cue`.
+    pub file: PathBuf,
+    pub title: Option<String>,
+    /// The track's performer, or the album's if the track doesn't say.
+    pub performer: Option<String>,
+    /// Where the track starts in `file` (its INDEX 01), in seconds.
+    pub start: f64,
+    /// Where the next track in the same file starts, or `None` if it runs to the end of the file.
+    pub end: Option<f64>,
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+// a line's words, with "quoted strings" kept together and unquoted
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let (word, after) = quoted.split_once('"').unwrap_or((quoted, ""));
+            words.push(word.to_string());
+            rest = after.trim_start();
+        } else {
+            let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            words.push(word.to_string());
+            rest = after.trim_start();
+        }
+    }
+    words
+}
+
+// MM:SS:FF.  minutes can go past 59.
+fn parse_time(time: &str) -> Option<f64> {
+    let mut parts = time.split(':').map(|x| x.parse::<u32>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || seconds >= 60 || frames >= 75 {
+        return None;
+    }
+    Some((minutes * 60 + seconds) as f64 + frames as f64 / 75.0)
+}
+
+/// Reads a cue sheet from its text.
+pub fn parse_cue(text: &str) -> std::io::Result<CueSheet> {
+    let mut sheet = CueSheet::default();
+    let mut file: Option<PathBuf> = None;
+
+    for (line_number, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        let error = |what: &str| invalid(format!("cue sheet line {}: {}", line_number + 1, what));
+        let words = words(line);
+        let Some(keyword) = words.first() else {
+            continue;
+        };
+        let argument = words.get(1).cloned();
+        // TITLE and PERFORMER belong to the album until the first TRACK
+        match (keyword.to_ascii_uppercase().as_str(), sheet.tracks.last_mut()) {
+            ("FILE", _) => file = Some(argument.ok_or_else(|| error("FILE without a filename"))?.into()),
+            ("TRACK", _) => {
+                let number = argument.and_then(|x| x.parse().ok()).ok_or_else(|| error("TRACK without a number"))?;
+                let file = file.clone().ok_or_else(|| error("TRACK before any FILE"))?;
+                // the start gets filled in by INDEX 01
+                sheet.tracks.push(CueTrack {number, file, title: None, performer: None, start: f64::NAN, end: None});
+            },
+            ("TITLE", Some(track)) => track.title = argument,
+            ("TITLE", None) => sheet.title = argument,
+            ("PERFORMER", Some(track)) => track.performer = argument,
+            ("PERFORMER", None) => sheet.performer = argument,
+            ("INDEX", Some(track)) if argument.as_deref().and_then(|x| x.parse::<u32>().ok()) == Some(1) => {
+                track.start = words.get(2).and_then(|x| parse_time(x)).ok_or_else(|| error("INDEX with a bad time"))?;
+                let (start, file) = (track.start, track.file.clone());
+                let previous = sheet.tracks.len().checked_sub(2).and_then(|i| sheet.tracks.get_mut(i));
+                if let Some(previous) = previous.filter(|x| x.file == file) {
+                    previous.end = Some(start);
+                }
+            },
+            // REM, INDEX 00 (the pregap, which stays with the track before), ISRC, FLAGS...
+            _ => {},
+        }
+    }
+    for track in sheet.tracks.iter_mut() {
+        if track.start.is_nan() {
+            return Err(invalid(format!("cue sheet track {} has no INDEX 01", track.number)));
+        }
+        if track.performer.is_none() {
+            track.performer = sheet.performer.clone();
+        }
+    }
+    Ok(sheet)
+}
+
+/// Reads the cue sheet at `path`, with the tracks' files resolved relative to it.
+pub fn read_cue(path: &Path) -> std::io::Result<CueSheet> {
+    let bytes = std::fs::read(path)?;
+    // plenty of cue sheets out there were written by Windows rippers in the local codepage.
+    // anything that isn't UTF-8 gets read as Latin-1, which is at least right for Western Europe.
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.into_bytes().iter().map(|&x| x as char).collect(),
+    };
+    let mut sheet = parse_cue(&text).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for track in sheet.tracks.iter_mut() {
+        track.file = dir.join(&track.file);
+    }
+    Ok(sheet)
+}
+
+/// Plans cutting `track` out of its file, which `ffprobe` is the probe of.  The manifest (and the
+/// output's tags) get the track's title, performer, number and length.
+pub fn plan_track(track: &CueTrack, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, options: &TranscodeOptions) -> TranscodePlan {
+    // as far as the rest of the planning's concerned, the file is just this track
+    let mut probe = ffprobe.clone();
+    probe.title = Some(track.title.clone().unwrap_or_else(|| format!("Track {:02}", track.number)));
+    probe.artist = track.performer.clone().or(probe.artist);
+    probe.track_number = Some(track.number);
+    probe.tags.insert("title".to_string(), probe.title.clone().unwrap());
+    probe.tags.insert("track".to_string(), track.number.to_string());
+    if let Some(artist) = &probe.artist {
+        probe.tags.insert("artist".to_string(), artist.clone());
+    }
+    // the last track runs to the end of the file, in case the duration was rounded down
+    let cut = Cut {start: track.start, length: track.end.map(|x| x - track.start)};
+    plan_cut(&track.file, &probe, outputdir, url_prefix, options, cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}REM GENRE Rock
+PERFORMER \"The Band\"
+TITLE \"The Album\"
+FILE \"disc one.flac\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Intro\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Second Song\"
+    PERFORMER \"The Band feat. Someone\"
+    INDEX 00 03:18:00
+    INDEX 01 03:20:37
+FILE \"disc two.flac\" WAVE
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+  TRACK 04 AUDIO
+    INDEX 01 61:00:74
+";
+
+    #[test]
+    fn parsing_a_cue_sheet() {
+        let sheet = parse_cue(SHEET).unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("The Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        let tracks: Vec<_> = sheet.tracks.iter()
+            .map(|x| (x.number, x.file.to_str().unwrap(), x.title.as_deref(), x.performer.as_deref(), x.start, x.end))
+            .collect();
+        assert_eq!(tracks, [
+            (1, "disc one.flac", Some("Intro"), Some("The Band"), 0.0, Some(200.0 + 37.0 / 75.0)),
+            // the pregap (INDEX 00) stays with the track before
+            (2, "disc one.flac", Some("Second Song"), Some("The Band feat. Someone"), 200.0 + 37.0 / 75.0, None),
+            // a new file doesn't end the last track of the one before
+            (3, "disc two.flac", None, Some("The Band"), 0.0, Some(3660.0 + 74.0 / 75.0)),
+            (4, "disc two.flac", None, Some("The Band"), 3660.0 + 74.0 / 75.0, None),
+        ]);
+    }
+
+    #[test]
+    fn bad_cue_sheets() {
+        let cases = [
+            "TRACK 01 AUDIO\nINDEX 01 00:00:00",
+            "FILE \"a.flac\" WAVE\nTRACK AUDIO\nINDEX 01 00:00:00",
+            "FILE \"a.flac\" WAVE\nTRACK 01 AUDIO\nINDEX 00 00:00:00",
+            "FILE \"a.flac\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:60:00",
+            "FILE \"a.flac\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:75",
+            "FILE \"a.flac\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00",
+            "FILE\nTRACK 01 AUDIO\nINDEX 01 00:00:00",
+        ];
+        for text in cases {
+            assert!(parse_cue(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn words_keep_quoted_strings_together() {
+        assert_eq!(words("  TITLE \"Two  Words\" extra"), ["TITLE", "Two  Words", "extra"]);
+        assert_eq!(words("TITLE \"unterminated"), ["TITLE", "unterminated"]);
+        assert!(words("   ").is_empty());
+    }
+}
@@ -1,42 +1,85 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
 
+#[allow(dead_code)]
 pub const CYTUBE_ACCEPTABLE_QUALITY_VALUES: [u16; 8] = [240, 360, 480, 540, 720, 1080, 1440, 2160];
 
+/// The version of the manifest format this writes, in `CytubeVideo::schema`.  See `migrate` for
+/// what changed in each.
+pub const SCHEMA_VERSION: u32 = 1;
 
-#[derive(Serialize)]
+/// `seconds` the way cytube wants it: a whole number of them.  It moves on to the next thing in the
+/// playlist once that many have gone by, so this rounds up rather than cut the end off.  Anything
+/// within a millisecond of a whole second is taken to be float noise and rounded to it.
+pub fn cytube_duration(seconds: f64) -> f64 {
+    ((seconds * 1000.0).round() / 1000.0).ceil()
+}
+
+fn serialize_duration<S: Serializer>(duration: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(cytube_duration(*duration))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
 pub struct CytubeVideo {
     pub title: String,
-    pub duration: f32,
+    /// In seconds.  Written out rounded; see `cytube_duration`.
+    #[serde(serialize_with = "serialize_duration")]
+    pub duration: f64,
+    /// A live stream, which cytube plays without trying to keep everyone to `duration`.  Nothing
+    /// here makes those, but a manifest that says so still reads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub live: Option<bool>,
+    /// The URL of an image for the playlist to show.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
     pub sources: Vec<Source>,
     pub audio_tracks: Vec<AudioTrack>,
     pub text_tracks: Vec<TextTrack>,
+    /// Which version of the format this manifest was written in, so older ones can be told apart
+    /// and brought up to date (see `migrate`).  Cytube ignores fields it doesn't know.  0 is
+    /// anything from before there was a version.
+    #[serde(default, rename = "cytubeGeneratorSchema")]
+    pub schema: u32,
+    /// Fields this doesn't know about, e.g. cytube extensions added by hand, kept so that
+    /// rewriting the manifest doesn't lose them.  The tracks keep theirs the same way.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
 pub struct Source {
     pub url: String,
-    pub content_type: &'static str,
+    pub content_type: String,
     pub quality: u16, // cytube accepts 240, 360, 480, 540, 720, 1080, 1440, and 2160
     pub bitrate: u64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
 pub struct TextTrack {
     pub url: String,
     pub name: String,
-    pub content_type: &'static str,
+    pub content_type: String,
+    /// Shown without the viewer picking it.  At most one track should have it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub default: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
 pub struct AudioTrack {
     pub url: String,
     pub label: String,
     pub language: String,
-    pub content_type: &'static str,
+    pub content_type: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 
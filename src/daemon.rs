@@ -0,0 +1,517 @@
+// A long-running transcode server, driven over a small JSON API:
+//
+//   POST   /jobs                  submit a `JobSpec`, get back {"id": ...}
+//   GET    /jobs[?status=...]     list jobs
+//   GET    /jobs/{id}             a job, with its progress (0 to 1) if it's running
+//   GET    /jobs/{id}/manifest    the finished manifest
+//   DELETE /jobs/{id}             cancel a job, killing ffmpeg if it's already running
+//   POST   /jobs/{id}/pause       pause a running job (ffmpeg's stopped where it is)
+//   POST   /jobs/{id}/resume      carry on with a paused one
+//   PUT    /jobs/{id}/priority    {"priority": n}: higher goes first
+//   GET    /metrics               Prometheus metrics (no API token needed, there's nothing secret in them)
+//
+// Jobs are kept in a `JobStore` and worked through by a fixed number of worker threads, highest
+// priority first, except that a submitter who's used up their quota (see `jobs::Quotas`) has to
+// wait while everyone else's jobs go ahead.  Anyone
+// who can reach the API can make this read any file (or URL) the daemon can, so either keep it on
+// localhost or set an API token.
+//
+// Several channels can share the one daemon without their files mixing: each submitter can be a
+// `Tenant`, with an output root, URL prefix and upload backend of their own.
+//
+// On SIGTERM (or ^C) it stops taking new jobs and gives the running ones `shutdown_timeout` to
+// finish.  Anything still going after that is killed and left marked as running, so the next
+// start puts it back in the queue.  See `systemd` for running it as a service.
+
+use crate::cytube_structs::CytubeVideo;
+use crate::ffprobe::ffprobe;
+use crate::gc::{collect_garbage, GcPolicy};
+use crate::jobs::{now, Job, JobId, JobSpec, JobStatus, JobStore, Quotas};
+use crate::metrics::{Metrics, Snapshot};
+use crate::notify::{notify_all, JobEvent, Notifier};
+use crate::plan::{DeviceLimits, LimitedRunner, ProgressRunner};
+use crate::transcode::{plan, TranscodeOptions};
+use crate::upload::{Throttle, Upload, Uploader};
+use axum::extract::{Path as UrlPath, Query, Request, State};
+use axum::http::{header::{AUTHORIZATION, CONTENT_TYPE}, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+mod client;
+mod systemd;
+
+pub use client::DaemonClient;
+
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    /// Ignored when systemd hands us a socket to listen on.
+    pub addr: SocketAddr,
+    /// How many jobs run at once.
+    pub workers: usize,
+    /// Every job's `outputdir` is taken relative to this (or its tenant's), and isn't allowed to
+    /// climb out of it.
+    pub output_root: PathBuf,
+    /// Submitters (see `JobSpec::submitter`) whose outputs go somewhere of their own.
+    pub tenants: HashMap<String, Tenant>,
+    /// If set, every request needs an `Authorization: Bearer <token>` header with it.
+    pub api_token: Option<String>,
+    /// What jobs get run with, before their own options are applied.
+    pub transcode: TranscodeOptions,
+    /// Told about every job that finishes or fails.  Not about cancelled ones: whoever cancelled
+    /// them already knows.
+    pub notifiers: Vec<Arc<dyn Notifier>>,
+    /// If set, every job's outputs are uploaded here, into a directory named like its `outputdir`.
+    pub uploader: Option<Arc<dyn Uploader>>,
+    /// Shared by every job's upload.
+    pub upload_throttle: Throttle,
+    /// How much each submitter's jobs get to run.
+    pub quotas: Quotas,
+    /// Sessions allowed per hardware device, across every worker.  See `plan::DeviceLimits`.
+    pub device_limits: HashMap<String, usize>,
+    /// If set, jobs wait to start until the outputs of everything running (as predicted by the
+    /// plan, see `TranscodePlan::predicted_bytes`) would fit in this many bytes.  A job predicted
+    /// to be bigger than the whole budget still gets to run, on its own.
+    pub disk_budget: Option<u64>,
+    /// If set, old outputs under `output_root` are cleaned up every `gc_interval`.
+    pub gc: Option<GcPolicy>,
+    pub gc_interval: Duration,
+    /// How long running jobs get to finish when the daemon's asked to stop.
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        DaemonOptions {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8081)),
+            workers: 1,
+            output_root: PathBuf::from("."),
+            tenants: HashMap::new(),
+            api_token: None,
+            transcode: TranscodeOptions::default(),
+            notifiers: Vec::new(),
+            uploader: None,
+            upload_throttle: Throttle::default(),
+            quotas: Quotas::default(),
+            device_limits: HashMap::new(),
+            disk_budget: None,
+            gc: None,
+            gc_interval: Duration::from_secs(60 * 60),
+            // under systemd's default TimeoutStopSec of 90
+            shutdown_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Where one submitter's outputs go, apart from everyone else's.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    /// Their jobs' `outputdir`s are taken relative to this instead of the daemon's.
+    pub output_root: PathBuf,
+    /// Where `output_root` is served from, for jobs that don't give a `url_prefix` and aren't
+    /// uploaded: the job's is this plus its `outputdir`.
+    pub url_prefix: Option<String>,
+    /// Where to upload their outputs instead of the daemon's `uploader`.  Without one, they go
+    /// to the daemon's, in a directory named after the tenant.
+    pub uploader: Option<Arc<dyn Uploader>>,
+}
+
+struct Running {
+    runner: Arc<ProgressRunner>,
+    // what runner.processed() will be when it's done
+    total: f64,
+    started: Instant,
+}
+
+struct Daemon {
+    store: Arc<dyn JobStore>,
+    options: DaemonOptions,
+    running: Mutex<HashMap<JobId, Running>>,
+    metrics: Metrics,
+    stopping: AtomicBool,
+    device_limits: DeviceLimits,
+    // held while working out who's over quota and claiming a job, so two workers can't both
+    // take a submitter's last slot
+    claiming: Mutex<()>,
+    // bytes predicted for the jobs currently holding a share of the disk budget
+    reserved: Mutex<u64>,
+    budget_freed: Condvar,
+}
+
+// a job's share of the disk budget, given back when it's dropped
+struct Reservation<'a> {
+    daemon: &'a Daemon,
+    bytes: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.daemon.reserved.lock().unwrap() -= self.bytes;
+        self.daemon.budget_freed.notify_all();
+    }
+}
+
+/// A job, as the API shows it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobView {
+    #[serde(flatten)]
+    pub job: Job,
+    /// 0 to 1, if it's running.
+    pub progress: Option<f64>,
+    /// Seconds of media encoded per second, while it's been running and not paused.
+    #[serde(default)]
+    pub speed: Option<f64>,
+    /// Seconds until it's done, going at `speed`.
+    #[serde(default)]
+    pub eta: Option<f64>,
+    #[serde(default)]
+    pub paused: bool,
+}
+
+// where a job's outputs get uploaded to, if there's an uploader.  `outputdir` has already been
+// through `resolve_outputdir`, so it's nothing but plain names
+fn remote_dir(outputdir: &Path) -> String {
+    outputdir.iter().map(|x| x.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+fn resolve_outputdir(root: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+    if dir.components().next().is_none() || !dir.components().all(|x| matches!(x, Component::Normal(_))) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} isn't a plain relative path", dir.display())));
+    }
+    Ok(root.join(dir))
+}
+
+// how much is in `dir`, not counting subdirectories.  a job's output never has any
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+    entries.filter_map(|x| x.ok()?.metadata().ok())
+        .filter(|x| x.is_file())
+        .map(|x| x.len())
+        .sum()
+}
+
+impl Daemon {
+    fn tenant(&self, spec: &JobSpec) -> Option<&Tenant> {
+        self.options.tenants.get(spec.submitter())
+    }
+
+    fn output_root(&self, spec: &JobSpec) -> &Path {
+        self.tenant(spec).map_or(&self.options.output_root, |x| &x.output_root)
+    }
+
+    // the uploader for `spec`'s outputs, if they're uploaded, and the directory they go in there
+    fn upload_target(&self, spec: &JobSpec) -> Option<(Arc<dyn Uploader>, String)> {
+        match self.tenant(spec) {
+            Some(Tenant {uploader: Some(uploader), ..}) => Some((uploader.clone(), remote_dir(&spec.outputdir))),
+            Some(_) => Some((self.options.uploader.clone()?, format!("{}/{}", spec.submitter(), remote_dir(&spec.outputdir)))),
+            None => Some((self.options.uploader.clone()?, remote_dir(&spec.outputdir))),
+        }
+    }
+
+    // waits until there's room in the disk budget for `bytes` more, or the job's cancelled
+    fn reserve_disk(&self, bytes: u64, runner: &ProgressRunner) -> std::io::Result<Reservation<'_>> {
+        let budget = self.options.disk_budget.unwrap_or(u64::MAX);
+        let mut reserved = self.reserved.lock().unwrap();
+        while *reserved > 0 && reserved.saturating_add(bytes) > budget {
+            if runner.is_cancelled() || self.stopping.load(Ordering::Relaxed) {
+                return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled"));
+            }
+            reserved = self.budget_freed.wait_timeout(reserved, Duration::from_secs(1)).unwrap().0;
+        }
+        *reserved += bytes;
+        Ok(Reservation {daemon: self, bytes})
+    }
+
+    fn view(&self, job: Job) -> JobView {
+        let running = self.running.lock().unwrap();
+        let Some(running) = running.get(&job.id) else {
+            return JobView {job, progress: None, speed: None, eta: None, paused: false};
+        };
+        let processed = running.runner.processed();
+        let progress = Some(running.total).filter(|x| *x > 0.0).map(|x| (processed / x).min(1.0));
+        let active = running.started.elapsed().saturating_sub(running.runner.paused_for()).as_secs_f64();
+        let speed = Some(processed / active).filter(|x| active > 0.0 && *x > 0.0);
+        let eta = speed.map(|x| (running.total - processed).max(0.0) / x);
+        JobView {job, progress, speed, eta, paused: running.runner.is_paused()}
+    }
+
+    fn run_job(&self, job: &Job, runner: Arc<ProgressRunner>) -> std::io::Result<CytubeVideo> {
+        let outputdir = resolve_outputdir(self.output_root(&job.spec), &job.spec.outputdir)?;
+        let input = Path::new(&job.spec.input);
+        let probe = ffprobe(input)?;
+        let mut options = self.options.transcode.clone();
+        job.spec.options.apply(&mut options);
+        if let Some((uploader, remote_dir)) = self.upload_target(&job.spec) {
+            options.upload = Some(Upload {uploader, remote_dir, throttle: self.options.upload_throttle.clone()});
+        }
+        let plan = plan(input, &probe, &outputdir, &job.spec.url_prefix, &options);
+
+        let stages = if plan.segments.is_some() { 2.0 } else { 1.0 };
+        self.running.lock().unwrap().insert(job.id, Running {runner: runner.clone(), total: probe.duration * stages, started: Instant::now()});
+        // it might've been cancelled between being claimed and showing up in `running`
+        if self.store.get(job.id)?.is_some_and(|x| x.status == JobStatus::Cancelled) {
+            runner.cancel();
+        }
+
+        let _reservation = self.reserve_disk(plan.predicted_bytes, &runner)?;
+        plan.execute(&LimitedRunner {inner: &*runner, limits: &self.device_limits})
+    }
+
+    // the next job whose submitter isn't over quota, marked as running
+    fn claim_next(&self) -> std::io::Result<Option<Job>> {
+        let _claiming = self.claiming.lock().unwrap();
+        let quotas = &self.options.quotas;
+        let jobs = match quotas.has_daily_limits() {
+            true => self.store.list(None)?,
+            false => self.store.list(Some(JobStatus::Running))?,
+        };
+        self.store.claim_next(&quotas.exhausted(&jobs, now()))
+    }
+
+    fn work(&self) {
+        while !self.stopping.load(Ordering::Relaxed) {
+            let job = match self.claim_next() {
+                Ok(Some(job)) => job,
+                Ok(None) => {
+                    std::thread::sleep(Duration::from_secs(1));
+                    continue;
+                },
+                Err(e) => {
+                    eprintln!("error fetching the next job: {}", e);
+                    std::thread::sleep(Duration::from_secs(5));
+                    continue;
+                },
+            };
+            if self.stopping.load(Ordering::Relaxed) {
+                // claimed just as we were told to stop; it gets requeued next time
+                break;
+            }
+            self.metrics.job_started(now().saturating_sub(job.created_at) as f64);
+            let runner = Arc::new(ProgressRunner::default());
+            let result = self.run_job(&job, runner.clone()).map_err(|e| e.to_string());
+            self.running.lock().unwrap().remove(&job.id);
+            if result.is_err() && self.stopping.load(Ordering::Relaxed) {
+                // killed by the shutdown, most likely.  leave it marked as running so it's
+                // requeued next time
+                eprintln!("job {} interrupted by shutdown, it'll be restarted next time", job.id);
+                continue;
+            }
+            if let Err(e) = self.store.finish(job.id, result.as_ref().map_err(|x| x.as_str())) {
+                eprintln!("error recording the outcome of job {}: {}", job.id, e);
+            }
+            let output_bytes = resolve_outputdir(self.output_root(&job.spec), &job.spec.outputdir).map(|x| dir_size(&x)).unwrap_or(0);
+            if let Ok(Some(job)) = self.store.get(job.id) {
+                self.metrics.job_finished(job.status, runner.processed(), output_bytes);
+                if matches!(job.status, JobStatus::Done | JobStatus::Failed) {
+                    notify_all(&self.options.notifiers, &JobEvent::from_job(&job));
+                }
+            }
+        }
+    }
+}
+
+struct ApiError(StatusCode, String);
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({"error": self.1}))).into_response()
+    }
+}
+
+fn not_found(id: JobId) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("no job {}", id))
+}
+
+async fn check_token(State(daemon): State<Arc<Daemon>>, request: Request, next: Next) -> Response {
+    if let Some(token) = &daemon.options.api_token {
+        let given = request.headers().get(AUTHORIZATION).and_then(|x| x.to_str().ok()).and_then(|x| x.strip_prefix("Bearer "));
+        if given != Some(token.as_str()) {
+            return ApiError(StatusCode::UNAUTHORIZED, "bad or missing API token".into()).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+async fn metrics(State(daemon): State<Arc<Daemon>>) -> Result<impl IntoResponse, ApiError> {
+    let mut snapshot = Snapshot {
+        queued: daemon.store.list(Some(JobStatus::Queued))?.len(),
+        ..Default::default()
+    };
+    for running in daemon.running.lock().unwrap().values() {
+        snapshot.running += 1;
+        let elapsed = running.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            snapshot.encode_speed += running.runner.processed() / elapsed;
+        }
+    }
+    Ok(([(CONTENT_TYPE, "text/plain; version=0.0.4")], daemon.metrics.render(&snapshot)))
+}
+
+async fn submit(State(daemon): State<Arc<Daemon>>, Json(mut spec): Json<JobSpec>) -> Result<impl IntoResponse, ApiError> {
+    resolve_outputdir(daemon.output_root(&spec), &spec.outputdir).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    if spec.url_prefix.is_empty() {
+        let served_from = daemon.tenant(&spec).and_then(|x| x.url_prefix.as_ref());
+        spec.url_prefix = match (daemon.upload_target(&spec), served_from) {
+            (Some((uploader, remote_dir)), _) => uploader.url_prefix(&remote_dir),
+            (None, Some(prefix)) => format!("{}/{}/", prefix.trim_end_matches('/'), remote_dir(&spec.outputdir)),
+            (None, None) => return Err(ApiError(StatusCode::BAD_REQUEST, "url_prefix is required".to_string())),
+        };
+    }
+    let id = daemon.store.submit(&spec)?;
+    Ok((StatusCode::CREATED, Json(serde_json::json!({"id": id}))))
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    status: Option<JobStatus>,
+}
+
+async fn list(State(daemon): State<Arc<Daemon>>, Query(query): Query<ListQuery>) -> Result<Json<Vec<JobView>>, ApiError> {
+    let jobs = daemon.store.list(query.status)?;
+    Ok(Json(jobs.into_iter().map(|job| daemon.view(job)).collect()))
+}
+
+async fn get_job(State(daemon): State<Arc<Daemon>>, UrlPath(id): UrlPath<JobId>) -> Result<Json<JobView>, ApiError> {
+    let job = daemon.store.get(id)?.ok_or_else(|| not_found(id))?;
+    Ok(Json(daemon.view(job)))
+}
+
+async fn get_manifest(State(daemon): State<Arc<Daemon>>, UrlPath(id): UrlPath<JobId>) -> Result<Json<CytubeVideo>, ApiError> {
+    let job = daemon.store.get(id)?.ok_or_else(|| not_found(id))?;
+    let manifest = job.manifest.ok_or_else(|| ApiError(StatusCode::CONFLICT, format!("job {} is {}", id, job.status)))?;
+    Ok(Json(manifest))
+}
+
+async fn cancel(State(daemon): State<Arc<Daemon>>, UrlPath(id): UrlPath<JobId>) -> Result<StatusCode, ApiError> {
+    if !daemon.store.cancel(id)? {
+        let job = daemon.store.get(id)?.ok_or_else(|| not_found(id))?;
+        return Err(ApiError(StatusCode::CONFLICT, format!("job {} is already {}", id, job.status)));
+    }
+    if let Some(running) = daemon.running.lock().unwrap().get(&id) {
+        running.runner.cancel();
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// pauses or resumes a running job
+fn set_paused(daemon: &Daemon, id: JobId, paused: bool) -> Result<StatusCode, ApiError> {
+    match daemon.running.lock().unwrap().get(&id) {
+        Some(running) if paused => running.runner.pause(),
+        Some(running) => running.runner.resume(),
+        None => return Err(ApiError(StatusCode::CONFLICT, format!("job {} isn't running", id))),
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause(State(daemon): State<Arc<Daemon>>, UrlPath(id): UrlPath<JobId>) -> Result<StatusCode, ApiError> {
+    set_paused(&daemon, id, true)
+}
+
+async fn resume(State(daemon): State<Arc<Daemon>>, UrlPath(id): UrlPath<JobId>) -> Result<StatusCode, ApiError> {
+    set_paused(&daemon, id, false)
+}
+
+#[derive(Deserialize)]
+struct PriorityBody {
+    priority: i32,
+}
+
+async fn set_priority(State(daemon): State<Arc<Daemon>>, UrlPath(id): UrlPath<JobId>, Json(body): Json<PriorityBody>) -> Result<StatusCode, ApiError> {
+    if !daemon.store.set_priority(id, body.priority)? {
+        let job = daemon.store.get(id)?.ok_or_else(|| not_found(id))?;
+        return Err(ApiError(StatusCode::CONFLICT, format!("job {} is already {}", id, job.status)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Runs the daemon: puts jobs left running by a previous run back in the queue, starts the
+/// workers, and serves the API until something goes wrong or it gets SIGTERM.  Blocks the calling
+/// thread.
+pub fn run_daemon(store: Arc<dyn JobStore>, options: DaemonOptions) -> std::io::Result<()> {
+    let requeued = store.requeue_running()?;
+    if requeued > 0 {
+        eprintln!("requeued {} jobs that were running when the daemon last stopped", requeued);
+    }
+
+    let listener = match systemd::listener() {
+        Some(listener) => listener,
+        None => std::net::TcpListener::bind(options.addr)?,
+    };
+    listener.set_nonblocking(true)?;
+
+    let device_limits = DeviceLimits::new(options.device_limits.clone());
+    let daemon = Arc::new(Daemon {store, options, running: Mutex::new(HashMap::new()), metrics: Metrics::default(), stopping: AtomicBool::new(false), device_limits, claiming: Mutex::new(()), reserved: Mutex::new(0), budget_freed: Condvar::new()});
+    if let Some(policy) = daemon.options.gc.clone() {
+        let daemon = daemon.clone();
+        std::thread::spawn(move || while !daemon.stopping.load(Ordering::Relaxed) {
+            let roots = [&daemon.options.output_root].into_iter().chain(daemon.options.tenants.values().map(|x| &x.output_root));
+            for root in roots {
+                match collect_garbage(root, &policy) {
+                    Ok(report) if !report.removed.is_empty() => eprintln!("gc: removed {} outputs, {} bytes from {}", report.removed.len(), report.freed, root.display()),
+                    Ok(_) => {},
+                    Err(e) => eprintln!("gc failed in {}: {}", root.display(), e),
+                }
+            }
+            std::thread::sleep(daemon.options.gc_interval);
+        });
+    }
+    let workers: Vec<_> = (0..daemon.options.workers.max(1)).map(|_| {
+        let daemon = daemon.clone();
+        std::thread::spawn(move || daemon.work())
+    }).collect();
+
+    let app = Router::new()
+        .route("/jobs", get(list).post(submit))
+        .route("/jobs/{id}", get(get_job).delete(cancel))
+        .route("/jobs/{id}/manifest", get(get_manifest))
+        .route("/jobs/{id}/pause", post(pause))
+        .route("/jobs/{id}/resume", post(resume))
+        .route("/jobs/{id}/priority", put(set_priority))
+        .layer(middleware::from_fn_with_state(daemon.clone(), check_token))
+        .route("/metrics", get(metrics))
+        .with_state(daemon.clone());
+
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    let handle = axum_server::Handle::new();
+    runtime.block_on(async {
+        let server = axum_server::from_tcp(listener).handle(handle.clone()).serve(app.into_make_service());
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        systemd::notify("READY=1");
+        tokio::select! {
+            result = server => return result,
+            _ = sigterm.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+        systemd::notify("STOPPING=1");
+        eprintln!("shutting down");
+        handle.graceful_shutdown(Some(Duration::from_secs(5)));
+        Ok(())
+    })?;
+
+    daemon.stopping.store(true, Ordering::Relaxed);
+    let deadline = Instant::now() + daemon.options.shutdown_timeout;
+    while Instant::now() < deadline && !workers.iter().all(|x| x.is_finished()) {
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    for running in daemon.running.lock().unwrap().values() {
+        running.runner.cancel();
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
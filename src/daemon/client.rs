@@ -0,0 +1,52 @@
+// Talking to a running daemon over its API, for tools that watch and steer it (see
+// examples/dashboard.rs) rather than going through curl.
+
+use super::JobView;
+use crate::jobs::JobId;
+use crate::notify::http_error;
+
+/// A daemon's API.
+#[derive(Debug, Clone)]
+pub struct DaemonClient {
+    /// e.g. `http://127.0.0.1:8081`
+    pub url: String,
+    /// The daemon's `api_token`, if it has one.
+    pub api_token: Option<String>,
+}
+
+impl DaemonClient {
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = ureq::request(method, &format!("{}{}", self.url.trim_end_matches('/'), path));
+        match &self.api_token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    /// Every job, oldest first.
+    pub fn jobs(&self) -> std::io::Result<Vec<JobView>> {
+        self.request("GET", "/jobs").call().map_err(http_error)?.into_json()
+    }
+
+    pub fn cancel(&self, id: JobId) -> std::io::Result<()> {
+        self.request("DELETE", &format!("/jobs/{}", id)).call().map_err(http_error)?;
+        Ok(())
+    }
+
+    pub fn pause(&self, id: JobId) -> std::io::Result<()> {
+        self.request("POST", &format!("/jobs/{}/pause", id)).call().map_err(http_error)?;
+        Ok(())
+    }
+
+    pub fn resume(&self, id: JobId) -> std::io::Result<()> {
+        self.request("POST", &format!("/jobs/{}/resume", id)).call().map_err(http_error)?;
+        Ok(())
+    }
+
+    pub fn set_priority(&self, id: JobId, priority: i32) -> std::io::Result<()> {
+        self.request("PUT", &format!("/jobs/{}/priority", id))
+            .send_json(serde_json::json!({"priority": priority}))
+            .map_err(http_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+// The bits of systemd's service protocol the daemon speaks, done by hand rather than linking
+// libsystemd.  Both are no-ops when not running under systemd.  A unit that uses all of it:
+//
+//   # cytube-daemon.socket
+//   [Socket]
+//   ListenStream=127.0.0.1:8081
+//
+//   # cytube-daemon.service
+//   [Service]
+//   Type=notify
+//   ExecStart=/usr/local/bin/cytube-daemon /srv/cytube /var/lib/cytube/jobs.db
+//   TimeoutStopSec=90
+//
+// and then `systemctl enable --now cytube-daemon.socket`.
+
+use std::net::TcpListener;
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+
+// the first fd systemd passes us; see sd_listen_fds(3)
+const LISTEN_FDS_START: i32 = 3;
+
+/// Tells systemd about a state change ("READY=1", "STOPPING=1", ...), if it's listening.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else { return };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+            // abstract namespace
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)
+        } else {
+            socket.send_to(state.as_bytes(), &path)
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("couldn't notify systemd: {}", e);
+    }
+}
+
+/// The socket systemd opened for us, if we were socket-activated.  Only the first one is used.
+pub fn listener() -> Option<TcpListener> {
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    // these are meant for us and not for ffmpeg
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    // SAFETY: systemd promises fd 3 is an open socket handed over to us, and nothing else in the
+    // process knows about it
+    Some(unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) })
+}
@@ -0,0 +1,241 @@
+// Farming the segments of a `SegmentedEncode` out to other machines.
+//
+// The protocol is just ssh.  Workers need ffmpeg on their PATH and nothing else: no shared
+// filesystem, no copy of the source file.  For each segment we stream-copy the relevant span of
+// the source out of the local file, pipe it over the ssh connection into a remote ffmpeg, and write
+// whatever comes back out of the connection straight into the segment file.
+//
+// Stream-copying can only cut on keyframes, so the local side sends a bit more than it needs with
+// the original timestamps intact (-copyts), and the remote side trims to the exact boundaries
+// before encoding.  That way the segments still butt up against each other like the local ones.
+
+use crate::invocation::shell_quote;
+use crate::plan::{Runner, SegmentRunner};
+use crate::segmented::SegmentedEncode;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// how much extra source to send past the end of a segment, in seconds, so the remote decoder has
+// every frame up to the cut point even when the reference frames run past it
+const TAIL_PADDING: f64 = 10.0;
+
+/// A machine segments can be sent to.
+#[derive(Debug, Clone)]
+pub enum Worker {
+    /// This machine.
+    Local { slots: usize },
+    /// Another machine, reached with `ssh <destination>`.  Authentication has to work
+    /// non-interactively (keys, an agent, ...).
+    Ssh { destination: String, slots: usize },
+}
+
+impl Worker {
+    fn slots(&self) -> usize {
+        match self {
+            Worker::Local { slots } | Worker::Ssh { slots, .. } => *slots,
+        }
+    }
+    fn name(&self) -> &str {
+        match self {
+            Worker::Local { .. } => "localhost",
+            Worker::Ssh { destination, .. } => destination,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentStatus {
+    Pending,
+    Running { worker: String },
+    Done { worker: String },
+    /// Ran out of attempts.  `error` is from the last one.
+    Failed { attempts: u32, error: String },
+}
+
+/// Hands the segments of an encode out to a set of workers, retrying failed segments until they
+/// succeed or run out of attempts.  A worker that fails a segment gets no more work, since the
+/// likeliest reason is that it's unreachable or has no ffmpeg, unless it's the last one left.
+/// Give it to `TranscodePlan::execute_with`.
+pub struct Scheduler {
+    workers: Vec<Worker>,
+    pub max_attempts: u32,
+    status: Arc<Mutex<Vec<SegmentStatus>>>,
+}
+
+impl Scheduler {
+    pub fn new(workers: Vec<Worker>) -> Self {
+        Scheduler {workers, max_attempts: 3, status: Arc::default()}
+    }
+
+    /// How many segments the workers can encode at once between them, which is a good number
+    /// to split the video into.
+    pub fn slots(&self) -> usize {
+        self.workers.iter().map(Worker::slots).sum()
+    }
+
+    /// A live view of where every segment is at, for reporting progress from another thread
+    /// while the segments are running.
+    pub fn status(&self) -> Arc<Mutex<Vec<SegmentStatus>>> {
+        self.status.clone()
+    }
+}
+
+impl SegmentRunner for Scheduler {
+    fn run_segments(&self, encode: &SegmentedEncode, runner: &dyn Runner) -> std::io::Result<()> {
+        *self.status.lock().unwrap() = vec![SegmentStatus::Pending; encode.segments.len()];
+        // (segment index, attempts so far)
+        let queue = Mutex::new((0..encode.segments.len()).map(|i| (i, 0u32)).collect::<VecDeque<_>>());
+        let in_flight = Mutex::new(0usize);
+        // by index into `workers`
+        let retired = Mutex::new(vec![false; self.workers.len()]);
+
+        std::thread::scope(|scope| {
+            for (w, worker) in self.workers.iter().enumerate() {
+                for _ in 0..worker.slots() {
+                    let (queue, in_flight, retired, status) = (&queue, &in_flight, &retired, &self.status);
+                    scope.spawn(move || loop {
+                        let next = {
+                            let mut queue = queue.lock().unwrap();
+                            if retired.lock().unwrap()[w] {
+                                break;
+                            }
+                            let next = queue.pop_front();
+                            if next.is_some() {
+                                *in_flight.lock().unwrap() += 1;
+                            }
+                            next
+                        };
+                        let (i, attempts) = match next {
+                            Some(x) => x,
+                            // nothing left to hand out, but something that's still running might
+                            // fail and need another go
+                            None if *in_flight.lock().unwrap() > 0 => {
+                                std::thread::sleep(Duration::from_millis(500));
+                                continue;
+                            },
+                            None => break,
+                        };
+
+                        status.lock().unwrap()[i] = SegmentStatus::Running { worker: worker.name().to_owned() };
+                        let result = match worker {
+                            Worker::Local { .. } => runner.run(&encode.command(i)),
+                            Worker::Ssh { destination, .. } => run_ssh(encode, i, destination),
+                        };
+                        {
+                            // requeue before dropping in_flight so idle threads don't quit early
+                            let mut queue = queue.lock().unwrap();
+                            if result.is_err() {
+                                let mut retired = retired.lock().unwrap();
+                                if retired.iter().enumerate().any(|(x, retired)| x != w && !retired) {
+                                    retired[w] = true;
+                                }
+                            }
+                            status.lock().unwrap()[i] = match result {
+                                Ok(()) => SegmentStatus::Done { worker: worker.name().to_owned() },
+                                Err(_) if attempts + 1 < self.max_attempts => {
+                                    queue.push_back((i, attempts + 1));
+                                    SegmentStatus::Pending
+                                },
+                                Err(e) => SegmentStatus::Failed { attempts: attempts + 1, error: e.to_string() },
+                            };
+                            *in_flight.lock().unwrap() -= 1;
+                        }
+                    });
+                }
+            }
+        });
+
+        if self.status.lock().unwrap().iter().any(|x| !matches!(x, SegmentStatus::Done { .. })) {
+            return Err(std::io::Error::other("one or more segments failed to encode"));
+        }
+        Ok(())
+    }
+}
+
+fn run_ssh(encode: &SegmentedEncode, i: usize, destination: &str) -> std::io::Result<()> {
+    let segment = &encode.segments[i];
+
+    let mut reader = Command::new("ffmpeg");
+    reader.args(["-hide_banner", "-loglevel", "error"]);
+    reader.args(["-ss", segment.start.to_string().as_str()]);
+    if let Some(length) = segment.length {
+        reader.args(["-t", (length + TAIL_PADDING).to_string().as_str()]);
+    }
+    reader.arg("-i").arg(encode.input.as_os_str());
+    reader.args(["-map", format!("0:{}", encode.video_index).as_str(), "-c", "copy", "-copyts", "-f", "matroska", "-"]);
+    let mut reader = reader.stdout(Stdio::piped()).spawn()?;
+
+    // -copyts keeps the source's own timestamps, which start at its start time rather than 0
+    let start = segment.start + encode.start_time;
+    let mut trim = format!("trim=start={}", start);
+    if let Some(length) = segment.length {
+        trim.push_str(&format!(":end={}", start + length));
+    }
+    trim.push_str(",setpts=PTS-STARTPTS");
+    if let Some(upload) = &encode.upload {
+        trim.push_str(&format!(",{}", upload));
+    }
+    // ssh glues its arguments together and hands them to the remote shell, so this has to be
+    // quoted by hand
+    let mut remote = String::from("ffmpeg -hide_banner -loglevel error");
+    for arg in &encode.device_args {
+        remote.push(' ');
+        remote.push_str(&shell_quote(arg));
+    }
+    remote.push_str(" -copyts -i - -map 0:0 -vf ");
+    remote.push_str(&shell_quote(&trim));
+    for arg in &encode.encoder_args {
+        remote.push(' ');
+        remote.push_str(&shell_quote(arg));
+    }
+    remote.push_str(" -f matroska -");
+
+    let ssh = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "--", destination, &remote])
+        .stdin(reader.stdout.take().unwrap())
+        .stdout(File::create(&segment.output)?)
+        .status();
+    let reader = reader.wait()?;
+    if !ssh?.success() || !reader.success() {
+        return Err(std::io::Error::other(format!("encoding segment {} on {} failed", i, destination)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::tests::{segmented_plan, FakeRunner};
+
+    #[test]
+    fn scheduling_locally() {
+        let scheduler = Scheduler::new(vec![Worker::Local {slots: 2}]);
+        let runner = FakeRunner::default();
+        segmented_plan("scheduling_locally").execute_with(&runner, &scheduler).unwrap();
+        assert_eq!(runner.runs.lock().unwrap().len(), 4);
+        assert!(scheduler.status().lock().unwrap().iter().all(|x| *x == SegmentStatus::Done {worker: "localhost".to_string()}));
+    }
+
+    #[test]
+    fn dead_workers_are_passed_over() {
+        // not a host ssh will even try to connect to
+        let dead = Worker::Ssh {destination: "no such host".to_string(), slots: 1};
+        let mut scheduler = Scheduler::new(vec![dead, Worker::Local {slots: 1}]);
+        // one go on the dead one, and then it has to be the live one
+        scheduler.max_attempts = 2;
+        let runner = FakeRunner::default();
+        segmented_plan("dead_workers_are_passed_over").execute_with(&runner, &scheduler).unwrap();
+        assert!(scheduler.status().lock().unwrap().iter().all(|x| matches!(x, SegmentStatus::Done {worker} if worker == "localhost")));
+    }
+
+    #[test]
+    fn the_last_worker_keeps_trying() {
+        let scheduler = Scheduler::new(vec![Worker::Ssh {destination: "no such host".to_string(), slots: 2}]);
+        let plan = segmented_plan("the_last_worker_keeps_trying");
+        assert!(plan.execute_with(&FakeRunner::default(), &scheduler).is_err());
+        assert!(scheduler.status().lock().unwrap().iter().all(|x| matches!(x, SegmentStatus::Failed {attempts: 3, ..})));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::compat::{find_audio_container, AudioContainer, BrowserProfile, VideoContainer};
+use crate::ffprobe::Track;
+use serde::{Deserialize, Serialize};
+
+/// What video gets encoded to when the source can't just be copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all="kebab-case")]
+pub enum VideoEncoder {
+    /// SVT-AV1 into WebM.  Smallest files, but old browsers and a lot of smart TVs can't play it.
+    #[default]
+    SvtAv1,
+    /// x264 into MP4.  Plays everywhere.
+    X264,
+    /// x265 into MP4.
+    X265,
+    /// libvpx's VP9 into WebM.  Slow, but plays anywhere AV1 doesn't except Safari.
+    Vp9,
+    /// H.264 on an NVIDIA card's NVENC, into MP4.  Very fast, but bigger than x264 for the same
+    /// quality.
+    Nvenc,
+    /// H.264 on the GPU through VA-API (Intel and AMD), into MP4.  Frames are uploaded to the
+    /// `HwAccel::Vaapi` device if there is one, or else `/dev/dri/renderD128`.
+    Vaapi,
+}
+
+impl VideoEncoder {
+    /// Every encoder, software ones first.
+    pub const ALL: [VideoEncoder; 6] = [
+        VideoEncoder::SvtAv1, VideoEncoder::X264, VideoEncoder::X265,
+        VideoEncoder::Vp9, VideoEncoder::Nvenc, VideoEncoder::Vaapi,
+    ];
+
+    /// The codec it makes, as ffprobe names it.
+    pub fn codec(&self) -> &'static str {
+        match self {
+            VideoEncoder::SvtAv1 => "av1",
+            VideoEncoder::X264 | VideoEncoder::Nvenc | VideoEncoder::Vaapi => "h264",
+            VideoEncoder::X265 => "hevc",
+            VideoEncoder::Vp9 => "vp9",
+        }
+    }
+
+    /// ffmpeg's name for it, for `-c:v`.
+    pub fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            VideoEncoder::SvtAv1 => "libsvtav1",
+            VideoEncoder::X264 => "libx264",
+            VideoEncoder::X265 => "libx265",
+            VideoEncoder::Vp9 => "libvpx-vp9",
+            VideoEncoder::Nvenc => "h264_nvenc",
+            VideoEncoder::Vaapi => "h264_vaapi",
+        }
+    }
+}
+
+/// Settings for SVT-AV1.
+/// Anything left as `None` is left to ffmpeg's (and SVT-AV1's) defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SvtAv1Options {
+    /// 0 (slowest, best) to 13 (fastest).
+    pub preset: Option<u8>,
+    /// 0 to 63, lower is better.
+    pub crf: Option<u8>,
+    /// Film grain synthesis strength, 0 to 50.  Denoises the source and re-synthesizes the grain on
+    /// playback, which saves a ton of bits on grainy film.  Leave this off for animation.
+    pub film_grain: Option<u8>,
+    /// log2 of the number of tile columns.  More tiles means more threads can work on a frame.
+    pub tile_columns: Option<u8>,
+}
+
+impl SvtAv1Options {
+    pub(crate) fn encoder_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), "libsvtav1".to_string()];
+        if let Some(preset) = self.preset {
+            args.extend(["-preset".to_string(), preset.to_string()]);
+        }
+        if let Some(crf) = self.crf {
+            args.extend(["-crf".to_string(), crf.to_string()]);
+        }
+        // the rest only exist as svt-av1 parameters
+        let mut params = Vec::new();
+        if let Some(film_grain) = self.film_grain {
+            params.push(format!("film-grain={}", film_grain));
+        }
+        if let Some(tile_columns) = self.tile_columns {
+            params.push(format!("tile-columns={}", tile_columns));
+        }
+        if !params.is_empty() {
+            args.extend(["-svtav1-params".to_string(), params.join(":")]);
+        }
+        args
+    }
+}
+
+/// A profile, level and pixel format to hold H.264 or HEVC to, e.g. High@4.1 yuv420p, for the
+/// sake of smart TV browsers that choke on anything fancier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct H26xConstraints {
+    /// Profile name as x264/x265 spell it: `baseline`, `main`, `high`, `high10`, `main10`, ...
+    pub profile: String,
+    /// e.g. `4.1`
+    pub level: String,
+    pub pix_fmt: Option<String>,
+}
+
+// profiles in roughly increasing order of what the decoder has to support, spelled the way x264
+// and x265 spell them.  ffprobe spells them differently ("High 4:2:2"), see normalize_profile.
+const H264_PROFILES: [&str; 9] = [
+    "constrainedbaseline", "baseline", "main", "extended", "high", "high10", "high422", "high444", "high444predictive",
+];
+const HEVC_PROFILES: [&str; 4] = ["mainstillpicture", "main", "main10", "rext"];
+
+fn normalize_profile(profile: &str) -> String {
+    profile.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase()
+}
+
+impl H26xConstraints {
+    pub(crate) fn encoder_args(constraints: Option<&H26xConstraints>, encoder: &str) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), encoder.to_string()];
+        if let Some(constraints) = constraints {
+            args.extend(["-profile:v".to_string(), constraints.profile.clone()]);
+            if encoder == "libx265" {
+                // libx265 doesn't take -level
+                args.extend(["-x265-params".to_string(), format!("level-idc={}", constraints.level)]);
+            } else {
+                args.extend(["-level:v".to_string(), constraints.level.clone()]);
+            }
+            // VA-API frames are uploaded as nv12, and it won't take anything else
+            if let Some(pix_fmt) = constraints.pix_fmt.as_ref().filter(|_| encoder != "h264_vaapi") {
+                args.extend(["-pix_fmt".to_string(), pix_fmt.clone()]);
+            }
+        }
+        args
+    }
+
+    /// Whether an H.264 or HEVC source track already fits within these constraints.  Anything
+    /// ffprobe didn't tell us about gets the benefit of the doubt.
+    pub fn accepts(&self, track: &Track) -> bool {
+        let (profiles, level_scale): (&[&str], f32) = match track.codec.as_str() {
+            "h264" => (&H264_PROFILES, 10.0),
+            "hevc" => (&HEVC_PROFILES, 30.0),
+            _ => return true,
+        };
+        let rank = |profile: &str| profiles.iter().position(|x| *x == normalize_profile(profile));
+        if let Some(profile) = &track.profile {
+            match (rank(profile), rank(&self.profile)) {
+                (Some(theirs), Some(ours)) if theirs <= ours => {},
+                _ => return false,
+            }
+        }
+        if let (Some(level), Ok(max)) = (track.level, self.level.parse::<f32>()) {
+            if level > (max * level_scale).round() as i32 {
+                return false;
+            }
+        }
+        if let (Some(pix_fmt), Some(ours)) = (&track.pix_fmt, &self.pix_fmt) {
+            if pix_fmt != ours {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// An audio codec we can encode to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum AudioCodec {
+    Opus,
+    Aac,
+    Vorbis,
+    Mp3,
+    Flac,
+}
+
+// in the order they're tried when nothing's preferred and the usual choice is ruled out
+const AUDIO_CODECS: [AudioCodec; 5] = [AudioCodec::Opus, AudioCodec::Aac, AudioCodec::Vorbis, AudioCodec::Mp3, AudioCodec::Flac];
+
+impl AudioCodec {
+    /// What ffprobe calls it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioCodec::Opus => "opus",
+            AudioCodec::Aac => "aac",
+            AudioCodec::Vorbis => "vorbis",
+            AudioCodec::Mp3 => "mp3",
+            AudioCodec::Flac => "flac",
+        }
+    }
+
+    /// The ffmpeg encoder for it.
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Aac => "aac",
+            AudioCodec::Vorbis => "libvorbis",
+            AudioCodec::Mp3 => "libmp3lame",
+            AudioCodec::Flac => "flac",
+        }
+    }
+}
+
+/// How an audio encoder spends its bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all="kebab-case")]
+pub enum AudioRateControl {
+    /// As many bits as each moment needs.  Best quality for the size, but the bitrate swings.
+    /// For MP3 and Vorbis this is quality-based, and `bitrate` is ignored.  AAC gets ABR, since
+    /// the VBR mode of ffmpeg's own AAC encoder is experimental.
+    Vbr,
+    /// VBR that stays close to `bitrate`, so a slow connection that can keep up with the average
+    /// keeps up all the way through.  Only Opus really does this; elsewhere it's ABR.
+    ConstrainedVbr,
+    /// Averages out to `bitrate` over the whole file.
+    Abr,
+    /// `bitrate`, all the time.
+    Cbr,
+}
+
+// -q:a for quality-based VBR, aiming for about 130-170kbps stereo.  libmp3lame's is LAME's -V
+// (0 is best, 4 averages around 165kbps); libvorbis's is oggenc's -q (10 is best, 5 is about
+// 160kbps)
+const MP3_VBR_QUALITY: &str = "4";
+const VORBIS_VBR_QUALITY: &str = "5";
+
+/// Preferences for audio: when it gets copied, and what it gets encoded to when it doesn't.
+///
+/// ```toml
+/// [audio]
+/// prefer = ["opus"]   # opus everywhere it can go
+/// never = ["aac"]     # no AAC, copied or encoded
+/// bitrate = 160
+/// rate_control = "constrained-vbr"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioPolicy {
+    /// Codecs to encode to, best first.  The first one that can go where the audio's going (and
+    /// that the target browsers play) wins.  Without one: AAC in MP4, Opus everywhere else.
+    pub prefer: Vec<AudioCodec>,
+    /// Codecs (as ffprobe names them) that are never copied, and never encoded to if there's
+    /// anything else.
+    pub never: Vec<String>,
+    /// Encode all audio, even when it could be copied.
+    pub always_encode: bool,
+    /// In kbps.  Left to the encoder if unset.  Doesn't apply to FLAC.
+    pub bitrate: Option<u32>,
+    /// Left to the encoder if unset: VBR for Opus and Vorbis, ABR for AAC, CBR for MP3.
+    pub rate_control: Option<AudioRateControl>,
+}
+
+impl AudioPolicy {
+    /// Whether audio in `codec` can be copied, as far as the policy's concerned.
+    pub fn may_copy(&self, codec: &str) -> bool {
+        !self.always_encode && !self.never.iter().any(|x| x == codec)
+    }
+
+    // the preferred codecs, then `default`, then anything, skipping what's ruled out and what
+    // `fits` says can't go there
+    fn choose(&self, default: AudioCodec, fits: impl Fn(AudioCodec) -> bool) -> AudioCodec {
+        let allowed = |x: &AudioCodec| fits(*x) && !self.never.iter().any(|y| y == x.name());
+        self.prefer.iter().copied().chain([default]).chain(AUDIO_CODECS)
+            .find(allowed)
+            .unwrap_or(default)
+    }
+
+    /// What to encode audio to when it goes alongside video in `container`.
+    pub fn codec_in(&self, target: BrowserProfile, container: VideoContainer) -> AudioCodec {
+        let default = match container {
+            VideoContainer::MP4 => AudioCodec::Aac,
+            VideoContainer::WEBM | VideoContainer::OGG => AudioCodec::Opus,
+        };
+        self.choose(default, |x| container.get_acceptable_audio_codecs().contains(&x.name()) && target.allows_audio_codec(x.name()))
+    }
+
+    /// What to encode standalone audio (its own file, for multi-language audio or music) to, and
+    /// what that goes in.  By default that follows the video: AAC in M4A if the video's in MP4,
+    /// Opus in Ogg otherwise.
+    pub fn standalone(&self, target: BrowserProfile, video_container: VideoContainer) -> (AudioContainer, AudioCodec) {
+        let default = match video_container {
+            VideoContainer::MP4 => AudioCodec::Aac,
+            VideoContainer::WEBM | VideoContainer::OGG => AudioCodec::Opus,
+        };
+        let codec = self.choose(default, |x| find_audio_container(x.name()).is_some() && target.allows_audio_codec(x.name()));
+        (find_audio_container(codec.name()).unwrap(), codec)
+    }
+
+    /// `-c:a` and friends for encoding to `codec`.
+    pub(crate) fn encoder_args(&self, codec: AudioCodec) -> Vec<String> {
+        use AudioRateControl::*;
+        let mut args = vec!["-c:a".to_string(), codec.encoder().to_string()];
+        let quality = match (codec, self.rate_control) {
+            (AudioCodec::Mp3, Some(Vbr)) => Some(MP3_VBR_QUALITY),
+            (AudioCodec::Vorbis, Some(Vbr)) => Some(VORBIS_VBR_QUALITY),
+            _ => None,
+        };
+        if let Some(quality) = quality {
+            args.extend(["-q:a".to_string(), quality.to_string()]);
+        } else if let Some(bitrate) = self.bitrate.filter(|_| codec != AudioCodec::Flac) {
+            args.extend(["-b:a".to_string(), format!("{}k", bitrate)]);
+            if codec == AudioCodec::Vorbis && self.rate_control == Some(Cbr) {
+                // libvorbis only holds still if the min and max are pinned to the average
+                args.extend(["-minrate".to_string(), format!("{}k", bitrate), "-maxrate".to_string(), format!("{}k", bitrate)]);
+            }
+        }
+        match (codec, self.rate_control) {
+            (AudioCodec::Opus, Some(Vbr)) => args.extend(["-vbr".to_string(), "on".to_string()]),
+            (AudioCodec::Opus, Some(ConstrainedVbr | Abr)) => args.extend(["-vbr".to_string(), "constrained".to_string()]),
+            (AudioCodec::Opus, Some(Cbr)) => args.extend(["-vbr".to_string(), "off".to_string()]),
+            (AudioCodec::Mp3, Some(ConstrainedVbr | Abr)) => args.extend(["-abr".to_string(), "1".to_string()]),
+            _ => {},
+        }
+        args
+    }
+}
+
+// rough bits-per-pixel each encoder lands on at its default CRF, for 8-bit live action.  every
+// CRF_HALVING steps of CRF away from the default roughly halves (or doubles) that.  these are
+// ballpark figures from eyeballing a pile of encodes, not gospel.
+const X264_BPP: (f32, f32, f32) = (0.10, 23.0, 6.0);
+const X265_BPP: (f32, f32, f32) = (0.06, 28.0, 6.0);
+const SVTAV1_BPP: (f32, f32, f32) = (0.045, 35.0, 8.0);
+const VP9_BPP: (f32, f32, f32) = (0.06, 31.0, 8.0);
+// the hardware encoders at their defaults.  there's no CRF to speak of
+const HARDWARE_H264_BPP: (f32, f32, f32) = (0.14, 23.0, 6.0);
+
+// what we assume when ffprobe doesn't know the frame rate
+const DEFAULT_FPS: f32 = 24000.0 / 1001.0;
+
+/// Guesses the video bitrate, in kbps, that `encoder` will produce for a video `height` pixels
+/// tall.  Used for the manifest, since the actual bitrate isn't known until the encode is done.
+pub fn estimate_video_kbps(encoder: VideoEncoder, av1: &SvtAv1Options, height: u16, frame_rate: Option<f32>) -> u64 {
+    let (bpp, default_crf, crf_halving) = match encoder {
+        VideoEncoder::SvtAv1 => SVTAV1_BPP,
+        VideoEncoder::X264 => X264_BPP,
+        VideoEncoder::X265 => X265_BPP,
+        VideoEncoder::Vp9 => VP9_BPP,
+        VideoEncoder::Nvenc | VideoEncoder::Vaapi => HARDWARE_H264_BPP,
+    };
+    let crf = match encoder {
+        VideoEncoder::SvtAv1 => av1.crf.map_or(default_crf, f32::from),
+        _ => default_crf,
+    };
+    let bpp = bpp * 2f32.powf((default_crf - crf) / crf_halving);
+    // we only know the height.  assume 16:9, which is close enough for most things
+    let width = height as f32 * 16.0 / 9.0;
+    (width * height as f32 * frame_rate.unwrap_or(DEFAULT_FPS) * bpp / 1000.0) as u64
+}
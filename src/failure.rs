@@ -0,0 +1,87 @@
+// Telling apart the ways a transcode can fail, so a script wrapping the CLI can do something
+// different when an upload fails than when the input's broken.  Errors are still io::Errors all
+// the way up; the stage rides along inside one (`Failure::wrap`), so nothing in between has to
+// know about it.
+
+use serde::Serialize;
+use std::path::Path;
+
+/// The stage a transcode failed at.  Each has its own exit code (see `exit_code`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Failure {
+    /// Anything not covered below.
+    Other,
+    /// ffprobe couldn't read the input.
+    Probe,
+    /// ffmpeg failed.
+    Encode,
+    /// The outputs were made but couldn't be uploaded.
+    Upload,
+    /// Something isn't right: the uploaded outputs aren't served the way the manifest says, or
+    /// the config file's invalid.
+    Validation,
+    /// Some of a batch worked and some didn't.
+    Partial,
+}
+
+/// The exit code for a bad command line.
+pub const USAGE_EXIT_CODE: i32 = 2;
+
+#[derive(Debug)]
+struct Tagged {
+    failure: Failure,
+    error: std::io::Error,
+}
+
+impl std::fmt::Display for Tagged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for Tagged {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl Failure {
+    /// 1 for `Other`, then 3 to 7 in the order above.  0 is success, and 2 is `USAGE_EXIT_CODE`.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::Other => 1,
+            Failure::Probe => 3,
+            Failure::Encode => 4,
+            Failure::Upload => 5,
+            Failure::Validation => 6,
+            Failure::Partial => 7,
+        }
+    }
+
+    /// `e`, marked as having happened at this stage.  Its kind and message stay the same.  If it's
+    /// already marked, it keeps the mark it has, which is the more specific one.
+    pub fn wrap(self, e: std::io::Error) -> std::io::Error {
+        match Failure::of(&e) {
+            Failure::Other => std::io::Error::new(e.kind(), Tagged {failure: self, error: e}),
+            _ => e,
+        }
+    }
+
+    /// The stage `e` was marked with, or `Other`.
+    pub fn of(e: &std::io::Error) -> Failure {
+        e.get_ref().and_then(|x| x.downcast_ref::<Tagged>()).map_or(Failure::Other, |x| x.failure)
+    }
+}
+
+/// An error as JSON, for `--error-format json`: `{"failure": "upload", "exit_code": 5, "input":
+/// ..., "message": ...}`.
+pub fn error_json(input: &Path, e: &std::io::Error) -> serde_json::Value {
+    let failure = Failure::of(e);
+    serde_json::json!({
+        "failure": failure,
+        "exit_code": failure.exit_code(),
+        "input": input.to_string_lossy(),
+        "message": e.to_string(),
+    })
+}
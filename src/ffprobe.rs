@@ -1,74 +1,257 @@
+use std::collections::HashMap;
 use std::path::Path;
 use std::process::{Command, Stdio};
 use fixedstr::str4;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[derive(strum::EnumString)]
 #[strum(serialize_all="snake_case")]
 pub enum TrackType {
+    #[default]
     Video,
     Audio,
     Subtitle,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Track {
     pub index: u16,
     pub kind: TrackType,
     pub codec: String,
     pub scanline_count: Option<u16>,
+    pub profile: Option<String>,
+    /// As ffprobe reports it: 41 for H.264 level 4.1, but 123 for HEVC level 4.1 (it's 30x there)
+    pub level: Option<i32>,
+    pub pix_fmt: Option<String>,
+    /// Video only, as ffprobe names them: `"bt709"`, or `"smpte2084"` (PQ) and `"arib-std-b67"`
+    /// (HLG) transfers for HDR.  Often not there at all for SDR video.
+    #[serde(default)]
+    pub color_transfer: Option<String>,
+    #[serde(default)]
+    pub color_primaries: Option<String>,
+    #[serde(default)]
+    pub color_space: Option<String>,
+    pub frame_rate: Option<f32>,
     pub language: Option<str4>,
     pub title: Option<String>,
+    /// Audio only.
+    #[serde(default)]
+    pub channels: Option<u16>,
+    /// Audio only, as ffmpeg names it: `"stereo"`, `"5.1(side)"`.
+    #[serde(default)]
+    pub channel_layout: Option<String>,
+    /// Audio only, in Hz.
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    /// In kbps, if the container says.  Matroska usually doesn't.
+    #[serde(default)]
+    pub bitrate: Option<u64>,
+    /// The dispositions that are set, like `default`, `forced` or `comment`.
+    #[serde(default)]
+    pub disposition: Vec<String>,
+    /// Video only: whether there are CEA-608/708 closed captions inside it.
+    #[serde(default)]
+    pub closed_captions: bool,
 }
 
-#[derive(Debug)]
+// codecs that are only ever a still picture, as far as we're concerned
+const IMAGE_CODECS: [&str; 4] = ["mjpeg", "png", "bmp", "webp"];
+
+impl Track {
+    /// Whether this is a picture rather than a video: cover art embedded in a music file, which
+    /// ffprobe reports as an mjpeg or png video stream.  MJPEG with a frame rate is real video.
+    pub fn is_cover_art(&self) -> bool {
+        matches!(self.kind, TrackType::Video) && (self.disposition.iter().any(|x| x == "attached_pic")
+            || IMAGE_CODECS.contains(&self.codec.as_str()) && self.frame_rate.is_none_or(|x| x == 0.0))
+    }
+
+    /// Whether this is HDR video (PQ or HLG), which looks washed out wherever it's played as if
+    /// it weren't.
+    pub fn is_hdr(&self) -> bool {
+        matches!(self.color_transfer.as_deref(), Some("smpte2084" | "arib-std-b67"))
+    }
+
+    /// Whether this is a video track that isn't cover art.
+    pub fn is_video(&self) -> bool {
+        matches!(self.kind, TrackType::Video) && !self.is_cover_art()
+    }
+}
+
+// adding a field?  bump `probe_cache::ENTRY_VERSION`, or cached results will come back without it
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FFprobeResult {
     pub tracks: Vec<Track>,
+    /// The container, as ffprobe names it, which can be a few names that ffmpeg reads the same
+    /// way: `"matroska,webm"`, `"mov,mp4,m4a,3gp,3g2,mj2"`.  See `is_format`.
+    #[serde(default)]
+    pub format_name: Option<String>,
     pub title: Option<String>,
-    pub duration: f32,
+    pub duration: f64,
+    /// When the file's timestamps start, in seconds.  Usually 0, but a transport stream starts
+    /// wherever the broadcaster's clock was.
+    #[serde(default)]
+    pub start_time: f64,
     pub bitrate: u64, // in kbps
+    /// Music tags.  These can be on the file or, in Ogg, on the audio stream.
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
+    /// Which track of the album this is.
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    #[serde(default)]
+    pub disc_number: Option<u32>,
+    /// All of the file's tags, with lower-case names.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    /// ReplayGain (or R128) tags, for this track alone and for the album it's on.
+    #[serde(default)]
+    pub track_gain: Option<ReplayGain>,
+    #[serde(default)]
+    pub album_gain: Option<ReplayGain>,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    /// MPEG-TS only, and only if there's more than one.  See `track_select::keep_program`.
+    #[serde(default)]
+    pub programs: Vec<Program>,
+}
+
+impl FFprobeResult {
+    /// Whether the container's `name` (`"webm"`, `"mp4"`, `"avi"`...), or one that ffmpeg reads
+    /// the same way.  An `.mkv` is `"webm"` too, as far as this goes.
+    pub fn is_format(&self, name: &str) -> bool {
+        has_format(self.format_name.as_deref(), name)
+    }
+}
+
+// `FFprobeResult::is_format`, for before there is one
+fn has_format(format_name: Option<&str>, name: &str) -> bool {
+    format_name.is_some_and(|x| x.split(',').any(|x| x == name))
 }
 
-fn parse_ffmpeg_line<'a>(line: &'a str) -> (&'a str, impl Iterator<Item=(&'a str, &'a str)>) {
-    let mut it = line.split("|");
+/// A program in an MPEG-TS file: one channel out of a broadcast multiplex, and the streams that
+/// make it up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    pub id: u32,
+    /// The channel's name, if the stream says.
+    pub name: Option<String>,
+    /// By stream index, like `Track::index`.
+    pub streams: Vec<u16>,
+}
+
+/// A chapter marker, in seconds from the start of the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+/// How much to turn something up or down to play at the same loudness as everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGain {
+    /// In dB, relative to ReplayGain's reference loudness (about -18 LUFS).
+    pub gain: f32,
+    /// The loudest sample, where 1.0 is full scale, if the tags say.
+    pub peak: Option<f32>,
+}
+
+const GAIN_TAGS: &str = "replaygain_track_gain,replaygain_track_peak,replaygain_album_gain,replaygain_album_peak,r128_track_gain,r128_album_gain";
+
+// `which` is "track" or "album".  `tags` has the gain tags, lower cased.
+fn replay_gain(tags: &HashMap<String, String>, which: &str) -> Option<ReplayGain> {
+    let tag = |scheme: &str, what: &str| tags.get(&format!("{}_{}_{}", scheme, which, what));
+    let gain = tag("replaygain", "gain").and_then(|x| x.trim().trim_end_matches("dB").trim_end_matches("db").trim().parse().ok())
+        // R128 gains (Opus uses these) are Q7.8 fixed point, relative to -23 LUFS
+        .or_else(|| tag("r128", "gain").and_then(|x| x.trim().parse::<i32>().ok()).map(|x| x as f32 / 256.0 + 5.0))?;
+    let peak = tag("replaygain", "peak").and_then(|x| x.trim().parse().ok());
+    Some(ReplayGain {gain, peak})
+}
+// track and disc tags are "3" or "3/12"
+fn parse_position(s: &str) -> Option<u32> {
+    s.split('/').next()?.trim().parse().ok()
+}
+
+fn parse_ffmpeg_line(line: &str) -> (&str, impl Iterator<Item=(&str, &str)>) {
+    let mut it = line.split('|');
     let kind = it.next().unwrap();
-    return (kind, it.map(|token| token.split_once("=").unwrap()));
+    // a '|' inside a tag value comes out escaped, but splitting on it anyway just leaves a
+    // fragment without an '=', which is dropped
+    (kind, it.filter_map(|token| token.split_once('=')))
+}
+
+// ffprobe reports frame rates as fractions, "24000/1001".  0/0 means it doesn't know.
+fn parse_rational(s: &str) -> Option<f32> {
+    let (num, den) = s.split_once('/')?;
+    let (num, den) = (num.parse::<f32>().ok()?, den.parse::<f32>().ok()?);
+    if den == 0.0 {
+        return None;
+    }
+    Some(num / den)
 }
 
 pub fn ffprobe(filename: &Path) -> std::io::Result<FFprobeResult> {
-    filename.metadata()?; // to make sure we can read the path before invoking ffmpeg
-                          // you could remove this but it would make error messages less
-                          // informative
+    // to make sure we can read the path before invoking ffmpeg.  you could remove this but it
+    // would make error messages less informative.  URLs go straight to ffprobe.
+    if !filename.to_string_lossy().contains("://") {
+        filename.metadata()?;
+    }
     let res = Command::new("ffprobe")
         .arg(filename.as_os_str())
         .arg("-of").arg("compact")
         .arg("-hide_banner")
-        .arg("-show_streams").arg("-show_format")
+        .arg("-show_streams").arg("-show_format").arg("-show_chapters")
         .arg("-show_entries")
-        .arg("stream_tags=title,language:stream=index,codec_type,codec_name,coded_height,bitrate:stream_disposition=:format=duration,bit_rate:format_tags=title")
+        .arg(format!("stream_tags=title,language,artist,album,track,disc,{}:stream=index,codec_type,codec_name,coded_height,profile,level,pix_fmt,color_transfer,color_primaries,color_space,avg_frame_rate,bit_rate,channels,channel_layout,sample_rate,closed_captions:stream_disposition=:format=duration,start_time,bit_rate,format_name:format_tags:chapter=start_time,end_time:chapter_tags=title", GAIN_TAGS))
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
         .spawn()?
         .wait_with_output()?;
     if !res.status.success() {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "FFprobe returned error"));
+        return Err(std::io::Error::other("FFprobe returned error"));
     }
     let output = std::str::from_utf8(&res.stdout).unwrap();
     let mut tracks = Vec::<Track>::new();
     let mut title: Option<String> = None;
-    let mut duration = 0.0f32;
+    let mut duration = 0.0f64;
     let mut bitrate = 0u64;
+    let (mut artist, mut album, mut track_number, mut disc_number) = (None, None, None, None);
+    let mut gain_tags = HashMap::new();
+    let mut tags = HashMap::new();
+    let mut format_name = None;
+    let mut start_time = 0.0f64;
+    let mut chapters = Vec::new();
 
     'a: for line in output.split("\n") {
         let (kind, params) = parse_ffmpeg_line(line);
         match kind {
             "format" => {
                 for (k,v) in params {
-                    match k {
-                        "duration" => {duration = v.parse().unwrap();}
-                        "bit_rate" => {bitrate = v.parse().unwrap();}
+                    if let Some(tag) = k.strip_prefix("tag:") {
+                        tags.insert(tag.to_ascii_lowercase(), v.to_owned());
+                    }
+                    // tags come out in whatever case the file has them in, and Vorbis comments
+                    // are usually upper case
+                    match k.to_ascii_lowercase().as_str() {
+                        // both are "N/A" for live streams and raw elementary streams, and stay 0
+                        "duration" => duration = v.parse().unwrap_or(0.0),
+                        "bit_rate" => bitrate = v.parse::<u64>().map_or(0, |x| x / 1000),
+                        "format_name" => format_name = Some(v.to_owned()),
+                        "start_time" => start_time = v.parse().unwrap_or(0.0),
                         "tag:title" => {title = Some(v.to_owned());}
-                        x => {println!("uncrecognized tag {}", x);},
+                        "tag:artist" => artist = Some(v.to_owned()),
+                        "tag:album" => album = Some(v.to_owned()),
+                        "tag:track" => track_number = parse_position(v),
+                        "tag:disc" => disc_number = parse_position(v),
+                        x if x.starts_with("tag:replaygain_") || x.starts_with("tag:r128_") => {
+                            gain_tags.insert(x["tag:".len()..].to_owned(), v.to_owned());
+                        },
+                        // kept in `tags`
+                        x if x.starts_with("tag:") => {},
+                        x => {eprintln!("uncrecognized tag {}", x);},
                     }
                 }
             },
@@ -76,11 +259,24 @@ pub fn ffprobe(filename: &Path) -> std::io::Result<FFprobeResult> {
                 let mut kind: Option<TrackType> = None;
                 let mut codec: Option<String> = None;
                 let mut scanline_count: Option<u16> = None;
+                let mut profile: Option<String> = None;
+                let mut level: Option<i32> = None;
+                let mut pix_fmt: Option<String> = None;
+                let mut color_transfer: Option<String> = None;
+                let mut color_primaries: Option<String> = None;
+                let mut color_space: Option<String> = None;
+                let mut frame_rate: Option<f32> = None;
                 let mut language: Option<str4> = None;
                 let mut title: Option<String> = None;
                 let mut index: Option<u16> = None;
+                let mut channels: Option<u16> = None;
+                let mut channel_layout: Option<String> = None;
+                let mut sample_rate: Option<u32> = None;
+                let mut bitrate: Option<u64> = None;
+                let mut disposition = Vec::new();
+                let mut closed_captions = false;
                 for (k,v) in params {
-                    match k {
+                    match k.to_ascii_lowercase().as_str() {
                         "codec_type" => {
                             kind = Some(match v.parse() {
                                 Ok(x) => x,
@@ -90,20 +286,88 @@ pub fn ffprobe(filename: &Path) -> std::io::Result<FFprobeResult> {
                         "index" => index = Some(v.parse().unwrap()),
                         "codec_name" => codec = Some(v.to_string()),
                         "coded_height" => scanline_count = Some(v.parse().unwrap()),
+                        "profile" => profile = Some(v.to_string()).filter(|x| x != "unknown"),
+                        // -99 means unknown
+                        "level" => level = v.parse().ok().filter(|x: &i32| *x >= 0),
+                        "pix_fmt" => pix_fmt = Some(v.to_string()),
+                        "color_transfer" => color_transfer = Some(v.to_string()).filter(|x| x != "unknown"),
+                        "color_primaries" => color_primaries = Some(v.to_string()).filter(|x| x != "unknown"),
+                        "color_space" => color_space = Some(v.to_string()).filter(|x| x != "unknown"),
+                        "avg_frame_rate" => frame_rate = parse_rational(v),
+                        "channels" => channels = v.parse().ok(),
+                        "sample_rate" => sample_rate = v.parse().ok().filter(|x| *x > 0),
+                        "channel_layout" => channel_layout = Some(v.to_string()).filter(|x| !x.is_empty() && x != "unknown"),
+                        "bit_rate" => bitrate = v.parse::<u64>().ok().map(|x| x / 1000),
+                        "closed_captions" => closed_captions = v == "1",
+                        x if x.starts_with("disposition:") => {
+                            if v == "1" {
+                                disposition.push(x["disposition:".len()..].to_owned());
+                            }
+                        },
                         "tag:language" => {language = Some(v.into())},
                         "tag:title" => title = Some(v.to_string()),
-                        x => {println!("uncrecognized tag {}", x);},
+                        // the file's own tags win over the stream's; those are parsed after this
+                        "tag:artist" => { artist.get_or_insert(v.to_string()); },
+                        "tag:album" => { album.get_or_insert(v.to_string()); },
+                        "tag:track" => track_number = track_number.or(parse_position(v)),
+                        "tag:disc" => disc_number = disc_number.or(parse_position(v)),
+                        x if x.starts_with("tag:replaygain_") || x.starts_with("tag:r128_") => {
+                            gain_tags.entry(x["tag:".len()..].to_owned()).or_insert(v.to_owned());
+                        },
+                        x => {eprintln!("uncrecognized tag {}", x);},
                     }
                 }
-                dbg!(line);
                 let index = index.expect("no index");
                 let kind = kind.expect("no codec_type");
                 let codec = codec.expect("no codec_name");
-                tracks.push(Track {index, kind, codec, scanline_count, language, title});
+                tracks.push(Track {index, kind, codec, scanline_count, profile, level, pix_fmt, color_transfer, color_primaries, color_space, frame_rate, language, title, channels, channel_layout, sample_rate, bitrate, disposition, closed_captions});
+            },
+            "chapter" => {
+                let mut chapter = Chapter {start: 0.0, end: 0.0, title: None};
+                for (k,v) in params {
+                    match k.to_ascii_lowercase().as_str() {
+                        "start_time" => chapter.start = v.parse().unwrap_or(0.0),
+                        "end_time" => chapter.end = v.parse().unwrap_or(0.0),
+                        "tag:title" => chapter.title = Some(v.to_owned()).filter(|x| !x.trim().is_empty()),
+                        _ => {},
+                    }
+                }
+                chapters.push(chapter);
             },
             _ => {},
         }
     }
-    Ok(FFprobeResult {tracks, title, duration, bitrate})
+    let (track_gain, album_gain) = (replay_gain(&gain_tags, "track"), replay_gain(&gain_tags, "album"));
+    let mut programs = Vec::new();
+    if has_format(format_name.as_deref(), "mpegts") {
+        programs = probe_programs(filename)?;
+        if programs.len() < 2 {
+            programs.clear();
+        }
+    }
+    Ok(FFprobeResult {tracks, format_name, title, duration, start_time, bitrate, artist, album, track_number, disc_number, tags, track_gain, album_gain, chapters, programs})
+}
+
+// a program's streams come out as `stream` lines of their own in the compact output, which can't
+// be told apart from the real ones, so this is a second ffprobe, in JSON
+fn probe_programs(filename: &Path) -> std::io::Result<Vec<Program>> {
+    let res = Command::new("ffprobe")
+        .arg(filename.as_os_str())
+        .arg("-of").arg("json")
+        .arg("-hide_banner")
+        .arg("-show_entries").arg("program=program_id:program_tags=service_name:program_stream=index")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?
+        .wait_with_output()?;
+    if !res.status.success() {
+        return Err(std::io::Error::other("FFprobe returned error"));
+    }
+    let json: serde_json::Value = serde_json::from_slice(&res.stdout)?;
+    Ok(json["programs"].as_array().into_iter().flatten().filter_map(|program| Some(Program {
+        id: program["program_id"].as_u64()? as u32,
+        name: program["tags"]["service_name"].as_str().map(str::to_owned),
+        streams: program["streams"].as_array().into_iter().flatten().filter_map(|x| Some(x["index"].as_u64()? as u16)).collect(),
+    })).collect())
 }
 
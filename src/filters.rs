@@ -0,0 +1,173 @@
+// ffmpeg filtergraphs, built up from typed pieces instead of pasted together with format!.
+//
+// A graph's written in a little language of its own, with two levels of escaping on top of
+// whatever the shell wants: an option's value escapes `\`, `'` and `:`, and then the whole filter
+// escapes `\`, `'`, `[`, `]`, `,` and `;` so the graph parser doesn't take them for its own.
+// Getting that by hand is how a subtitle file with a colon in its name breaks burn-in, so it's
+// only ever done here, in `Display`.
+//
+// The output is one argument, so it goes to `Command` as-is; there's no shell quoting to worry
+// about on top.
+
+use crate::invocation::FfmpegInvocation;
+use std::fmt;
+
+/// One filter and its options, like `scale=w=-2:h=720`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub name: String,
+    /// (name, value), or just the value for options given by position.
+    pub options: Vec<(Option<String>, String)>,
+}
+
+impl Filter {
+    pub fn new(name: &str) -> Self {
+        Filter {name: name.to_owned(), options: Vec::new()}
+    }
+
+    /// Adds an option by position, like the `2` in `split=2`.
+    pub fn arg<T: ToString>(mut self, value: T) -> Self {
+        self.options.push((None, value.to_string()));
+        self
+    }
+
+    /// Adds a named option, like `h=720`.
+    pub fn opt<T: ToString>(mut self, name: &str, value: T) -> Self {
+        self.options.push((Some(name.to_owned()), value.to_string()));
+        self
+    }
+}
+
+// puts a backslash in front of any of `special` in `s`
+fn escape(s: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// a filter with only the first level of escaping, on its option values
+fn unescaped_filter(filter: &Filter) -> String {
+    let options: Vec<String> = filter.options.iter()
+        .map(|(name, value)| match name {
+            Some(name) => format!("{}={}", name, escape(value, "\\':")),
+            None => escape(value, "\\':"),
+        })
+        .collect();
+    match options.is_empty() {
+        true => filter.name.clone(),
+        false => format!("{}={}", filter.name, options.join(":")),
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&escape(&unescaped_filter(self), "\\'[],;"))
+    }
+}
+
+/// Filters one after another, each fed by the last.  On its own it's what `-vf` and `-af` take.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Chain(pub Vec<Filter>);
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, filter: Filter) -> Self {
+        self.0.push(filter);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Filter> for Chain {
+    fn from(filter: Filter) -> Self {
+        Chain(vec![filter])
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, filter) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", filter)?;
+        }
+        Ok(())
+    }
+}
+
+/// A chain with labelled pads at either end: `[0:3]split=2[a][b]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelledChain {
+    /// Stream specifiers of the inputs (`0:3`) or labels from other chains, without the `[]`s.
+    pub inputs: Vec<String>,
+    pub chain: Chain,
+    pub outputs: Vec<String>,
+}
+
+/// A whole graph, for `-filter_complex`.  Its outputs get `-map`ped as `[label]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterGraph {
+    pub chains: Vec<LabelledChain>,
+}
+
+impl FilterGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `chain`, reading from `inputs` and writing to `outputs`.
+    pub fn chain(&mut self, inputs: &[&str], chain: impl Into<Chain>, outputs: &[&str]) -> &mut Self {
+        self.chains.push(LabelledChain {
+            inputs: inputs.iter().map(|x| x.to_string()).collect(),
+            chain: chain.into(),
+            outputs: outputs.iter().map(|x| x.to_string()).collect(),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Adds the graph to `command` as its `-filter_complex`, unless it's empty.
+    pub fn add_to(&self, command: &mut FfmpegInvocation) {
+        if !self.is_empty() {
+            command.global_arg("-filter_complex").global_arg(self.to_string());
+        }
+    }
+}
+
+/// What to `-map` for the output pad `label`.
+pub fn pad(label: &str) -> String {
+    format!("[{}]", label)
+}
+
+impl fmt::Display for FilterGraph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, chain) in self.chains.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            for input in &chain.inputs {
+                write!(f, "[{}]", input)?;
+            }
+            write!(f, "{}", chain.chain)?;
+            for output in &chain.outputs {
+                write!(f, "[{}]", output)?;
+            }
+        }
+        Ok(())
+    }
+}
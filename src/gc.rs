@@ -0,0 +1,255 @@
+// Cleaning up old outputs.  An output directory is any directory with a manifest in it; they're
+// removed oldest first (by when the manifest was written) until what's left is young enough and
+// small enough.  Anything whose manifest URL is protected is never touched, however old it is:
+// the idea is to protect everything on the channel's playlist, so nothing queued up disappears
+// out from under the viewers.
+//
+// Cytube can give you the playlist's URLs: "Retrieve playlist links" in the playlist menu.  Feed
+// the result to `parse_protected`.
+
+use crate::manifest::{read_manifest, MANIFEST_FILENAME};
+use crate::signing::unsigned;
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Default)]
+pub struct GcPolicy {
+    /// Remove outputs older than this.
+    pub max_age: Option<Duration>,
+    /// Remove the oldest outputs until everything left adds up to no more than this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Manifest URLs that mustn't be removed.
+    pub protected: HashSet<String>,
+    /// Just report what would be removed.
+    pub dry_run: bool,
+}
+
+/// An output directory, as GC sees it.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub dir: PathBuf,
+    /// Where the manifest is served from, going by the URLs in it, without a signature.  `None`
+    /// if it couldn't be read.
+    pub manifest_url: Option<String>,
+    pub bytes: u64,
+    pub written: SystemTime,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    pub removed: Vec<Output>,
+    pub freed: u64,
+    /// How many outputs were due for removal but protected, counting ones whose manifests
+    /// couldn't be read when anything's protected at all, since there's no telling if they are.
+    pub protected: usize,
+}
+
+/// Pulls every URL out of `text`, which can be separated by commas, whitespace or both.  Signatures
+/// are left off, the same as `Output::manifest_url`'s.
+pub fn parse_protected(text: &str) -> HashSet<String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| x.contains("://"))
+        .map(|x| unsigned(x).to_owned())
+        .collect()
+}
+
+// the manifest sits next to the outputs it lists, at the same URL prefix
+fn manifest_url(dir: &Path) -> Option<String> {
+    let manifest = read_manifest(dir).ok()?;
+    let url = unsigned(&manifest.sources.first()?.url);
+    let prefix = &url[..url.rfind('/')? + 1];
+    Some(format!("{}{}", prefix, MANIFEST_FILENAME))
+}
+
+// bytes used by `dir` and everything under it.  files with other hardlinks (from the transcode
+// cache, say) don't free anything when they're removed, so they don't count.
+fn disk_usage(dir: &Path) -> std::io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            total += disk_usage(&entry.path())?;
+        } else if metadata.nlink() == 1 {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Every output directory under `root`.  Hidden directories (staging, quarantined failures) are
+/// skipped, and so is `root` itself, even with a manifest in it: removing that would take
+/// everything else with it.
+pub fn find_outputs(root: &Path) -> std::io::Result<Vec<Output>> {
+    let mut outputs = Vec::new();
+    let mut stack = vec![root.to_owned()];
+    while let Some(dir) = stack.pop() {
+        if let Some(metadata) = fs::metadata(dir.join(MANIFEST_FILENAME)).ok().filter(|_| dir != root) {
+            outputs.push(Output {
+                manifest_url: manifest_url(&dir),
+                bytes: disk_usage(&dir)?,
+                written: metadata.modified()?,
+                dir,
+            });
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+                stack.push(entry.path());
+            }
+        }
+    }
+    Ok(outputs)
+}
+
+/// Removes outputs under `root` according to `policy`.
+pub fn collect_garbage(root: &Path, policy: &GcPolicy) -> std::io::Result<GcReport> {
+    let mut outputs = find_outputs(root)?;
+    outputs.sort_by_key(|x| x.written);
+    let mut total: u64 = outputs.iter().map(|x| x.bytes).sum();
+    let now = SystemTime::now();
+
+    let mut report = GcReport::default();
+    for output in outputs {
+        let too_old = policy.max_age.is_some_and(|max| now.duration_since(output.written).unwrap_or_default() > max);
+        let too_big = policy.max_bytes.is_some_and(|max| total > max);
+        if !too_old && !too_big {
+            continue;
+        }
+        // a manifest that's half written or been edited by hand could still be on the playlist
+        let protected = match &output.manifest_url {
+            Some(url) => policy.protected.contains(url),
+            None => !policy.protected.is_empty(),
+        };
+        if protected {
+            report.protected += 1;
+            continue;
+        }
+        if !policy.dry_run {
+            fs::remove_dir_all(&output.dir)?;
+        }
+        total -= output.bytes;
+        report.freed += output.bytes;
+        report.removed.push(output);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    // a fresh directory for one test, under the system's temp directory
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("cytube-generator-gc-tests").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // an output `days` old with `bytes` of video in it, served from `url_prefix`, or with a
+    // manifest that doesn't parse if that's `None`
+    fn output(root: &Path, name: &str, url_prefix: Option<&str>, days: u32, bytes: usize) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.mp4"), vec![0; bytes]).unwrap();
+        let manifest = match url_prefix {
+            Some(prefix) => serde_json::json!({
+                "title": name, "duration": 10.0, "audioTracks": [], "textTracks": [],
+                "sources": [{"url": format!("{}main.mp4?Signature=abc", prefix), "contentType": "video/mp4", "quality": 720, "bitrate": 1000}],
+            }).to_string(),
+            None => "{\"title\": ".to_string(),
+        };
+        fs::write(dir.join(MANIFEST_FILENAME), manifest).unwrap();
+        let written = SystemTime::now() - DAY * days;
+        File::options().write(true).open(dir.join(MANIFEST_FILENAME)).unwrap().set_modified(written).unwrap();
+    }
+
+    fn removed(report: &GcReport) -> Vec<String> {
+        let mut names: Vec<_> = report.removed.iter().map(|x| x.dir.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    fn library(name: &str) -> PathBuf {
+        let root = scratch(name);
+        output(&root, "old", Some("https://cdn.example/old/"), 30, 1000);
+        output(&root, "older", Some("https://cdn.example/older/"), 60, 1000);
+        output(&root, "new", Some("https://cdn.example/new/"), 1, 1000);
+        output(&root, "nested/deeper", Some("https://cdn.example/deeper/"), 45, 1000);
+        // staging and quarantine aren't outputs, however old
+        output(&root, ".new.staging", Some("https://cdn.example/new/"), 90, 1000);
+        root
+    }
+
+    #[test]
+    fn collecting() {
+        let protected = parse_protected("https://cdn.example/older/manifest.json?Expires=1,\nhttps://cdn.example/elsewhere/manifest.json");
+        // (max age in days, max bytes, protected, what's removed, how many were protected)
+        let cases = [
+            (None, None, false, vec![], 0),
+            (Some(40), None, false, vec!["deeper", "older"], 0),
+            (Some(40), None, true, vec!["deeper"], 1),
+            (Some(100), None, false, vec![], 0),
+            // oldest first until it fits
+            (None, Some(3000), false, vec!["deeper", "older"], 0),
+            (None, Some(3000), true, vec!["deeper", "old"], 1),
+        ];
+        for (i, (days, max_bytes, protect, expected, protected_count)) in cases.into_iter().enumerate() {
+            let root = library(&format!("collecting_{}", i));
+            let policy = GcPolicy {
+                max_age: days.map(|x| DAY * x),
+                max_bytes,
+                protected: if protect { protected.clone() } else { HashSet::new() },
+                dry_run: false,
+            };
+            let report = collect_garbage(&root, &policy).unwrap();
+            assert_eq!(removed(&report), expected, "case {}", i);
+            assert_eq!(report.protected, protected_count, "case {}", i);
+            for output in &report.removed {
+                assert!(!output.dir.exists());
+            }
+            assert!(root.join(".new.staging").exists() && root.join("new").exists());
+        }
+    }
+
+    #[test]
+    fn dry_runs_remove_nothing() {
+        let root = library("dry_run");
+        let report = collect_garbage(&root, &GcPolicy {max_age: Some(DAY * 40), dry_run: true, ..Default::default()}).unwrap();
+        assert_eq!(removed(&report), ["deeper", "older"]);
+        assert!(report.freed >= 2000);
+        assert!(report.removed.iter().all(|x| x.dir.exists()));
+    }
+
+    #[test]
+    fn unreadable_manifests() {
+        let root = scratch("unreadable");
+        output(&root, "mangled", None, 60, 1000);
+        let policy = GcPolicy {max_age: Some(DAY * 40), ..Default::default()};
+        // there's no telling whether it's on the playlist
+        let protected = GcPolicy {protected: parse_protected("https://cdn.example/other/manifest.json"), ..policy.clone()};
+        let report = collect_garbage(&root, &protected).unwrap();
+        assert!(report.removed.is_empty());
+        assert_eq!(report.protected, 1);
+        // but with nothing protected, it's just old
+        assert_eq!(removed(&collect_garbage(&root, &policy).unwrap()), ["mangled"]);
+    }
+
+    #[test]
+    fn root_is_never_an_output() {
+        let root = scratch("root");
+        output(&root, "", Some("https://cdn.example/"), 60, 1000);
+        output(&root, "show", Some("https://cdn.example/show/"), 60, 1000);
+        let report = collect_garbage(&root, &GcPolicy {max_age: Some(DAY), ..Default::default()}).unwrap();
+        assert_eq!(removed(&report), ["show"]);
+        assert!(root.join(MANIFEST_FILENAME).exists());
+    }
+}
@@ -0,0 +1,209 @@
+// A structured ffmpeg command line.
+//
+// `std::process::Command` is write-only: you can't get an ffmpeg command back out of one in any
+// shape that tells you which options belong to which file, and its Debug output isn't something
+// you can paste into a shell.  This keeps the pieces apart (global options, each input with its
+// options, each output with its options) and only flattens them into a `Command` at the last
+// moment.
+//
+// The builder methods mirror `Command`'s so the code building these reads the same way ffmpeg's
+// own command line does: pile up options with `arg`/`args`, then `input` or `output` claims them
+// for a file.
+//
+// For storing or comparing, there's `InvocationSpec`: the same thing as plain strings, which
+// serializes the same way everywhere, so planned commands can be snapshotted and diffed.
+
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::process::Command;
+
+/// A file ffmpeg reads or writes, with the options that apply to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileSpec {
+    pub args: Vec<OsString>,
+    pub path: OsString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegInvocation {
+    /// What to run ffmpeg through, if anything, like `taskset --cpu-list 0-3`: the first is the
+    /// program that actually gets run, and `program` and the rest go on its command line.
+    pub launcher: Vec<OsString>,
+    pub program: OsString,
+    /// Options that aren't tied to any file, like `-hide_banner`.
+    pub global_args: Vec<OsString>,
+    pub inputs: Vec<FileSpec>,
+    pub outputs: Vec<FileSpec>,
+    // options that haven't been claimed by an input or output yet
+    pending: Vec<OsString>,
+}
+
+impl Default for FfmpegInvocation {
+    fn default() -> Self {
+        FfmpegInvocation {
+            launcher: Vec::new(),
+            program: "ffmpeg".into(),
+            global_args: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl FfmpegInvocation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn global_arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.global_args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Queues up an option for whichever file comes next.
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.pending.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where I: IntoIterator<Item=S>, S: AsRef<OsStr> {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Adds an input with all the options queued up since the last file.  Returns its index, for
+    /// use in `-map`.
+    pub fn input<S: AsRef<OsStr>>(&mut self, path: S) -> usize {
+        let args = std::mem::take(&mut self.pending);
+        self.inputs.push(FileSpec {args, path: path.as_ref().to_owned()});
+        self.inputs.len() - 1
+    }
+
+    /// Adds an output with all the options queued up since the last file.
+    pub fn output<S: AsRef<OsStr>>(&mut self, path: S) -> &mut Self {
+        let args = std::mem::take(&mut self.pending);
+        self.outputs.push(FileSpec {args, path: path.as_ref().to_owned()});
+        self
+    }
+
+    /// Everything after the program name, in the order ffmpeg wants it.
+    pub fn to_args(&self) -> Vec<OsString> {
+        let mut args = self.global_args.clone();
+        for input in &self.inputs {
+            args.extend(input.args.iter().cloned());
+            args.push("-i".into());
+            args.push(input.path.clone());
+        }
+        for output in &self.outputs {
+            args.extend(output.args.iter().cloned());
+            args.push(output.path.clone());
+        }
+        // anything left over would be ignored by ffmpeg (with a warning), but pass it along
+        // anyway rather than silently dropping it
+        args.extend(self.pending.iter().cloned());
+        args
+    }
+
+    pub fn to_command(&self) -> Command {
+        let mut command = match self.launcher.split_first() {
+            Some((launcher, args)) => {
+                let mut command = Command::new(launcher);
+                command.args(args).arg(&self.program);
+                command
+            },
+            None => Command::new(&self.program),
+        };
+        command.args(self.to_args());
+        command
+    }
+
+    /// The invocation as plain data.  Arguments that aren't valid UTF-8 get mangled.
+    pub fn spec(&self) -> InvocationSpec {
+        let strings = |x: &[OsString]| x.iter().map(|x| x.to_string_lossy().into_owned()).collect();
+        let file = |x: &FileSpec| FileArgs {args: strings(&x.args), path: x.path.to_string_lossy().into_owned()};
+        InvocationSpec {
+            launcher: strings(&self.launcher),
+            program: self.program.to_string_lossy().into_owned(),
+            global_args: strings(&self.global_args),
+            inputs: self.inputs.iter().map(file).collect(),
+            outputs: self.outputs.iter().map(file).collect(),
+            unclaimed_args: strings(&self.pending),
+        }
+    }
+}
+
+/// A `FileSpec` as plain strings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileArgs {
+    pub args: Vec<String>,
+    pub path: String,
+}
+
+/// An `FfmpegInvocation` as plain strings, laid out the same way: global options, then each
+/// input and output with its own.  It's meant to stay put from version to version, so a plan's
+/// commands can be saved and compared against what a later version plans for the same input.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InvocationSpec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub launcher: Vec<String>,
+    pub program: String,
+    pub global_args: Vec<String>,
+    pub inputs: Vec<FileArgs>,
+    pub outputs: Vec<FileArgs>,
+    /// Options queued up after the last file, which ffmpeg ignores.  Nearly always empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unclaimed_args: Vec<String>,
+}
+
+impl From<InvocationSpec> for FfmpegInvocation {
+    fn from(spec: InvocationSpec) -> Self {
+        let strings = |x: Vec<String>| x.into_iter().map(OsString::from).collect();
+        let file = |x: FileArgs| FileSpec {args: strings(x.args), path: x.path.into()};
+        FfmpegInvocation {
+            launcher: strings(spec.launcher),
+            program: spec.program.into(),
+            global_args: strings(spec.global_args),
+            inputs: spec.inputs.into_iter().map(file).collect(),
+            outputs: spec.outputs.into_iter().map(file).collect(),
+            pending: strings(spec.unclaimed_args),
+        }
+    }
+}
+
+fn is_shell_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c)
+}
+
+/// `arg` quoted for a POSIX shell, if it needs to be.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(is_shell_safe) {
+        return arg.to_owned();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn write_quoted(f: &mut fmt::Formatter, arg: &OsStr) -> fmt::Result {
+    f.write_str(&shell_quote(&arg.to_string_lossy()))
+}
+
+/// Prints the command line, quoted so it can be pasted straight into a POSIX shell.  Arguments
+/// that aren't valid UTF-8 get mangled, so those won't round-trip.
+impl fmt::Display for FfmpegInvocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for arg in &self.launcher {
+            write_quoted(f, arg)?;
+            f.write_str(" ")?;
+        }
+        write_quoted(f, &self.program)?;
+        for arg in self.to_args() {
+            f.write_str(" ")?;
+            write_quoted(f, &arg)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+// Bookkeeping for transcode jobs when running as a daemon: what's been asked for, what's running,
+// what came out, and what went wrong.
+//
+// The store is just a record.  Whatever runs the jobs claims them with `claim_next`, does the work,
+// and reports back with `finish`.  Storage lives behind `JobStore` so the daemon doesn't care where
+// it goes; there's an in-memory one that forgets everything on restart, and (with the `sqlite`
+// feature) one that doesn't.
+//
+// Jobs can say who submitted them (a user, or a channel), and `Quotas` limits how much each
+// submitter gets to run at once and in a day, so one big batch can't hog the machine.  Past their
+// quota, a submitter's jobs just stay queued, and everyone else's go ahead of them.
+
+use crate::compat::BrowserProfile;
+use crate::cytube_structs::CytubeVideo;
+use crate::encoder::VideoEncoder;
+use crate::transcode::{DefaultSubtitles, ReplayGainMode, TranscodeOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteJobStore;
+
+pub type JobId = i64;
+
+/// What a job is supposed to do.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobSpec {
+    /// The file to transcode.  Can also be a URL ffmpeg knows how to read.
+    pub input: String,
+    pub outputdir: PathBuf,
+    /// Can be left out if the daemon uploads its outputs; it's filled in from where they're
+    /// uploaded to.
+    #[serde(default)]
+    pub url_prefix: String,
+    #[serde(default)]
+    pub options: JobOptions,
+    /// Who it's for, like a user or channel name, for `Quotas`.  Jobs without one count as one
+    /// submitter, `""`.
+    #[serde(default)]
+    pub submitter: Option<String>,
+    /// The job's priority to start with.  See `Job::priority`.
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl JobSpec {
+    /// Who the quotas count this against.
+    pub fn submitter(&self) -> &str {
+        self.submitter.as_deref().unwrap_or("")
+    }
+}
+
+/// Per-job overrides for whatever `TranscodeOptions` the daemon runs with.  Unset means leave it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobOptions {
+    pub preferred_language: Option<String>,
+    pub audio_languages: Option<Vec<String>>,
+    pub subtitle_languages: Option<Vec<String>>,
+    pub no_subtitles: Option<bool>,
+    pub default_subtitles: Option<DefaultSubtitles>,
+    pub target: Option<BrowserProfile>,
+    pub fallback_encoder: Option<VideoEncoder>,
+    pub renditions: Option<Vec<u16>>,
+    pub fragmented_mp4: Option<bool>,
+    pub parallel_segments: Option<usize>,
+    pub replay_gain: Option<ReplayGainMode>,
+    pub strip_metadata: Option<bool>,
+    pub deterministic: Option<bool>,
+}
+
+impl JobOptions {
+    pub fn apply(&self, options: &mut TranscodeOptions) {
+        if let Some(language) = &self.preferred_language {
+            options.preferred_language = Some(language.as_str().into());
+        }
+        if let Some(languages) = &self.audio_languages {
+            options.audio_languages = Some(languages.iter().map(|x| x.as_str().into()).collect());
+        }
+        if let Some(languages) = &self.subtitle_languages {
+            options.subtitle_languages = Some(languages.iter().map(|x| x.as_str().into()).collect());
+        }
+        if let Some(no_subtitles) = self.no_subtitles {
+            options.no_subtitles = no_subtitles;
+        }
+        if let Some(default_subtitles) = self.default_subtitles {
+            options.default_subtitles = default_subtitles;
+        }
+        if let Some(target) = self.target {
+            options.target = target;
+        }
+        if let Some(encoder) = self.fallback_encoder {
+            options.fallback_encoder = encoder;
+        }
+        if let Some(renditions) = &self.renditions {
+            options.renditions = renditions.clone();
+        }
+        if let Some(fragmented_mp4) = self.fragmented_mp4 {
+            options.fragmented_mp4 = fragmented_mp4;
+        }
+        if let Some(parallel_segments) = self.parallel_segments {
+            options.parallel_segments = parallel_segments;
+        }
+        if self.replay_gain.is_some() {
+            options.replay_gain = self.replay_gain;
+        }
+        if let Some(strip_metadata) = self.strip_metadata {
+            options.strip_metadata = strip_metadata;
+        }
+        if let Some(deterministic) = self.deterministic {
+            options.deterministic = deterministic;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::EnumString, strum::Display)]
+#[strum(serialize_all="snake_case")]
+#[serde(rename_all="snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job is over, one way or another.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: JobId,
+    pub spec: JobSpec,
+    pub status: JobStatus,
+    /// The finished manifest, once the job's done.
+    pub manifest: Option<CytubeVideo>,
+    /// Why it failed, if it did.
+    pub error: Option<String>,
+    /// Queued jobs with a higher priority go first; ones with the same priority go in the order
+    /// they came in.  The spec's, unless it's been changed.
+    #[serde(default)]
+    pub priority: i32,
+    /// Seconds since the epoch.
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// When it last started running.  For a finished job, it ran from then until `updated_at`.
+    #[serde(default)]
+    pub started_at: Option<u64>,
+}
+
+/// How much one submitter's jobs get to run.  Unset means no limit.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quota {
+    /// Jobs running at once.
+    pub max_concurrent: Option<usize>,
+    /// Minutes spent encoding (by the clock, not of media) in the last 24 hours, counting jobs
+    /// that started in that time.  A job that goes over is let finish.
+    pub max_daily_minutes: Option<f64>,
+}
+
+/// Everyone's quotas.  The `[quotas]` table in the config file: `[quotas.default]` for anyone
+/// without one of their own in `[quotas.submitters.<name>]`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quotas {
+    pub default: Quota,
+    pub submitters: HashMap<String, Quota>,
+}
+
+// what "daily" means for `max_daily_minutes`
+const QUOTA_WINDOW: u64 = 24 * 60 * 60;
+
+impl Quotas {
+    pub fn quota(&self, submitter: &str) -> &Quota {
+        self.submitters.get(submitter).unwrap_or(&self.default)
+    }
+
+    /// Whether anyone has a daily limit, i.e. whether `exhausted` needs finished jobs too.
+    pub fn has_daily_limits(&self) -> bool {
+        self.default.max_daily_minutes.is_some() || self.submitters.values().any(|x| x.max_daily_minutes.is_some())
+    }
+
+    /// The submitters who can't start another job right now, going by `jobs` (the running ones,
+    /// and the finished ones if there are daily limits).
+    pub fn exhausted(&self, jobs: &[Job], now: u64) -> Vec<String> {
+        // (running, seconds spent encoding) for each submitter
+        let mut usage = HashMap::<&str, (usize, u64)>::new();
+        for job in jobs {
+            let Some(started_at) = job.started_at.filter(|x| now.saturating_sub(*x) < QUOTA_WINDOW) else { continue };
+            let usage = usage.entry(job.spec.submitter()).or_default();
+            match job.status {
+                JobStatus::Running => {
+                    usage.0 += 1;
+                    usage.1 += now.saturating_sub(started_at);
+                },
+                JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled => usage.1 += job.updated_at.saturating_sub(started_at),
+                JobStatus::Queued => {},
+            }
+        }
+        usage.into_iter()
+            .filter(|(submitter, (running, seconds))| {
+                let quota = self.quota(submitter);
+                quota.max_concurrent.is_some_and(|x| *running >= x)
+                    || quota.max_daily_minutes.is_some_and(|x| *seconds as f64 / 60.0 >= x)
+            })
+            .map(|(submitter, _)| submitter.to_string())
+            .collect()
+    }
+}
+
+/// Somewhere to keep jobs.  Has to be shareable between the thread taking submissions and the ones
+/// doing the work.
+pub trait JobStore: Send + Sync {
+    /// Adds a job to the end of the queue.
+    fn submit(&self, spec: &JobSpec) -> std::io::Result<JobId>;
+    fn get(&self, id: JobId) -> std::io::Result<Option<Job>>;
+    /// Every job, or every job with the given status, oldest first.
+    fn list(&self, status: Option<JobStatus>) -> std::io::Result<Vec<Job>>;
+    /// Marks the queued job that's next (highest priority, then oldest) as running and returns it,
+    /// passing over the jobs of the submitters in `skip`.  Two callers never get the same job.
+    fn claim_next(&self, skip: &[String]) -> std::io::Result<Option<Job>>;
+    /// Records the outcome of a running job.  Does nothing if the job isn't running anymore (it
+    /// got cancelled in the meantime, say).
+    fn finish(&self, id: JobId, result: Result<&CytubeVideo, &str>) -> std::io::Result<()>;
+    /// Cancels a job that hasn't finished yet.  Returns whether there was one to cancel.  Stopping
+    /// the actual work, if it's already running, is up to whoever's running it.
+    fn cancel(&self, id: JobId) -> std::io::Result<bool>;
+    /// Changes a job's priority.  Returns whether there was a job that hadn't finished to change.
+    fn set_priority(&self, id: JobId, priority: i32) -> std::io::Result<bool>;
+    /// Puts anything still marked running back in the queue.  For when the daemon starts up
+    /// after dying partway through a job.  Returns how many there were.
+    fn requeue_running(&self) -> std::io::Result<usize>;
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Keeps jobs in memory.  Everything's gone when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<Vec<Job>>,
+}
+
+impl JobStore for MemoryJobStore {
+    fn submit(&self, spec: &JobSpec) -> std::io::Result<JobId> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len() as JobId + 1;
+        jobs.push(Job {
+            id,
+            spec: spec.clone(),
+            status: JobStatus::Queued,
+            manifest: None,
+            error: None,
+            priority: spec.priority,
+            created_at: now(),
+            updated_at: now(),
+            started_at: None,
+        });
+        Ok(id)
+    }
+
+    fn get(&self, id: JobId) -> std::io::Result<Option<Job>> {
+        Ok(self.jobs.lock().unwrap().iter().find(|job| job.id == id).cloned())
+    }
+
+    fn list(&self, status: Option<JobStatus>) -> std::io::Result<Vec<Job>> {
+        Ok(self.jobs.lock().unwrap().iter().filter(|job| status.is_none_or(|x| x == job.status)).cloned().collect())
+    }
+
+    fn claim_next(&self, skip: &[String]) -> std::io::Result<Option<Job>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        // max_by_key takes the last of equals, so reversed, that's the oldest
+        let next = jobs.iter_mut().rev()
+            .filter(|job| job.status == JobStatus::Queued && !skip.iter().any(|x| x == job.spec.submitter()))
+            .max_by_key(|job| job.priority);
+        let Some(job) = next else { return Ok(None) };
+        job.status = JobStatus::Running;
+        job.updated_at = now();
+        job.started_at = Some(now());
+        Ok(Some(job.clone()))
+    }
+
+    fn finish(&self, id: JobId, result: Result<&CytubeVideo, &str>) -> std::io::Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id && job.status == JobStatus::Running) {
+            match result {
+                Ok(manifest) => {
+                    job.status = JobStatus::Done;
+                    job.manifest = Some(manifest.clone());
+                },
+                Err(error) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(error.to_owned());
+                },
+            }
+            job.updated_at = now();
+        }
+        Ok(())
+    }
+
+    fn cancel(&self, id: JobId) -> std::io::Result<bool> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.iter_mut().find(|job| job.id == id && !job.status.is_finished()) else { return Ok(false) };
+        job.status = JobStatus::Cancelled;
+        job.updated_at = now();
+        Ok(true)
+    }
+
+    fn set_priority(&self, id: JobId, priority: i32) -> std::io::Result<bool> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.iter_mut().find(|job| job.id == id && !job.status.is_finished()) else { return Ok(false) };
+        job.priority = priority;
+        job.updated_at = now();
+        Ok(true)
+    }
+
+    fn requeue_running(&self) -> std::io::Result<usize> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut count = 0;
+        for job in jobs.iter_mut().filter(|job| job.status == JobStatus::Running) {
+            job.status = JobStatus::Queued;
+            job.updated_at = now();
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    fn spec(input: &str, submitter: Option<&str>, priority: i32) -> JobSpec {
+        JobSpec {
+            input: input.to_string(),
+            outputdir: PathBuf::from("/out"),
+            url_prefix: String::new(),
+            options: JobOptions::default(),
+            submitter: submitter.map(str::to_owned),
+            priority,
+        }
+    }
+
+    // a job by `submitter` that started `ago` seconds before `NOW` and ran for `ran` (or is still
+    // running, if that's `None`)
+    fn job(submitter: &str, ago: u64, ran: Option<u64>) -> Job {
+        let started_at = NOW - ago;
+        Job {
+            id: 0,
+            spec: spec("in.mkv", Some(submitter).filter(|x| !x.is_empty()), 0),
+            status: if ran.is_some() { JobStatus::Done } else { JobStatus::Running },
+            manifest: None,
+            error: None,
+            priority: 0,
+            created_at: started_at,
+            updated_at: ran.map_or(NOW, |x| started_at + x),
+            started_at: Some(started_at),
+        }
+    }
+
+    #[test]
+    fn exhausting_quotas() {
+        let quotas = Quotas {
+            default: Quota {max_concurrent: Some(2), max_daily_minutes: None},
+            submitters: HashMap::from([
+                ("daily".to_string(), Quota {max_concurrent: None, max_daily_minutes: Some(60.0)}),
+                ("unlimited".to_string(), Quota::default()),
+            ]),
+        };
+        let hour = 60 * 60;
+        let cases = [
+            (vec![], vec![]),
+            (vec![job("a", 10, None)], vec![]),
+            (vec![job("a", 10, None), job("a", 20, None)], vec!["a"]),
+            // finished jobs don't count against concurrency
+            (vec![job("a", 10, None), job("a", 20, Some(5))], vec![]),
+            // jobs without a submitter are all one submitter
+            (vec![job("", 10, None), job("", 20, None), job("a", 10, None)], vec![""]),
+            (vec![job("unlimited", 10, None), job("unlimited", 20, None), job("unlimited", 30, None)], vec![]),
+            (vec![job("daily", 2 * hour, Some(30 * 60))], vec![]),
+            (vec![job("daily", 2 * hour, Some(30 * 60)), job("daily", 5 * hour, Some(30 * 60))], vec!["daily"]),
+            // what's running so far counts too
+            (vec![job("daily", 2 * hour, Some(30 * 60)), job("daily", 30 * 60, None)], vec!["daily"]),
+            // anything that started more than a day ago doesn't
+            (vec![job("daily", 2 * hour, Some(30 * 60)), job("daily", 25 * hour, Some(30 * 60))], vec![]),
+        ];
+        for (i, (jobs, expected)) in cases.into_iter().enumerate() {
+            let mut exhausted = quotas.exhausted(&jobs, NOW);
+            exhausted.sort();
+            assert_eq!(exhausted, expected, "case {}", i);
+        }
+    }
+
+    // what every `JobStore` has to do about the order jobs are claimed in
+    pub(crate) fn check_claiming(store: &dyn JobStore) {
+        let low = store.submit(&spec("low", Some("a"), 0)).unwrap();
+        let first = store.submit(&spec("first", Some("a"), 5)).unwrap();
+        let second = store.submit(&spec("second", Some("b"), 5)).unwrap();
+        let third = store.submit(&spec("third", None, 5)).unwrap();
+        let last = store.submit(&spec("last", Some("b"), 0)).unwrap();
+        let claim = |skip: &[&str]| {
+            let skip: Vec<String> = skip.iter().map(|x| x.to_string()).collect();
+            store.claim_next(&skip).unwrap().map(|x| x.id)
+        };
+        // highest priority first, and the oldest of those
+        assert_eq!(claim(&[]), Some(first));
+        // passing over the submitters who are out of quota, including the one without a name
+        assert_eq!(claim(&["b", ""]), Some(low));
+        assert_eq!(claim(&["a", "b"]), Some(third));
+        assert_eq!(claim(&[]), Some(second));
+        assert_eq!(claim(&["b"]), None);
+        assert_eq!(claim(&[]), Some(last));
+        assert_eq!(claim(&[]), None);
+        let running = store.list(Some(JobStatus::Running)).unwrap();
+        assert_eq!(running.len(), 5);
+        assert!(running.iter().all(|x| x.started_at.is_some()));
+    }
+
+    #[test]
+    fn claiming() {
+        check_claiming(&MemoryJobStore::default());
+    }
+}
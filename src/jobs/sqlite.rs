@@ -0,0 +1,163 @@
+// Jobs in an SQLite database, so they survive restarts and there's a history to go digging in
+// with the sqlite3 shell.  Specs and manifests are stored as JSON text.
+
+use super::{now, Job, JobId, JobSpec, JobStatus, JobStore};
+use crate::cytube_structs::CytubeVideo;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    spec TEXT NOT NULL,
+    status TEXT NOT NULL,
+    manifest TEXT,
+    error TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS jobs_by_status ON jobs (status, id);
+";
+
+// columns added since the first version of the schema, for databases made before them
+const MIGRATIONS: [(&str, &str); 2] = [
+    ("priority", "ALTER TABLE jobs ADD COLUMN priority INTEGER NOT NULL DEFAULT 0"),
+    ("started_at", "ALTER TABLE jobs ADD COLUMN started_at INTEGER"),
+];
+
+const COLUMNS: &str = "id, spec, status, manifest, error, priority, created_at, updated_at, started_at";
+
+type RawJob = (JobId, String, String, Option<String>, Option<String>, i32, i64, i64, Option<i64>);
+
+fn db_error(e: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+fn read_row(row: &Row) -> rusqlite::Result<RawJob> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?))
+}
+
+fn to_job((id, spec, status, manifest, error, priority, created_at, updated_at, started_at): RawJob) -> std::io::Result<Job> {
+    Ok(Job {
+        id,
+        spec: serde_json::from_str(&spec)?,
+        status: status.parse().map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("job {} has unknown status {}", id, status)))?,
+        manifest: manifest.map(|x| serde_json::from_str(&x)).transpose()?,
+        error,
+        priority,
+        created_at: created_at as u64,
+        updated_at: updated_at as u64,
+        started_at: started_at.map(|x| x as u64),
+    })
+}
+
+/// Keeps jobs in an SQLite database file.
+pub struct SqliteJobStore {
+    db: Mutex<Connection>,
+}
+
+impl SqliteJobStore {
+    /// Opens the database at `path`, creating it if it isn't there.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let db = Connection::open(path).map_err(db_error)?;
+        // WAL so the sqlite3 shell can read it while the daemon's writing to it
+        db.pragma_update(None, "journal_mode", "WAL").map_err(db_error)?;
+        db.execute_batch(SCHEMA).map_err(db_error)?;
+        for (column, migration) in MIGRATIONS {
+            let exists: bool = db.query_row("SELECT EXISTS (SELECT 1 FROM pragma_table_info('jobs') WHERE name = ?1)", [column], |row| row.get(0))
+                .map_err(db_error)?;
+            if !exists {
+                db.execute_batch(migration).map_err(db_error)?;
+            }
+        }
+        Ok(SqliteJobStore {db: Mutex::new(db)})
+    }
+
+    fn query(&self, sql: &str, params: impl rusqlite::Params) -> std::io::Result<Vec<Job>> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare(sql).map_err(db_error)?;
+        let rows = statement.query_map(params, read_row).map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>().map_err(db_error)?;
+        rows.into_iter().map(to_job).collect()
+    }
+}
+
+impl JobStore for SqliteJobStore {
+    fn submit(&self, spec: &JobSpec) -> std::io::Result<JobId> {
+        let db = self.db.lock().unwrap();
+        db.execute("INSERT INTO jobs (spec, status, priority, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)",
+                   params![serde_json::to_string(spec)?, JobStatus::Queued.to_string(), spec.priority, now() as i64]).map_err(db_error)?;
+        Ok(db.last_insert_rowid())
+    }
+
+    fn get(&self, id: JobId) -> std::io::Result<Option<Job>> {
+        let db = self.db.lock().unwrap();
+        let row = db.query_row(&format!("SELECT {} FROM jobs WHERE id = ?1", COLUMNS), [id], read_row)
+            .optional().map_err(db_error)?;
+        row.map(to_job).transpose()
+    }
+
+    fn list(&self, status: Option<JobStatus>) -> std::io::Result<Vec<Job>> {
+        match status {
+            Some(status) => self.query(&format!("SELECT {} FROM jobs WHERE status = ?1 ORDER BY id", COLUMNS), [status.to_string()]),
+            None => self.query(&format!("SELECT {} FROM jobs ORDER BY id", COLUMNS), []),
+        }
+    }
+
+    fn claim_next(&self, skip: &[String]) -> std::io::Result<Option<Job>> {
+        // one statement, so it's atomic even with other processes using the same database.  the
+        // submitters to skip go in as a JSON array
+        let mut jobs = self.query(&format!("UPDATE jobs SET status = ?1, updated_at = ?2, started_at = ?2
+                                            WHERE id = (SELECT id FROM jobs WHERE status = ?3
+                                                        AND coalesce(json_extract(spec, '$.submitter'), '') NOT IN (SELECT value FROM json_each(?4))
+                                                        ORDER BY priority DESC, id LIMIT 1)
+                                            RETURNING {}", COLUMNS),
+                                  params![JobStatus::Running.to_string(), now() as i64, JobStatus::Queued.to_string(), serde_json::to_string(skip)?])?;
+        Ok(jobs.pop())
+    }
+
+    fn finish(&self, id: JobId, result: Result<&CytubeVideo, &str>) -> std::io::Result<()> {
+        let (status, manifest, error) = match result {
+            Ok(manifest) => (JobStatus::Done, Some(serde_json::to_string(manifest)?), None),
+            Err(error) => (JobStatus::Failed, None, Some(error)),
+        };
+        self.db.lock().unwrap().execute("UPDATE jobs SET status = ?1, manifest = ?2, error = ?3, updated_at = ?4
+                                         WHERE id = ?5 AND status = ?6",
+                                        params![status.to_string(), manifest, error, now() as i64, id, JobStatus::Running.to_string()])
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn cancel(&self, id: JobId) -> std::io::Result<bool> {
+        let changed = self.db.lock().unwrap().execute("UPDATE jobs SET status = ?1, updated_at = ?2 WHERE id = ?3 AND status IN (?4, ?5)",
+                                                      params![JobStatus::Cancelled.to_string(), now() as i64, id,
+                                                              JobStatus::Queued.to_string(), JobStatus::Running.to_string()])
+            .map_err(db_error)?;
+        Ok(changed > 0)
+    }
+
+    fn set_priority(&self, id: JobId, priority: i32) -> std::io::Result<bool> {
+        let changed = self.db.lock().unwrap().execute("UPDATE jobs SET priority = ?1, updated_at = ?2 WHERE id = ?3 AND status IN (?4, ?5)",
+                                                      params![priority, now() as i64, id,
+                                                              JobStatus::Queued.to_string(), JobStatus::Running.to_string()])
+            .map_err(db_error)?;
+        Ok(changed > 0)
+    }
+
+    fn requeue_running(&self) -> std::io::Result<usize> {
+        self.db.lock().unwrap().execute("UPDATE jobs SET status = ?1, updated_at = ?2 WHERE status = ?3",
+                                        params![JobStatus::Queued.to_string(), now() as i64, JobStatus::Running.to_string()])
+            .map_err(db_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claiming() {
+        crate::jobs::tests::check_claiming(&SqliteJobStore::open(Path::new(":memory:")).unwrap());
+    }
+}
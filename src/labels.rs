@@ -0,0 +1,329 @@
+// What audio and text tracks are called in the player's pickers.  A track's label is the name of
+// its language, then its title in brackets if it has one: "English (Commentary)".  A template
+// (`fill_label_template`) can put the codec, channels and bitrate in too.
+//
+// Languages are named in their own language out of the box ("Deutsch", "日本語", "español"),
+// which is what a channel with viewers from all over wants: everyone finds their own language in
+// the picker, whatever the rest of the labels are in.  A channel
+// whose viewers all read one language can have them named in that instead, with names from CLDR
+// (the Unicode Consortium's locale data, which is what browsers and operating systems use): point
+// `LabelLanguage::Localized` at the `languages.json` for the locale, from the cldr-json
+// repository's `cldr-localenames-full/main/<locale>/`.  English names are built in.
+
+use crate::ffmpeg_languages::{ENGLISH_NAMES, FF2CT, LANGUAGES};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Language names in one language, keyed by ISO 639-2/B code (what ffmpeg reports) or by BCP 47
+/// tag (what CLDR and cytube use).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LanguageNames {
+    pub names: HashMap<String, String>,
+}
+
+impl LanguageNames {
+    /// The built-in English names.
+    pub fn english() -> Self {
+        LanguageNames {names: ENGLISH_NAMES.iter().map(|(code, name)| (code.to_string(), name.to_string())).collect()}
+    }
+
+    /// Names from a CLDR `languages.json`, or from a JSON object of code to name.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let json: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+        // {"main": {"fr": {"localeDisplayNames": {"languages": {...}}}}}
+        let languages = json["main"].as_object()
+            .and_then(|x| x.values().next())
+            .map_or(&json, |x| &x["localeDisplayNames"]["languages"]);
+        let names: HashMap<String, String> = languages.as_object().into_iter().flatten()
+            .filter_map(|(code, name)| Some((code.clone(), name.as_str()?.to_string())))
+            .collect();
+        if names.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: no language names in it", path.display())));
+        }
+        Ok(LanguageNames {names})
+    }
+
+    /// What `language` (ISO 639-2/B) is called, if it's in here.
+    pub fn get(&self, language: &str) -> Option<&str> {
+        self.names.get(language)
+            .or_else(|| self.names.get(*FF2CT.get(language)?))
+            .map(String::as_str)
+    }
+}
+
+/// What language the languages in track labels are named in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LabelLanguage {
+    /// Each in its own (its endonym), from a built-in table of every language with an ISO 639-1
+    /// code.  Anything else is named in English.
+    #[default]
+    Native,
+    /// All in one, with English names for any it doesn't have.  They're capitalized, since
+    /// they're the start of the label: French's "anglais" comes out as "Anglais".
+    Localized(Arc<LanguageNames>),
+}
+
+// a names file that can't be read fails the config it's in, rather than labelling everything in
+// the wrong language
+impl TryFrom<String> for LabelLanguage {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        LabelLanguage::parse(&s).map_err(|e| format!("couldn't read label_language {:?}: {}", s, e))
+    }
+}
+
+impl LabelLanguage {
+    /// `"native"` (or `"endonym"`), `"english"`, or a file for `LanguageNames::load`: how it's given in the config
+    /// file and on the command line.
+    pub fn parse(s: &str) -> std::io::Result<Self> {
+        match s {
+            "native" | "endonym" => Ok(LabelLanguage::Native),
+            "english" => Ok(LabelLanguage::Localized(Arc::new(LanguageNames::english()))),
+            path => Ok(LabelLanguage::Localized(Arc::new(LanguageNames::load(Path::new(path))?))),
+        }
+    }
+
+    /// What `language` (ISO 639-2/B) is called.  Languages nobody's got a name for are just
+    /// their code.
+    pub fn name(&self, language: &str) -> String {
+        match self {
+            LabelLanguage::Native => LANGUAGES.get(language).or_else(|| ENGLISH_NAMES.get(language)).copied().unwrap_or(language).to_string(),
+            LabelLanguage::Localized(names) => {
+                let name = names.get(language).or_else(|| ENGLISH_NAMES.get(language).copied()).unwrap_or(language);
+                let mut chars = name.chars();
+                chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+            },
+        }
+    }
+
+    /// The label for a track in `language` (ISO 639-2/B) with `title`, without a template.
+    pub fn label(&self, language: &str, title: Option<&str>) -> String {
+        let mut s = self.name(language);
+        if let Some(title) = title {
+            s.push_str(" (");
+            s.push_str(title);
+            s.push(')');
+        }
+        s
+    }
+}
+
+/// What a track's label can be made of.  What isn't known, or doesn't apply (a text track's
+/// channels), is `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LabelFields<'a> {
+    /// ISO 639-2/B.
+    pub language: &'a str,
+    pub title: Option<&'a str>,
+    /// As ffprobe names it.
+    pub codec: Option<&'a str>,
+    pub channels: Option<u16>,
+    /// As ffprobe names it, which says more than `channels` does when it's known.
+    pub channel_layout: Option<&'a str>,
+    /// In kbps.
+    pub bitrate: Option<u64>,
+}
+
+/// What a codec's called where people will see it.
+pub fn codec_display_name(codec: &str) -> String {
+    match codec {
+        "opus" => "Opus".to_string(),
+        "vorbis" => "Vorbis".to_string(),
+        "ac3" => "AC-3".to_string(),
+        "eac3" => "E-AC-3".to_string(),
+        "truehd" => "TrueHD".to_string(),
+        "mp2" => "MP2".to_string(),
+        x if x.starts_with("pcm_") => "PCM".to_string(),
+        x => x.to_ascii_uppercase(),
+    }
+}
+
+/// How many channels there are, the way it'd be written on the box: "Stereo", "5.1".
+pub fn channels_display_name(channels: u16) -> String {
+    match channels {
+        1 => "Mono".to_string(),
+        2 => "Stereo".to_string(),
+        3 => "2.1".to_string(),
+        6 => "5.1".to_string(),
+        8 => "7.1".to_string(),
+        x => format!("{} channels", x),
+    }
+}
+
+/// What a channel layout's called where people will see it: `"5.1(side)"` is "5.1", `"stereo"`
+/// "Stereo".  Without a layout, it's what the channel count usually means.
+pub fn channel_layout_display_name(layout: Option<&str>, channels: Option<u16>) -> Option<String> {
+    let Some(layout) = layout else {
+        return channels.map(channels_display_name);
+    };
+    // which speakers the surround ones are doesn't matter to anybody picking a track
+    let layout = layout.split('(').next().unwrap_or(layout);
+    Some(match layout {
+        "downmix" => "Stereo".to_string(),
+        x => {
+            let mut chars = x.chars();
+            chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+        },
+    })
+}
+
+/// Makes labels that are the same tell their tracks apart by their channel layouts (display
+/// names, from `channel_layout_display_name`): two "English" tracks become "English (Stereo)" and
+/// "English (5.1)", and "English (Commentary)" becomes "English (Commentary, 5.1)".  Labels that
+/// already say, or whose tracks have the same layout, are left alone.
+pub fn distinguish_labels(labels: &mut [String], layouts: &[Option<String>]) {
+    let original = labels.to_vec();
+    for (i, label) in labels.iter_mut().enumerate() {
+        let same: Vec<usize> = (0..original.len()).filter(|&j| original[j] == original[i]).collect();
+        let Some(layout) = &layouts[i] else {
+            continue;
+        };
+        if same.len() < 2 || same.iter().all(|&j| layouts[j].as_ref() == Some(layout)) {
+            continue;
+        }
+        match label.strip_suffix(')') {
+            Some(rest) if rest.contains('(') => *label = format!("{}, {})", rest, layout),
+            _ => *label = format!("{} ({})", label, layout),
+        }
+    }
+}
+
+// dashes and such between parts of a label, which go when a part next to them is empty
+fn is_separator(token: &str) -> bool {
+    token.chars().all(|x| matches!(x, '-' | '–' | '—' | '/' | '|' | ',' | ':' | '·'))
+}
+
+/// `template` with `{lang}`, `{title}`, `{codec}`, `{channels}` and `{bitrate}` filled in, the
+/// language named in `language`.  Whatever's unknown comes out empty, and so do brackets with
+/// nothing left in them and the separators either side of a gap, so
+/// `"{lang} – {codec} {channels} ({title})"` is "English – Opus 5.1 (Commentary)" for a commentary
+/// track, and just "English" for a track whose codec and channels aren't known and that has no
+/// title.
+pub fn fill_label_template(template: &str, fields: &LabelFields, language: &LabelLanguage) -> String {
+    let filled = template
+        .replace("{lang}", &language.name(fields.language))
+        .replace("{title}", fields.title.unwrap_or(""))
+        .replace("{codec}", &fields.codec.map(codec_display_name).unwrap_or_default())
+        .replace("{channels}", &channel_layout_display_name(fields.channel_layout, fields.channels).unwrap_or_default())
+        .replace("{bitrate}", &fields.bitrate.map(|x| format!("{} kbps", x)).unwrap_or_default());
+    let mut tokens: Vec<String> = filled.split_whitespace().map(str::to_owned).collect();
+    // "( )" has already been split up, so look for brackets on either side of a space too
+    loop {
+        let before = tokens.len();
+        tokens.retain(|x| x != "()" && x != "[]");
+        if let Some(i) = tokens.windows(2).position(|x| (x[0] == "(" && x[1] == ")") || (x[0] == "[" && x[1] == "]")) {
+            tokens.drain(i..i + 2);
+        }
+        if tokens.len() == before {
+            break;
+        }
+    }
+    let mut label: Vec<&str> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1);
+        let dangling = label.is_empty() || next.is_none_or(|x| is_separator(x) || x.starts_with('(') || x.starts_with('['));
+        if !(is_separator(token) && dangling) {
+            label.push(token);
+        }
+    }
+    label.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english() -> LabelLanguage {
+        LabelLanguage::Localized(Arc::new(LanguageNames::english()))
+    }
+
+    #[test]
+    fn naming_languages() {
+        let french = LabelLanguage::Localized(Arc::new(LanguageNames {names: HashMap::from([("en".to_string(), "anglais".to_string())])}));
+        let cases = [
+            (LabelLanguage::Native, "ger", "Deutsch"),
+            (LabelLanguage::Native, "jpn", "日本語"),
+            (english(), "ger", "German"),
+            // by the IETF tag CLDR uses, capitalized, and English for what it doesn't have
+            (french.clone(), "eng", "Anglais"),
+            (french, "ger", "German"),
+            (LabelLanguage::Native, "qaa", "qaa"),
+        ];
+        for (language, code, expected) in cases {
+            assert_eq!(language.name(code), expected, "{}", code);
+        }
+        assert_eq!(english().label("eng", Some("Commentary")), "English (Commentary)");
+        assert_eq!(english().label("eng", None), "English");
+    }
+
+    #[test]
+    fn reading_label_languages() {
+        let parse = |x: &str| serde_json::from_value::<LabelLanguage>(Value::from(x));
+        assert_eq!(parse("native").unwrap(), LabelLanguage::Native);
+        assert_eq!(parse("english").unwrap(), english());
+        // a typo'd file's an error, not native labels
+        assert!(parse("/nonexistent/languages.json").unwrap_err().to_string().contains("label_language"));
+    }
+
+    #[test]
+    fn filling_label_templates() {
+        let full = LabelFields {language: "eng", title: Some("Commentary"), codec: Some("opus"), channels: Some(6), channel_layout: Some("5.1(side)"), bitrate: Some(128)};
+        let bare = LabelFields {language: "eng", ..Default::default()};
+        let cases = [
+            ("{lang} – {codec} {channels} ({title})", full, "English – Opus 5.1 (Commentary)"),
+            ("{lang} – {codec} {channels} ({title})", bare, "English"),
+            ("{lang} – {codec} {channels} ({title})", LabelFields {codec: Some("ac3"), ..bare}, "English – AC-3"),
+            ("{lang} – {codec} {channels} ({title})", LabelFields {title: Some("Dub"), ..bare}, "English (Dub)"),
+            ("{lang} | {bitrate} | {codec}", LabelFields {codec: Some("aac"), ..bare}, "English | AAC"),
+            ("{lang} | {bitrate} | {codec}", full, "English | 128 kbps | Opus"),
+            ("[{codec}] {lang}", bare, "English"),
+            ("{lang} [ {channels} ]", LabelFields {channels: Some(2), ..bare}, "English [ Stereo ]"),
+            ("{lang} [ {channels} ]", bare, "English"),
+            ("- {lang} -", bare, "English"),
+        ];
+        for (template, fields, expected) in cases {
+            assert_eq!(fill_label_template(template, &fields, &english()), expected, "{:?}", template);
+        }
+    }
+
+    #[test]
+    fn codec_names() {
+        let codecs = [("opus", "Opus"), ("eac3", "E-AC-3"), ("pcm_s16le", "PCM"), ("aac", "AAC"), ("flac", "FLAC")];
+        for (codec, expected) in codecs {
+            assert_eq!(codec_display_name(codec), expected);
+        }
+    }
+
+    #[test]
+    fn channel_layout_names() {
+        let layouts = [
+            (Some("5.1(side)"), Some(6), Some("5.1")),
+            (Some("stereo"), Some(2), Some("Stereo")),
+            (Some("downmix"), Some(2), Some("Stereo")),
+            (None, Some(1), Some("Mono")),
+            (None, Some(4), Some("4 channels")),
+            (None, None, None),
+        ];
+        for (layout, channels, expected) in layouts {
+            assert_eq!(channel_layout_display_name(layout, channels).as_deref(), expected, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn distinguishing_labels() {
+        let mut labels = ["English", "English", "English (Commentary)", "English (Commentary)", "Deutsch"].map(str::to_owned);
+        let layouts = [Some("Stereo"), Some("5.1"), Some("Stereo"), Some("Stereo"), None].map(|x| x.map(str::to_owned));
+        distinguish_labels(&mut labels, &layouts);
+        // the commentaries are the same mix, so there's nothing to tell them apart by
+        assert_eq!(labels, ["English (Stereo)", "English (5.1)", "English (Commentary)", "English (Commentary)", "Deutsch"]);
+
+        let mut labels = ["English (Commentary)", "English (Commentary)"].map(str::to_owned);
+        distinguish_labels(&mut labels, &[Some("Stereo".to_string()), Some("5.1".to_string())]);
+        assert_eq!(labels, ["English (Commentary, Stereo)", "English (Commentary, 5.1)"]);
+    }
+}
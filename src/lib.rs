@@ -1,5 +1,54 @@
-mod cytube_structs;
+pub mod batch;
+pub mod benchmark;
+pub mod cgroup;
+#[cfg(feature = "channel")]
+pub mod channel;
+pub mod chapters;
+pub mod clip;
+pub mod compat;
+pub mod config;
+pub mod cue;
+pub mod cytube_structs;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+pub mod distributed;
+pub mod encoder;
+pub mod failure;
+pub mod filters;
 mod ffmpeg_languages;
 pub mod ffprobe;
+pub mod gc;
+pub mod invocation;
+pub mod jobs;
+pub mod labels;
+#[cfg(feature = "libav")]
+pub mod libav;
+pub mod manifest;
+pub mod manifest_diff;
+pub mod metadata;
+#[cfg(feature = "daemon")]
+pub mod metrics;
+pub mod migrate;
+#[cfg(feature = "notify")]
+pub mod notify;
+pub mod plan;
+pub mod playlist;
+#[cfg(feature = "preflight")]
+pub mod preflight;
+pub mod preview_page;
+pub mod probe_cache;
+pub mod release_name;
+pub mod segmented;
+pub mod signing;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod size_model;
+pub mod storyboard;
+pub mod subtitles;
+#[cfg(feature = "testmedia")]
+pub mod testmedia;
+pub mod track_select;
 pub mod transcode;
-
+pub mod transcode_cache;
+pub mod upload;
+pub mod waveform;
@@ -0,0 +1,176 @@
+// Running ffmpeg invocations in-process through the libav libraries instead of spawning the CLI.
+//
+// This is nowhere near a reimplementation of the ffmpeg CLI.  It handles the one shape of
+// command that doesn't need any codecs: a single plain file input, with every output made up of
+// `-map 0:N` stream copies into some container.  That covers remuxing a file whose tracks can all
+// be copied, which is the common case and the one where process startup and stderr scraping are
+// most of the overhead.  Anything else (encoding, filters, lavfi/concat inputs, stream specifiers
+// other than plain indexes) is reported as `ErrorKind::Unsupported`, or handed to the CLI if
+// `fallback_to_cli` is set.
+
+use crate::invocation::{FfmpegInvocation, FileSpec};
+use crate::plan::{CliRunner, Runner};
+use ffmpeg_next as ffmpeg;
+use ffmpeg::{codec, encoder, format, Dictionary};
+use std::ffi::OsString;
+use std::io;
+use std::path::PathBuf;
+
+/// Runs ffmpeg invocations with libav.  Only stream copies are supported; see the module docs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LibavRunner {
+    /// Run invocations this can't handle with the ffmpeg CLI instead of failing them.
+    pub fallback_to_cli: bool,
+}
+
+impl Runner for LibavRunner {
+    fn run(&self, invocation: &FfmpegInvocation) -> io::Result<()> {
+        match parse(invocation) {
+            Ok((input, outputs)) => remux(input, &outputs),
+            Err(e) if e.kind() == io::ErrorKind::Unsupported && self.fallback_to_cli => CliRunner.run(invocation),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+struct Output {
+    path: PathBuf,
+    format: Option<String>,
+    // input stream indexes, in output order
+    streams: Vec<usize>,
+    // muxer options, like movflags
+    options: Vec<(String, String)>,
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("the libav backend doesn't support {}", what))
+}
+
+fn av_error(e: ffmpeg::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn next_str<'a>(args: &mut impl Iterator<Item=&'a OsString>) -> io::Result<Option<&'a str>> {
+    match args.next() {
+        Some(arg) => arg.to_str().map(Some).ok_or_else(|| unsupported("non-UTF-8 arguments")),
+        None => Ok(None),
+    }
+}
+
+fn value<'a>(args: &mut impl Iterator<Item=&'a OsString>, option: &str) -> io::Result<&'a str> {
+    next_str(args)?.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is missing its value", option)))
+}
+
+fn parse(invocation: &FfmpegInvocation) -> io::Result<(PathBuf, Vec<Output>)> {
+    // pinning or limiting ffmpeg means running it as a process of its own
+    if !invocation.launcher.is_empty() {
+        return Err(unsupported("running ffmpeg through a launcher"));
+    }
+    let mut args = invocation.global_args.iter();
+    while let Some(arg) = next_str(&mut args)? {
+        match arg {
+            // nothing gets printed and outputs always get overwritten, so these are no-ops
+            "-hide_banner" | "-y" | "-nostdin" => {},
+            "-loglevel" | "-v" => { value(&mut args, arg)?; },
+            _ => return Err(unsupported(&format!("the global option {}", arg))),
+        }
+    }
+
+    let [input] = invocation.inputs.as_slice() else {
+        return Err(unsupported("more than one input"));
+    };
+    let mut args = input.args.iter();
+    while let Some(arg) = next_str(&mut args)? {
+        match arg {
+            // only matters for decoding, which stream copies don't do
+            "-hwaccel" | "-hwaccel_device" => { value(&mut args, arg)?; },
+            _ => return Err(unsupported(&format!("the input option {}", arg))),
+        }
+    }
+
+    let outputs = invocation.outputs.iter().map(parse_output).collect::<io::Result<Vec<_>>>()?;
+    Ok((PathBuf::from(&input.path), outputs))
+}
+
+fn parse_output(spec: &FileSpec) -> io::Result<Output> {
+    let mut output = Output {path: PathBuf::from(&spec.path), format: None, streams: Vec::new(), options: Vec::new()};
+    let mut args = spec.args.iter();
+    while let Some(arg) = next_str(&mut args)? {
+        match arg {
+            "-map" => {
+                let map = value(&mut args, arg)?;
+                let stream = map.strip_prefix("0:").and_then(|x| x.parse().ok())
+                    .ok_or_else(|| unsupported(&format!("-map {}", map)))?;
+                output.streams.push(stream);
+            },
+            "-c" | "-c:v" | "-c:a" | "-c:s" | "-codec" => {
+                let codec = value(&mut args, arg)?;
+                if codec != "copy" {
+                    return Err(unsupported(&format!("encoding ({} {})", arg, codec)));
+                }
+            },
+            // only affects encoders
+            "-strict" => { value(&mut args, arg)?; },
+            "-movflags" => output.options.push(("movflags".into(), value(&mut args, arg)?.into())),
+            "-f" => output.format = Some(value(&mut args, arg)?.into()),
+            _ => return Err(unsupported(&format!("the output option {}", arg))),
+        }
+    }
+    // ffmpeg's automatic stream selection isn't worth reimplementing
+    if output.streams.is_empty() {
+        return Err(unsupported("outputs without -map"));
+    }
+    Ok(output)
+}
+
+fn remux(input: PathBuf, outputs: &[Output]) -> io::Result<()> {
+    ffmpeg::init().map_err(av_error)?;
+    let mut ictx = format::input(&input).map_err(av_error)?;
+
+    // for each output: the muxer, and for each input stream the output streams it goes to
+    let mut muxers = Vec::new();
+    for output in outputs {
+        let mut octx = match &output.format {
+            Some(format) => format::output_as(&output.path, format),
+            None => format::output(&output.path),
+        }.map_err(av_error)?;
+        let mut mapping = vec![Vec::new(); ictx.nb_streams() as usize];
+        for &index in &output.streams {
+            let ist = ictx.stream(index)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} has no stream {}", input.display(), index)))?;
+            let mut ost = octx.add_stream(encoder::find(codec::Id::None)).map_err(av_error)?;
+            ost.set_parameters(ist.parameters());
+            // the source container's codec tag doesn't necessarily mean anything in this one
+            unsafe { (*ost.parameters().as_mut_ptr()).codec_tag = 0; }
+            mapping[index].push(ost.index());
+        }
+        let mut options = Dictionary::new();
+        for (key, value) in &output.options {
+            options.set(key, value);
+        }
+        octx.write_header_with(options).map_err(av_error)?;
+        muxers.push((octx, mapping));
+    }
+
+    for (stream, packet) in ictx.packets() {
+        let time_base = stream.time_base();
+        for (octx, mapping) in muxers.iter_mut() {
+            // some demuxers (mpegts) can turn up new streams partway through.  nobody asked for
+            // those, so they're dropped.
+            let Some(targets) = mapping.get(stream.index()) else { continue };
+            for &ost_index in targets {
+                let mut packet = packet.clone();
+                // the muxer gets to pick the output time base when the header's written
+                packet.rescale_ts(time_base, octx.stream(ost_index).unwrap().time_base());
+                packet.set_position(-1);
+                packet.set_stream(ost_index);
+                packet.write_interleaved(octx).map_err(av_error)?;
+            }
+        }
+    }
+
+    for (mut octx, _) in muxers {
+        octx.write_trailer().map_err(av_error)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+// Reading and writing the manifest file, and filling it in with real numbers once the outputs
+// exist.
+//
+// `remux` has to fill in bitrates and such before ffmpeg has even run, so for anything that gets
+// transcoded they're guesses.  The two-phase flow is: run the ffmpeg command, and only if it
+// succeeds, write the manifest and `finalize_manifest` it, which re-probes every output it can
+// find and overwrites the guesses with what actually came out.  It also checks that the sources
+// all came out about as long as each other, since cytube doesn't take kindly to a quality switch
+// that jumps past the end of the video.
+
+use crate::cytube_structs::{cytube_duration, CytubeVideo};
+use crate::ffprobe::ffprobe;
+use crate::signing::unsigned;
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Stands in for the URL prefix when where the outputs will be served from isn't known yet, e.g.
+/// `plan(..., "{{BASE}}/", ...)`.  `rebase_manifest` puts the real one in later.
+pub const PREFIX_PLACEHOLDER: &str = "{{BASE}}";
+
+/// How far, in seconds, any source's length can be from the manifest's duration before
+/// `finalize_manifest` gives up on it.  Copied video can only be cut on a keyframe, so a bit of
+/// slack is normal.
+pub const DURATION_TOLERANCE: f64 = 2.0;
+
+pub fn write_manifest(outputdir: &Path, manifest: &CytubeVideo) -> std::io::Result<()> {
+    write_manifest_to(&outputdir.join(MANIFEST_FILENAME), manifest)
+}
+
+/// `write_manifest`, to a file by any name.
+pub fn write_manifest_to(path: &Path, manifest: &CytubeVideo) -> std::io::Result<()> {
+    let f = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    let mut f = BufWriter::new(f);
+    serde_json::to_writer(&mut f, manifest)?;
+    f.flush()
+}
+
+/// Replaces the manifest in `outputdir` with `manifest` all at once, for when it might be being
+/// served while it's rewritten.
+pub fn replace_manifest(outputdir: &Path, manifest: &CytubeVideo) -> std::io::Result<()> {
+    let temp = outputdir.join(format!(".{}.new", MANIFEST_FILENAME));
+    write_manifest_to(&temp, manifest)?;
+    std::fs::rename(temp, outputdir.join(MANIFEST_FILENAME))
+}
+
+pub fn read_manifest(outputdir: &Path) -> std::io::Result<CytubeVideo> {
+    read_manifest_from(&outputdir.join(MANIFEST_FILENAME))
+}
+
+/// `read_manifest`, from a file by any name.
+pub fn read_manifest_from(path: &Path) -> std::io::Result<CytubeVideo> {
+    let f = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(f))?)
+}
+
+// the manifest only has URLs.  everything remux generates is `url_prefix + filename` with the
+// file sitting directly in the output directory, so as long as the prefix ends in a slash the
+// last path segment is the filename (once any signature's off the end).
+pub(crate) fn local_file(outputdir: &Path, url: &str) -> Option<PathBuf> {
+    let filename = unsigned(url).rsplit('/').next()?;
+    let path = outputdir.join(filename);
+    path.is_file().then_some(path)
+}
+
+/// Re-probes the outputs listed in the manifest in `outputdir` and rewrites it with their measured
+/// bitrate, quality (frame height) and duration.  Sources whose files can't be found or probed
+/// keep whatever they had.  Fails if any source's length is more than `DURATION_TOLERANCE` off
+/// the duration.  Returns the updated manifest.
+pub fn finalize_manifest(outputdir: &Path) -> std::io::Result<CytubeVideo> {
+    let mut manifest = read_manifest(outputdir)?;
+    let mut duration: Option<f64> = None;
+    let mut lengths = Vec::new();
+
+    for source in manifest.sources.iter_mut() {
+        let Some(path) = local_file(outputdir, &source.url) else { continue };
+        let Ok(probed) = ffprobe(&path) else { continue };
+        if probed.bitrate > 0 {
+            source.bitrate = probed.bitrate;
+        }
+        if let Some(height) = probed.tracks.iter()
+            .find(|track| track.is_video())
+            .and_then(|track| track.scanline_count) {
+            source.quality = height;
+        }
+        // the sources should all be the same length.  if they're not, go with the longest so
+        // cytube doesn't cut any of them off before the end.
+        if probed.duration > 0.0 {
+            duration = Some(duration.map_or(probed.duration, |x| x.max(probed.duration)));
+            lengths.push((source.url.clone(), probed.duration));
+        }
+    }
+    if let Some(duration) = duration {
+        manifest.duration = duration;
+    }
+    // against what cytube will be told, not the longest source, so rounding counts too
+    let expected = cytube_duration(manifest.duration);
+    if let Some((url, length)) = lengths.iter().find(|(_, length)| (length - expected).abs() > DURATION_TOLERANCE) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("{} is {:.3}s long, but the manifest says {}s", url, length, expected)));
+    }
+
+    write_manifest(outputdir, &manifest)?;
+    Ok(manifest)
+}
+
+/// Copies the fields this doesn't know about (`CytubeVideo::extra`, and the tracks') from `old`
+/// into `new`, for when a manifest that might have had things added to it by hand is replaced.
+/// Tracks are matched up by URL.  Anything `new` already has is left alone.
+pub fn keep_extra_fields(old: &CytubeVideo, new: &mut CytubeVideo) {
+    fn keep<'a>(old: impl Iterator<Item = (&'a String, &'a Map<String, Value>)>, new: impl Iterator<Item = (&'a String, &'a mut Map<String, Value>)>) {
+        let old: Vec<_> = old.map(|(url, extra)| (unsigned(url), extra)).collect();
+        for (url, extra) in new {
+            if let Some((_, old)) = old.iter().find(|(x, _)| *x == unsigned(url)) {
+                for (key, value) in old.iter() {
+                    extra.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+        }
+    }
+    for (key, value) in &old.extra {
+        new.extra.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+    keep(old.sources.iter().map(|x| (&x.url, &x.extra)), new.sources.iter_mut().map(|x| (&x.url, &mut x.extra)));
+    keep(old.audio_tracks.iter().map(|x| (&x.url, &x.extra)), new.audio_tracks.iter_mut().map(|x| (&x.url, &mut x.extra)));
+    keep(old.text_tracks.iter().map(|x| (&x.url, &x.extra)), new.text_tracks.iter_mut().map(|x| (&x.url, &mut x.extra)));
+}
+
+/// Whether any of the manifest's URLs still have `PREFIX_PLACEHOLDER` in them.
+pub fn has_placeholder(manifest: &CytubeVideo) -> bool {
+    manifest.sources.iter().map(|x| &x.url)
+        .chain(manifest.audio_tracks.iter().map(|x| &x.url))
+        .chain(manifest.text_tracks.iter().map(|x| &x.url))
+        .any(|x| x.contains(PREFIX_PLACEHOLDER))
+}
+
+/// `url` with `PREFIX_PLACEHOLDER` replaced by `prefix`.  `"{{BASE}}/a.mp4"` and
+/// `"{{BASE}}a.mp4"` both come out as `prefix` then `a.mp4`, with one slash between them whether
+/// or not `prefix` ends in one.
+pub fn rebase_url(url: &str, prefix: &str) -> String {
+    match url.split_once(PREFIX_PLACEHOLDER) {
+        Some((before, after)) => format!("{}{}/{}", before, prefix.trim_end_matches('/'), after.trim_start_matches('/')),
+        None => url.to_owned(),
+    }
+}
+
+/// `manifest` with the placeholder in its URLs replaced by `prefix`.  See `rebase_url`.
+pub fn rebase(manifest: &CytubeVideo, prefix: &str) -> CytubeVideo {
+    let mut manifest = manifest.clone();
+    for url in manifest.sources.iter_mut().map(|x| &mut x.url)
+        .chain(manifest.audio_tracks.iter_mut().map(|x| &mut x.url))
+        .chain(manifest.text_tracks.iter_mut().map(|x| &mut x.url)) {
+        *url = rebase_url(url, prefix);
+    }
+    manifest
+}
+
+/// Puts the real URL prefix into the manifest in `outputdir`, which was written with
+/// `PREFIX_PLACEHOLDER` as its prefix, and rewrites it.  The media isn't touched.  Signed URLs
+/// can't be signed until they're real, so they need signing afterwards (`resign_manifest`).
+/// Returns the new manifest.
+pub fn rebase_manifest(outputdir: &Path, prefix: &str) -> std::io::Result<CytubeVideo> {
+    let manifest = rebase(&read_manifest(outputdir)?, prefix);
+    replace_manifest(outputdir, &manifest)?;
+    Ok(manifest)
+}
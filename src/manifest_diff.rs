@@ -0,0 +1,182 @@
+// What's different between two manifests, for when something that's already queued gets
+// regenerated and the question is what the channel will actually see change.
+//
+// Tracks are matched up by what a viewer picks them by, not by where they are in the list:
+// sources by quality and content type, audio tracks by language and label, text tracks by name.
+// URLs are compared without their signatures, which change every time they're signed.
+
+use crate::cytube_structs::{cytube_duration, CytubeVideo};
+use crate::signing::unsigned;
+use serde::Serialize;
+use std::fmt;
+
+/// One difference between two manifests.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// `item` (e.g. `"source 1080p video/mp4"`) is only in the new manifest.
+    Added { item: String, url: String },
+    /// `item` is only in the old one.
+    Removed { item: String, url: String },
+    /// `item` is in both, but its `field` isn't the same.  The manifest's own fields (title,
+    /// duration...) are under `"manifest"`.
+    Changed { item: String, field: &'static str, old: String, new: String },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added {item, url} => write!(f, "+ {} ({})", item, url),
+            Change::Removed {item, url} => write!(f, "- {} ({})", item, url),
+            Change::Changed {item, field, old, new} => write!(f, "~ {} {}: {} -> {}", item, field, old, new),
+        }
+    }
+}
+
+// a track as far as diffing's concerned: what it's matched up by, its URL, and its other fields
+struct Item {
+    name: String,
+    url: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+fn diff_items(old: Vec<Item>, new: Vec<Item>, changes: &mut Vec<Change>) {
+    let mut new: Vec<Option<Item>> = new.into_iter().map(Some).collect();
+    for old in old {
+        let Some(new) = new.iter_mut().find(|x| x.as_ref().is_some_and(|x| x.name == old.name)).and_then(Option::take) else {
+            changes.push(Change::Removed {item: old.name, url: old.url});
+            continue;
+        };
+        if old.url != new.url {
+            changes.push(Change::Changed {item: old.name.clone(), field: "url", old: old.url, new: new.url});
+        }
+        for ((field, old_value), (_, new_value)) in old.fields.into_iter().zip(new.fields) {
+            if old_value != new_value {
+                changes.push(Change::Changed {item: old.name.clone(), field, old: old_value, new: new_value});
+            }
+        }
+    }
+    changes.extend(new.into_iter().flatten().map(|x| Change::Added {item: x.name, url: x.url}));
+}
+
+fn sources(manifest: &CytubeVideo) -> Vec<Item> {
+    manifest.sources.iter().map(|x| Item {
+        name: format!("source {}p {}", x.quality, x.content_type),
+        url: unsigned(&x.url).to_string(),
+        fields: vec![("bitrate", x.bitrate.to_string())],
+    }).collect()
+}
+
+fn audio_tracks(manifest: &CytubeVideo) -> Vec<Item> {
+    manifest.audio_tracks.iter().map(|x| Item {
+        name: format!("audio track {} {:?}", x.language, x.label),
+        url: unsigned(&x.url).to_string(),
+        fields: vec![("content type", x.content_type.clone())],
+    }).collect()
+}
+
+fn text_tracks(manifest: &CytubeVideo) -> Vec<Item> {
+    manifest.text_tracks.iter().map(|x| Item {
+        name: format!("text track {:?}", x.name),
+        url: unsigned(&x.url).to_string(),
+        fields: vec![("content type", x.content_type.clone()), ("default", x.default.to_string())],
+    }).collect()
+}
+
+/// Everything that's different in `new` from `old`, the manifest's own fields first, then the
+/// sources, audio tracks and text tracks.  Empty if the channel wouldn't see a difference.
+pub fn diff(old: &CytubeVideo, new: &CytubeVideo) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let fields = |x: &CytubeVideo| vec![
+        ("title", x.title.clone()),
+        // what cytube's told, not what was measured
+        ("duration", cytube_duration(x.duration).to_string()),
+        ("live", format!("{:?}", x.live)),
+        ("thumbnail", format!("{:?}", x.thumbnail)),
+    ];
+    for ((field, old_value), (_, new_value)) in fields(old).into_iter().zip(fields(new)) {
+        if old_value != new_value {
+            changes.push(Change::Changed {item: "manifest".to_string(), field, old: old_value, new: new_value});
+        }
+    }
+    diff_items(sources(old), sources(new), &mut changes);
+    diff_items(audio_tracks(old), audio_tracks(new), &mut changes);
+    diff_items(text_tracks(old), text_tracks(new), &mut changes);
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn manifest(changes: impl FnOnce(&mut serde_json::Value)) -> CytubeVideo {
+        let mut manifest = json!({
+            "title": "Movie",
+            "duration": 5400.2,
+            "sources": [
+                {"url": "https://cdn.example/movie/1080.mp4?Signature=a", "contentType": "video/mp4", "quality": 1080, "bitrate": 5000},
+                {"url": "https://cdn.example/movie/720.mp4?Signature=a", "contentType": "video/mp4", "quality": 720, "bitrate": 2500},
+            ],
+            "audioTracks": [
+                {"url": "https://cdn.example/movie/eng.m4a?Signature=a", "label": "English", "language": "eng", "contentType": "audio/mp4"},
+            ],
+            "textTracks": [
+                {"url": "https://cdn.example/movie/eng.vtt?Signature=a", "name": "English", "contentType": "text/vtt"},
+            ],
+        });
+        changes(&mut manifest);
+        serde_json::from_value(manifest).unwrap()
+    }
+
+    #[test]
+    fn diffing() {
+        let old = manifest(|_| {});
+        let source = || json!({"url": "https://cdn.example/movie/480.mp4", "contentType": "video/mp4", "quality": 480, "bitrate": 1000});
+        type Edit = fn(&mut serde_json::Value);
+        let cases: Vec<(Edit, Vec<Change>)> = vec![
+            (|_| {}, vec![]),
+            // signed again, and measured a little differently, but the same as far as cytube's concerned
+            (|x| {
+                for track in ["sources", "audioTracks", "textTracks"] {
+                    for x in x[track].as_array_mut().unwrap() {
+                        x["url"] = x["url"].as_str().unwrap().replace("Signature=a", "Signature=b").into();
+                    }
+                }
+                x["duration"] = 5400.7.into();
+            }, vec![]),
+            (|x| x["title"] = "Movie (2001)".into(), vec![
+                Change::Changed {item: "manifest".into(), field: "title", old: "Movie".into(), new: "Movie (2001)".into()},
+            ]),
+            (|x| x["duration"] = 5401.5.into(), vec![
+                Change::Changed {item: "manifest".into(), field: "duration", old: "5401".into(), new: "5402".into()},
+            ]),
+            (|x| { x["sources"].as_array_mut().unwrap().remove(0); }, vec![
+                Change::Removed {item: "source 1080p video/mp4".into(), url: "https://cdn.example/movie/1080.mp4".into()},
+            ]),
+            (|x| x["sources"][1]["bitrate"] = 2000.into(), vec![
+                Change::Changed {item: "source 720p video/mp4".into(), field: "bitrate", old: "2500".into(), new: "2000".into()},
+            ]),
+            (|x| x["audioTracks"][0]["url"] = "https://cdn.example/movie/eng.opus".into(), vec![
+                Change::Changed {
+                    item: "audio track eng \"English\"".into(), field: "url",
+                    old: "https://cdn.example/movie/eng.m4a".into(), new: "https://cdn.example/movie/eng.opus".into(),
+                },
+            ]),
+            // a track that's been relabeled is a different one as far as a viewer's concerned
+            (|x| x["textTracks"][0]["name"] = "English (SDH)".into(), vec![
+                Change::Removed {item: "text track \"English\"".into(), url: "https://cdn.example/movie/eng.vtt".into()},
+                Change::Added {item: "text track \"English (SDH)\"".into(), url: "https://cdn.example/movie/eng.vtt".into()},
+            ]),
+        ];
+        for (i, (edit, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(diff(&old, &manifest(edit)), expected, "case {}", i);
+        }
+        // swapping the order around isn't a change, but a new source is
+        let new = manifest(|x| {
+            x["sources"].as_array_mut().unwrap().reverse();
+            x["sources"].as_array_mut().unwrap().push(source());
+        });
+        assert_eq!(diff(&old, &new), [Change::Added {item: "source 480p video/mp4".into(), url: "https://cdn.example/movie/480.mp4".into()}]);
+    }
+}
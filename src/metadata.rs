@@ -0,0 +1,143 @@
+// Getting titles and such from somewhere other than the file itself: a database, a scraper,
+// a spreadsheet someone keeps of what's on the channel.  Implement `MetadataSource` and put it in
+// `TranscodeOptions::metadata`; it's asked about every file just before the manifest is made.
+//
+// `MetadataFile` is the simple version, a JSON or CSV file mapping filenames to metadata.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// What a `MetadataSource` knows about a file.  Anything left `None` is worked out the usual way.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metadata {
+    /// The whole title, as it should appear on the playlist.  Overrides `title_template`.
+    pub title: Option<String>,
+    /// Goes in `{episode_title}` (or after the episode number, without a template) in place of
+    /// whatever was in the filename.
+    pub episode_title: Option<String>,
+    /// In seconds.  For when the container lies about it.
+    pub duration: Option<f64>,
+    /// The URL of an image for the playlist to show.  Goes in the manifest as is.
+    pub thumbnail: Option<String>,
+}
+
+pub trait MetadataSource: Send + Sync + std::fmt::Debug {
+    /// Whatever's known about `media_file`.  A lookup that fails should say so and return `None`;
+    /// not finding a nicer title is no reason to fail a transcode.
+    fn lookup(&self, media_file: &Path) -> Option<Metadata>;
+}
+
+/// A JSON or CSV file (going by the extension) of metadata, keyed by filename.  A key with a `/`
+/// in it has to match the input's path as given; otherwise just the filename has to match.
+///
+/// JSON is an object of `Metadata` objects:
+///
+/// ```json
+/// {"Show.S02E05.1080p.mkv": {"episode_title": "The One Where", "duration": 1320.5}}
+/// ```
+///
+/// CSV has a header row naming the columns, `file` and any of `title`, `episode_title`,
+/// `duration` and `thumbnail`, in any order.  Empty cells count as not given.
+///
+/// The file's read again on every lookup, so it can be edited while the daemon's running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataFile {
+    pub path: PathBuf,
+}
+
+impl MetadataFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        MetadataFile {path: path.into()}
+    }
+
+    /// Everything in the file.
+    pub fn load(&self) -> std::io::Result<HashMap<String, Metadata>> {
+        let text = std::fs::read_to_string(&self.path)?;
+        let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", self.path.display(), e));
+        if self.path.extension().is_some_and(|x| x.eq_ignore_ascii_case("csv")) {
+            parse_csv(&text).map_err(invalid)
+        } else {
+            serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))
+        }
+    }
+}
+
+impl MetadataSource for MetadataFile {
+    fn lookup(&self, media_file: &Path) -> Option<Metadata> {
+        let mut entries = match self.load() {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("couldn't read metadata file: {}", e);
+                return None;
+            },
+        };
+        entries.remove(media_file.to_string_lossy().as_ref())
+            .or_else(|| entries.remove(media_file.file_name()?.to_string_lossy().as_ref()))
+    }
+}
+
+// one record per line, fields separated by commas, and double quotes around any field with commas,
+// quotes (doubled) or newlines in it.  that's all spreadsheets write.
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            _ if quoted => field.push(c),
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' => {},
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            },
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|x| x.iter().any(|x| !x.is_empty()));
+    Ok(records)
+}
+
+fn parse_csv(text: &str) -> Result<HashMap<String, Metadata>, String> {
+    let mut records = csv_records(text)?.into_iter();
+    let header = records.next().unwrap_or_default();
+    let column = |name: &str| header.iter().position(|x| x.trim() == name);
+    let file = column("file").ok_or("no \"file\" column")?;
+    let (title, episode_title, duration, thumbnail) = (column("title"), column("episode_title"), column("duration"), column("thumbnail"));
+
+    let mut entries = HashMap::new();
+    for (line, record) in records.enumerate() {
+        let cell = |i: Option<usize>| i.and_then(|i| record.get(i)).map(|x| x.trim()).filter(|x| !x.is_empty());
+        let Some(key) = cell(Some(file)) else {
+            continue;
+        };
+        let duration = match cell(duration) {
+            Some(x) => Some(x.parse().map_err(|_| format!("record {}: bad duration {:?}", line + 1, x))?),
+            None => None,
+        };
+        entries.insert(key.to_string(), Metadata {
+            title: cell(title).map(str::to_owned),
+            episode_title: cell(episode_title).map(str::to_owned),
+            duration,
+            thumbnail: cell(thumbnail).map(str::to_owned),
+        });
+    }
+    Ok(entries)
+}
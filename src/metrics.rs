@@ -0,0 +1,103 @@
+// Counters for the daemon, in Prometheus' text exposition format.  There are few enough of them
+// that writing the format out by hand is less work than pulling in a client library.
+
+use crate::jobs::JobStatus;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// upper bounds, in seconds, of the queue wait histogram's buckets
+const QUEUE_WAIT_BUCKETS: [f64; 8] = [1.0, 5.0, 30.0, 60.0, 300.0, 900.0, 3600.0, 14400.0];
+
+#[derive(Debug, Default)]
+struct Counters {
+    finished: [u64; 3], // done, failed, cancelled
+    media_seconds: f64,
+    output_bytes: u64,
+    queue_wait_buckets: [u64; QUEUE_WAIT_BUCKETS.len()],
+    queue_wait_count: u64,
+    queue_wait_sum: f64,
+}
+
+/// What's happening right now, as of the scrape.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub queued: usize,
+    pub running: usize,
+    /// Seconds of media encoded per second, summed over every running job.
+    pub encode_speed: f64,
+}
+
+/// Everything the daemon counts between scrapes.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Metrics {
+    /// A job got picked up after `wait` seconds in the queue.
+    pub fn job_started(&self, wait: f64) {
+        let mut counters = self.counters.lock().unwrap();
+        for (i, bound) in QUEUE_WAIT_BUCKETS.iter().enumerate() {
+            if wait <= *bound {
+                counters.queue_wait_buckets[i] += 1;
+            }
+        }
+        counters.queue_wait_count += 1;
+        counters.queue_wait_sum += wait;
+    }
+
+    /// A job ended up `status` after encoding `media_seconds` of media into `output_bytes` of
+    /// files.
+    pub fn job_finished(&self, status: JobStatus, media_seconds: f64, output_bytes: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        match status {
+            JobStatus::Done => counters.finished[0] += 1,
+            JobStatus::Failed => counters.finished[1] += 1,
+            JobStatus::Cancelled => counters.finished[2] += 1,
+            JobStatus::Queued | JobStatus::Running => {},
+        }
+        counters.media_seconds += media_seconds;
+        counters.output_bytes += output_bytes;
+    }
+
+    /// The whole page.
+    pub fn render(&self, now: &Snapshot) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut s = String::new();
+        // writing to a String can't fail
+        let _ = (|| -> std::fmt::Result {
+            writeln!(s, "# HELP cytube_jobs Jobs currently waiting or running.")?;
+            writeln!(s, "# TYPE cytube_jobs gauge")?;
+            writeln!(s, "cytube_jobs{{status=\"queued\"}} {}", now.queued)?;
+            writeln!(s, "cytube_jobs{{status=\"running\"}} {}", now.running)?;
+
+            writeln!(s, "# HELP cytube_jobs_finished_total Jobs finished since the daemon started.")?;
+            writeln!(s, "# TYPE cytube_jobs_finished_total counter")?;
+            for (status, count) in ["done", "failed", "cancelled"].iter().zip(counters.finished) {
+                writeln!(s, "cytube_jobs_finished_total{{status=\"{}\"}} {}", status, count)?;
+            }
+
+            writeln!(s, "# HELP cytube_encode_speed Seconds of media encoded per second, over all running jobs.")?;
+            writeln!(s, "# TYPE cytube_encode_speed gauge")?;
+            writeln!(s, "cytube_encode_speed {}", now.encode_speed)?;
+
+            writeln!(s, "# HELP cytube_media_seconds_total Seconds of media encoded by finished jobs.")?;
+            writeln!(s, "# TYPE cytube_media_seconds_total counter")?;
+            writeln!(s, "cytube_media_seconds_total {}", counters.media_seconds)?;
+
+            writeln!(s, "# HELP cytube_output_bytes_total Bytes written to output directories by finished jobs.")?;
+            writeln!(s, "# TYPE cytube_output_bytes_total counter")?;
+            writeln!(s, "cytube_output_bytes_total {}", counters.output_bytes)?;
+
+            writeln!(s, "# HELP cytube_queue_wait_seconds How long jobs waited in the queue before starting.")?;
+            writeln!(s, "# TYPE cytube_queue_wait_seconds histogram")?;
+            for (bound, count) in QUEUE_WAIT_BUCKETS.iter().zip(counters.queue_wait_buckets) {
+                writeln!(s, "cytube_queue_wait_seconds_bucket{{le=\"{}\"}} {}", bound, count)?;
+            }
+            writeln!(s, "cytube_queue_wait_seconds_bucket{{le=\"+Inf\"}} {}", counters.queue_wait_count)?;
+            writeln!(s, "cytube_queue_wait_seconds_sum {}", counters.queue_wait_sum)?;
+            writeln!(s, "cytube_queue_wait_seconds_count {}", counters.queue_wait_count)
+        })();
+        s
+    }
+}
@@ -0,0 +1,96 @@
+// Bringing manifests written by older versions up to date, for libraries of them that were made
+// before something about the format changed.  A manifest says which version it's in
+// (`CytubeVideo::schema`), and each migration takes it from one version to the next, on the JSON
+// so that fields can be renamed or change type along the way.
+//
+// What each version changed:
+//
+// 1. The version marker itself, and durations rounded up to whole seconds (`cytube_duration`);
+//    before, they were written as measured, and cytube could cut the last fraction of a second off.
+
+use crate::cytube_structs::{cytube_duration, CytubeVideo, SCHEMA_VERSION};
+use crate::manifest::{replace_manifest, MANIFEST_FILENAME};
+use serde_json::Value;
+use std::path::Path;
+
+// the migration from version `i` to `i + 1` is `MIGRATIONS[i]`
+const MIGRATIONS: [fn(&mut Value); SCHEMA_VERSION as usize] = [to_1];
+
+fn to_1(manifest: &mut Value) {
+    if let Some(duration) = manifest["duration"].as_f64() {
+        manifest["duration"] = cytube_duration(duration).into();
+    }
+}
+
+fn invalid(e: impl ToString) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// The version `manifest` is in.
+pub fn schema_version(manifest: &Value) -> std::io::Result<u32> {
+    match &manifest["cytubeGeneratorSchema"] {
+        Value::Null => Ok(0),
+        x => x.as_u64().and_then(|x| u32::try_from(x).ok()).ok_or_else(|| invalid(format!("bad schema version {}", x))),
+    }
+}
+
+/// `manifest`, in whatever version it's in, brought up to `SCHEMA_VERSION`.  Fails on manifests
+/// from a newer version than this one, which it can't know how to read.
+pub fn migrate(mut manifest: Value) -> std::io::Result<CytubeVideo> {
+    let version = schema_version(&manifest)?;
+    if version > SCHEMA_VERSION {
+        return Err(invalid(format!("the manifest is version {}, newer than this knows ({})", version, SCHEMA_VERSION)));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut manifest);
+    }
+    manifest["cytubeGeneratorSchema"] = SCHEMA_VERSION.into();
+    serde_json::from_value(manifest).map_err(invalid)
+}
+
+/// Brings the manifest in `outputdir` up to date, and rewrites it if it wasn't.  Returns the
+/// version it was in.
+pub fn migrate_manifest(outputdir: &Path) -> std::io::Result<u32> {
+    let manifest: Value = serde_json::from_slice(&std::fs::read(outputdir.join(MANIFEST_FILENAME))?)?;
+    let version = schema_version(&manifest)?;
+    if version < SCHEMA_VERSION {
+        replace_manifest(outputdir, &migrate(manifest)?)?;
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn manifest(schema: Value, duration: f64) -> Value {
+        json!({
+            "title": "x", "duration": duration, "sources": [], "audioTracks": [], "textTracks": [],
+            "cytubeGeneratorSchema": schema,
+        })
+    }
+
+    #[test]
+    fn schema_versions() {
+        let cases = [(Value::Null, Some(0)), (json!(0), Some(0)), (json!(1), Some(1)), (json!(-1), None), (json!("1"), None), (json!(1.5), None), (json!(1u64 << 32), None)];
+        for (schema, expected) in cases {
+            assert_eq!(schema_version(&manifest(schema.clone(), 0.0)).ok(), expected, "{}", schema);
+        }
+    }
+
+    #[test]
+    fn migrating() {
+        // (version, duration in, duration out)
+        let cases = [(Value::Null, 12.4, 13.0), (json!(0), 12.0004, 12.0), (json!(1), 12.4, 12.4)];
+        for (schema, duration, expected) in cases {
+            let migrated = migrate(manifest(schema.clone(), duration)).unwrap();
+            assert_eq!(migrated.duration, expected, "{}", schema);
+            assert_eq!(migrated.schema, SCHEMA_VERSION);
+        }
+        assert!(migrate(manifest(json!(SCHEMA_VERSION + 1), 12.0)).is_err());
+        assert!(migrate(manifest(json!("new"), 12.0)).is_err());
+        // a manifest that isn't one at all
+        assert!(migrate(json!({"title": "x"})).is_err());
+    }
+}
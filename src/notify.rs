@@ -0,0 +1,110 @@
+// Telling other things when a transcode finishes, so they don't have to poll for it.
+
+use crate::cytube_structs::CytubeVideo;
+use crate::jobs::{Job, JobId, JobStatus};
+use crate::manifest::MANIFEST_FILENAME;
+use crate::signing::{hex, hmac_sha256};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod chat;
+pub use chat::{message, Discord, Irc, Matrix};
+
+/// A finished (or failed) transcode.  This is also exactly what gets POSTed to webhooks.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEvent {
+    /// `None` for one-off transcodes that didn't go through a job queue.
+    pub job_id: Option<JobId>,
+    pub status: JobStatus,
+    pub input: String,
+    pub title: Option<String>,
+    pub duration: Option<f64>,
+    /// Where the manifest is going to be served from, i.e. what to give Cytube.
+    pub manifest_url: Option<String>,
+    pub error: Option<String>,
+}
+
+impl JobEvent {
+    /// The event for a transcode of `input` that came out with `result`.
+    pub fn new(input: &str, url_prefix: &str, result: Result<&CytubeVideo, &str>) -> Self {
+        match result {
+            Ok(manifest) => JobEvent {
+                job_id: None,
+                status: JobStatus::Done,
+                input: input.to_owned(),
+                title: Some(manifest.title.clone()),
+                duration: Some(manifest.duration),
+                manifest_url: Some(format!("{}{}", url_prefix, MANIFEST_FILENAME)),
+                error: None,
+            },
+            Err(error) => JobEvent {
+                job_id: None,
+                status: JobStatus::Failed,
+                input: input.to_owned(),
+                title: None,
+                duration: None,
+                manifest_url: None,
+                error: Some(error.to_owned()),
+            },
+        }
+    }
+
+    pub fn from_job(job: &Job) -> Self {
+        let mut event = match (&job.manifest, &job.error) {
+            (Some(manifest), _) => JobEvent::new(&job.spec.input, &job.spec.url_prefix, Ok(manifest)),
+            (None, error) => JobEvent::new(&job.spec.input, &job.spec.url_prefix, Err(error.as_deref().unwrap_or(""))),
+        };
+        event.job_id = Some(job.id);
+        event.status = job.status;
+        event
+    }
+}
+
+/// Something to tell about finished transcodes.
+pub trait Notifier: Send + Sync + std::fmt::Debug {
+    fn notify(&self, event: &JobEvent) -> std::io::Result<()>;
+}
+
+/// Sends `event` to every notifier, trying each one a few times before giving up on it.  Failures
+/// are printed and otherwise ignored: a chat bot being down is no reason to fail a transcode.
+pub fn notify_all(notifiers: &[Arc<dyn Notifier>], event: &JobEvent) {
+    for notifier in notifiers {
+        for attempt in 1..=3 {
+            match notifier.notify(event) {
+                Ok(()) => break,
+                Err(e) if attempt == 3 => eprintln!("giving up on a notification: {}", e),
+                Err(_) => std::thread::sleep(Duration::from_secs(2 * attempt)),
+            }
+        }
+    }
+}
+
+pub(crate) fn http_error(e: ureq::Error) -> std::io::Error {
+    std::io::Error::other(e.to_string())
+}
+
+/// POSTs the event as JSON to a URL.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    /// If set, the body is signed with HMAC-SHA256 using this as the key, and the signature sent
+    /// GitHub-style as `X-Signature-256: sha256=<hex>`, so the receiver can check it came from us.
+    pub secret: Option<String>,
+}
+
+impl Notifier for Webhook {
+    fn notify(&self, event: &JobEvent) -> std::io::Result<()> {
+        let body = serde_json::to_string(event)?;
+        let mut request = ureq::post(&self.url)
+            .timeout(Duration::from_secs(30))
+            .set("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            let signature = hex(&hmac_sha256(secret.as_bytes(), body.as_bytes()));
+            request = request.set("X-Signature-256", &format!("sha256={}", signature));
+        }
+        request.send_string(&body).map_err(http_error)?;
+        Ok(())
+    }
+}
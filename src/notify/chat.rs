@@ -0,0 +1,187 @@
+// Announcing finished transcodes in chat, so nobody has to keep checking the server to find out
+// whether a file's ready yet.
+
+use super::{http_error, JobEvent, Notifier};
+use crate::jobs::JobStatus;
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// One line of plain text describing the event.
+pub fn message(event: &JobEvent) -> String {
+    let mut s = match event.status {
+        JobStatus::Done => format!("Ready: {}", event.title.as_deref().unwrap_or(&event.input)),
+        status => format!("{}: {}", status, event.input),
+    };
+    if let Some(duration) = event.duration {
+        s.push_str(&format!(" ({})", format_duration(duration)));
+    }
+    if let Some(url) = &event.manifest_url {
+        s.push_str(" - ");
+        s.push_str(url);
+    }
+    if let Some(error) = &event.error {
+        s.push_str(" - ");
+        s.push_str(error);
+    }
+    s
+}
+
+/// Posts to a channel through a Discord webhook.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Discord {
+    /// `https://discord.com/api/webhooks/...`, from the channel's integration settings.
+    pub webhook_url: String,
+}
+
+impl Notifier for Discord {
+    fn notify(&self, event: &JobEvent) -> std::io::Result<()> {
+        ureq::post(&self.webhook_url)
+            .timeout(Duration::from_secs(30))
+            .send_json(serde_json::json!({"content": message(event)}))
+            .map_err(http_error)?;
+        Ok(())
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// Posts a notice to a Matrix room, as a user that's already in it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix {
+    /// e.g. `https://matrix.org`
+    pub homeserver: String,
+    /// The room's internal ID (`!abc123:example.org`), not an alias.
+    pub room_id: String,
+    pub access_token: String,
+}
+
+impl Notifier for Matrix {
+    fn notify(&self, event: &JobEvent) -> std::io::Result<()> {
+        // the transaction ID only has to be unique per access token, and it's what keeps a retried
+        // request from posting twice
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let txn = format!("{}.{}", SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos(), COUNTER.fetch_add(1, Ordering::Relaxed));
+        let url = format!("{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+                          self.homeserver.trim_end_matches('/'), percent_encode(&self.room_id), txn);
+        ureq::put(&url)
+            .timeout(Duration::from_secs(30))
+            .set("Authorization", &format!("Bearer {}", self.access_token))
+            .send_json(serde_json::json!({"msgtype": "m.notice", "body": message(event)}))
+            .map_err(http_error)?;
+        Ok(())
+    }
+}
+
+/// Connects to an IRC server, says its piece in a channel and leaves.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Irc {
+    /// `host:port`
+    pub server: String,
+    #[serde(default)]
+    pub tls: bool,
+    pub nick: String,
+    pub channel: String,
+    /// Server password (PASS).  Plenty of networks accept `nick:password` here to identify with
+    /// NickServ.
+    pub password: Option<String>,
+}
+
+fn irc_error(message: String) -> std::io::Error {
+    std::io::Error::other(message)
+}
+
+impl Irc {
+    fn session<S: Read + Write>(&self, stream: S, event: &JobEvent) -> std::io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        fn send<S: Read + Write>(stream: &mut BufReader<S>, line: &str) -> std::io::Result<()> {
+            let stream = stream.get_mut();
+            stream.write_all(line.as_bytes())?;
+            stream.write_all(b"\r\n")?;
+            stream.flush()
+        }
+
+        if let Some(password) = &self.password {
+            send(&mut stream, &format!("PASS {}", password))?;
+        }
+        send(&mut stream, &format!("NICK {}", self.nick))?;
+        send(&mut stream, &format!("USER {} 0 * :cytube-generator", self.nick))?;
+
+        // nothing can be sent to a channel until the server's welcomed us
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                return Err(irc_error(format!("{} hung up before letting us in", self.server)));
+            }
+            let line = line.trim_end();
+            if let Some(token) = line.strip_prefix("PING ") {
+                send(&mut stream, &format!("PONG {}", token))?;
+                continue;
+            }
+            let mut words = line.split(' ');
+            let command = match words.next() {
+                Some(prefix) if prefix.starts_with(':') => words.next().unwrap_or(""),
+                x => x.unwrap_or(""),
+            };
+            match command {
+                "001" => break,
+                "ERROR" | "432" | "433" | "464" | "465" => return Err(irc_error(format!("{} said: {}", self.server, line))),
+                _ => {},
+            }
+        }
+
+        // no newlines allowed, they'd end the command early
+        let text = message(event).replace(['\r', '\n'], " ");
+        send(&mut stream, &format!("JOIN {}", self.channel))?;
+        send(&mut stream, &format!("PRIVMSG {} :{}", self.channel, text))?;
+        send(&mut stream, "QUIT :done")?;
+        // wait for the server to hang up, so the message isn't lost if we close first
+        while stream.read_line(&mut line).is_ok_and(|x| x > 0) {
+            line.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Notifier for Irc {
+    fn notify(&self, event: &JobEvent) -> std::io::Result<()> {
+        let stream = TcpStream::connect(&self.server)?;
+        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+        if !self.tls {
+            return self.session(stream, event);
+        }
+        let host = self.server.rsplit_once(':').map_or(self.server.as_str(), |x| x.0);
+        let roots = rustls::RootCertStore {roots: webpki_roots::TLS_SERVER_ROOTS.to_vec()};
+        let config = rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        let name = rustls::pki_types::ServerName::try_from(host.to_owned())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let connection = rustls::ClientConnection::new(Arc::new(config), name).map_err(std::io::Error::other)?;
+        self.session(rustls::StreamOwned::new(connection, stream), event)
+    }
+}
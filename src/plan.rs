@@ -0,0 +1,592 @@
+use crate::chapters::{write_skip_markers, SkipMarker};
+use crate::cytube_structs::CytubeVideo;
+use crate::failure::Failure;
+use crate::invocation::{FfmpegInvocation, InvocationSpec};
+use crate::manifest::{finalize_manifest, has_placeholder, keep_extra_fields, read_manifest, write_manifest, MANIFEST_FILENAME};
+use crate::preview_page::write_preview_page;
+use crate::signing::SignedUrls;
+use crate::segmented::{cleanup_segments, segment_list_path, SegmentedEncode};
+use crate::size_model::{SizeGuess, SizeModel};
+use crate::storyboard::Storyboard;
+use crate::transcode_cache::TranscodeCache;
+use crate::upload::{upload_files, SegmentPipeline, Upload};
+use crate::waveform::Waveform;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Everything `transcode::plan` decided to do with a file.
+#[derive(Debug)]
+pub struct TranscodePlan {
+    pub input: PathBuf,
+    pub outputdir: PathBuf,
+    /// Where ffmpeg actually writes everything, until it's all done and gets moved into
+    /// `outputdir`.  Can be `outputdir` itself, in which case nothing's moved.
+    pub staging: PathBuf,
+    /// What `execute` does with whatever got written if it fails.
+    pub partial_outputs: PartialOutputs,
+    /// Roughly how much disk the transcode will take up at its peak, in bytes.
+    pub predicted_bytes: u64,
+    /// Transcoded outputs whose size was guessed, for `size_model` to learn from once they exist.
+    pub size_guesses: Vec<SizeGuess>,
+    pub size_model: Option<Arc<SizeModel>>,
+    /// If set, `execute` reuses an identical earlier transcode from here rather than running
+    /// anything, and saves this one for next time.
+    pub cache: Option<TranscodeCache>,
+    /// If set, the URLs in the manifest are signed as it's written.
+    pub signed_urls: Option<SignedUrls>,
+    /// If set, everything's uploaded once it's in `outputdir`.
+    pub upload: Option<Upload>,
+    /// Video segments that have to be encoded before `command` can run, if the options asked for
+    /// a segmented encode and the video needs transcoding.
+    pub segments: Option<SegmentedEncode>,
+    /// If set, `command` writes a storyboard's sprite sheets, and `execute` writes its VTT once
+    /// it knows how long the outputs really are.
+    pub storyboard: Option<Storyboard>,
+    /// If set, `execute` writes waveform peaks for each of `audio_outputs` once they're done.
+    pub waveform: Option<Waveform>,
+    /// The outputs that are just audio, by filename.
+    pub audio_outputs: Vec<String>,
+    /// Written next to the manifest, if there are any.
+    pub skip_markers: Vec<SkipMarker>,
+    /// If set, `execute` writes `preview.html` next to the manifest.
+    pub preview_page: bool,
+    /// The main ffmpeg invocation, which writes every output.
+    pub command: FfmpegInvocation,
+    /// The manifest, as best as it can be filled in before anything's been encoded.
+    pub manifest: CytubeVideo,
+}
+
+/// What to do with the files a failed (or cancelled) transcode leaves behind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartialOutputs {
+    /// Delete them.
+    #[default]
+    Delete,
+    /// Move them into `.failed/` in the output directory, replacing whatever the last failure put
+    /// there.
+    Quarantine,
+    /// Leave them where they are, for debugging.  If the plan writes to a staging directory, that's
+    /// where they'll be.
+    Keep,
+}
+
+/// Where `PartialOutputs::Quarantine` puts things, relative to the output directory.
+pub const FAILED_DIR: &str = ".failed";
+
+/// Something that can run ffmpeg commands.  Has to be `Sync` because segments get run in parallel.
+pub trait Runner: Sync {
+    /// Runs `invocation` to completion.  A non-zero exit is an error.
+    fn run(&self, invocation: &FfmpegInvocation) -> std::io::Result<()>;
+}
+
+/// Something that can encode the segments of a `SegmentedEncode`, on this machine or farmed out to
+/// others (`distributed::Scheduler`).
+pub trait SegmentRunner: Sync {
+    /// Encodes every segment of `encode`, whose scratch directory already exists, and waits for
+    /// all of them.  Anything run on this machine is run with `runner`.  Fails if any of them does.
+    fn run_segments(&self, encode: &SegmentedEncode, runner: &dyn Runner) -> std::io::Result<()>;
+}
+
+/// Encodes every segment on this machine, all at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalSegments;
+
+impl SegmentRunner for LocalSegments {
+    fn run_segments(&self, encode: &SegmentedEncode, runner: &dyn Runner) -> std::io::Result<()> {
+        std::thread::scope(|scope| {
+            let handles = (0..encode.segments.len())
+                .map(|i| scope.spawn(move || runner.run(&encode.command(i))))
+                .collect::<Vec<_>>();
+            // join all of them before bailing, so nothing's left running
+            let results = handles.into_iter().map(|x| x.join().unwrap()).collect::<Vec<_>>();
+            results.into_iter().collect::<std::io::Result<()>>()
+        })
+    }
+}
+
+/// Runs the ffmpeg CLI as a child process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CliRunner;
+
+impl Runner for CliRunner {
+    fn run(&self, invocation: &FfmpegInvocation) -> std::io::Result<()> {
+        let status = invocation.to_command().status()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!("ffmpeg exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+/// Runs the ffmpeg CLI like `CliRunner`, but keeps count of how far along it is, and can be told
+/// to stop, or to pause for a while.
+#[derive(Debug, Default)]
+pub struct ProgressRunner {
+    // seconds of output written, summed over every invocation this has run
+    processed: Mutex<f64>,
+    cancelled: AtomicBool,
+    // the ffmpegs running right now, by pid
+    children: Mutex<Vec<u32>>,
+    // when the current pause started, if it's paused, and how long it's been paused before that
+    paused: Mutex<(Option<Instant>, Duration)>,
+}
+
+impl ProgressRunner {
+    /// How many seconds of media have been written so far, across every command run.  A segmented
+    /// plan goes over the video twice (once for the segments, once to put them together), so the
+    /// total for a whole plan is the duration times the number of stages.
+    pub fn processed(&self) -> f64 {
+        *self.processed.lock().unwrap()
+    }
+
+    // sends every running ffmpeg `signal`.  std can only kill a child, and only from whoever owns
+    // it, which is the thread reading its progress
+    fn signal(&self, signal: &str) {
+        for pid in self.children.lock().unwrap().iter() {
+            let _ = Command::new("kill").arg(format!("-{}", signal)).arg(pid.to_string()).stderr(Stdio::null()).status();
+        }
+    }
+
+    /// Kills whatever's running, and fails anything run afterwards.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.signal("KILL");
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Stops whatever's running where it is (with SIGSTOP), and holds off anything run afterwards,
+    /// until `resume`.
+    pub fn pause(&self) {
+        let mut paused = self.paused.lock().unwrap();
+        if paused.0.is_none() {
+            paused.0 = Some(Instant::now());
+            self.signal("STOP");
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut paused = self.paused.lock().unwrap();
+        if let Some(since) = paused.0.take() {
+            paused.1 += since.elapsed();
+            self.signal("CONT");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.lock().unwrap().0.is_some()
+    }
+
+    /// How long it's spent paused altogether, for working out how fast it goes when it isn't.
+    pub fn paused_for(&self) -> Duration {
+        let paused = self.paused.lock().unwrap();
+        paused.1 + paused.0.map_or(Duration::ZERO, |x| x.elapsed())
+    }
+}
+
+impl Runner for ProgressRunner {
+    fn run(&self, invocation: &FfmpegInvocation) -> std::io::Result<()> {
+        let cancelled = || std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled");
+        while self.is_paused() && !self.is_cancelled() {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        if self.is_cancelled() {
+            return Err(cancelled());
+        }
+        let mut invocation = invocation.clone();
+        invocation.global_arg("-nostats").global_arg("-progress").global_arg("pipe:1");
+        let mut child = {
+            // held while it starts, so a pause or cancel can't come in between the spawn and
+            // it being in the list
+            let mut children = self.children.lock().unwrap();
+            let child = invocation.to_command().stdout(Stdio::piped()).spawn()?;
+            children.push(child.id());
+            child
+        };
+        // in case one came in between the checks above and the spawn.  the pause lock's held so a
+        // resume can't come in between this and the signal
+        let paused = self.paused.lock().unwrap();
+        if self.is_cancelled() {
+            let _ = child.kill();
+        } else if paused.0.is_some() {
+            let _ = Command::new("kill").arg("-STOP").arg(child.id().to_string()).status();
+        }
+        drop(paused);
+        let forget = |pid: u32| self.children.lock().unwrap().retain(|x| *x != pid);
+
+        // ffmpeg writes a block of key=value lines every half a second or so
+        let mut last = 0.0;
+        for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+            let Ok(line) = line else { break };
+            if let Some(Ok(us)) = line.strip_prefix("out_time_us=").map(|x| x.parse::<i64>()) {
+                let time = us as f64 / 1e6;
+                if time > last {
+                    *self.processed.lock().unwrap() += time - last;
+                    last = time;
+                }
+            }
+        }
+        let status = child.wait();
+        forget(child.id());
+        let status = status?;
+        if self.is_cancelled() {
+            return Err(cancelled());
+        }
+        if !status.success() {
+            return Err(std::io::Error::other(format!("ffmpeg exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+/// How many ffmpegs can use each hardware device at once.  Consumer NVIDIA cards only allow a
+/// handful of sessions, and VA-API drivers fall over past some number too, and either way the
+/// ffmpeg that doesn't get one just fails.
+///
+/// Devices are named by their `-hwaccel_device` if the command has one (e.g.
+/// `/dev/dri/renderD128`), or else by the `-hwaccel` itself (`vaapi`, `cuda`, `auto`).  Encodes
+/// count too: a VA-API encode by its device, and NVENC as `nvenc`, which is what consumer cards
+/// actually limit.  Devices that aren't listed aren't limited.
+#[derive(Debug, Default)]
+pub struct DeviceLimits {
+    limits: HashMap<String, usize>,
+    in_use: Mutex<HashMap<String, usize>>,
+    freed: Condvar,
+}
+
+impl DeviceLimits {
+    pub fn new(limits: HashMap<String, usize>) -> Self {
+        DeviceLimits {limits, ..Default::default()}
+    }
+
+    // the device `invocation` decodes or encodes on, if it's limited
+    fn device(&self, invocation: &FfmpegInvocation) -> Option<String> {
+        let mut hwaccel = None;
+        let mut device = None;
+        for input in &invocation.inputs {
+            for pair in input.args.windows(2) {
+                match pair[0].to_str() {
+                    Some("-hwaccel") => hwaccel = Some(pair[1].to_string_lossy().into_owned()),
+                    Some("-hwaccel_device") => device = Some(pair[1].to_string_lossy().into_owned()),
+                    _ => {},
+                }
+            }
+        }
+        // `-init_hw_device vaapi=enc:/dev/dri/renderD128`
+        let encoder_device = invocation.global_args.windows(2)
+            .find(|pair| pair[0] == "-init_hw_device")
+            .and_then(|pair| pair[1].to_str()?.split_once(':').map(|x| x.1.to_string()));
+        let nvenc = invocation.outputs.iter().any(|x| x.args.iter().any(|x| x == "h264_nvenc"));
+        let decoder = device.or(hwaccel).filter(|x| x != "none");
+        [decoder, encoder_device, nvenc.then(|| "nvenc".to_string())].into_iter().flatten()
+            .find(|x| self.limits.contains_key(x))
+    }
+
+    fn acquire(&self, device: &str) {
+        let limit = self.limits[device].max(1);
+        let mut in_use = self.in_use.lock().unwrap();
+        while in_use.get(device).copied().unwrap_or(0) >= limit {
+            in_use = self.freed.wait(in_use).unwrap();
+        }
+        *in_use.entry(device.to_owned()).or_default() += 1;
+    }
+
+    fn release(&self, device: &str) {
+        *self.in_use.lock().unwrap().get_mut(device).unwrap() -= 1;
+        self.freed.notify_all();
+    }
+}
+
+/// Runs commands with another runner, but waits for a free session on the hardware device each one
+/// uses first.  Share one `DeviceLimits` between everything that might run at the same time.
+pub struct LimitedRunner<'a> {
+    pub inner: &'a dyn Runner,
+    pub limits: &'a DeviceLimits,
+}
+
+impl Runner for LimitedRunner<'_> {
+    fn run(&self, invocation: &FfmpegInvocation) -> std::io::Result<()> {
+        let Some(device) = self.limits.device(invocation) else {
+            return self.inner.run(invocation);
+        };
+        self.limits.acquire(&device);
+        let result = self.inner.run(invocation);
+        self.limits.release(&device);
+        result
+    }
+}
+
+impl TranscodePlan {
+    /// Every ffmpeg the plan runs, as plain data: the segment encodes, if there are any, then the
+    /// main command.
+    pub fn invocations(&self) -> Vec<InvocationSpec> {
+        let segments = self.segments.iter().flat_map(|x| (0..x.segments.len()).map(|i| x.command(i)));
+        segments.chain([self.command.clone()]).map(|x| x.spec()).collect()
+    }
+
+    /// Every command in the plan, in the order they have to run: the segment encodes first (these
+    /// don't depend on each other and can run at the same time), then the main command.  The
+    /// segments' scratch directory has to exist first; see `SegmentedEncode::prepare`.
+    pub fn to_commands(&self) -> Vec<FfmpegInvocation> {
+        let mut commands = Vec::new();
+        if let Some(segments) = &self.segments {
+            commands.extend((0..segments.segments.len()).map(|i| segments.command(i)));
+        }
+        commands.push(self.command.clone());
+        commands
+    }
+
+    /// Runs the whole plan with `runner`, then writes the manifest and fills it in from the actual
+    /// outputs.  If anything fails, no manifest gets written.  Everything's written to `staging`
+    /// first and moved into `outputdir` at the end, manifest last, so a web server pointed at
+    /// `outputdir` never sees a half-finished transcode.  Then it's all uploaded, if the plan
+    /// says to, manifest last; CMAF segments are uploaded while the rest are still being encoded.
+    /// With the `upload` feature, the upload fails if the files aren't served right afterwards.
+    ///
+    /// Segments, if there are any, are encoded on this machine; see `execute_with`.
+    pub fn execute(self, runner: &dyn Runner) -> std::io::Result<CytubeVideo> {
+        self.execute_with(runner, &LocalSegments)
+    }
+
+    /// `execute`, with the segments encoded by `segments`, e.g. a `distributed::Scheduler` that
+    /// sends them to other machines.  They're encoded into the staging directory once it's been
+    /// cleared out, so they have to be encoded as part of this rather than beforehand.
+    pub fn execute_with(self, runner: &dyn Runner, segments: &dyn SegmentRunner) -> std::io::Result<CytubeVideo> {
+        if self.staging != self.outputdir {
+            // whatever an earlier failed run left behind would get published along with this one
+            match std::fs::remove_dir_all(&self.staging) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {},
+            }
+        }
+        std::fs::create_dir_all(&self.staging)?;
+        // inputs that aren't files (URLs, mostly) just don't get cached.  neither does anything
+        // written straight into the output directory, where it can't be told apart from whatever
+        // else is in there
+        let cached = self.cache.as_ref()
+            .filter(|_| self.staging != self.outputdir)
+            .and_then(|cache| Some((cache, cache.key(&self).ok()?)));
+        let hit = match cached {
+            Some((cache, ref key)) => cache.fetch(key, &self.staging)?,
+            None => false,
+        };
+        // CMAF segments start uploading as soon as they're written, if there's an upload
+        let pipeline = self.upload.as_ref().filter(|_| !hit).map(|x| SegmentPipeline::new(x, &self.staging));
+        let result = std::thread::scope(|scope| {
+            if let Some(pipeline) = &pipeline {
+                scope.spawn(|| pipeline.run());
+            }
+            let result = if hit { Ok(()) } else { self.run_commands(runner, segments) };
+            if let Some(pipeline) = &pipeline {
+                pipeline.finish();
+            }
+            result
+        });
+        if let Err(e) = result {
+            if let Err(cleanup) = self.clean_up_failure() {
+                eprintln!("couldn't clean up after a failed transcode: {}", cleanup);
+            }
+            return Err(Failure::Encode.wrap(e));
+        }
+        cleanup_segments(&self.staging)?;
+        if let Some((cache, key)) = cached.filter(|_| !hit) {
+            if let Err(e) = cache.store(&key, &self.staging) {
+                eprintln!("couldn't cache the transcode: {}", e);
+            }
+        }
+
+        // a manifest that's being replaced might have had things added to it by hand
+        let mut manifest = self.manifest.clone();
+        if let Ok(old) = read_manifest(&self.outputdir) {
+            keep_extra_fields(&old, &mut manifest);
+        }
+        write_manifest(&self.staging, &manifest)?;
+        let mut manifest = finalize_manifest(&self.staging)?;
+        if let Some(storyboard) = &self.storyboard {
+            storyboard.write_vtt(&self.staging, manifest.duration)?;
+        }
+        if let Some(waveform) = &self.waveform {
+            waveform.write_peaks(&self.staging, &self.audio_outputs)?;
+        }
+        if !self.skip_markers.is_empty() {
+            write_skip_markers(&self.staging, &self.skip_markers)?;
+        }
+        // after finalizing, which needs the plain URLs to find the files.  URLs that are still
+        // placeholders get signed once they're real
+        if let Some(signed_urls) = self.signed_urls.as_ref().filter(|_| !has_placeholder(&manifest)) {
+            manifest = signed_urls.sign_manifest(&manifest)?;
+            write_manifest(&self.staging, &manifest)?;
+        }
+        if self.preview_page {
+            write_preview_page(&self.staging, &manifest)?;
+        }
+        if let Some(model) = self.size_model.as_ref().filter(|_| !hit) {
+            model.record_outputs(&self.staging, &self.size_guesses, manifest.duration);
+            if let Err(e) = model.save() {
+                eprintln!("couldn't save the size model: {}", e);
+            }
+        }
+        if self.staging != self.outputdir {
+            publish(&self.staging, &self.outputdir)?;
+        }
+        if let Some(upload) = &self.upload {
+            let uploaded = pipeline.map(|x| x.uploaded()).unwrap_or_default();
+            let uploading = match uploaded.is_empty() {
+                true => upload.uploader.upload_dir(&self.outputdir, &upload.remote_dir, &upload.throttle),
+                false => upload_files(&*upload.uploader, &self.outputdir, &upload.remote_dir, &upload.throttle, &uploaded),
+            };
+            uploading.map_err(|e| Failure::Upload.wrap(e))?;
+            #[cfg(feature = "upload")]
+            crate::upload::verify_upload(&self.outputdir, &manifest, &format!("{}{}", upload.url_prefix(), MANIFEST_FILENAME))
+                .map_err(|e| Failure::Validation.wrap(e))?;
+        }
+        Ok(manifest)
+    }
+
+    // runs the segments, then the main command
+    fn run_commands(&self, runner: &dyn Runner, segment_runner: &dyn SegmentRunner) -> std::io::Result<()> {
+        if let Some(segments) = &self.segments {
+            segments.prepare()?;
+            segment_runner.run_segments(segments, runner)?;
+        }
+        runner.run(&self.command)
+    }
+
+    // deals with the partial outputs according to `partial_outputs`
+    fn clean_up_failure(&self) -> std::io::Result<()> {
+        let quarantine = self.outputdir.join(FAILED_DIR);
+        if self.partial_outputs == PartialOutputs::Quarantine {
+            match std::fs::remove_dir_all(&quarantine) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {},
+            }
+        }
+        if self.staging != self.outputdir {
+            // everything in the staging directory is ours
+            return match self.partial_outputs {
+                PartialOutputs::Delete => std::fs::remove_dir_all(&self.staging),
+                PartialOutputs::Quarantine => {
+                    std::fs::create_dir_all(&self.outputdir)?;
+                    std::fs::rename(&self.staging, &quarantine)
+                },
+                PartialOutputs::Keep => Ok(()),
+            };
+        }
+
+        // writing straight into the output directory, where there might be other things, so only
+        // touch what the plan says it writes
+        match self.partial_outputs {
+            PartialOutputs::Delete => cleanup_segments(&self.outputdir)?,
+            PartialOutputs::Quarantine => std::fs::create_dir_all(&quarantine)?,
+            PartialOutputs::Keep => return Ok(()),
+        }
+        let mut paths: Vec<PathBuf> = self.command.outputs.iter().map(|x| PathBuf::from(&x.path)).collect();
+        if self.segments.is_some() {
+            paths.push(segment_list_path(&self.outputdir).parent().unwrap().to_owned());
+        }
+        for path in paths.iter().filter(|x| x.exists()) {
+            match self.partial_outputs {
+                PartialOutputs::Quarantine => std::fs::rename(path, quarantine.join(path.file_name().unwrap()))?,
+                _ if path.is_dir() => std::fs::remove_dir_all(path)?,
+                _ => std::fs::remove_file(path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// moves everything in `staging` into `outputdir`, replacing whatever's there by the same name, and
+// then removes `staging`.  each rename is atomic, and the manifest goes last, so anything reading
+// the manifest only ever sees files that are completely written.
+fn publish(staging: &Path, outputdir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(outputdir)?;
+    for entry in std::fs::read_dir(staging)? {
+        let entry = entry?;
+        if entry.file_name() == MANIFEST_FILENAME {
+            continue;
+        }
+        let target = outputdir.join(entry.file_name());
+        // rename() won't replace a directory that has anything in it
+        if entry.file_type()?.is_dir() && target.is_dir() {
+            std::fs::remove_dir_all(&target)?;
+        }
+        std::fs::rename(entry.path(), target)?;
+    }
+    std::fs::rename(staging.join(MANIFEST_FILENAME), outputdir.join(MANIFEST_FILENAME))?;
+    std::fs::remove_dir(staging)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ffprobe::FFprobeResult;
+    use crate::transcode::{plan, TranscodeOptions};
+
+    /// Pretends to be ffmpeg: writes something to every output, after checking that the segments
+    /// a concat input lists are there to be read.
+    #[derive(Debug, Default)]
+    pub(crate) struct FakeRunner {
+        pub(crate) runs: Mutex<Vec<FfmpegInvocation>>,
+    }
+
+    impl Runner for FakeRunner {
+        fn run(&self, invocation: &FfmpegInvocation) -> std::io::Result<()> {
+            for input in invocation.inputs.iter().map(|x| Path::new(&x.path)).filter(|x| x.ends_with("segments.txt")) {
+                for line in std::fs::read_to_string(input)?.lines() {
+                    let segment = input.with_file_name(line.trim_start_matches("file '").trim_end_matches('\''));
+                    if !segment.is_file() {
+                        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} isn't there", segment.display())));
+                    }
+                }
+            }
+            for output in &invocation.outputs {
+                std::fs::write(&output.path, "not really a video")?;
+            }
+            self.runs.lock().unwrap().push(invocation.clone());
+            Ok(())
+        }
+    }
+
+    /// A plan for a WMV (which has to be transcoded) into `<temp>/<name>/out`, in 3 segments.
+    /// Whatever an earlier run left behind is cleared out first.
+    pub(crate) fn segmented_plan(name: &str) -> TranscodePlan {
+        let dir = std::env::temp_dir().join("cytube-generator-plan-tests").join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let probe: FFprobeResult = serde_json::from_value(serde_json::json!({
+            "tracks": [
+                {"index": 0, "kind": "Video", "codec": "wmv3", "scanline_count": 480, "frame_rate": 29.97},
+                {"index": 1, "kind": "Audio", "codec": "wmav2", "channels": 2},
+            ],
+            "title": "Home Movie", "duration": 600.0, "bitrate": 1500,
+        })).unwrap();
+        let options = TranscodeOptions {parallel_segments: 3, ..Default::default()};
+        plan(&dir.join("home movie.wmv"), &probe, &dir.join("out"), "http://example.com/", &options)
+    }
+
+    #[test]
+    fn segmented_plans_run_through() {
+        let plan = segmented_plan("segmented_plans_run_through");
+        let (outputdir, staging) = (plan.outputdir.clone(), plan.staging.clone());
+        assert_eq!(plan.segments.as_ref().map(|x| x.segments.len()), Some(3));
+        // left over from a run that died
+        std::fs::create_dir_all(staging.join(".segments")).unwrap();
+        std::fs::write(staging.join(".segments/part_000.mkv"), "stale").unwrap();
+
+        let runner = FakeRunner::default();
+        let manifest = plan.execute(&runner).unwrap();
+        // the segments, then the command that puts them together
+        assert_eq!(runner.runs.lock().unwrap().len(), 4);
+        assert_eq!(manifest.sources.len(), 1);
+        assert!(outputdir.join(MANIFEST_FILENAME).is_file());
+        assert!(!staging.exists());
+        assert!(!outputdir.join(".segments").exists());
+    }
+}
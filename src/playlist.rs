@@ -0,0 +1,52 @@
+// An ordered list of transcodes, e.g. a season of a show, for queueing up in one go.
+
+use crate::cytube_structs::CytubeVideo;
+use crate::manifest::MANIFEST_FILENAME;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub const PLAYLIST_FILENAME: &str = "playlist.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub title: String,
+    pub duration: f64,
+    /// What to give Cytube.
+    pub manifest_url: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    pub entries: Vec<PlaylistEntry>,
+}
+
+impl Playlist {
+    /// Adds a transcode whose outputs are served from `url_prefix`.
+    pub fn push(&mut self, manifest: &CytubeVideo, url_prefix: &str) {
+        self.entries.push(PlaylistEntry {
+            title: manifest.title.clone(),
+            duration: manifest.duration,
+            manifest_url: format!("{}{}", url_prefix, MANIFEST_FILENAME),
+        });
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        self.entries.iter().map(|x| x.manifest_url.clone()).collect()
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.entries.iter().map(|x| x.duration).sum()
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut f, self)?;
+        f.flush()
+    }
+
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
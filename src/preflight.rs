@@ -0,0 +1,127 @@
+// Checking a manifest's URLs the way Cytube's player will use them, since when it can't, all
+// anyone sees is a video that won't load.  What the player needs:
+//
+// - HTTPS everywhere: Cytube is served over HTTPS, and browsers block mixed content.
+// - CORS headers on the media: the player loads it with `crossorigin`, so text tracks (and
+//   anything going through MSE) only work if the host says the channel's origin is allowed.
+//   The manifest itself is fetched by Cytube's server, so it doesn't need them.
+// - Range requests on audio and video, or seeking doesn't work.
+// - The content type the manifest says.  .vtt served as text/plain is the classic.
+
+use crate::cytube_structs::CytubeVideo;
+
+/// Where the player's requests come from, unless told otherwise.
+pub const DEFAULT_ORIGIN: &str = "https://cytu.be";
+
+/// What fetching the first byte of a URL turned up.
+#[derive(Debug, Clone, Default)]
+pub struct Fetched {
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// The whole length, from Content-Range (or Content-Length, if the range was ignored).
+    pub length: Option<u64>,
+    /// Whether the range was honoured.
+    pub ranges: bool,
+    /// Access-Control-Allow-Origin, if there was one.
+    pub allow_origin: Option<String>,
+}
+
+/// Fetches the first byte of `url`, as a request from `origin`.  A ranged GET rather than a HEAD,
+/// since presigned S3 URLs are only good for GETs.  The error says what went wrong, including
+/// the URL not giving a 2xx.
+pub fn fetch(url: &str, origin: &str) -> Result<Fetched, String> {
+    let request = ureq::get(url)
+        .set("Range", "bytes=0-0")
+        .set("Accept-Encoding", "identity")
+        .set("Origin", origin);
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => return Err(format!("gives {}", status)),
+        Err(e) => return Err(format!("can't be reached: {}", e)),
+    };
+    let ranges = response.status() == 206;
+    let length = match ranges {
+        true => response.header("Content-Range").and_then(|x| x.rsplit_once('/')?.1.parse().ok()),
+        false => response.header("Content-Length").and_then(|x| x.parse().ok()),
+    };
+    Ok(Fetched {
+        status: response.status(),
+        content_type: response.header("Content-Type").map(str::to_owned),
+        length,
+        ranges,
+        allow_origin: response.header("Access-Control-Allow-Origin").map(str::to_owned),
+    })
+}
+
+/// Whether a served content type is close enough to the one the manifest says.  Parameters
+/// (`; charset=...`) and case don't matter, HLS playlists have two names, and browsers don't
+/// mind audio/ and video/ being mixed up for the same container.
+pub fn same_type(served: &str, expected: &str) -> bool {
+    let essence = |x: &str| x.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let (served, expected) = (essence(served), essence(expected));
+    let hls = ["application/x-mpegurl", "application/vnd.apple.mpegurl"];
+    let subtype = |x: &str| x.strip_prefix("audio/").or_else(|| x.strip_prefix("video/")).map(str::to_owned);
+    served == expected
+        || (hls.contains(&served.as_str()) && hls.contains(&expected.as_str()))
+        || subtype(&served).is_some_and(|x| Some(x) == subtype(&expected))
+}
+
+/// How one URL fared.
+#[derive(Debug, Clone)]
+pub struct UrlReport {
+    pub url: String,
+    /// What the manifest says it is.
+    pub content_type: String,
+    /// Everything wrong with it.  Empty if it's fine.
+    pub problems: Vec<String>,
+}
+
+// everything wrong with `url` for the player.  `media` is whether it's audio or video, which
+// needs range requests; playlists and subtitles are fetched whole.  `cors` is whether the browser
+// fetches it at all, rather than Cytube's server
+fn check(url: &str, content_type: &str, origin: &str, media: bool, cors: bool) -> UrlReport {
+    let mut problems = Vec::new();
+    if !url.starts_with("https://") {
+        problems.push("isn't HTTPS".to_string());
+    }
+    match fetch(url, origin) {
+        Err(e) => problems.push(e),
+        Ok(fetched) => {
+            match fetched.content_type.as_deref() {
+                Some(served) if same_type(served, content_type) => {},
+                served => problems.push(format!("is served as {:?}, not {}", served.unwrap_or(""), content_type)),
+            }
+            if media && !fetched.ranges {
+                problems.push("ignores range requests, so it can't be seeked".to_string());
+            }
+            match fetched.allow_origin.as_deref() {
+                _ if !cors => {},
+                Some(allowed) if allowed == "*" || allowed.trim_end_matches('/') == origin.trim_end_matches('/') => {},
+                Some(allowed) => problems.push(format!("only allows {} (Access-Control-Allow-Origin), not {}", allowed, origin)),
+                None => problems.push("has no Access-Control-Allow-Origin header".to_string()),
+            }
+        },
+    }
+    UrlReport {url: url.to_owned(), content_type: content_type.to_owned(), problems}
+}
+
+/// Checks the manifest at `manifest_url`, and every URL in it, for everything Cytube's player
+/// needs, as a channel on `origin` (like `"https://cytu.be"`).  The error is for the manifest not
+/// being fetchable or readable at all; anything else is in the reports, the manifest's first.
+pub fn preflight(manifest_url: &str, origin: &str) -> std::io::Result<Vec<UrlReport>> {
+    let mut reports = vec![check(manifest_url, "application/json", origin, false, false)];
+    let manifest: CytubeVideo = ureq::get(manifest_url).call()
+        .map_err(|e| std::io::Error::other(format!("couldn't fetch the manifest: {}", e)))?
+        .into_json()?;
+
+    let media = manifest.sources.iter().map(|x| (&x.url, &x.content_type))
+        .chain(manifest.audio_tracks.iter().map(|x| (&x.url, &x.content_type)));
+    for (url, content_type) in media {
+        let media = content_type.starts_with("video/") || content_type.starts_with("audio/");
+        reports.push(check(url, content_type, origin, media, true));
+    }
+    for track in &manifest.text_tracks {
+        reports.push(check(&track.url, &track.content_type, origin, false, true));
+    }
+    Ok(reports)
+}
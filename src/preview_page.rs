@@ -0,0 +1,97 @@
+// `preview.html`: a page next to the manifest that plays the outputs in a plain <video>, to check
+// them in a browser before queueing them on the channel.  It works opened straight off the disk as
+// well as served from wherever the outputs end up, since everything that's in the output directory
+// is referred to by its bare filename.
+//
+// Browsers don't switch between separate audio files by themselves, so when there are audio
+// tracks a second, hidden <audio> plays the one that's picked, and a few lines of script keep it
+// in step with the video.
+
+use crate::cytube_structs::CytubeVideo;
+use crate::manifest::local_file;
+use std::fmt::Write;
+use std::path::Path;
+
+pub const PREVIEW_PAGE_FILENAME: &str = "preview.html";
+
+// also used by `serve`'s player page, which has the same elements
+pub(crate) const AUDIO_SYNC_SCRIPT: &str = r#"
+const video = document.getElementById("video");
+const audio = document.getElementById("audio");
+const picker = document.getElementById("audio-track");
+function pick() {
+    audio.pause();
+    video.muted = picker.value !== "";
+    if (picker.value === "") {
+        audio.removeAttribute("src");
+        return;
+    }
+    audio.src = picker.value;
+    audio.currentTime = video.currentTime;
+    if (!video.paused) audio.play();
+}
+picker.addEventListener("change", pick);
+video.addEventListener("play", () => { if (picker.value !== "") { audio.currentTime = video.currentTime; audio.play(); } });
+video.addEventListener("pause", () => audio.pause());
+video.addEventListener("seeked", () => { audio.currentTime = video.currentTime; });
+video.addEventListener("ratechange", () => { audio.playbackRate = video.playbackRate; });
+video.addEventListener("volumechange", () => { audio.volume = video.volume; });
+// only nudge it when it's drifted far enough to hear
+video.addEventListener("timeupdate", () => {
+    if (picker.value !== "" && Math.abs(audio.currentTime - video.currentTime) > 0.3) audio.currentTime = video.currentTime;
+});
+pick();
+"#;
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+// where the page finds `url`: by its filename if it's in `outputdir`, so the page works wherever
+// it's opened from, and where the manifest says otherwise
+fn page_url(outputdir: &Path, url: &str) -> String {
+    match local_file(outputdir, url).as_deref().and_then(Path::file_name) {
+        Some(filename) => escape_html(&filename.to_string_lossy()),
+        None => escape_html(url),
+    }
+}
+
+/// The preview page for `manifest`, whose outputs are in `outputdir`.
+pub fn preview_page(outputdir: &Path, manifest: &CytubeVideo) -> String {
+    let title = escape_html(&manifest.title);
+    let mut page = String::new();
+    writeln!(page, "<!DOCTYPE html>").unwrap();
+    writeln!(page, "<html><head><meta charset=\"utf-8\"><title>{}</title>", title).unwrap();
+    writeln!(page, "<style>body {{ background: #111; color: #ddd; font-family: sans-serif; }} video {{ width: 100%; max-height: 85vh; }}</style>").unwrap();
+    writeln!(page, "</head><body>").unwrap();
+    writeln!(page, "<h1>{}</h1>", title).unwrap();
+    writeln!(page, "<video id=\"video\" controls preload=\"metadata\">").unwrap();
+    // browsers play the first one they can, so best first
+    let mut sources = manifest.sources.iter().collect::<Vec<_>>();
+    sources.sort_by_key(|x| std::cmp::Reverse(x.quality));
+    for source in sources {
+        writeln!(page, "<source src=\"{}\" type=\"{}\">", page_url(outputdir, &source.url), escape_html(&source.content_type)).unwrap();
+    }
+    for track in &manifest.text_tracks {
+        writeln!(page, "<track kind=\"subtitles\" src=\"{}\" label=\"{}\"{}>", page_url(outputdir, &track.url), escape_html(&track.name),
+            if track.default { " default" } else { "" }).unwrap();
+    }
+    writeln!(page, "</video>").unwrap();
+    if !manifest.audio_tracks.is_empty() {
+        writeln!(page, "<p><label>Audio: <select id=\"audio-track\">").unwrap();
+        for track in &manifest.audio_tracks {
+            writeln!(page, "<option value=\"{}\">{} ({})</option>", page_url(outputdir, &track.url), escape_html(&track.label), escape_html(&track.language)).unwrap();
+        }
+        writeln!(page, "<option value=\"\">The video's own</option>").unwrap();
+        writeln!(page, "</select></label></p>").unwrap();
+        writeln!(page, "<audio id=\"audio\" preload=\"auto\"></audio>").unwrap();
+        writeln!(page, "<script>{}</script>", AUDIO_SYNC_SCRIPT).unwrap();
+    }
+    writeln!(page, "</body></html>").unwrap();
+    page
+}
+
+/// Writes `manifest`'s preview page to `outputdir`, where its outputs are.
+pub fn write_preview_page(outputdir: &Path, manifest: &CytubeVideo) -> std::io::Result<()> {
+    std::fs::write(outputdir.join(PREVIEW_PAGE_FILENAME), preview_page(outputdir, manifest))
+}
@@ -0,0 +1,175 @@
+// Caching ffprobe results, so going over a big library a second time doesn't mean probing
+// thousands of files again.
+//
+// Each entry is a JSON file in the cache directory, named after a hash of its key.  Entries carry
+// the size and mtime they were made from, so a file that's changed since gets probed again and the
+// stale entry overwritten; nothing needs cleaning up by hand unless you want the disk space back.
+
+use crate::ffprobe::{ffprobe, FFprobeResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// how much of each end of the file goes into a content key
+const QUICK_HASH_CHUNK: u64 = 1 << 20;
+
+// bump this whenever `FFprobeResult` gains a field (or probing starts filling one in differently).
+// the new fields are `#[serde(default)]`, so older entries would still read, just with them empty,
+// and e.g. an MPEG-TS file would look like it has no programs.  entries from another version are
+// misses instead.
+const ENTRY_VERSION: u32 = 1;
+
+/// What identifies a file for caching purposes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheKey {
+    /// Its absolute path.  Free to compute, but renaming or moving a file loses its entry.
+    #[default]
+    Path,
+    /// A hash of its size and the first and last megabyte of it.  Survives renames and moves, at
+    /// the cost of reading 2 MB per lookup.  Not a hash of the whole file: a file that's been
+    /// edited in the middle without changing size or mtime will get a stale result.
+    Content,
+}
+
+/// A directory full of cached ffprobe results.
+#[derive(Debug, Clone)]
+pub struct ProbeCache {
+    pub dir: PathBuf,
+    pub key: CacheKey,
+    /// Entries older than this get probed again even if the file looks unchanged.
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    // entries from before there was one are 0
+    #[serde(default)]
+    version: u32,
+    size: u64,
+    mtime: u64, // in ns since the epoch
+    probed_at: u64, // in seconds since the epoch
+    result: FFprobeResult,
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+// feeds `CacheKey::Content`'s idea of a file's identity into `hasher`
+pub(crate) fn quick_hash(hasher: &mut Sha256, path: &Path, size: u64) -> std::io::Result<()> {
+    let mut f = File::open(path)?;
+    let mut buf = Vec::new();
+    hasher.update(size.to_le_bytes());
+    (&mut f).take(QUICK_HASH_CHUNK).read_to_end(&mut buf)?;
+    if size > QUICK_HASH_CHUNK {
+        f.seek(SeekFrom::Start(size.saturating_sub(QUICK_HASH_CHUNK).max(QUICK_HASH_CHUNK)))?;
+        f.take(QUICK_HASH_CHUNK).read_to_end(&mut buf)?;
+    }
+    hasher.update(&buf);
+    Ok(())
+}
+
+impl ProbeCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ProbeCache {dir: dir.into(), key: CacheKey::default(), max_age: None}
+    }
+
+    /// `$XDG_CACHE_HOME/cytube-generator/probe`, falling back on `~/.cache`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(dir.join("cytube-generator").join("probe"))
+    }
+
+    fn entry_path(&self, path: &Path, size: u64) -> std::io::Result<PathBuf> {
+        let mut hasher = Sha256::new();
+        match self.key {
+            CacheKey::Path => hasher.update(path.canonicalize()?.as_os_str().as_encoded_bytes()),
+            CacheKey::Content => quick_hash(&mut hasher, path, size)?,
+        }
+        Ok(self.dir.join(hex(&hasher.finalize())).with_extension("json"))
+    }
+
+    /// The cached result for `path`, if there's one and it's still good.
+    pub fn get(&self, path: &Path) -> std::io::Result<Option<FFprobeResult>> {
+        let metadata = path.metadata()?;
+        let entry_path = self.entry_path(path, metadata.len())?;
+        let entry: Entry = match fs::read(&entry_path) {
+            Ok(x) => match serde_json::from_slice(&x) {
+                Ok(x) => x,
+                // mangled somehow.  treat it as a miss and let it get overwritten.
+                Err(_) => return Ok(None),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // written by another version, and missing what this one probes for
+        if entry.version != ENTRY_VERSION {
+            return Ok(None);
+        }
+        if entry.size != metadata.len() || entry.mtime != since_epoch(metadata.modified()?).as_nanos() as u64 {
+            return Ok(None);
+        }
+        if let Some(max_age) = self.max_age {
+            if since_epoch(SystemTime::now()).as_secs().saturating_sub(entry.probed_at) > max_age.as_secs() {
+                return Ok(None);
+            }
+        }
+        Ok(Some(entry.result))
+    }
+
+    /// Stores `result` as the probe result for `path`.
+    pub fn put(&self, path: &Path, result: &FFprobeResult) -> std::io::Result<()> {
+        let metadata = path.metadata()?;
+        let entry_path = self.entry_path(path, metadata.len())?;
+        let entry = Entry {
+            version: ENTRY_VERSION,
+            size: metadata.len(),
+            mtime: since_epoch(metadata.modified()?).as_nanos() as u64,
+            probed_at: since_epoch(SystemTime::now()).as_secs(),
+            result: result.clone(),
+        };
+        fs::create_dir_all(&self.dir)?;
+        // write and rename, so a concurrent reader never sees half an entry
+        let tmp = entry_path.with_extension("json.tmp");
+        File::create(&tmp)?.write_all(&serde_json::to_vec(&entry)?)?;
+        fs::rename(tmp, entry_path)
+    }
+
+    /// Like `ffprobe::ffprobe`, but goes through the cache.
+    pub fn probe(&self, path: &Path) -> std::io::Result<FFprobeResult> {
+        if let Some(result) = self.get(path)? {
+            return Ok(result);
+        }
+        let result = ffprobe(path)?;
+        // failing to cache isn't worth failing the probe over
+        let _ = self.put(path, &result);
+        Ok(result)
+    }
+
+    /// Forgets `path`, so the next lookup probes it again.
+    pub fn invalidate(&self, path: &Path) -> std::io::Result<()> {
+        let entry_path = self.entry_path(path, path.metadata()?.len())?;
+        match fs::remove_file(entry_path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            x => x,
+        }
+    }
+
+    /// Empties the cache.
+    pub fn clear(&self) -> std::io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            x => x,
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// Making sense of release filenames, so manifests can be titled "Show S02E05 – Episode Title"
+// rather than `Show.S02E05.Episode.Title.1080p.BluRay.x264-GROUP`.
+//
+// Handles the usual schemes: scene-style dotted names with SxxEyy (or 2x05) and a trailing
+// -GROUP, movies with a year, and anime-style `[Group] Show - 05 [1080p]`.  Anything it doesn't
+// recognize ends up in the title, so the worst case is a title with some junk left in it.
+
+/// What could be worked out from a filename.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReleaseName {
+    /// The show's (or movie's) name.
+    pub title: String,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    pub episode_title: Option<String>,
+    pub year: Option<u16>,
+    /// The release group.
+    pub group: Option<String>,
+}
+
+// tokens that mean the interesting part of the name is over: resolutions, sources, codecs and
+// other release details.  compared case-insensitively.
+const JUNK: [&str; 44] = [
+    "480p", "576p", "720p", "1080p", "1080i", "2160p", "4k", "uhd",
+    "bluray", "blu-ray", "bdrip", "brrip", "bdremux", "remux", "web", "web-dl", "webdl", "webrip", "hdtv", "dvdrip", "dvd", "hdrip",
+    "x264", "x265", "h264", "h265", "h.264", "h.265", "hevc", "avc", "av1", "xvid", "10bit", "hdr", "hdr10", "dv",
+    "aac", "ac3", "dts", "flac", "ddp5.1", "dd5.1", "proper", "repack",
+];
+
+fn is_junk(token: &str) -> bool {
+    let token = token.trim_matches(|c: char| c == '[' || c == ']' || c == '(' || c == ')');
+    JUNK.iter().any(|x| x.eq_ignore_ascii_case(token))
+}
+
+// "S02E05", "s2e5", "S02E05E06" (the first episode wins), or "2x05"
+fn parse_episode_tag(token: &str) -> Option<(u32, u32)> {
+    let lower = token.to_ascii_lowercase();
+    let (season, episode) = if let Some(rest) = lower.strip_prefix('s') {
+        rest.split_once('e')?
+    } else {
+        lower.split_once('x')?
+    };
+    let episode = episode.split('e').next()?;
+    if season.is_empty() || episode.is_empty() || !season.bytes().chain(episode.bytes()).all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    Some((season.parse().ok()?, episode.parse().ok()?))
+}
+
+fn parse_year(token: &str) -> Option<u16> {
+    let token = token.trim_matches(|c: char| c == '(' || c == ')');
+    let year: u16 = token.parse().ok().filter(|_| token.len() == 4)?;
+    (1900..=2099).contains(&year).then_some(year)
+}
+
+fn join(tokens: &[&str]) -> Option<String> {
+    let joined = tokens.join(" ").trim_matches(|c: char| c == '-' || c == '–' || c.is_whitespace()).to_string();
+    (!joined.is_empty()).then_some(joined)
+}
+
+/// Picks apart a filename (without its extension).
+pub fn parse_release_name(stem: &str) -> ReleaseName {
+    let mut name = ReleaseName::default();
+    let mut rest = stem.trim();
+
+    // [Group] at the front
+    if let Some(tag) = rest.strip_prefix('[') {
+        if let Some((group, after)) = tag.split_once(']') {
+            name.group = Some(group.to_string());
+            rest = after.trim_start();
+        }
+    }
+    // dots or underscores standing in for spaces, and -GROUP on the end
+    let mut owned = rest.to_string();
+    if !owned.contains(' ') {
+        if let Some((before, group)) = owned.rsplit_once('-') {
+            // "...WEB-DL" with no group after it isn't a group called DL
+            let hyphenated = format!("{}-{}", before.rsplit(['.', '_']).next().unwrap_or(""), group);
+            if name.group.is_none() && !group.is_empty() && group.chars().all(|c| c.is_ascii_alphanumeric()) && before.contains(['.', '_']) && !is_junk(&hyphenated) {
+                name.group = Some(group.to_string());
+                owned.truncate(before.len());
+            }
+        }
+        owned = owned.replace(['.', '_'], " ");
+    }
+    // anything else in square brackets is release details
+    let mut cleaned = String::new();
+    let mut depth = 0;
+    for c in owned.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth > 0 => depth -= 1,
+            _ if depth == 0 => cleaned.push(c),
+            _ => {},
+        }
+    }
+
+    let tokens: Vec<&str> = cleaned.split_whitespace().collect();
+    let end = tokens.iter().position(|x| is_junk(x)).unwrap_or(tokens.len());
+    let tokens = &tokens[..end];
+
+    if let Some(i) = tokens.iter().position(|x| parse_episode_tag(x).is_some()) {
+        let (season, episode) = parse_episode_tag(tokens[i]).unwrap();
+        (name.season, name.episode) = (Some(season), Some(episode));
+        let mut title = &tokens[..i];
+        if let Some(year) = title.last().and_then(|x| parse_year(x)) {
+            name.year = Some(year);
+            title = &title[..title.len() - 1];
+        }
+        name.title = join(title).unwrap_or_default();
+        name.episode_title = join(&tokens[i + 1..]);
+    } else if let Some(i) = (0..tokens.len()).rev().find(|&i| tokens[i] == "-" && tokens.get(i + 1).is_some_and(|x| x.parse::<u32>().is_ok())) {
+        // anime style, "Show - 05", maybe with " - Episode Title" after it
+        name.episode = tokens[i + 1].parse().ok();
+        name.title = join(&tokens[..i]).unwrap_or_default();
+        name.episode_title = join(&tokens[i + 2..]);
+    } else if let Some(i) = tokens.iter().skip(1).rposition(|x| parse_year(x).is_some()) {
+        // a movie.  the year can't be the first thing, or "1917 (2019)" would lose its title
+        name.year = parse_year(tokens[i + 1]);
+        name.title = join(&tokens[..i + 1]).unwrap_or_default();
+    } else {
+        name.title = join(tokens).unwrap_or_default();
+    }
+    if name.title.is_empty() && name.episode.is_none() {
+        name.title = stem.to_string();
+    }
+    name
+}
+
+impl ReleaseName {
+    /// "Show S02E05 – Episode Title", "Show - 05", or "Movie (2019)", depending on what's known.
+    pub fn display_title(&self) -> String {
+        let mut title = self.title.clone();
+        match (self.season, self.episode) {
+            (Some(season), Some(episode)) => title.push_str(&format!(" S{:02}E{:02}", season, episode)),
+            (None, Some(episode)) => title.push_str(&format!(" - {:02}", episode)),
+            _ => if let Some(year) = self.year {
+                title.push_str(&format!(" ({})", year));
+            },
+        }
+        if let Some(episode_title) = &self.episode_title {
+            title.push_str(" – ");
+            title.push_str(episode_title);
+        }
+        title.trim_start().to_string()
+    }
+
+    /// Fills in `template`: `{title}`, `{season}`, `{episode}` (both zero-padded to 2 digits),
+    /// `{episode_title}`, `{year}` and `{group}`.  Anything that isn't known comes out empty.
+    pub fn fill_template(&self, template: &str) -> String {
+        let number = |x: Option<u32>| x.map(|x| format!("{:02}", x)).unwrap_or_default();
+        template
+            .replace("{title}", &self.title)
+            .replace("{season}", &number(self.season))
+            .replace("{episode}", &number(self.episode))
+            .replace("{episode_title}", self.episode_title.as_deref().unwrap_or(""))
+            .replace("{year}", &self.year.map(|x| x.to_string()).unwrap_or_default())
+            .replace("{group}", self.group.as_deref().unwrap_or(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(title: &str, season: Option<u32>, episode: Option<u32>, episode_title: Option<&str>, year: Option<u16>, group: Option<&str>) -> ReleaseName {
+        ReleaseName {
+            title: title.to_string(),
+            season,
+            episode,
+            episode_title: episode_title.map(str::to_owned),
+            year,
+            group: group.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn parsing_release_names() {
+        let cases = [
+            ("Show.Name.S02E05.Episode.Title.1080p.BluRay.x264-GROUP", name("Show Name", Some(2), Some(5), Some("Episode Title"), None, Some("GROUP"))),
+            ("show_name_s2e5", name("show name", Some(2), Some(5), None, None, None)),
+            ("Show.Name.2x05.720p.HDTV", name("Show Name", Some(2), Some(5), None, None, None)),
+            ("Show.Name.S01E01E02.WEB-DL", name("Show Name", Some(1), Some(1), None, None, None)),
+            ("Show (2005) S01E01", name("Show", Some(1), Some(1), None, Some(2005), None)),
+            ("[SubGroup] Anime Show - 05 [1080p]", name("Anime Show", None, Some(5), None, None, Some("SubGroup"))),
+            ("[SubGroup] Anime Show - 12 - The Finale [1080p][ABCD1234]", name("Anime Show", None, Some(12), Some("The Finale"), None, Some("SubGroup"))),
+            ("Movie.Name.2019.1080p.WEB-DL.x264-GRP", name("Movie Name", None, None, None, Some(2019), Some("GRP"))),
+            ("1917 (2019)", name("1917", None, None, None, Some(2019), None)),
+            ("home video", name("home video", None, None, None, None, None)),
+            ("1080p", name("1080p", None, None, None, None, None)),
+        ];
+        for (stem, expected) in cases {
+            assert_eq!(parse_release_name(stem), expected, "{:?}", stem);
+        }
+    }
+
+    #[test]
+    fn display_titles() {
+        let cases = [
+            (name("Show", Some(2), Some(5), Some("Title"), None, None), "Show S02E05 – Title"),
+            (name("Show", None, Some(5), None, None, None), "Show - 05"),
+            (name("Movie", None, None, None, Some(2019), None), "Movie (2019)"),
+            (name("Show", Some(1), Some(1), None, Some(2005), None), "Show S01E01"),
+            (name("", None, Some(3), None, None, None), "- 03"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(name.display_title(), expected);
+        }
+    }
+
+    #[test]
+    fn filling_templates() {
+        let show = name("Show", Some(2), Some(5), Some("Title"), None, Some("GRP"));
+        assert_eq!(show.fill_template("{title} {season}x{episode} {episode_title} [{group}]"), "Show 02x05 Title [GRP]");
+        assert_eq!(show.fill_template("{title} ({year})"), "Show ()");
+    }
+}
@@ -0,0 +1,162 @@
+use crate::ffprobe::FFprobeResult;
+use crate::filters::Chain;
+use crate::transcode::{copyable_video_container, Cut, HwAccel, TranscodeOptions};
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::invocation::FfmpegInvocation;
+use std::process::Child;
+
+// scratch space for the per-segment encodes.  lives inside the output directory so the concat
+// list can use plain relative filenames.
+const SEGMENT_DIR: &str = ".segments";
+const SEGMENT_LIST: &str = "segments.txt";
+
+/// Where the concat list for a segmented encode into `outputdir` lives.
+pub fn segment_list_path(outputdir: &Path) -> PathBuf {
+    outputdir.join(SEGMENT_DIR).join(SEGMENT_LIST)
+}
+
+/// One time slice of the source video.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub start: f64,
+    /// `None` for the last segment, which runs to the end of the file in case the duration was
+    /// rounded down.
+    pub length: Option<f64>,
+    pub output: PathBuf,
+}
+
+/// The video half of a transcode, split into time segments that get encoded by separate ffmpeg
+/// processes at the same time.  The command returned by `remux` picks the finished segments back
+/// up through the concat demuxer, so this has to `run` to completion before that command starts.
+#[derive(Debug)]
+pub struct SegmentedEncode {
+    pub segments: Vec<Segment>,
+    pub(crate) input: PathBuf,
+    pub(crate) video_index: u16,
+    // see `FFprobeResult::start_time`
+    pub(crate) start_time: f64,
+    hwaccel: HwAccel,
+    pub(crate) encoder_args: Vec<String>,
+    // see `TranscodeOptions::encoder_device_args` and `encoder_upload`
+    pub(crate) device_args: Vec<String>,
+    pub(crate) upload: Option<Chain>,
+    // see `TranscodeOptions::cpu_affinity`
+    launcher: Vec<OsString>,
+    dir: PathBuf,
+}
+
+/// Plans a segmented encode of `media_file`'s video track, or returns `None` if the options don't
+/// ask for one or the video can be copied as-is (in which case there's nothing to speed up).
+/// With a `cut`, only that much of the video is split up, and `ffprobe`'s duration should be the
+/// cut's.
+pub fn plan_segments(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, options: &TranscodeOptions, cut: Option<Cut>) -> Option<SegmentedEncode> {
+    if options.parallel_segments < 2 {
+        return None;
+    }
+    let video = ffprobe.tracks.iter().find(|track| track.is_video())?;
+    if copyable_video_container(video, options).is_some() {
+        return None;
+    }
+
+    let dir = outputdir.join(SEGMENT_DIR);
+    let length = ffprobe.duration / options.parallel_segments as f64;
+    let start = cut.map_or(0.0, |x| x.start);
+    // the last segment runs to the end, unless the cut stops before it
+    let last_length = cut.and_then(|x| x.length).map(|_| length);
+    let segments = (0..options.parallel_segments).map(|i| Segment {
+        start: start + length * i as f64,
+        length: if i + 1 < options.parallel_segments { Some(length) } else { last_length },
+        output: dir.join(format!("part_{:03}.mkv", i)),
+    }).collect();
+    Some(SegmentedEncode {
+        segments,
+        input: media_file.to_owned(),
+        video_index: video.index,
+        start_time: ffprobe.start_time,
+        hwaccel: options.hwaccel.clone(),
+        encoder_args: options.video_encoder_args(video),
+        device_args: options.encoder_device_args(options.encoder()),
+        upload: options.encoder_upload(options.encoder()),
+        launcher: options.launcher(),
+        dir,
+    })
+}
+
+impl SegmentedEncode {
+    /// The ffmpeg command that encodes segment `i` on this machine.
+    pub fn command(&self, i: usize) -> FfmpegInvocation {
+        let segment = &self.segments[i];
+        let mut command = FfmpegInvocation::new();
+        command.launcher = self.launcher.clone();
+        command.global_arg("-hide_banner").global_arg("-y");
+        for arg in &self.device_args {
+            command.global_arg(arg);
+        }
+        // -ss before -i seeks to the nearest keyframe, then ffmpeg decodes up to the exact
+        // timestamp and throws the rest away, so the segments butt up against each other
+        command.args(["-ss", segment.start.to_string().as_str()]);
+        if let Some(length) = segment.length {
+            command.args(["-t", length.to_string().as_str()]);
+        }
+        self.hwaccel.add_input_args(&mut command);
+        command.input(&self.input);
+        command.args(["-map", format!("0:{}", self.video_index).as_str(), "-an", "-sn"]);
+        if let Some(upload) = &self.upload {
+            command.arg("-vf").arg(upload.to_string());
+        }
+        command.args(&self.encoder_args);
+        command.output(&segment.output);
+        command
+    }
+
+    /// Creates the scratch directory and writes the concat list `remux`'s command reads from.
+    pub fn prepare(&self) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut list = String::new();
+        for segment in &self.segments {
+            list.push_str("file '");
+            list.push_str(&segment.output.file_name().unwrap().to_string_lossy());
+            list.push_str("'\n");
+        }
+        fs::File::create(self.dir.join(SEGMENT_LIST))?.write_all(list.as_bytes())
+    }
+
+    /// Runs every segment encode in parallel on this machine and waits for all of them.  Fails if
+    /// any of them does.
+    pub fn run(self) -> std::io::Result<()> {
+        self.prepare()?;
+
+        let mut children = Vec::<Child>::new();
+        for i in 0..self.segments.len() {
+            match self.command(i).to_command().spawn() {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                },
+            }
+        }
+        let mut failed = false;
+        for mut child in children {
+            failed |= !child.wait()?.success();
+        }
+        if failed {
+            return Err(std::io::Error::other("ffmpeg failed to encode a segment"));
+        }
+        Ok(())
+    }
+}
+
+/// Removes the intermediate segments once the main ffmpeg command has consumed them.
+pub fn cleanup_segments(outputdir: &Path) -> std::io::Result<()> {
+    match fs::remove_dir_all(outputdir.join(SEGMENT_DIR)) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        x => x,
+    }
+}
@@ -0,0 +1,126 @@
+// A small static file server for output directories, for channels that don't want to set up
+// nginx just to point Cytube at the box doing the transcoding.
+//
+// tower-http does the actual file serving (Range requests, conditional requests, HEAD).  The
+// only thing layered on top is fixing up the Content-Type for the file types we write, because
+// mime_guess's idea of them doesn't match what players expect (or what's in the manifest), and
+// permissive CORS headers, because the player fetches text tracks and CMAF segments with XHR from
+// the channel's origin.  `/player` is a page that plays a manifest with cytube's pickers (see
+// `player`), to try the outputs out before they go on the channel.
+//
+// Cytube only plays media served over HTTPS, so in practice this needs `ServeOptions::tls` set,
+// either to a certificate you already have or (with the `acme` feature) to get one from Let's
+// Encrypt by itself.
+
+use axum::extract::Request;
+use axum::http::header::{HeaderValue, CONTENT_TYPE};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tower_http::cors::CorsLayer;
+use tower_http::services::ServeDir;
+
+mod player;
+
+/// Content types for files we generate that mime_guess gets wrong or doesn't know about.
+const CONTENT_TYPES: [(&str, &str); 4] = [
+    ("m3u8", "application/x-mpegURL"),
+    ("m4a", "audio/mp4"),
+    ("m4s", "video/iso.segment"),
+    ("mpd", "application/dash+xml"),
+];
+
+/// Where the server's certificate comes from.
+#[derive(Debug, Clone)]
+pub enum Tls {
+    /// PEM files.  `cert` can have the intermediates appended after the server's certificate.
+    Files { cert: PathBuf, key: PathBuf },
+    /// Get (and renew) a certificate over ACME, using the TLS-ALPN-01 challenge.  That means the
+    /// server has to be reachable on port 443 of every domain in `domains`.
+    #[cfg(feature = "acme")]
+    Acme {
+        domains: Vec<String>,
+        /// Contact email addresses for the CA account.
+        contact: Vec<String>,
+        /// Where to keep the account key and certificates between runs.  Without one, every
+        /// start asks for a new certificate, and Let's Encrypt's rate limits kick in quickly.
+        cache: Option<PathBuf>,
+        /// Use Let's Encrypt's production directory rather than staging.
+        production: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub addr: SocketAddr,
+    /// Serve HTTPS instead of plain HTTP.
+    pub tls: Option<Tls>,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {addr: SocketAddr::from(([0, 0, 0, 0], 8080)), tls: None}
+    }
+}
+
+async fn fix_content_type(request: Request, next: Next) -> Response {
+    let extension = request.uri().path().rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+    let mut response = next.run(request).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    if let Some((_, content_type)) = CONTENT_TYPES.iter().find(|(ext, _)| Some(*ext) == extension.as_deref()) {
+        response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    }
+    response
+}
+
+/// The HTTP service serving the files in `dir`, and the player page, for embedding in something
+/// else.
+pub fn router(dir: &Path) -> Router {
+    Router::new()
+        .route("/player", get(player::player))
+        .fallback_service(ServeDir::new(dir))
+        .layer(middleware::from_fn(fix_content_type))
+        .layer(CorsLayer::permissive())
+}
+
+/// Serves the files in `dir` over HTTP(S) until something goes wrong.  Blocks the calling thread.
+pub fn serve(dir: &Path, options: &ServeOptions) -> std::io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let app = router(dir).into_make_service();
+        match &options.tls {
+            None => axum_server::bind(options.addr).serve(app).await,
+            Some(Tls::Files { cert, key }) => {
+                let config = RustlsConfig::from_pem_file(cert, key).await?;
+                axum_server::bind_rustls(options.addr, config).serve(app).await
+            },
+            #[cfg(feature = "acme")]
+            Some(Tls::Acme { domains, contact, cache, production }) => {
+                use futures_util::StreamExt;
+                use rustls_acme::{caches::DirCache, AcmeConfig};
+
+                let mut state = AcmeConfig::new(domains)
+                    .contact(contact.iter().map(|email| format!("mailto:{}", email)))
+                    .cache_option(cache.clone().map(DirCache::new))
+                    .directory_lets_encrypt(*production)
+                    .state();
+                let acceptor = state.axum_acceptor(state.default_rustls_config());
+                // the state has to be polled for orders and renewals to actually happen
+                tokio::spawn(async move {
+                    while let Some(event) = state.next().await {
+                        if let Err(e) = event {
+                            eprintln!("acme error: {:?}", e);
+                        }
+                    }
+                });
+                axum_server::bind(options.addr).acceptor(acceptor).serve(app).await
+            },
+        }
+    })
+}
@@ -0,0 +1,130 @@
+// `/player`: a page that plays a manifest the way cytube would, with its quality, audio track and
+// subtitle pickers, to try the whole thing out before it goes on the channel.  It reads the
+// manifest with the same fetch cytube does, so CORS and content types get tried out too.
+//
+// `/player?manifest=show/01/manifest.json` plays that manifest (relative to the server's root;
+// `manifest.json` without one).  The URLs in it are used as they are, unless `&local` is given, in
+// which case every file's looked for next to the manifest by its filename, for outputs that
+// aren't where their URLs say yet.
+
+use crate::cytube_structs::CYTUBE_ACCEPTABLE_QUALITY_VALUES;
+use crate::preview_page::AUDIO_SYNC_SCRIPT;
+use axum::response::Html;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Player</title>
+<style>
+body { background: #111; color: #ddd; font-family: sans-serif; }
+video { width: 100%; max-height: 80vh; background: #000; }
+#pickers { display: flex; gap: 2em; margin: 0.5em 0; }
+#problems { color: #f77; }
+</style>
+</head><body>
+<h1 id="title">Loading...</h1>
+<video id="video" controls crossorigin="anonymous"></video>
+<audio id="audio" crossorigin="anonymous" preload="auto"></audio>
+<div id="pickers">
+<label>Quality: <select id="quality"></select></label>
+<label>Audio: <select id="audio-track"></select></label>
+<label>Subtitles: <select id="text-track"><option value="">Off</option></select></label>
+</div>
+<pre id="problems"></pre>
+<script>{{AUDIO_SYNC}}</script>
+<script>
+const QUALITIES = {{QUALITIES}};
+const params = new URLSearchParams(location.search);
+const manifestUrl = new URL(params.get("manifest") || "manifest.json", location.href);
+const local = params.has("local");
+const quality = document.getElementById("quality");
+const textPicker = document.getElementById("text-track");
+const problems = document.getElementById("problems");
+
+function problem(message) {
+    problems.textContent += message + "\n";
+}
+
+function resolve(url) {
+    url = new URL(url, manifestUrl);
+    return local ? new URL(url.pathname.split("/").pop(), manifestUrl).href : url.href;
+}
+
+function option(select, value, label) {
+    const option = document.createElement("option");
+    option.value = value;
+    option.textContent = label;
+    select.append(option);
+    return option;
+}
+
+// switches to another source where the last one was, the way cytube's quality switch does
+function play(source) {
+    const time = video.currentTime, paused = video.paused;
+    video.src = resolve(source.url);
+    video.addEventListener("loadedmetadata", () => {
+        video.currentTime = time;
+        if (!paused) video.play();
+    }, {once: true});
+}
+
+function showText() {
+    for (const track of video.textTracks) {
+        track.mode = track.label === textPicker.value ? "showing" : "disabled";
+    }
+}
+
+// the things cytube's own checks would turn the manifest away for
+function check(manifest) {
+    if (!manifest.title) problem("no title");
+    if (!(manifest.duration > 0)) problem("the duration isn't a positive number");
+    if (!manifest.sources || manifest.sources.length === 0) problem("no sources");
+    for (const source of manifest.sources || []) {
+        if (!QUALITIES.includes(source.quality)) problem(source.url + ": cytube doesn't know quality " + source.quality);
+        if (!source.contentType) problem(source.url + ": no content type");
+    }
+    for (const track of manifest.audioTracks || []) {
+        if (!track.label || !track.language) problem(track.url + ": audio tracks need a label and a language");
+    }
+    if ((manifest.textTracks || []).filter(x => x.default).length > 1) problem("more than one default text track");
+}
+
+video.addEventListener("error", () => problem("couldn't play " + video.currentSrc + " (media error " + video.error.code + ")"));
+audio.addEventListener("error", () => problem("couldn't play " + audio.currentSrc + " (media error " + audio.error.code + ")"));
+
+fetch(manifestUrl).then(response => {
+    if (!response.ok) throw new Error(manifestUrl + ": " + response.status + " " + response.statusText);
+    return response.json();
+}).then(manifest => {
+    document.title = manifest.title;
+    document.getElementById("title").textContent = manifest.title;
+    check(manifest);
+
+    const sources = [...(manifest.sources || [])].sort((a, b) => b.quality - a.quality);
+    sources.forEach((source, i) => option(quality, i, source.quality + "p (" + source.contentType + ")"));
+    quality.addEventListener("change", () => play(sources[quality.value]));
+    if (sources.length > 0) play(sources[0]);
+
+    const audioTracks = manifest.audioTracks || [];
+    audioTracks.forEach(track => option(picker, resolve(track.url), track.label + " (" + track.language + ")"));
+    option(picker, "", audioTracks.length > 0 ? "The video's own" : "The video's own (no audio tracks)");
+    picker.dispatchEvent(new Event("change"));
+
+    for (const track of manifest.textTracks || []) {
+        const element = document.createElement("track");
+        element.kind = "subtitles";
+        element.src = resolve(track.url);
+        element.label = track.name;
+        video.append(element);
+        option(textPicker, track.name, track.name).selected = !!track.default;
+    }
+    textPicker.addEventListener("change", showText);
+    // the tracks only show up in textTracks once they're in the document
+    setTimeout(showText);
+}).catch(e => problem(e.message));
+</script>
+</body></html>
+"#;
+
+pub(super) async fn player() -> Html<String> {
+    let qualities = format!("{:?}", CYTUBE_ACCEPTABLE_QUALITY_VALUES);
+    Html(PAGE.replace("{{QUALITIES}}", &qualities).replace("{{AUDIO_SYNC}}", AUDIO_SYNC_SCRIPT))
+}
@@ -0,0 +1,134 @@
+// Predicting how big a transcode's output will be, calibrated against what earlier transcodes
+// actually came out as.
+//
+// The starting point is the bits-per-pixel table in `encoder::estimate_video_kbps`, which is a
+// ballpark for "typical" live action.  Real libraries aren't typical (anime compresses far better,
+// grainy film far worse), so for every encoder and settings combination we keep the average ratio
+// of actual to guessed bitrate, and scale future guesses by it.  The average is over log ratios, so
+// one 3x outlier doesn't move things as much as it would otherwise, and it's weighted towards
+// recent encodes so the model follows a library that changes over time.
+
+use crate::encoder::VideoEncoder;
+use crate::transcode::TranscodeOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// past this many samples, older ones start fading out
+const MAX_WEIGHT: f64 = 50.0;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Stats {
+    // (effective) number of samples
+    weight: f64,
+    // sum of ln(actual / guessed)
+    log_ratio_sum: f64,
+}
+
+/// An output whose size was guessed at planning time, to check against what it turns out to be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeGuess {
+    /// Relative to the output directory.
+    pub filename: String,
+    /// See `settings_key`.
+    pub key: String,
+    /// The uncalibrated guess, in kbps.
+    pub guessed_kbps: u64,
+}
+
+/// Calibration data for size predictions.  Shared between threads, so it lives behind an `Arc` in
+/// `TranscodeOptions`.
+#[derive(Debug, Default)]
+pub struct SizeModel {
+    /// Where `save` writes to.  `None` keeps it in memory only.
+    pub path: Option<PathBuf>,
+    stats: Mutex<HashMap<String, Stats>>,
+}
+
+/// What identifies "the same settings" for calibration purposes: the encoder and whatever options
+/// change its bits-per-pixel, e.g. `svt-av1 crf=30 film-grain=8`.
+pub fn settings_key(options: &TranscodeOptions) -> String {
+    let encoder = options.encoder();
+    let mut key = serde_json::to_value(encoder).ok().and_then(|x| x.as_str().map(str::to_owned)).unwrap_or_default();
+    if encoder == VideoEncoder::SvtAv1 {
+        for (name, value) in [("crf", options.av1.crf), ("preset", options.av1.preset), ("film-grain", options.av1.film_grain)] {
+            if let Some(value) = value {
+                key.push_str(&format!(" {}={}", name, value));
+            }
+        }
+    }
+    key
+}
+
+impl SizeModel {
+    /// `$XDG_STATE_HOME/cytube-generator/size-model.json`, falling back on `~/.local/state`.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("state"),
+        };
+        Some(dir.join("cytube-generator").join("size-model.json"))
+    }
+
+    /// Reads the model saved at `path`.  If there's nothing there yet, you get an empty one that'll
+    /// save there.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let stats = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(SizeModel {path: Some(path.to_owned()), stats: Mutex::new(stats)})
+    }
+
+    /// Writes the model back to `path`, if it has one.
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec(&*self.stats.lock().unwrap())?;
+        // write-and-rename, so a crash halfway through doesn't lose the whole thing
+        let tmp = path.with_extension("tmp");
+        std::fs::File::create(&tmp)?.write_all(&json)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// What to multiply an uncalibrated guess for `key` by.  1 until something's been recorded.
+    pub fn correction(&self, key: &str) -> f64 {
+        match self.stats.lock().unwrap().get(key) {
+            Some(stats) if stats.weight > 0.0 => (stats.log_ratio_sum / stats.weight).exp(),
+            _ => 1.0,
+        }
+    }
+
+    /// Learns from an output that was guessed at `guessed_kbps` and came out at `actual_kbps`.
+    pub fn record(&self, key: &str, guessed_kbps: f64, actual_kbps: f64) {
+        if guessed_kbps <= 0.0 || actual_kbps <= 0.0 {
+            return;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(key.to_owned()).or_default();
+        if stats.weight >= MAX_WEIGHT {
+            stats.log_ratio_sum *= (MAX_WEIGHT - 1.0) / stats.weight;
+            stats.weight = MAX_WEIGHT - 1.0;
+        }
+        stats.weight += 1.0;
+        stats.log_ratio_sum += (actual_kbps / guessed_kbps).ln();
+    }
+
+    /// Records every guess in `guesses` against the files in `outputdir`, for media `duration`
+    /// seconds long.
+    pub fn record_outputs(&self, outputdir: &Path, guesses: &[SizeGuess], duration: f64) {
+        if duration <= 0.0 {
+            return;
+        }
+        for guess in guesses {
+            let Ok(metadata) = std::fs::metadata(outputdir.join(&guess.filename)) else { continue };
+            let actual_kbps = metadata.len() as f64 * 8.0 / 1000.0 / duration;
+            self.record(&guess.key, guess.guessed_kbps as f64, actual_kbps);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Storyboards: a thumbnail every so often, tiled into sprite sheets, plus a WebVTT file saying
+// which part of which sheet goes with which stretch of the video.  Players that do seek previews
+// (video.js's vtt-thumbnails, Plyr, JW Player...) read the VTT.  Cytube's own manifest has nowhere
+// to put it, so it's just written next to the manifest, as `storyboard.vtt`, for whatever wants it.
+//
+// The thumbnails come out of the same ffmpeg as everything else, off the same decode, so they
+// cost next to nothing on top.  The VTT's written afterwards, once the real duration's known.
+
+use crate::filters::{pad, Chain, Filter, FilterGraph};
+use crate::invocation::FfmpegInvocation;
+use crate::ffprobe::Track;
+use serde::Deserialize;
+use std::fmt::Write;
+use std::path::Path;
+
+pub const STORYBOARD_VTT: &str = "storyboard.vtt";
+
+/// How to lay out a storyboard.  The `[storyboard]` table in the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Storyboard {
+    /// Seconds between thumbnails.
+    pub interval: f64,
+    /// Each thumbnail's size.  Videos that aren't the same shape are letterboxed to fit.
+    pub width: u16,
+    pub height: u16,
+    /// How many thumbnails go across and down each sheet.
+    pub columns: u16,
+    pub rows: u16,
+}
+
+impl Default for Storyboard {
+    fn default() -> Self {
+        Storyboard {interval: 10.0, width: 160, height: 90, columns: 10, rows: 10}
+    }
+}
+
+// ffmpeg numbers the sheets from 1
+fn sheet_filename(number: usize) -> String {
+    format!("storyboard_{:03}.jpg", number)
+}
+
+// HH:MM:SS.mmm
+fn vtt_time(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!("{:02}:{:02}:{:02}.{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+}
+
+impl Storyboard {
+    /// Adds the sprite sheets to `command` as an output of their own, made from `video` (input 0)
+    /// by way of `graph`, and written to `dir`.
+    pub fn add_output(&self, command: &mut FfmpegInvocation, graph: &mut FilterGraph, video: &Track, dir: &Path) {
+        let (width, height) = (self.width, self.height);
+        let chain = Chain::new()
+            .then(Filter::new("fps").arg(format!("1/{}", self.interval)))
+            .then(Filter::new("scale").opt("w", width).opt("h", height).opt("force_original_aspect_ratio", "decrease"))
+            .then(Filter::new("pad").opt("w", width).opt("h", height).opt("x", "(ow-iw)/2").opt("y", "(oh-ih)/2"))
+            .then(Filter::new("tile").arg(format!("{}x{}", self.columns, self.rows)))
+            // the mjpeg encoder only does full range
+            .then(Filter::new("format").arg("yuvj420p"));
+        graph.chain(&[&format!("0:{}", video.index)], chain, &["storyboard"]);
+        command.args(["-map", pad("storyboard").as_str(), "-c:v", "mjpeg", "-q:v", "5", "-f", "image2"]);
+        command.output(dir.join("storyboard_%03d.jpg"));
+    }
+
+    /// The VTT for a storyboard of something `duration` seconds long.  The sheets are referred to
+    /// by relative URLs, so they have to stay next to it.
+    pub fn vtt(&self, duration: f64) -> String {
+        let mut vtt = "WEBVTT\n".to_string();
+        let per_sheet = self.columns as usize * self.rows as usize;
+        let count = (duration / self.interval).ceil() as usize;
+        for i in 0..count {
+            let start = i as f64 * self.interval;
+            let end = (start + self.interval).min(duration);
+            let (sheet, position) = (i / per_sheet + 1, i % per_sheet);
+            let x = (position % self.columns as usize) * self.width as usize;
+            let y = (position / self.columns as usize) * self.height as usize;
+            let _ = write!(vtt, "\n{} --> {}\n{}#xywh={},{},{},{}\n", vtt_time(start), vtt_time(end),
+                sheet_filename(sheet), x, y, self.width, self.height);
+        }
+        vtt
+    }
+
+    /// Writes the VTT for a storyboard of something `duration` seconds long to `dir`.
+    pub fn write_vtt(&self, dir: &Path, duration: f64) -> std::io::Result<()> {
+        std::fs::write(dir.join(STORYBOARD_VTT), self.vtt(duration))
+    }
+}
@@ -0,0 +1,65 @@
+// Backfilling subtitles on transcodes that are already out there: converting an input's subtitle
+// tracks to VTT next to outputs that were made without them, and adding them to the manifest.
+// The video and audio aren't touched.
+//
+// Nothing's uploaded.  For outputs served from somewhere else, upload the new VTT files and the
+// manifest afterwards.
+
+use crate::cytube_structs::{CytubeVideo, TextTrack};
+use crate::failure::Failure;
+use crate::ffprobe::FFprobeResult;
+use crate::manifest::{has_placeholder, read_manifest, replace_manifest};
+use crate::plan::Runner;
+use crate::signing::{unsigned, SignedUrls};
+use crate::transcode::{plan_subtitles, TranscodeOptions};
+use std::path::Path;
+
+/// Converts the subtitles in `media_file` that `options` keeps to VTT files in `outputdir`, and
+/// returns their text tracks (with URLs under `url_prefix`) without adding them to anything.
+/// Files with the same names are replaced.
+pub fn extract_subtitles(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, options: &TranscodeOptions, runner: &dyn Runner) -> std::io::Result<Vec<TextTrack>> {
+    // written somewhere hidden first, so nothing half-written gets served
+    let staging = outputdir.join(".subtitles.staging");
+    match std::fs::remove_dir_all(&staging) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    let (command, text_tracks) = plan_subtitles(media_file, ffprobe, &staging, url_prefix, options);
+    if text_tracks.is_empty() {
+        return Ok(text_tracks);
+    }
+    std::fs::create_dir_all(&staging)?;
+    runner.run(&command).map_err(|e| Failure::Encode.wrap(e))?;
+    for entry in std::fs::read_dir(&staging)? {
+        let entry = entry?;
+        std::fs::rename(entry.path(), outputdir.join(entry.file_name()))?;
+    }
+    std::fs::remove_dir(&staging)?;
+    Ok(text_tracks)
+}
+
+/// The URL prefix the manifest's files are under, going by its first source.
+pub fn url_prefix_of(manifest: &CytubeVideo) -> Option<String> {
+    let url = unsigned(&manifest.sources.first()?.url);
+    Some(url[..url.rfind('/')? + 1].to_owned())
+}
+
+/// Adds `text_tracks` to the manifest in `outputdir`, in place of any it already has at the same
+/// URLs, and rewrites it, signing every URL again if there's `signed_urls`.  If one of the new
+/// tracks is the default, the old ones stop being.  Returns the new manifest.
+pub fn add_text_tracks(outputdir: &Path, text_tracks: &[TextTrack], signed_urls: Option<&SignedUrls>) -> std::io::Result<CytubeVideo> {
+    let mut manifest = read_manifest(outputdir)?;
+    manifest.text_tracks.retain(|x| !text_tracks.iter().any(|new| unsigned(&new.url) == unsigned(&x.url)));
+    if text_tracks.iter().any(|x| x.default) {
+        for track in manifest.text_tracks.iter_mut() {
+            track.default = false;
+        }
+    }
+    manifest.text_tracks.extend(text_tracks.iter().cloned());
+    if let Some(signed_urls) = signed_urls.filter(|_| !has_placeholder(&manifest)) {
+        manifest = signed_urls.sign_manifest(&manifest)?;
+    }
+    // it's probably being served while this happens
+    replace_manifest(outputdir, &manifest)?;
+    Ok(manifest)
+}
@@ -0,0 +1,195 @@
+// Making small media files to test against, with ffmpeg's lavfi sources: a test pattern for the
+// video, sine waves for the audio, and subtitles from a generated SRT.  Any mix of tracks,
+// languages and codecs can be made, so the awkward inputs (three audio languages, AC-3, MPEG-4
+// Part 2, forced signs subtitles, chapters) can be tested for real rather than by hand-written
+// probe output.  The integration tests in `tests/` use it.
+//
+// It needs an ffmpeg with the encoders asked for (libx264 and the usual suspects), so it's behind
+// the `testmedia` feature, and the tests skip themselves when there's no ffmpeg at all.
+
+use crate::invocation::FfmpegInvocation;
+use crate::plan::{CliRunner, Runner};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A test pattern video track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVideo {
+    /// The ffmpeg encoder, like `libx264` or `mpeg4`.
+    pub encoder: String,
+    pub width: u16,
+    pub height: u16,
+    pub frame_rate: u16,
+    /// e.g. `yuv420p10le`, for the pixel formats that can't be copied.
+    pub pix_fmt: Option<String>,
+}
+
+impl Default for TestVideo {
+    fn default() -> Self {
+        TestVideo {encoder: "libx264".to_string(), width: 320, height: 240, frame_rate: 25, pix_fmt: Some("yuv420p".to_string())}
+    }
+}
+
+/// A sine wave audio track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestAudio {
+    /// The ffmpeg encoder, like `aac`, `ac3` or `flac`.
+    pub encoder: String,
+    pub channels: u16,
+    /// In Hz.  Tracks with different ones can be told apart by ear, if it comes to that.
+    pub frequency: u32,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    /// Dispositions to set, like `default` or `comment`.
+    pub disposition: Vec<String>,
+}
+
+impl Default for TestAudio {
+    fn default() -> Self {
+        TestAudio {encoder: "aac".to_string(), channels: 2, frequency: 440, language: None, title: None, disposition: Vec::new()}
+    }
+}
+
+/// A subtitle track, with a line every couple of seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestSubtitle {
+    /// The ffmpeg encoder, like `srt`, `ass` or `mov_text`.
+    pub encoder: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub disposition: Vec<String>,
+}
+
+impl Default for TestSubtitle {
+    fn default() -> Self {
+        TestSubtitle {encoder: "srt".to_string(), language: None, title: None, disposition: Vec::new()}
+    }
+}
+
+/// A file to make.  The container's whatever the output filename's extension says, so the codecs
+/// have to be ones it takes (Matroska takes all of them).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestMedia {
+    /// In seconds.
+    pub duration: f64,
+    pub video: Option<TestVideo>,
+    pub audio: Vec<TestAudio>,
+    pub subtitles: Vec<TestSubtitle>,
+    /// (start, end, title), in seconds.
+    pub chapters: Vec<(f64, f64, String)>,
+    pub title: Option<String>,
+}
+
+/// Whether there's an ffmpeg (and ffprobe) to run.  Tests that need them should skip themselves
+/// without.
+pub fn ffmpeg_available() -> bool {
+    ["ffmpeg", "ffprobe"].iter().all(|program| Command::new(program).arg("-version")
+        .stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|x| x.success()))
+}
+
+// a line every two seconds
+fn srt(duration: f64) -> String {
+    let time = |seconds: u32| format!("00:{:02}:{:02},000", seconds / 60, seconds % 60);
+    let mut srt = String::new();
+    for (i, start) in (0..duration as u32).step_by(2).enumerate() {
+        let _ = write!(srt, "{}\n{} --> {}\nLine {}\n\n", i + 1, time(start), time(start + 1), i + 1);
+    }
+    srt
+}
+
+fn ffmetadata(chapters: &[(f64, f64, String)]) -> String {
+    let mut metadata = ";FFMETADATA1\n".to_string();
+    for (start, end, title) in chapters {
+        let _ = write!(metadata, "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (start * 1000.0) as u64, (end * 1000.0) as u64, title);
+    }
+    metadata
+}
+
+// `-metadata:s:a:0 language=jpn` and so on
+fn add_stream_tags(command: &mut FfmpegInvocation, stream: &str, language: &Option<String>, title: &Option<String>, disposition: &[String]) {
+    if let Some(language) = language {
+        command.args([format!("-metadata:s:{}", stream), format!("language={}", language)]);
+    }
+    if let Some(title) = title {
+        command.args([format!("-metadata:s:{}", stream), format!("title={}", title)]);
+    }
+    let disposition = match disposition.is_empty() {
+        true => "0".to_string(),
+        false => disposition.join("+"),
+    };
+    command.args([format!("-disposition:{}", stream), disposition]);
+}
+
+impl TestMedia {
+    // the files the subtitles and chapters are read from, next to `path`
+    fn side_files(path: &Path) -> (PathBuf, PathBuf) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        (path.with_file_name(format!(".{}.srt", name)), path.with_file_name(format!(".{}.ffmetadata", name)))
+    }
+
+    /// The command that makes the file at `path`.  It reads the subtitles and chapters from files
+    /// that `generate` writes first.
+    pub fn command(&self, path: &Path) -> FfmpegInvocation {
+        let (srt_path, metadata_path) = Self::side_files(path);
+        let duration = self.duration.to_string();
+        let mut command = FfmpegInvocation::new();
+        command.global_arg("-hide_banner").global_arg("-v").global_arg("error").global_arg("-y");
+        let mut maps = Vec::new();
+        if let Some(video) = &self.video {
+            command.args(["-f", "lavfi"]);
+            let input = command.input(format!("testsrc2=size={}x{}:rate={}:duration={}", video.width, video.height, video.frame_rate, duration));
+            maps.push(format!("{}:0", input));
+        }
+        for audio in &self.audio {
+            command.args(["-f", "lavfi"]);
+            let input = command.input(format!("sine=frequency={}:sample_rate=48000:duration={}", audio.frequency, duration));
+            maps.push(format!("{}:0", input));
+        }
+        for _ in &self.subtitles {
+            let input = command.input(&srt_path);
+            maps.push(format!("{}:0", input));
+        }
+        let chapters = (!self.chapters.is_empty()).then(|| command.input(&metadata_path));
+
+        for map in &maps {
+            command.args(["-map", map.as_str()]);
+        }
+        if let Some(input) = chapters {
+            command.args(["-map_chapters".to_string(), input.to_string()]);
+        }
+        if let Some(video) = &self.video {
+            command.args(["-c:v", video.encoder.as_str()]);
+            if let Some(pix_fmt) = &video.pix_fmt {
+                command.args(["-pix_fmt", pix_fmt.as_str()]);
+            }
+            add_stream_tags(&mut command, "v:0", &None, &None, &["default".to_string()]);
+        }
+        for (i, audio) in self.audio.iter().enumerate() {
+            command.args([format!("-c:a:{}", i), audio.encoder.clone(), format!("-ac:a:{}", i), audio.channels.to_string()]);
+            add_stream_tags(&mut command, &format!("a:{}", i), &audio.language, &audio.title, &audio.disposition);
+        }
+        for (i, subtitle) in self.subtitles.iter().enumerate() {
+            command.args([format!("-c:s:{}", i), subtitle.encoder.clone()]);
+            add_stream_tags(&mut command, &format!("s:{}", i), &subtitle.language, &subtitle.title, &subtitle.disposition);
+        }
+        if let Some(title) = &self.title {
+            command.args(["-metadata".to_string(), format!("title={}", title)]);
+        }
+        command.args(["-t", duration.as_str()]);
+        command.output(path);
+        command
+    }
+
+    /// Makes the file at `path`.
+    pub fn generate(&self, path: &Path) -> std::io::Result<()> {
+        let (srt_path, metadata_path) = Self::side_files(path);
+        std::fs::write(&srt_path, srt(self.duration))?;
+        std::fs::write(&metadata_path, ffmetadata(&self.chapters))?;
+        let result = CliRunner.run(&self.command(path));
+        let _ = std::fs::remove_file(srt_path);
+        let _ = std::fs::remove_file(metadata_path);
+        result
+    }
+}
@@ -0,0 +1,131 @@
+// Picking tracks by hand before a transcode, for files where going by language isn't enough: a
+// commentary track in the same language as the real one, a dub nobody wants, signs-and-songs
+// subtitles next to the full ones.  It's a plain prompt on whatever terminal it's given rather
+// than a full-screen UI, so it works over ssh, in tmux, and piped.
+
+use crate::ffprobe::{FFprobeResult, Program, Track, TrackType};
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+/// `probe` with only the tracks in `keep` (by stream index), and the video, which is always kept.
+pub fn keep_tracks(probe: &FFprobeResult, keep: &BTreeSet<u16>) -> FFprobeResult {
+    let mut probe = probe.clone();
+    probe.tracks.retain(|x| matches!(x.kind, TrackType::Video) || keep.contains(&x.index));
+    probe
+}
+
+/// The program to use, out of a multi-program MPEG-TS (a broadcast capture): `program` (by id) if
+/// it's given, or else the first one with video in it.  `None` if there aren't programs to pick
+/// between, or none of them has video.
+pub fn choose_program(probe: &FFprobeResult, program: Option<u32>) -> std::io::Result<Option<&Program>> {
+    match program {
+        Some(id) => match probe.programs.iter().find(|x| x.id == id) {
+            Some(program) => Ok(Some(program)),
+            None if probe.programs.is_empty() => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("no program {}: there's only the one", id))),
+            None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("no program {}: there's {}", id,
+                probe.programs.iter().map(describe_program).collect::<Vec<_>>().join(", ")))),
+        },
+        None => Ok(probe.programs.iter().find(|program| probe.tracks.iter().any(|x| x.is_video() && program.streams.contains(&x.index)))),
+    }
+}
+
+/// `probe` with only the tracks in the program `choose_program` picks, so nothing from the
+/// other channels in the multiplex gets mixed in.  A `program` that isn't there gets a warning,
+/// and the one with video instead.
+pub fn keep_program(probe: &FFprobeResult, program: Option<u32>) -> FFprobeResult {
+    let chosen = choose_program(probe, program).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        choose_program(probe, None).unwrap_or_default()
+    });
+    let Some(chosen) = chosen else {
+        return probe.clone();
+    };
+    let mut probe = probe.clone();
+    probe.tracks.retain(|x| chosen.streams.contains(&x.index));
+    // so doing it again leaves it alone
+    probe.programs.clear();
+    probe
+}
+
+/// A program's id and name, for picking it out from the rest.
+pub fn describe_program(program: &Program) -> String {
+    match &program.name {
+        Some(name) => format!("{} ({})", program.id, name),
+        None => program.id.to_string(),
+    }
+}
+
+/// One line about `track`, for picking it out from the rest.
+pub fn describe(track: &Track) -> String {
+    let kind = match track.kind {
+        TrackType::Video => "video",
+        TrackType::Audio => "audio",
+        TrackType::Subtitle => "subtitle",
+    };
+    let details = match track.kind {
+        TrackType::Video => track.scanline_count.map(|x| format!("{}p", x)),
+        TrackType::Audio => track.channels.map(|x| format!("{} ch", x)),
+        TrackType::Subtitle => None,
+    };
+    format!("{:>3}  {:<8}  {:<10}  {:<3}  {:<6}  {:<16}  {}",
+        track.index, kind, track.codec,
+        track.language.as_ref().map_or("", |x| x.as_str()),
+        details.unwrap_or_default(),
+        track.disposition.join(","),
+        track.title.as_deref().unwrap_or("")).trim_end().to_owned()
+}
+
+const HELP: &str = "\
+numbers toggle tracks (\"3 4\"), \"m N\" muxes audio track N into the video and drops the other audio,
+\"a\" keeps everything, \"n\" drops all the audio and subtitles, enter goes ahead, \"q\" gives up";
+
+/// Shows the tracks in `probe` on `output` and lets whoever's at `input` choose which audio and
+/// subtitle tracks to keep, starting from all of them.  Returns `probe` with only those (see
+/// `keep_tracks`).  Running out of input counts as going ahead; giving up is an `Interrupted`
+/// error.
+pub fn select_tracks(probe: &FFprobeResult, mut input: impl BufRead, mut output: impl Write) -> std::io::Result<FFprobeResult> {
+    let optional: Vec<&Track> = probe.tracks.iter().filter(|x| !matches!(x.kind, TrackType::Video)).collect();
+    let mut keep: BTreeSet<u16> = optional.iter().map(|x| x.index).collect();
+    writeln!(output, "{}", HELP)?;
+    loop {
+        writeln!(output)?;
+        for track in &probe.tracks {
+            let mark = match matches!(track.kind, TrackType::Video) || keep.contains(&track.index) {
+                true => "[x]",
+                false => "[ ]",
+            };
+            writeln!(output, "{} {}", mark, describe(track))?;
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        let mut words = line.split_whitespace().peekable();
+        match words.peek().copied() {
+            None => break,
+            Some("q") => return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "track selection cancelled")),
+            Some("a") => keep = optional.iter().map(|x| x.index).collect(),
+            Some("n") => keep.clear(),
+            Some("m") => match words.nth(1).and_then(|x| x.parse::<u16>().ok()) {
+                Some(index) if optional.iter().any(|x| x.index == index && matches!(x.kind, TrackType::Audio)) => {
+                    keep.retain(|x| !optional.iter().any(|track| track.index == *x && matches!(track.kind, TrackType::Audio)));
+                    keep.insert(index);
+                },
+                _ => writeln!(output, "\"m\" takes the number of an audio track")?,
+            },
+            Some(_) => {
+                for word in words {
+                    match word.parse::<u16>().ok().filter(|x| optional.iter().any(|track| track.index == *x)) {
+                        Some(index) if !keep.remove(&index) => { keep.insert(index); },
+                        Some(_) => {},
+                        None => writeln!(output, "no audio or subtitle track {:?}", word)?,
+                    }
+                }
+            },
+        }
+    }
+    Ok(keep_tracks(probe, &keep))
+}
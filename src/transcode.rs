@@ -1,159 +1,868 @@
-use crate::ffprobe::{FFprobeResult, Track, TrackType};
-use crate::cytube_structs::{CytubeVideo, Source, TextTrack as CTTextTrack, AudioTrack as CTAudioTrack};
+use crate::benchmark::AutoEncoder;
+use crate::cgroup::Cgroup;
+use crate::chapters::skip_markers;
+use crate::ffprobe::{Chapter, FFprobeResult, Track, TrackType};
+use crate::compat::{AudioContainer, BrowserProfile, CodecPolicy, VideoContainer};
+use crate::filters::{pad, Chain, Filter, FilterGraph};
+use crate::cytube_structs::{CytubeVideo, Source, SCHEMA_VERSION, TextTrack as CTTextTrack, AudioTrack as CTAudioTrack};
 use crate::ffmpeg_languages::*;
-use std::path::Path;
+use crate::encoder::{estimate_video_kbps, AudioCodec, AudioPolicy, H26xConstraints, SvtAv1Options, VideoEncoder};
+use crate::plan::{PartialOutputs, TranscodePlan};
+use crate::labels::{channel_layout_display_name, distinguish_labels, fill_label_template, LabelFields, LabelLanguage};
+use crate::metadata::{Metadata, MetadataSource};
+use crate::release_name::parse_release_name;
+use crate::segmented::{plan_segments, segment_list_path};
+use crate::signing::SignedUrls;
+use crate::size_model::{settings_key, SizeGuess, SizeModel};
+use crate::storyboard::Storyboard;
+use crate::track_select::keep_program;
+use crate::transcode_cache::TranscodeCache;
+use crate::upload::Upload;
+use crate::waveform::Waveform;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use crate::invocation::FfmpegInvocation;
 use std::process::Command;
 use fixedstr::str4;
 use std::collections::HashMap;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
 
-const BITMAP_SUBTITLE_CODECS: [&'static str; 4] = [
+const BITMAP_SUBTITLE_CODECS: [&str; 4] = [
     "dvb_subtitle",
     "dvd_subtitle",
     "hdmv_pgs_subtitle",
     "xsub",
 ];
 
-enum VideoContainer {
-    MP4, WEBM, OGG
+// whether `track` can be turned into WebVTT with `options` as they are
+fn convertible_subtitles(track: &Track, options: &TranscodeOptions) -> bool {
+    match track.codec.as_str() {
+        // ffmpeg can't do OCR
+        codec if BITMAP_SUBTITLE_CODECS.contains(&codec) => false,
+        "dvb_teletext" => options.teletext,
+        _ => true,
+    }
 }
 
-fn find_video_container(video_codec: &str) -> Option<VideoContainer> {
-    use VideoContainer::*;
-    match video_codec {
-        "av1" | "vp8" | "vp9" => Some(WEBM),
-        "h264" | // H.264
-        "hevc" | // H.265
-        "mpeg4"| // MP4V-ES
-        "mpeg2video"
-                 => Some(MP4),
-        "theora" => Some(OGG),
-        _ => None,
+fn strcat(first: &str, rest: &[&str]) -> String {
+    let mut s = String::from(first);
+    for next in rest {
+        s.push_str(next);
     }
+    s
+}
+
+/// How the muxed video source gets packaged on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Packaging {
+    /// One progressive file, `main.<ext>`.
+    #[default]
+    Progressive,
+    /// A single set of CMAF fMP4 segments referenced by both a DASH manifest (`main.mpd`) and an
+    /// HLS playlist (`master.m3u8`), so serving both manifest types doesn't double the storage.
+    /// Only applies when the video ends up in an MP4-family container.
+    Cmaf { segment_duration: u32 },
+}
+
+/// The tags `TranscodeOptions::copy_tags` copies if it's left unset.
+pub const DEFAULT_COPIED_TAGS: [&str; 6] = ["title", "artist", "album_artist", "album", "date", "track"];
+
+// codecs that can be decoded and re-encoded to FLAC without losing anything
+const LOSSLESS_AUDIO_CODECS: [&str; 6] = ["alac", "ape", "flac", "mlp", "tta", "wavpack"];
+
+// cytube wants a quality on every source, even ones without any picture
+const AUDIO_ONLY_QUALITY: u16 = 240;
+
+// what we assume the stereo audio we encode alongside transcoded video comes out at, in kbps
+const ESTIMATED_AUDIO_KBPS: u64 = 128;
+
+// where VA-API encodes go when `hwaccel` doesn't name a device
+const DEFAULT_VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+// (filename, mimetype) of each manifest the dash muxer writes in CMAF mode
+const CMAF_MANIFESTS: [(&str, &str); 2] = [
+    ("master.m3u8", "application/x-mpegURL"),
+    ("main.mpd", "application/dash+xml"),
+];
+
+fn add_cmaf_output(command: &mut FfmpegInvocation, outputdir: &Path, segment_duration: u32) {
+    // the dash muxer writes fMP4 segments and, with hls_playlist, an HLS playlist pointing at the
+    // very same segments.  movflags=cmaf makes the segments themselves CMAF-conformant.
+    command.args([
+                 "-f", "dash",
+                 "-dash_segment_type", "mp4",
+                 "-format_options", "movflags=+cmaf",
+                 "-seg_duration", segment_duration.to_string().as_str(),
+                 "-use_template", "1",
+                 "-use_timeline", "0",
+                 "-hls_playlist", "1",
+                 "-init_seg_name", "init_$RepresentationID$.m4s",
+                 "-media_seg_name", "chunk_$RepresentationID$_$Number%05d$.m4s",
+    ]);
+    command.output(outputdir.join(CMAF_MANIFESTS[1].0));
+}
+
+/// Hardware decoding for the input side.  Only matters when the video actually gets decoded, i.e.
+/// when it's being transcoded.  Decoded frames are copied back to system memory, so this works
+/// with any encoder.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HwAccel {
+    #[default]
+    None,
+    /// Let ffmpeg pick whatever it can find.
+    Auto,
+    /// VA-API, optionally on a specific render node (e.g. `/dev/dri/renderD128`).
+    Vaapi { device: Option<PathBuf> },
+    Cuda,
 }
 
-impl VideoContainer {
-    fn get_acceptable_audio_codecs(&self) -> &'static [&'static str] {
-        use VideoContainer::*;
+impl HwAccel {
+    /// Adds the `-hwaccel` options.  These are input options, so this goes right before the `-i`.
+    pub(crate) fn add_input_args(&self, command: &mut FfmpegInvocation) {
         match self {
-            MP4  => &["aac", "alac", "flac", "opus", "mp3"],
-            WEBM => &["opus", "vorbis"],
-            OGG  => &["opus", "vorbis", "flac"],
+            HwAccel::None => {},
+            HwAccel::Auto => { command.args(["-hwaccel", "auto"]); },
+            HwAccel::Vaapi { device } => {
+                command.args(["-hwaccel", "vaapi"]);
+                if let Some(device) = device {
+                    command.arg("-hwaccel_device").arg(device);
+                }
+            },
+            HwAccel::Cuda => { command.args(["-hwaccel", "cuda"]); },
         }
     }
-    fn preferred_audio_encoder(&self) -> &'static str {
-        use VideoContainer::*;
-        match self {
-            MP4 => "aac",
-            WEBM | OGG => "libopus",
+}
+
+/// Which subtitle track, if any, the manifest marks as the one to show by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum DefaultSubtitles {
+    /// None of them; the viewer turns subtitles on if they want them.
+    #[default]
+    None,
+    /// Forced subtitles (signs, and dialogue that isn't in the audio's language) in the
+    /// preferred language.
+    Forced,
+    /// The preferred language's full subtitles, or its forced ones if that's all there is.
+    Preferred,
+    /// Whichever one the input marks as default.
+    Source,
+}
+
+// forced subtitles aren't always flagged as such, but they're nearly always called something
+// like "Signs & Songs"
+fn is_forced(track: &Track) -> bool {
+    let title = track.title.as_deref().unwrap_or("").to_lowercase();
+    track.disposition.iter().any(|x| x == "forced") || title.contains("forced") || title.contains("sign")
+}
+
+impl DefaultSubtitles {
+    // the stream index of the track to mark, out of `tracks`
+    fn choose(&self, tracks: &[&Track], preferred_language: Option<str4>) -> Option<u16> {
+        let preferred = || tracks.iter().filter(|x| x.language.is_some() && x.language == preferred_language);
+        let track = match self {
+            DefaultSubtitles::None => None,
+            DefaultSubtitles::Forced => preferred().find(|x| is_forced(x)),
+            DefaultSubtitles::Preferred => preferred().find(|x| !is_forced(x)).or_else(|| preferred().next()),
+            DefaultSubtitles::Source => tracks.iter().find(|x| x.disposition.iter().any(|x| x == "default")),
+        };
+        track.map(|x| x.index)
+    }
+}
+
+/// Which ReplayGain to go by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum ReplayGainMode {
+    /// Every track at the same loudness.
+    Track,
+    /// Every album at the same loudness, with the quiet songs on it still quieter than the loud
+    /// ones.  Tracks without an album gain go by their track gain.
+    Album,
+}
+
+impl ReplayGainMode {
+    /// How many dB to turn `ffprobe`'s file up by, or `None` if it isn't tagged.  Held down to
+    /// what the peak allows, so nothing clips.
+    pub fn gain(&self, ffprobe: &FFprobeResult) -> Option<f32> {
+        let tags = match self {
+            ReplayGainMode::Track => ffprobe.track_gain,
+            ReplayGainMode::Album => ffprobe.album_gain.or(ffprobe.track_gain),
+        }?;
+        let headroom = tags.peak.filter(|x| *x > 0.0).map_or(f32::INFINITY, |x| -20.0 * x.log10());
+        Some(tags.gain.min(headroom))
+    }
+}
+
+/// What to do with 10-bit (Main 10) HEVC.  It copies into MP4 just fine, but only some
+/// browser/OS combinations will actually play it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TenBitHevcPolicy {
+    /// Copy it anyway.
+    #[default]
+    Copy,
+    /// Transcode it to 8-bit with the fallback encoder.
+    Transcode,
+    /// Copy it, and also emit an 8-bit transcode alongside it.
+    Both,
+}
+
+// HDR HEVC is always 10-bit, even when the profile and pixel format don't make it out of the
+// container
+fn is_10bit_hevc(video: &Track) -> bool {
+    video.codec == "hevc" && (
+        video.profile.as_deref() == Some("Main 10") ||
+        video.pix_fmt.as_ref().is_some_and(|x| x.contains("p10")) ||
+        video.is_hdr()
+    )
+}
+
+/// Knobs controlling how `remux` lays out its outputs.
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOptions {
+    /// Language (ISO 639-2/B, as ffmpeg reports it) to prefer when there's a choice to be made.
+    /// Its audio and text tracks go first in the manifest, so they're what the player starts with.
+    pub preferred_language: Option<str4>,
+    /// Only audio tracks in these languages are kept; the rest are left out of the outputs and
+    /// the manifest.  Tracks with no language tag are always kept, since there's no telling what
+    /// they are.  `None` keeps everything.  After `preferred_language`, tracks go in the manifest
+    /// in the order their languages are listed here.
+    pub audio_languages: Option<Vec<str4>>,
+    /// The same, for subtitles.
+    pub subtitle_languages: Option<Vec<str4>>,
+    /// Leave out every subtitle track.
+    pub no_subtitles: bool,
+    /// Which program of an MPEG-TS with more than one (a broadcast capture) to use, by id.
+    /// Without one, it's the first with video in it.  Tracks in the others are left out.
+    pub program: Option<u32>,
+    /// Pull CEA-608/708 closed captions out of the video (broadcast captures have them there
+    /// instead of in a subtitle track) into a text track of their own.  It means decoding the
+    /// whole video a second time, since the captions only come out of the decoder.
+    pub closed_captions: bool,
+    /// Convert DVB teletext subtitles, which needs an ffmpeg built with libzvbi
+    /// (`--enable-libzvbi`).  Without this they're left out.
+    pub teletext: bool,
+    pub default_subtitles: DefaultSubtitles,
+    /// What language track labels name languages in.
+    pub label_language: LabelLanguage,
+    /// What goes in track labels, e.g. `"{lang} – {codec} {channels} ({title})"`; see
+    /// `labels::fill_label_template`.  Without one, it's the language and then the title in
+    /// brackets.  Tracks without a language are labelled by their title either way.
+    pub label_template: Option<String>,
+    /// Heights of smaller renditions to encode alongside the main one (e.g. `[720, 480]`), for
+    /// viewers whose connection can't keep up with the full thing.  They're all scaled from the
+    /// one decode of the source, in the same ffmpeg.  Heights the source isn't taller than are
+    /// skipped.  They should be qualities cytube knows (`CYTUBE_ACCEPTABLE_QUALITY_VALUES`), and
+    /// they're always progressive files, even when the main one's CMAF.
+    pub renditions: Vec<u16>,
+    /// Also make a storyboard (thumbnail sprite sheets and a VTT pointing into them) for seek
+    /// previews.  See `storyboard`.
+    pub storyboard: Option<Storyboard>,
+    /// Also write waveform peaks for each audio output (music, or a film's separate audio
+    /// tracks).  See `waveform`.
+    pub waveform: Option<Waveform>,
+    /// Write skip markers for chapters named like an opening, credits or a preview.  See
+    /// `chapters::skip_markers`.
+    pub skip_markers: bool,
+    /// Write `preview.html`, a page that plays the outputs, next to the manifest.  See
+    /// `preview_page`.
+    pub preview_page: bool,
+    /// Write the MP4 video source as a fragmented MP4 (`frag_keyframe+empty_moov`), so it can be
+    /// played back while it's still being written or uploaded.
+    pub fragmented_mp4: bool,
+    pub packaging: Packaging,
+    /// When the video has to be transcoded, split it into this many time segments and encode them
+    /// in parallel (see `segmented::plan_segments`).  0 or 1 means don't.
+    pub parallel_segments: usize,
+    pub hwaccel: HwAccel,
+    /// How many threads the video encoder and ffmpeg's filters get.  `None` leaves it to them,
+    /// which is usually one per core.
+    pub threads: Option<u16>,
+    /// CPUs to keep ffmpeg on, in `taskset`'s list format (e.g. `4-11` or `0,2,4-7`), so it
+    /// leaves the rest alone for whatever else the machine does.  It's run through `taskset`.
+    pub cpu_affinity: Option<String>,
+    /// Run ffmpeg in a cgroup with a memory limit and a CPU weight.  See `cgroup`.
+    pub cgroup: Option<Cgroup>,
+    /// What to encode the video to when the source can't be copied.
+    pub fallback_encoder: VideoEncoder,
+    /// Pick the encoder from a benchmark of this machine's instead: the fastest one that's good
+    /// enough and that `target` can play.  `fallback_encoder` is only used if none of them is.
+    pub auto_encoder: Option<Arc<AutoEncoder>>,
+    pub av1: SvtAv1Options,
+    /// What audio gets copied, and what it's encoded to when it isn't.
+    pub audio: AudioPolicy,
+    /// Profile/level/pixel format to hold H.264 to, both when encoding it with x264 and when
+    /// deciding whether an H.264 source can be copied as-is.
+    pub h264: Option<H26xConstraints>,
+    /// Same as `h264`, for HEVC and x265.
+    pub hevc: Option<H26xConstraints>,
+    pub hevc_10bit: TenBitHevcPolicy,
+    /// Which browsers have to be able to play the result.  Decides what gets copied, and can
+    /// overrule `fallback_encoder`.
+    pub target: BrowserProfile,
+    /// Overrides for which codecs get copied, on top of `target`.  Usually from the config file.
+    pub codec_policy: CodecPolicy,
+    /// What happens to the outputs of a transcode that fails or gets cancelled.
+    pub partial_outputs: PartialOutputs,
+    /// Calibrates bitrate guesses against earlier transcodes, and learns from this one.  Without
+    /// it, guesses come straight from the encoder's bits-per-pixel table.
+    pub size_model: Option<Arc<SizeModel>>,
+    /// Where to look for (and keep) finished transcodes, so the same input with the same options
+    /// is only ever encoded once.
+    pub cache: Option<TranscodeCache>,
+    /// What to title the manifest, filled in from the filename; see `ReleaseName::fill_template`.
+    /// Without one, the title is the file's title tag if it has one, or else the filename tidied
+    /// up by `ReleaseName::display_title`.
+    pub title_template: Option<String>,
+    /// Somewhere to look up titles and such that beat what's in the file.
+    pub metadata: Option<Arc<dyn MetadataSource>>,
+    /// Keep music gapless, so an album queued track by track plays without seams: tracks cut out
+    /// of a bigger file (a cue sheet rip) are decoded and cut on the exact sample (into FLAC, if
+    /// the source is lossless) rather than copied, and MP4 outputs always get an edit list
+    /// trimming the encoder's priming samples.
+    pub gapless: bool,
+    /// Turn music up or down by its ReplayGain (or R128) tags, so everything plays at about the
+    /// same loudness.  Means transcoding anything that has the tags.
+    pub replay_gain: Option<ReplayGainMode>,
+    /// Tags to copy from the input file onto every output, by name (any of
+    /// `FFprobeResult::tags`), and nothing else.  `None` means `DEFAULT_COPIED_TAGS`.  An input
+    /// without a `title` gets the manifest's.
+    pub copy_tags: Option<Vec<String>>,
+    /// Write outputs with no tags at all (on the file or its streams), no chapters and no
+    /// encoder version strings, so nothing about where the input came from (GPS coordinates
+    /// and creation times from a phone, say) gets published.  Overrides `copy_tags`.  Anything
+    /// inside a stream that's copied rather than encoded is left alone.
+    pub strip_metadata: bool,
+    /// Make the same input with the same options come out byte-for-byte the same every time
+    /// (with the same ffmpeg), so copies can be deduplicated by hash: no encoder version
+    /// strings, no dates, no random IDs.  Tags from `copy_tags` are still written.
+    pub deterministic: bool,
+    /// Sign the URLs in the manifest (see `signing`), for outputs that are going somewhere that
+    /// isn't public.  They're signed as the finished manifest is written.
+    pub signed_urls: Option<SignedUrls>,
+    /// Upload the outputs once they're done.  The URL prefix passed to `plan` should be
+    /// `Upload::url_prefix`, so the manifest points at them.
+    pub upload: Option<Upload>,
+}
+
+impl TranscodeOptions {
+    // for an output in `container`, with `fragmented_mp4`: moov atom up front and a fragment at
+    // every keyframe, so the browser can start playing before the file is finished (and proxies
+    // doing chunked transfer don't choke on it)
+    fn add_fragmented_mp4_args(&self, command: &mut FfmpegInvocation, container: VideoContainer) {
+        if self.fragmented_mp4 && matches!(container, VideoContainer::MP4) {
+            command.args(["-movflags", "frag_keyframe+empty_moov+default_base_moof"]);
         }
     }
-    fn extension(&self) -> &'static str {
-        use VideoContainer::*;
-        match self {
-            MP4  => "mp4",
-            WEBM => "webm",
-            OGG  => "ogv",
+
+    fn keeps_audio(&self, track: &Track) -> bool {
+        match (&self.audio_languages, &track.language) {
+            (Some(languages), Some(language)) => languages.contains(language),
+            _ => true,
         }
     }
-    fn mimetype(&self) -> &'static str {
-        use VideoContainer::*;
-        match self {
-            MP4  => "video/mp4",
-            WEBM => "video/webm",
-            OGG  => "video/ogg",
+
+    // where tracks in `language` go in the manifest, lowest first: the preferred language, then
+    // the order they're listed in `languages`, then everything else.  cytube's player picks the
+    // first audio and text tracks to start with
+    fn language_rank(&self, language: Option<&str4>, languages: Option<&Vec<str4>>) -> usize {
+        let listed = languages.and_then(|x| x.iter().position(|x| Some(x) == language));
+        match language {
+            Some(_) if language == self.preferred_language.as_ref() => 0,
+            _ => listed.map_or(usize::MAX, |x| x + 1),
+        }
+    }
+
+    // a track's label, from `label_template` if there is one
+    fn track_label(&self, fields: &LabelFields) -> String {
+        match &self.label_template {
+            Some(template) => fill_label_template(template, fields, &self.label_language),
+            None => self.label_language.label(fields.language, fields.title),
+        }
+    }
+
+    fn keeps_subtitle(&self, track: &Track) -> bool {
+        match (&self.subtitle_languages, &track.language) {
+            _ if self.no_subtitles => false,
+            (Some(languages), Some(language)) => languages.contains(language),
+            _ => true,
+        }
+    }
+
+    // what estimate_transcoded_kbps would be without the size model
+    fn guess_transcoded_kbps(&self, video: &Track) -> u64 {
+        estimate_video_kbps(self.encoder(), &self.av1, video.scanline_count.unwrap_or(1080), video.frame_rate) + ESTIMATED_AUDIO_KBPS
+    }
+
+    /// Best guess at the total bitrate, in kbps, of `video` after it's been through
+    /// `fallback_encoder`, plus the audio that goes with it.
+    pub(crate) fn estimate_transcoded_kbps(&self, video: &Track) -> u64 {
+        let correction = self.size_model.as_ref().map_or(1.0, |x| x.correction(&settings_key(self)));
+        (self.guess_transcoded_kbps(video) as f64 * correction) as u64
+    }
+
+    // remembers that `filename` is a transcode of `video`, so the size model can check the guess
+    fn size_guess(&self, filename: &str, video: &Track) -> SizeGuess {
+        SizeGuess {filename: filename.to_owned(), key: settings_key(self), guessed_kbps: self.guess_transcoded_kbps(video)}
+    }
+
+    fn video_container(&self, video_codec: &str) -> Option<VideoContainer> {
+        self.codec_policy.video_container(self.target, video_codec)
+    }
+
+    fn audio_container(&self, audio_codec: &str) -> Option<AudioContainer> {
+        self.codec_policy.audio_container(self.target, audio_codec).filter(|_| self.audio.may_copy(audio_codec))
+    }
+
+    fn accepts_audio_in(&self, container: VideoContainer, audio_codec: &str) -> bool {
+        self.codec_policy.accepts_audio_in(self.target, container, audio_codec) && self.audio.may_copy(audio_codec)
+    }
+
+    /// What `auto_encoder` picks, or `fallback_encoder`, unless the target browsers can't play
+    /// what it makes.
+    pub(crate) fn encoder(&self) -> VideoEncoder {
+        self.auto_encoder.as_ref().and_then(|x| x.choose(self.target))
+            .unwrap_or_else(|| self.target.fallback_encoder(self.fallback_encoder))
+    }
+
+    // the renditions `video` gets, tallest first
+    fn rendition_heights(&self, video: &Track) -> Vec<u16> {
+        let mut heights: Vec<u16> = self.renditions.iter().copied()
+            .filter(|x| video.scanline_count.is_some_and(|height| *x < height))
+            .collect();
+        heights.sort_by(|a, b| b.cmp(a));
+        heights.dedup();
+        heights
+    }
+
+    /// ffmpeg arguments for encoding `video` with `encoder()`.
+    pub(crate) fn video_encoder_args(&self, video: &Track) -> Vec<String> {
+        self.encoder_args_for(self.encoder(), video)
+    }
+
+    /// ffmpeg arguments for encoding `video` with `encoder`, with these options' settings for it.
+    pub(crate) fn encoder_args_for(&self, encoder: VideoEncoder, video: &Track) -> Vec<String> {
+        let mut args = match encoder {
+            VideoEncoder::SvtAv1 => self.av1.encoder_args(),
+            VideoEncoder::X264 | VideoEncoder::Nvenc | VideoEncoder::Vaapi => H26xConstraints::encoder_args(self.h264.as_ref(), encoder.ffmpeg_name()),
+            VideoEncoder::X265 => H26xConstraints::encoder_args(self.hevc.as_ref(), "libx265"),
+            // libvpx is constant quality only with a bitrate of 0, and single-threaded without row-mt
+            VideoEncoder::Vp9 => ["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "31", "-row-mt", "1"].map(str::to_string).to_vec(),
+        };
+        if self.deterministic && encoder == VideoEncoder::X265 {
+            // x265 puts its version and command line in the stream otherwise
+            add_encoder_param(&mut args, "-x265-params", "info=0");
+        }
+        if let Some(threads) = self.threads {
+            // x265 and SVT-AV1 ignore -threads and size their thread pools themselves
+            match encoder {
+                VideoEncoder::SvtAv1 => add_encoder_param(&mut args, "-svtav1-params", &format!("lp={}", threads)),
+                VideoEncoder::X265 => add_encoder_param(&mut args, "-x265-params", &format!("pools={}", threads)),
+                VideoEncoder::X264 | VideoEncoder::Vp9 => args.extend(["-threads".to_string(), threads.to_string()]),
+                // they run on the GPU
+                VideoEncoder::Nvenc | VideoEncoder::Vaapi => {},
+            }
+        }
+        // the encoders would happily keep it 10-bit otherwise.  VA-API's upload makes it 8-bit anyway
+        if self.hevc_10bit != TenBitHevcPolicy::Copy && is_10bit_hevc(video) && encoder != VideoEncoder::Vaapi && !args.iter().any(|x| x == "-pix_fmt") {
+            args.extend(["-pix_fmt".to_string(), "yuv420p".to_string()]);
+        }
+        args
+    }
+
+    /// What to run ffmpeg through, for `cgroup` and `cpu_affinity`.  See
+    /// `FfmpegInvocation::launcher`.
+    pub(crate) fn launcher(&self) -> Vec<OsString> {
+        let mut launcher = self.cgroup.as_ref().map(Cgroup::launcher).unwrap_or_default();
+        if let Some(cpus) = &self.cpu_affinity {
+            launcher.extend(["taskset".into(), "--cpu-list".into(), cpus.into()]);
+        }
+        launcher
+    }
+
+    /// Global options the encoder needs before any of the inputs: VA-API has to be told which
+    /// device to upload frames to.
+    pub(crate) fn encoder_device_args(&self, encoder: VideoEncoder) -> Vec<String> {
+        if encoder != VideoEncoder::Vaapi {
+            return Vec::new();
+        }
+        let device = match &self.hwaccel {
+            HwAccel::Vaapi { device: Some(device) } => device.to_string_lossy().into_owned(),
+            _ => DEFAULT_VAAPI_DEVICE.to_string(),
+        };
+        vec!["-init_hw_device".to_string(), format!("vaapi=enc:{}", device), "-filter_hw_device".to_string(), "enc".to_string()]
+    }
+
+    /// What the video goes through last before the encoder, if anything: VA-API only encodes
+    /// frames that are on the GPU.  Goes on the end of the video's filters, or in a `-vf` if it
+    /// doesn't have any.
+    pub(crate) fn encoder_upload(&self, encoder: VideoEncoder) -> Option<Chain> {
+        (encoder == VideoEncoder::Vaapi).then(|| Chain::new().then(Filter::new("format").arg("nv12")).then(Filter::new("hwupload")))
+    }
+
+    // `encoder_upload`, as a `-vf`, for video that's mapped straight from the input
+    fn encoder_upload_args(&self) -> Vec<String> {
+        match self.encoder_upload(self.encoder()) {
+            Some(chain) => vec!["-vf".to_string(), chain.to_string()],
+            None => Vec::new(),
         }
     }
 }
 
-enum AudioContainer {
-    M4A, OGG,
-    // Every source I can find on the internet says that M4A files are just renamed MP4 files that
-    // only contain audio tracks.  However, when I ask ffmpeg to create an M4A file and an MP4 file
-    // with the exact same contents, I get two different files.  It puts the sequence "M4A_" in the
-    // file subtype of one but not the other.  Also it will refuse to put any codec besides AAC or
-    // ALAC in an M4A file.  To work around this, I'm producing "pseudo-M4A" files which are
-    // actually literally renamed MP4 files that only contain audio tracks.  My testing says
-    // browsers will still play them despite the header saying it's an ISO MP4 rather than an M4A.
-    // This allows me to embed audio codecs like MP3 that cytube would otherwise reject.
-    PseudoM4A,
-}
-
-fn find_audio_container(audio_codec: &str) -> Option<AudioContainer> {
-    // Now here's where things get wacky.
-    // Cytube doesn't support adding bare FLAC files, citing browser compatiblitity
-    // issues with the FLAC codec.
-    // Maybe the documentation is just old and Cytube hasn't been updated in a while,
-    // but caniuse.com tells a very different story: green lights across the board for
-    // any browser released in the last couple years, with a 95% compatibility rating.
-    // I should probably see about bugging the guys at Cytube to remove that
-    // restriction.
-    // In the meantime, however, just because we can't use the FLAC *container*
-    // doesn't mean that we can't play FLAC-encoded *audio*.
-    // You see, one of the container formats that Cytube *does* accept is Ogg, and
-    // there are three audio codecs (that browsers support) that can go inside an
-    // Ogg file: Vorbis, Opus, and FLAC.
-    // If we embed FLAC data inside an Ogg file, Cytube won't know the difference.  The
-    // entire point of the custom metadata files is that Cytube doesn't have to
-    // retrieve the files from the media host to run ffprobe on them.  It doesn't know
-    // about the codecs, only the container.  We just tell the server we have an
-    // Ogg file and it says "great" and ships it to the clients.
-    // The Cytube client (webpage) doesn't do any enforcement on its end.  As long as
-    // the browser can play it, it'll play ball.
-    // We can play FLAC files, we just can't *tell Cytube* we're playing FLAC files.
-    use AudioContainer::*;
-    match audio_codec {
-        "aac" | "alac" | "aac_latm" => Some(M4A),
-        "opus" | "vorbis" | "flac" => Some(OGG),
-        "mp3" => Some(PseudoM4A),
-        _ => None,
+// adds `param` to the `option` (`-x265-params` or `-svtav1-params`) in `args`, or adds the option
+fn add_encoder_param(args: &mut Vec<String>, option: &str, param: &str) {
+    match args.iter().position(|x| x == option) {
+        Some(i) => {
+            args[i + 1].push(':');
+            args[i + 1].push_str(param);
+        },
+        None => args.extend([option.to_string(), param.to_string()]),
     }
 }
 
-impl AudioContainer {
-    fn extension(&self) -> &'static str {
-        use AudioContainer::*;
-        match self {
-            OGG => "ogg",
-            M4A | PseudoM4A => "m4a",
+// the rates libopus takes.  it's handed anything else resampled to whichever of these ffmpeg
+// thinks is closest, which for 44.1kHz can be 24kHz
+const OPUS_SAMPLE_RATES: [u32; 5] = [48000, 24000, 16000, 12000, 8000];
+
+// -ac and -ar for encoding audio with `channels` and `sample_rate` (unknown's `None`) to `codec`:
+// more than two channels are downmixed to stereo, which is faster to encode and all a browser plays
+// anyway, and opus gets 48kHz if it can't have the source's rate
+fn audio_format_args(channels: Option<u16>, sample_rate: Option<u32>, codec: AudioCodec) -> Vec<String> {
+    let mut args = Vec::new();
+    if channels.is_none_or(|x| x > 2) {
+        args.extend(["-ac".to_string(), "2".to_string()]);
+    }
+    if codec == AudioCodec::Opus && sample_rate.is_none_or(|x| !OPUS_SAMPLE_RATES.contains(&x)) {
+        args.extend(["-ar".to_string(), "48000".to_string()]);
+    }
+    args
+}
+
+// how many channels `track` has once it's out: encoded ones are downmixed to stereo
+fn output_channels(track: &Track, options: &TranscodeOptions) -> Option<u16> {
+    match options.audio_container(&track.codec) {
+        Some(_) => track.channels,
+        None => Some(track.channels.map_or(2, |x| x.min(2))),
+    }
+}
+
+// which of a language's audio tracks get their own files: the first, and then any that sound
+// different from the ones already picked, since remuxes often carry a stereo mix next to a 5.1 one
+fn distinct_mixes<'a>(tracks: &[&'a Track], options: &TranscodeOptions) -> Vec<&'a Track> {
+    let mut chosen: Vec<&Track> = Vec::new();
+    for track in tracks {
+        let channels = output_channels(track, options);
+        if chosen.is_empty() || channels.is_some() && chosen.iter().all(|x| output_channels(x, options) != channels) {
+            chosen.push(track);
         }
     }
-    fn mimetype(&self) -> &'static str {
-        use AudioContainer::*;
-        match self {
-            OGG => "audio/ogg",
-            M4A | PseudoM4A => "audio/mp4",
+    chosen
+}
+
+fn fallback_container(encoder: VideoEncoder) -> VideoContainer {
+    match encoder {
+        VideoEncoder::SvtAv1 | VideoEncoder::Vp9 => VideoContainer::WEBM,
+        VideoEncoder::X264 | VideoEncoder::X265 | VideoEncoder::Nvenc | VideoEncoder::Vaapi => VideoContainer::MP4,
+    }
+}
+
+/// The container the video gets copied into, or `None` if it has to be transcoded, either because
+/// the target browsers can't play the codec or because it breaks the configured profile/level constraints.
+pub(crate) fn copyable_video_container(video: &Track, options: &TranscodeOptions) -> Option<VideoContainer> {
+    let constraints = match video.codec.as_str() {
+        "h264" => options.h264.as_ref(),
+        "hevc" => options.hevc.as_ref(),
+        _ => None,
+    };
+    if constraints.is_some_and(|x| !x.accepts(video)) {
+        return None;
+    }
+    if options.hevc_10bit == TenBitHevcPolicy::Transcode && is_10bit_hevc(video) {
+        return None;
+    }
+    options.video_container(&video.codec)
+}
+
+/// What `plan` does with a track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum TrackAction {
+    Copy,
+    Transcode,
+    /// Left out of the outputs.
+    Drop,
+}
+
+/// What `plan` does with one of the input's tracks, and why, for working out why something got
+/// transcoded.  See `explain`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackVerdict {
+    pub index: u16,
+    pub action: TrackAction,
+    pub reason: String,
+}
+
+// why `video` can't be copied, if it can't.  the same checks as copyable_video_container
+fn video_transcode_reason(video: &Track, options: &TranscodeOptions) -> Option<String> {
+    let constraints = match video.codec.as_str() {
+        "h264" => options.h264.as_ref(),
+        "hevc" => options.hevc.as_ref(),
+        _ => None,
+    };
+    if constraints.is_some_and(|x| !x.accepts(video)) {
+        return Some(format!("{} breaks the configured profile/level/pixel format constraints", video.codec));
+    }
+    if options.hevc_10bit == TenBitHevcPolicy::Transcode && is_10bit_hevc(video) {
+        let what = if video.is_hdr() { "10-bit HDR HEVC" } else { "10-bit HEVC" };
+        return Some(format!("{}, and hevc_10bit says to transcode it", what));
+    }
+    match options.video_container(&video.codec) {
+        Some(_) => None,
+        None => Some(format!("{} won't play in the {} browser profile (or the codec policy rules it out)", video.codec, options.target)),
+    }
+}
+
+/// What `plan` would do with each of `ffprobe`'s tracks under `options`.
+pub fn explain(ffprobe: &FFprobeResult, options: &TranscodeOptions) -> Vec<TrackVerdict> {
+    let all_tracks = &ffprobe.tracks;
+    let ffprobe = &keep_program(ffprobe, options.program);
+    use TrackAction::*;
+    let verdict = |track: &Track, action, reason: &str| TrackVerdict {index: track.index, action, reason: reason.to_string()};
+    let mut verdicts = Vec::new();
+    let video = ffprobe.tracks.iter().find(|x| x.is_video());
+    let audio_tracks: Vec<&Track> = ffprobe.tracks.iter().filter(|x| matches!(x.kind, TrackType::Audio)).collect();
+    let kept_audio: Vec<&Track> = audio_tracks.iter().copied().filter(|x| options.keeps_audio(x)).collect();
+    let mut languages: Vec<str4> = kept_audio.iter().map(|x| x.language.unwrap_or("".into())).collect();
+    languages.sort();
+    languages.dedup();
+    let video_container = video.and_then(|x| copyable_video_container(x, options));
+
+    // the audio tracks plan picks, and what it does with each
+    let mut picked = HashMap::new();
+    match video {
+        Some(_) if languages.len() == 1 => {
+            let chosen = kept_audio.iter().find(|x| video_container.is_some_and(|container| options.accepts_audio_in(container, &x.codec))).unwrap_or(&kept_audio[0]);
+            let copied = video_container.is_some_and(|container| options.accepts_audio_in(container, &chosen.codec));
+            picked.insert(chosen.index, match copied {
+                true => (Copy, "muxed into the video".to_string()),
+                false => (Transcode, format!("muxed into the video, and {} can't go in with it", chosen.codec)),
+            });
+        },
+        Some(_) => for language in &languages {
+            let tracks: Vec<&Track> = kept_audio.iter().copied().filter(|x| x.language.unwrap_or("".into()) == *language).collect();
+            for track in distinct_mixes(&tracks, options) {
+                picked.insert(track.index, match options.audio_container(&track.codec) {
+                    Some(container) => (Copy, format!("its own .{} file, with silence muxed into the video", container.extension())),
+                    None => (Transcode, format!("its own file, and {} won't play on its own", track.codec)),
+                });
+            }
+        },
+        None => if let Some(track) = kept_audio.iter().find(|x| x.language.is_some() && x.language == options.preferred_language).or(kept_audio.first()) {
+            let gain = options.replay_gain.and_then(|x| x.gain(ffprobe)).is_some();
+            picked.insert(track.index, match options.audio_container(&track.codec) {
+                Some(_) if gain => (Transcode, "the source, turned up or down by its ReplayGain".to_string()),
+                Some(container) => (Copy, format!("the source, as .{}", container.extension())),
+                None => (Transcode, format!("the source, and {} won't play in the {} browser profile", track.codec, options.target)),
+            });
+        },
+    }
+
+    for track in all_tracks {
+        verdicts.push(match track.kind {
+            _ if !ffprobe.tracks.iter().any(|x| x.index == track.index) => verdict(track, Drop, "in another program"),
+            TrackType::Video if track.is_cover_art() => verdict(track, Drop, "cover art"),
+            TrackType::Video if Some(track.index) != video.map(|x| x.index) => verdict(track, Drop, "only the first video track is used"),
+            TrackType::Video => match video_transcode_reason(track, options) {
+                Some(reason) => verdict(track, Transcode, &reason),
+                None => verdict(track, Copy, &format!("plays as-is in .{}", video_container.map_or("", |x| x.extension()))),
+            },
+            TrackType::Audio if !options.keeps_audio(track) => verdict(track, Drop, "not in audio_languages"),
+            TrackType::Audio => match picked.get(&track.index) {
+                Some((action, reason)) => verdict(track, *action, reason),
+                None if video.is_none() => verdict(track, Drop, "without video, only one audio track is used"),
+                None if languages.len() == 1 => verdict(track, Drop, "only one audio track gets muxed into the video"),
+                None => verdict(track, Drop, "another track in the same language, with the same channels, was picked"),
+            },
+            TrackType::Subtitle if options.no_subtitles => verdict(track, Drop, "no_subtitles"),
+            TrackType::Subtitle if !options.keeps_subtitle(track) => verdict(track, Drop, "not in subtitle_languages"),
+            TrackType::Subtitle if track.codec == "dvb_teletext" && !options.teletext => verdict(track, Drop, "teletext, and the teletext option's off"),
+            TrackType::Subtitle if !convertible_subtitles(track, options) => verdict(track, Drop, "bitmap subtitles can't be turned into text"),
+            TrackType::Subtitle => verdict(track, Transcode, "converted to WebVTT"),
+        });
+    }
+    verdicts
+}
+
+fn manifest_title(media_file: &Path, ffprobe: &FFprobeResult, options: &TranscodeOptions, metadata: &Metadata) -> String {
+    if let Some(title) = &metadata.title {
+        return title.clone();
+    }
+    let stem = media_file.file_stem().unwrap().to_string_lossy();
+    let mut name = parse_release_name(&stem);
+    if metadata.episode_title.is_some() {
+        name.episode_title = metadata.episode_title.clone();
+    }
+    match &options.title_template {
+        Some(template) => name.fill_template(template),
+        None => music_title(ffprobe).or_else(|| ffprobe.title.clone()).unwrap_or_else(|| name.display_title()),
+    }
+}
+
+// "Artist – Title" from the tags, for anything without video.  Ogg keeps its tags on the stream, so
+// the title can come from the audio track.
+fn music_title(ffprobe: &FFprobeResult) -> Option<String> {
+    if ffprobe.tracks.iter().any(|x| x.is_video()) {
+        return None;
+    }
+    let audio = ffprobe.tracks.iter().find(|x| matches!(x.kind, TrackType::Audio));
+    let title = ffprobe.title.as_ref().or(audio.and_then(|x| x.title.as_ref()))?;
+    Some(match &ffprobe.artist {
+        Some(artist) => format!("{} – {}", artist, title),
+        None => title.clone(),
+    })
+}
+
+/// Builds the ffmpeg command for `media_file`, plus the manifest that describes the result.
+/// Nothing is run.  This is just `plan` minus the segmented encode, which you'll have to get from
+/// `segmented::plan_segments` yourself if you asked for one.
+pub fn remux(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, options: &TranscodeOptions) -> (Command, CytubeVideo) {
+    let plan = plan_into(media_file, ffprobe, outputdir, outputdir, url_prefix, options, None);
+    (plan.command.to_command(), plan.manifest)
+}
+
+/// Works out everything `remux` would do, without committing to how it gets run.  The plan can be
+/// inspected and tweaked before it's turned into commands or executed.
+///
+/// Everything gets written into a staging directory next to `outputdir` (see `staging_dir`), and
+/// only moved into `outputdir` once it's all there; see `TranscodePlan::execute`.
+pub fn plan(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, options: &TranscodeOptions) -> TranscodePlan {
+    plan_into(media_file, ffprobe, outputdir, &staging_dir(outputdir), url_prefix, options, None)
+}
+
+/// A stretch of an input, for transcoding just that part of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cut {
+    /// In seconds.
+    pub start: f64,
+    /// `None` runs to the end of the input.
+    pub length: Option<f64>,
+}
+
+impl Cut {
+    // -ss before -i, like a segmented encode
+    fn add_input_args(&self, command: &mut FfmpegInvocation) {
+        command.args(["-ss", self.start.to_string().as_str()]);
+        if let Some(length) = self.length {
+            command.args(["-t", length.to_string().as_str()]);
         }
     }
 }
 
-fn strcat(first: &str, rest: &[&str]) -> String {
-    let mut s = String::from(first);
-    for next in rest {
-        s.push_str(next);
+/// `plan`, for just `cut` of `media_file`.  The manifest's duration is the cut's.
+pub fn plan_cut(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, options: &TranscodeOptions, cut: Cut) -> TranscodePlan {
+    let mut probe = ffprobe.clone();
+    probe.duration = cut.length.unwrap_or(ffprobe.duration - cut.start);
+    // chapters are timed from the start of the output
+    probe.chapters = ffprobe.chapters.iter()
+        .map(|x| Chapter {start: (x.start - cut.start).max(0.0), end: (x.end - cut.start).min(probe.duration), ..x.clone()})
+        .filter(|x| x.end > x.start)
+        .collect();
+    plan_into(media_file, &probe, outputdir, &staging_dir(outputdir), url_prefix, options, Some(cut))
+}
+
+/// `plan`, for a `length`-second sample of `media_file`, to check how the whole thing would come
+/// out (quality, subtitles, audio sync) before spending hours on it.  Everything's done exactly as
+/// it would be for the whole file.  The sample's taken from a third of the way in, past any intro
+/// or cold open; a file that's no longer than `length` is done whole.
+pub fn plan_preview(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, options: &TranscodeOptions, length: f64) -> TranscodePlan {
+    if ffprobe.duration <= length {
+        return plan(media_file, ffprobe, outputdir, url_prefix, options);
     }
-    s
+    let start = (ffprobe.duration / 3.0).min(ffprobe.duration - length);
+    plan_cut(media_file, ffprobe, outputdir, url_prefix, options, Cut {start, length: Some(length)})
 }
 
-pub fn remux(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_prefix: &str, preferred_language: Option<str4>) -> (Command, CytubeVideo) {
+// adds to `graph` a split of `video` (decoded once) into a scaled copy for each of `heights`, plus
+// one left alone for the main encode if `with_main`.  returns what to -map for the main encode, if
+// it's in there, and for each height
+fn add_rendition_filter(graph: &mut FilterGraph, video: &Track, heights: &[u16], with_main: bool, upload: Option<Chain>) -> (Option<String>, Vec<String>) {
+    if heights.is_empty() {
+        return (None, Vec::new());
+    }
+    let mut split: Vec<String> = heights.iter().map(|x| format!("s{}", x)).collect();
+    if with_main {
+        split.insert(0, "main".to_string());
+    }
+    let split: Vec<&str> = split.iter().map(String::as_str).collect();
+    graph.chain(&[&format!("0:{}", video.index)], Filter::new("split").arg(split.len()), &split);
+    let upload = upload.unwrap_or_default();
+    for height in heights {
+        let mut scale = Chain::from(Filter::new("scale").arg(-2).arg(height));
+        scale.0.extend(upload.0.iter().cloned());
+        graph.chain(&[&format!("s{}", height)], scale, &[&format!("v{}", height)]);
+    }
+    let main = match upload.is_empty() {
+        true => "main",
+        false => {
+            graph.chain(&["main"], upload, &["main_up"]);
+            "main_up"
+        },
+    };
+    (with_main.then(|| pad(main)), heights.iter().map(|x| pad(&format!("v{}", x))).collect())
+}
+
+/// Where `plan` has ffmpeg write the outputs for `outputdir`: a hidden sibling directory, so it's
+/// on the same filesystem (and can be renamed from) but isn't in the directory being served.
+pub fn staging_dir(outputdir: &Path) -> PathBuf {
+    let absolute = std::path::absolute(outputdir).unwrap_or_else(|_| outputdir.to_owned());
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => parent.join(format!(".{}.staging", name.to_string_lossy())),
+        // the root directory, of all places
+        _ => absolute.join(".staging"),
+    }
+}
+
+// `plan`, with ffmpeg writing into `staging` rather than `outputdir`.  they can be the same.
+fn plan_into(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, staging: &Path, url_prefix: &str, options: &TranscodeOptions, cut: Option<Cut>) -> TranscodePlan {
+    // the streams from every program are in the file together, but only one's going out
+    let ffprobe = &keep_program(ffprobe, options.program);
     let mut subtitle_tracks: Vec<&Track> = Vec::new();
     let mut audio_tracks: Vec<&Track> = Vec::new();
     let mut video_tracks: Vec<&Track> = Vec::new();
     use TrackType::*;
     for track in &ffprobe.tracks {
         match track.kind {
+            // cover art isn't video.  music with a picture in it is still music
+            Video if track.is_cover_art() => {},
             Video => video_tracks.push(track),
-            Audio => audio_tracks.push(track),
+            Audio if options.keeps_audio(track) => audio_tracks.push(track),
+            Audio => {},
             Subtitle => subtitle_tracks.push(track),
         }
     }
 
-    let mut command = Command::new("ffmpeg");
-    command.arg("-hide_banner");
-    command.arg("-i").arg(media_file.as_os_str());
+    let mut command = FfmpegInvocation::new();
+    command.global_arg("-hide_banner");
+    let mut graph = FilterGraph::new();
+    if let Some(cut) = cut {
+        cut.add_input_args(&mut command);
+    }
+    options.hwaccel.add_input_args(&mut command);
+    if ffprobe.is_format("avi") && video_tracks.first().is_some_and(|x| copyable_video_container(x, options).is_some()) {
+        // AVI has no timestamps to speak of, just a frame count, and copying without any leaves
+        // the muxer guessing (badly, with B-frames)
+        command.args(["-fflags", "+genpts"]);
+    }
+    command.input(media_file);
 
     let mut ct_sources = Vec::new();
     let mut ct_audio_tracks = Vec::new();
-    let mut ct_text_tracks = Vec::new();
+    // for disk budgeting: roughly what every output adds up to, at the most disk we'll use at once
+    let mut predicted_kbps = 0;
+    let mut size_guesses = Vec::new();
+    let mut audio_outputs = Vec::new();
 
     // okay so fun fact
     // if the main video file contains a muxed audio track
@@ -174,15 +883,35 @@ pub fn remux(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_p
     }
     
     if let Some(video) = video_tracks.first() {
-        let video_container = find_video_container(&video.codec);
+        let video_container = copyable_video_container(video, options);
 
-        let (audio_track, audio_source) = if audio_tracks_by_language.len() == 1 {
+        // if the video's being encoded in segments ahead of time, pick the finished product up
+        // from the concat list instead of encoding it here
+        let segmented_video = if video_container.is_none() && options.parallel_segments > 1 {
+            command.args(["-f", "concat"]);
+            Some(format!("{}:0", command.input(segment_list_path(staging))))
+        } else {
+            None
+        };
+
+        // the main encode comes out of the same decode as the renditions, unless it's copied or
+        // encoded in segments
+        let heights = options.rendition_heights(video);
+        let (main_video, rendition_videos) = add_rendition_filter(&mut graph, video, &heights, video_container.is_none() && segmented_video.is_none(), options.encoder_upload(options.encoder()));
+
+        // what the silence muxed into the video with multiple audio languages is made at
+        let mut silence_sample_rate = 48000;
+        let (audio_track, audio_source) = if audio_tracks_by_language.is_empty() {
+            // no audio at all (a screen recording, say).  the video goes out on its own, rather
+            // than with silence nobody needs
+            (None, None)
+        } else if audio_tracks_by_language.len() == 1 {
             // one audio language.  mux it into the video.
             let mut chosen_audio = audio_tracks.first().unwrap();
             let mut highest_score = 0;
             for audio in audio_tracks.iter() {
                 let mut score = 0;
-                if video_container.as_ref().map_or(false, |container| container.get_acceptable_audio_codecs().contains(&audio.codec.as_str())) {
+                if video_container.is_some_and(|container| options.accepts_audio_in(container, &audio.codec)) {
                     score += 100;
                 }
                 // TODO sort audio tracks by channel count!
@@ -191,129 +920,488 @@ pub fn remux(media_file: &Path, ffprobe: &FFprobeResult, outputdir: &Path, url_p
                     highest_score = score;
                 }
             }
-            (Some(chosen_audio), format!("0:{}", chosen_audio.index))
+            (Some(chosen_audio), Some(format!("0:{}", chosen_audio.index)))
         } else {
             // multiple audio languages.  break out each into its own audio file and embed silence
             // into the muxed video.
-            for (language, audio_tracks) in audio_tracks_by_language.iter() {
+            let mut languages: Vec<_> = audio_tracks_by_language.iter().collect();
+            languages.sort_by_key(|(language, tracks)| (options.language_rank(Some(language), options.audio_languages.as_ref()), tracks[0].index));
+            // the silence stands in for the first language's track, which is the one that plays
+            // unless someone picks another.  at its rate, so the video's encoded audio isn't
+            // resampled from something nobody listens to
+            if let Some(sample_rate) = languages.first().and_then(|(_, tracks)| tracks[0].sample_rate) {
+                silence_sample_rate = sample_rate;
+            }
+            for (language, audio_tracks) in languages {
                 let language = language.as_str();
-                let audio_track = audio_tracks.first().unwrap(); // TODO choose an audio track more
-                                                                 // intelligently than this.
-                if let Some(container) = find_audio_container(&audio_track.codec) {
+                let first_track = ct_audio_tracks.len();
+                let mut layouts = Vec::new();
+                for audio_track in distinct_mixes(audio_tracks, options) {
+                    let (container, codec) = match options.audio_container(&audio_track.codec) {
+                        Some(container) => (container, None),
+                        // AC-3, DTS, TrueHD and friends (or anything the target browsers can't play).
+                        // browsers won't touch them, so transcode to whatever goes best with the video.
+                        None => {
+                            let (container, codec) = options.audio.standalone(options.target, video_container.unwrap_or(fallback_container(options.encoder())));
+                            (container, Some(codec))
+                        },
+                    };
                     let filename = format!("audio_{}_{}.{}", audio_track.index, language, container.extension());
 
                     command.arg("-map");
                     command.arg(format!("0:{}", audio_track.index));
-                    command.args(["-c", "copy"]);
-                    command.arg(outputdir.join(&filename));
+                    match codec {
+                        None => { command.args(["-c", "copy"]); },
+                        Some(codec) => { command.args(options.audio.encoder_args(codec)).args(audio_format_args(audio_track.channels, audio_track.sample_rate, codec)); },
+                    }
+                    if let Some(muxer) = container.muxer() {
+                        command.args(["-f", muxer]);
+                    }
+                    command.output(staging.join(&filename));
+                    audio_outputs.push(filename.clone());
+                    predicted_kbps += ESTIMATED_AUDIO_KBPS;
 
+                    // what comes out, which is only the source's if it's copied
+                    let fields = LabelFields {
+                        language,
+                        title: audio_track.title.as_deref(),
+                        codec: Some(codec.map_or(audio_track.codec.as_str(), |x| x.name())),
+                        channels: output_channels(audio_track, options),
+                        channel_layout: audio_track.channel_layout.as_deref().filter(|_| codec.is_none()),
+                        bitrate: match codec {
+                            Some(codec) => options.audio.bitrate.filter(|_| codec != AudioCodec::Flac).map(u64::from),
+                            None => audio_track.bitrate,
+                        },
+                    };
+                    layouts.push(channel_layout_display_name(fields.channel_layout, fields.channels));
                     ct_audio_tracks.push(CTAudioTrack {
-                        content_type: container.mimetype(),
+                        content_type: container.mimetype().to_string(),
                         language: FF2CT.get(language).unwrap_or(&language).to_string(),
-                        label: build_language_string(&language, audio_track.title.as_ref().map(|x|x.as_str())),
+                        label: options.track_label(&fields),
                         url: strcat(url_prefix, &[&filename]),
+                        extra: Map::new(),
                     });
-                } // TODO transcode additional audio tracks.
+                }
+                let mut labels: Vec<String> = ct_audio_tracks[first_track..].iter().map(|x| x.label.clone()).collect();
+                distinguish_labels(&mut labels, &layouts);
+                for (track, label) in ct_audio_tracks[first_track..].iter_mut().zip(labels) {
+                    track.label = label;
+                }
             }
-            // TODO copy the sample rate and channel layout from the source file!
-            command.args(["-f", "lavfi", "-t", ffprobe.duration.to_string().as_str(), "-i", "anullsrc=channel_layout=stereo:sample_rate=48000",
-            ]);
-            (None, "1:0".to_string())
+            // stereo, since it's only silence
+            command.args(["-f", "lavfi", "-t", ffprobe.duration.to_string().as_str()]);
+            let silence = command.input(format!("anullsrc=channel_layout=stereo:sample_rate={}", silence_sample_rate));
+            (None, Some(format!("{}:0", silence)))
+        };
+        // what's going into the video's audio, for `audio_format_args`
+        let (audio_channels, audio_sample_rate) = match audio_track {
+            Some(audio) => (audio.channels, audio.sample_rate),
+            None => (Some(2), Some(silence_sample_rate)),
         };
         command.args([
                      "-map",
-                     format!("0:{}", video.index).as_str(),
-                     "-map", &audio_source,
+                     segmented_video.clone().or(main_video.clone()).unwrap_or_else(|| format!("0:{}", video.index)).as_str(),
         ]);
+        if let Some(audio_source) = &audio_source {
+            command.args(["-map", audio_source.as_str()]);
+        }
+        // maps the audio for another encode of the video, encoded to go in `container`
+        let add_encoded_audio = |command: &mut FfmpegInvocation, container: VideoContainer| {
+            if let Some(audio_source) = &audio_source {
+                command.args(["-map", audio_source.as_str()]);
+                let codec = options.audio.codec_in(options.target, container);
+                command.args(options.audio.encoder_args(codec)).args(audio_format_args(audio_channels, audio_sample_rate, codec));
+            }
+        };
 
         if let Some(video_container) = video_container {
-            command.args([
-                         "-c:v", "copy",
-                         "-c:a",
-            ]);
+            predicted_kbps += ffprobe.bitrate;
+            command.args(["-c:v", "copy"]);
             if let Some(audio) = audio_track {
-                if video_container.get_acceptable_audio_codecs().contains(&audio.codec.as_str()) {
-                    command.arg("copy");
+                if options.accepts_audio_in(video_container, &audio.codec) {
+                    command.args(["-c:a", "copy"]);
                     if matches!(video_container, VideoContainer::MP4) && audio.codec == "flac" {
                         // ffmpeg doesn't like putting FLAC streams inside MP4 files, considers it
                         // experimental.  we have to tell it that that's okay
                         command.args(["-strict", "experimental"]);
                     }
                 } else {
-                    command.args([video_container.preferred_audio_encoder(),
-                    "-ac", "2"]); // downmix to stereo to make encoding faster
+                    let codec = options.audio.codec_in(options.target, video_container);
+                    command.args(options.audio.encoder_args(codec));
+                    command.args(audio_format_args(audio.channels, audio.sample_rate, codec));
                 }
-            } else {
+            } else if audio_source.is_some() {
                 // above code has elected not to embed an audio track in the file.
                 // all we're encoding is silence so codec doesn't particularly matter.
-                command.arg(video_container.preferred_audio_encoder());
+                command.args(["-c:a", options.audio.codec_in(options.target, video_container).encoder()]);
             }
 
-            let filename = format!("main.{}", video_container.extension());
+            match (options.packaging, &video_container) {
+                (Packaging::Cmaf { segment_duration }, VideoContainer::MP4) => {
+                    add_cmaf_output(&mut command, staging, segment_duration);
+                    for (filename, content_type) in CMAF_MANIFESTS {
+                        ct_sources.push(Source{
+                            bitrate: ffprobe.bitrate,
+                            content_type: content_type.to_string(),
+                            quality: video.scanline_count.unwrap(), // TODO
+                            url: strcat(url_prefix, &[filename]),
+                            extra: Map::new(),
+                        });
+                    }
+                },
+                _ => {
+                    options.add_fragmented_mp4_args(&mut command, video_container);
 
-            command.arg(outputdir.join(&filename));
-            ct_sources.push(Source{
-                bitrate: ffprobe.bitrate,
-                content_type: video_container.mimetype(),
-                quality: video.scanline_count.unwrap(), // TODO
-                url: strcat(url_prefix, &[filename.as_str()]),
-            });
+                    let filename = format!("main.{}", video_container.extension());
+
+                    command.output(staging.join(&filename));
+                    ct_sources.push(Source{
+                        bitrate: ffprobe.bitrate,
+                        content_type: video_container.mimetype().to_string(),
+                        quality: video.scanline_count.unwrap(), // TODO
+                        url: strcat(url_prefix, &[filename.as_str()]),
+                        extra: Map::new(),
+                    });
+                },
+            }
+
+            if options.hevc_10bit == TenBitHevcPolicy::Both && is_10bit_hevc(video) {
+                // an 8-bit rendition next to the 10-bit copy.  cytube's player can't tell two
+                // sources of the same quality apart and just plays the first one, so the one
+                // that plays everywhere goes first.
+                let container = fallback_container(options.encoder());
+                command.args(["-map", format!("0:{}", video.index).as_str()]);
+                command.args(options.encoder_upload_args());
+                command.args(options.video_encoder_args(video));
+                add_encoded_audio(&mut command, container);
+                let filename = format!("main_8bit.{}", container.extension());
+                command.output(staging.join(&filename));
+                predicted_kbps += options.estimate_transcoded_kbps(video);
+                size_guesses.push(options.size_guess(&filename, video));
+                ct_sources.insert(0, Source{
+                    bitrate: options.estimate_transcoded_kbps(video),
+                    content_type: container.mimetype().to_string(),
+                    quality: video.scanline_count.unwrap(), // TODO
+                    url: strcat(url_prefix, &[filename.as_str()]),
+                    extra: Map::new(),
+                });
+            }
         } else {
-            // the codec used in the original video file isn't supported by the browser
-            // AV1 transcode it is
-            command.args(["-c:v", "libstvav1", "-c:a", "libopus", "-ac", "2"]);
-            command.arg(outputdir.join("main.webm"));
+            // the codec used in the original video file isn't supported by the browser (or isn't
+            // supported by the browsers we were told to care about).  transcode it.
+            let container = fallback_container(options.encoder());
+            predicted_kbps += options.estimate_transcoded_kbps(video);
+            if segmented_video.is_some() {
+                // the segments are still around while they're being put together
+                predicted_kbps += options.estimate_transcoded_kbps(video);
+                command.args(["-c:v", "copy"]);
+            } else {
+                if main_video.is_none() {
+                    command.args(options.encoder_upload_args());
+                }
+                command.args(options.video_encoder_args(video));
+            }
+            if audio_source.is_some() {
+                let codec = options.audio.codec_in(options.target, container);
+                command.args(options.audio.encoder_args(codec)).args(audio_format_args(audio_channels, audio_sample_rate, codec));
+            }
+            if let Packaging::Cmaf { segment_duration } = options.packaging {
+                // everything we encode to is fine in CMAF
+                add_cmaf_output(&mut command, staging, segment_duration);
+                for (filename, content_type) in CMAF_MANIFESTS {
+                    ct_sources.push(Source{
+                        bitrate: options.estimate_transcoded_kbps(video),
+                        content_type: content_type.to_string(),
+                        quality: video.scanline_count.unwrap(), // TODO
+                        url: strcat(url_prefix, &[filename]),
+                        extra: Map::new(),
+                    });
+                }
+            } else {
+                options.add_fragmented_mp4_args(&mut command, container);
+                let filename = format!("main.{}", container.extension());
+                command.output(staging.join(&filename));
+                size_guesses.push(options.size_guess(&filename, video));
+                ct_sources.push(Source{
+                    bitrate: options.estimate_transcoded_kbps(video),
+                    content_type: container.mimetype().to_string(),
+                    quality: video.scanline_count.unwrap(), // TODO
+                    url: strcat(url_prefix, &[filename.as_str()]),
+                    extra: Map::new(),
+                });
+            }
+        }
+
+        let container = fallback_container(options.encoder());
+        for (height, label) in heights.iter().zip(&rendition_videos) {
+            let rendition = Track {scanline_count: Some(*height), ..(*video).clone()};
+            command.args(["-map", label.as_str()]);
+            command.args(options.video_encoder_args(&rendition));
+            add_encoded_audio(&mut command, container);
+            options.add_fragmented_mp4_args(&mut command, container);
+            let filename = format!("main_{}p.{}", height, container.extension());
+            command.output(staging.join(&filename));
+            predicted_kbps += options.estimate_transcoded_kbps(&rendition);
+            size_guesses.push(options.size_guess(&filename, &rendition));
             ct_sources.push(Source{
-                bitrate: ffprobe.bitrate, // TODO figure out the actual bitrate
-                content_type: "video/webm",
-                quality: video.scanline_count.unwrap(), // TODO
-                url: strcat(url_prefix, &["main.webm"]),
+                bitrate: options.estimate_transcoded_kbps(&rendition),
+                content_type: container.mimetype().to_string(),
+                quality: *height,
+                url: strcat(url_prefix, &[filename.as_str()]),
+                extra: Map::new(),
             });
         }
+
+        if let Some(storyboard) = &options.storyboard {
+            storyboard.add_output(&mut command, &mut graph, video, staging);
+        }
+    } else if let Some(audio) = audio_tracks.iter().find(|x| x.language.is_some() && x.language == options.preferred_language).or(audio_tracks.first()) {
+        // no video, so it's music (or a podcast, or a radio drama).  the audio is the source.
+        // copying only cuts at packet boundaries, which leaves a gap or a repeat of up to a
+        // packet's worth (a tenth of a second, for FLAC) where one track meets the next.  decoding
+        // cuts on the exact sample.
+        let gapless_cut = options.gapless && cut.is_some();
+        let gain = options.replay_gain.and_then(|x| x.gain(ffprobe));
+        // either of those means decoding, and a lossless source might as well stay lossless
+        let decode = gapless_cut || gain.is_some();
+        let lossless = LOSSLESS_AUDIO_CODECS.contains(&audio.codec.as_str()) || audio.codec.starts_with("pcm_");
+        let (container, codec) = match options.audio_container(&audio.codec) {
+            Some(container) if !decode => (container, None),
+            _ if decode && lossless && options.audio_container("flac").is_some() => (AudioContainer::OGG, Some(AudioCodec::Flac)),
+            _ => {
+                let (container, codec) = options.audio.standalone(options.target, fallback_container(options.encoder()));
+                (container, Some(codec))
+            },
+        };
+        let bitrate = match codec {
+            None | Some(AudioCodec::Flac) => ffprobe.bitrate,
+            Some(_) => options.audio.bitrate.map_or(ESTIMATED_AUDIO_KBPS, u64::from),
+        };
+        command.args(["-map", format!("0:{}", audio.index).as_str()]);
+        match codec {
+            None => { command.args(["-c", "copy"]); },
+            Some(codec) => { command.args(options.audio.encoder_args(codec)).args(audio_format_args(audio.channels, audio.sample_rate, codec)); },
+        }
+        if let Some(gain) = gain {
+            command.arg("-af").arg(Filter::new("volume").arg(format!("{:.2}dB", gain)).to_string());
+        }
+        if options.gapless && container != AudioContainer::OGG {
+            // AAC encoders put silence (priming) at the front.  an edit list tells the player to
+            // skip it, which ffmpeg only writes when it thinks it's needed unless told otherwise.
+            // Opus in Ogg does the same with its pre-skip, always.
+            command.args(["-use_editlist", "1"]);
+        }
+        if let Some(muxer) = container.muxer() {
+            command.args(["-f", muxer]);
+        }
+        let filename = format!("main.{}", container.extension());
+        command.output(staging.join(&filename));
+        audio_outputs.push(filename.clone());
+        predicted_kbps += bitrate;
+        ct_sources.push(Source{
+            bitrate,
+            content_type: container.source_mimetype().to_string(),
+            quality: AUDIO_ONLY_QUALITY,
+            url: strcat(url_prefix, &[filename.as_str()]),
+            extra: Map::new(),
+        });
     }
 
-    for sub_track in subtitle_tracks {
-        if BITMAP_SUBTITLE_CODECS.contains(&sub_track.codec.as_str()) {
-            // ffmpeg can't do OCR
-            continue;
+    let mut ct_text_tracks = add_subtitle_outputs(&mut command, media_file, cut, &subtitle_tracks, staging, url_prefix, options);
+    if let Some(video) = video_tracks.first().filter(|x| x.closed_captions && options.closed_captions) {
+        ct_text_tracks.push(add_caption_output(&mut command, media_file, cut, video, caption_label(ffprobe, video, options), staging, url_prefix));
+    }
+
+    // only if the video's actually encoded here, rather than copied or put together from segments
+    let encoder = options.encoder().ffmpeg_name();
+    if command.outputs.iter().any(|x| x.args.iter().any(|x| x == encoder)) {
+        for arg in options.encoder_device_args(options.encoder()) {
+            command.global_arg(arg);
         }
-        command.args(["-map", format!("0:{}", sub_track.index).as_str()]);
+    }
+    if let Some(threads) = options.threads {
+        command.global_arg("-filter_threads").global_arg(threads.to_string());
+        command.global_arg("-filter_complex_threads").global_arg(threads.to_string());
+    }
+    command.launcher = options.launcher();
+    graph.add_to(&mut command);
+    let metadata = options.metadata.as_ref().and_then(|x| x.lookup(media_file)).unwrap_or_default();
+    let title = manifest_title(media_file, ffprobe, options, &metadata);
+    add_tags(&mut command, ffprobe, options, &title);
+    TranscodePlan {
+        input: media_file.to_owned(),
+        outputdir: outputdir.to_owned(),
+        staging: staging.to_owned(),
+        partial_outputs: options.partial_outputs,
+        predicted_bytes: (predicted_kbps as f64 * 1000.0 / 8.0 * ffprobe.duration) as u64,
+        size_guesses,
+        size_model: options.size_model.clone(),
+        cache: options.cache.clone(),
+        signed_urls: options.signed_urls.clone(),
+        upload: options.upload.clone(),
+        segments: plan_segments(media_file, ffprobe, staging, options, cut),
+        storyboard: options.storyboard.clone().filter(|_| !video_tracks.is_empty()),
+        waveform: options.waveform.clone(),
+        audio_outputs,
+        preview_page: options.preview_page,
+        skip_markers: match options.skip_markers {
+            true => skip_markers(&ffprobe.chapters),
+            false => Vec::new(),
+        },
+        command,
+        manifest: CytubeVideo {
+            title,
+            duration: metadata.duration.unwrap_or(ffprobe.duration),
+            live: None,
+            thumbnail: metadata.thumbnail,
+            sources: ct_sources,
+            audio_tracks: ct_audio_tracks,
+            text_tracks: ct_text_tracks,
+            schema: SCHEMA_VERSION,
+            extra: Map::new(),
+        },
+    }
+}
+
+// maps the text subtitles out of `subtitle_tracks` that `options` keeps to VTT files in
+// `staging`, and returns their text tracks for the manifest.  `media_file` is input 0, cut to
+// `cut` if there is one
+fn add_subtitle_outputs(command: &mut FfmpegInvocation, media_file: &Path, cut: Option<Cut>, subtitle_tracks: &[&Track], staging: &Path, url_prefix: &str, options: &TranscodeOptions) -> Vec<CTTextTrack> {
+    let mut subtitle_tracks: Vec<&Track> = subtitle_tracks.iter().copied()
+        .filter(|x| options.keeps_subtitle(x) && convertible_subtitles(x, options))
+        .collect();
+    // stream order within a language.  the sort's stable
+    subtitle_tracks.sort_by_key(|x| options.language_rank(x.language.as_ref(), options.subtitle_languages.as_ref()));
+    let default_subtitles = options.default_subtitles.choose(&subtitle_tracks, options.preferred_language);
+    let mut ct_text_tracks = Vec::new();
+    let mut teletext_input = None;
+    for sub_track in subtitle_tracks {
+        let input = match sub_track.codec.as_str() {
+            // libzvbi decodes teletext to bitmaps unless it's told otherwise, and decoder options
+            // go on the input, so teletext gets an input of its own.  only the pages marked as
+            // subtitles, not the news and weather
+            "dvb_teletext" => *teletext_input.get_or_insert_with(|| {
+                if let Some(cut) = cut {
+                    cut.add_input_args(command);
+                }
+                command.args(["-c:s", "libzvbi_teletext", "-txt_format", "text", "-txt_page", "subtitle"]);
+                command.input(media_file)
+            }),
+            _ => 0,
+        };
+        command.args(["-map", format!("{}:{}", input, sub_track.index).as_str()]);
+        // mov_text (MP4's own) and ASS keep their bold and italics as WebVTT tags; anything
+        // fancier than that is lost
+        command.args(["-c:s", "webvtt"]);
         let lang = match &sub_track.language {
             Some(x) => x.as_str(),
             None => "unknown",
         };
         let filename = format!("sub_{}_{}.vtt", sub_track.index, lang);
-        command.arg(outputdir.join(&filename).as_os_str());
+        command.output(staging.join(&filename));
 
         let language_string = match sub_track.language {
-            Some(x) => build_language_string(x.as_str(), sub_track.title.as_ref().map(|x|x.as_str())),
+            Some(x) => options.track_label(&LabelFields {language: x.as_str(), title: sub_track.title.as_deref(), ..Default::default()}),
             None => sub_track.title.clone().unwrap_or("Unknown".to_string()),
         };
 
         ct_text_tracks.push(CTTextTrack {
-            content_type: "text/vtt",
+            content_type: "text/vtt".to_string(),
             url: strcat(url_prefix, &[filename.as_str()]),
             name: language_string,
+            default: default_subtitles == Some(sub_track.index),
+            extra: Map::new(),
         });
     }
+    ct_text_tracks
+}
 
-    dbg!(&command);
-    (command,
-    CytubeVideo {
-        title: ffprobe.title.clone().unwrap_or_else(|| media_file.file_stem().unwrap().to_string_lossy().to_string()),
-        duration: ffprobe.duration,
-        sources: ct_sources,
-        audio_tracks: ct_audio_tracks,
-        text_tracks: ct_text_tracks,
-    })
+// maps the closed captions in `video` to a VTT file in `staging`, and returns its text track.
+// they're side data on the video frames, which only the movie source can pull out as a stream
+// of their own
+fn add_caption_output(command: &mut FfmpegInvocation, media_file: &Path, cut: Option<Cut>, video: &Track, name: String, staging: &Path, url_prefix: &str) -> CTTextTrack {
+    let mut graph = FilterGraph::new();
+    let movie = Filter::new("movie").arg(media_file.to_string_lossy()).opt("streams", video.index);
+    // lavfi takes an output called outN+subcc to mean outN and its captions
+    graph.chain(&[], movie, &["out0+subcc"]);
+    if let Some(cut) = cut {
+        cut.add_input_args(command);
+    }
+    command.args(["-f", "lavfi"]);
+    let input = command.input(graph.to_string());
+    // the captions are the movie source's second output
+    command.args(["-map", format!("{}:1", input).as_str(), "-c:s", "webvtt"]);
+    let filename = format!("sub_{}_cc.vtt", video.index);
+    command.output(staging.join(&filename));
+    CTTextTrack {
+        content_type: "text/vtt".to_string(),
+        url: strcat(url_prefix, &[filename.as_str()]),
+        name,
+        default: false,
+        extra: Map::new(),
+    }
 }
 
-fn build_language_string(language: &str, title: Option<&str>) -> String {
-    let mut s = String::from(*LANGUAGES.get(language).unwrap_or(&language));
-    if let Some(title) = title {
-        s.push_str(" (");
-        s.push_str(title);
-        s.push(')');
+// the language closed captions are probably in: the video's, if it says, or else the first audio
+// track's
+fn caption_language(ffprobe: &FFprobeResult, video: &Track) -> Option<str4> {
+    video.language.or_else(|| ffprobe.tracks.iter().find(|x| matches!(x.kind, TrackType::Audio))?.language)
+}
+
+fn caption_label(ffprobe: &FFprobeResult, video: &Track, options: &TranscodeOptions) -> String {
+    match caption_language(ffprobe, video) {
+        Some(language) => options.track_label(&LabelFields {language: language.as_str(), title: Some("CC"), ..Default::default()}),
+        None => "Closed captions".to_string(),
+    }
+}
+
+/// Just the subtitles of `media_file`, converted to VTT files in `dir`, for adding them to a
+/// transcode that's already done (see `subtitles::extract_subtitles`).  Returns the command, which
+/// has no outputs if there's nothing to convert, and the manifest's text tracks for them.
+pub fn plan_subtitles(media_file: &Path, ffprobe: &FFprobeResult, dir: &Path, url_prefix: &str, options: &TranscodeOptions) -> (FfmpegInvocation, Vec<CTTextTrack>) {
+    let ffprobe = &keep_program(ffprobe, options.program);
+    let subtitle_tracks: Vec<&Track> = ffprobe.tracks.iter().filter(|x| matches!(x.kind, TrackType::Subtitle)).collect();
+    let mut command = FfmpegInvocation::new();
+    command.launcher = options.launcher();
+    command.global_arg("-hide_banner");
+    command.input(media_file);
+    let mut text_tracks = add_subtitle_outputs(&mut command, media_file, None, &subtitle_tracks, dir, url_prefix, options);
+    if let Some(video) = ffprobe.tracks.iter().find(|x| x.is_video() && x.closed_captions).filter(|_| options.closed_captions) {
+        text_tracks.push(add_caption_output(&mut command, media_file, None, video, caption_label(ffprobe, video, options), dir, url_prefix));
+    }
+    (command, text_tracks)
+}
+
+// ffmpeg copies whatever tags the input has onto every output unless it's told not to.  instead,
+// every output gets exactly the tags `copy_tags` asks for.  this is only file-level tags; each
+// stream's tags (language and title, mostly) still go along with it, unless we're stripping.
+fn add_tags(command: &mut FfmpegInvocation, ffprobe: &FFprobeResult, options: &TranscodeOptions, title: &str) {
+    let names: Vec<&str> = match &options.copy_tags {
+        Some(names) if !options.strip_metadata => names.iter().map(String::as_str).collect(),
+        None if !options.strip_metadata => DEFAULT_COPIED_TAGS.to_vec(),
+        _ => Vec::new(),
+    };
+    let mut args = vec!["-map_metadata".to_string(), "-1".to_string()];
+    if options.strip_metadata {
+        // stream tags are where phones put creation times and the like, and chapter names can say
+        // anything
+        args.extend(["-map_metadata:s", "-1", "-map_chapters", "-1"].map(String::from));
+    }
+    if options.strip_metadata || options.deterministic {
+        // bitexact leaves out the "Lavf60.3.100"s ffmpeg signs its work with, and everything
+        // muxers make up fresh every time: Matroska's segment UID and date, Ogg's stream serial
+        // numbers, the DASH manifest's comment.  with no creation_time tag (map_metadata), MP4s
+        // are dated 1904.
+        args.extend(["-fflags", "+bitexact", "-flags:v", "+bitexact", "-flags:a", "+bitexact"].map(String::from));
+    }
+    for name in names {
+        let value = ffprobe.tags.get(name).map(String::as_str).or(Some(title).filter(|_| name == "title"));
+        if let Some(value) = value {
+            args.extend(["-metadata".to_string(), format!("{}={}", name, value)]);
+        }
+    }
+    for output in command.outputs.iter_mut() {
+        output.args.splice(0..0, args.iter().map(OsString::from));
     }
-    s
 }
+
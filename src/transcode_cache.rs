@@ -0,0 +1,127 @@
+// Reusing finished transcodes.  The same movie gets transcoded the same way for different
+// channels all the time, and there's no point encoding it twice.
+//
+// A transcode is identified by its input plus the exact ffmpeg commands the plan runs, with the
+// paths taken out, so anything in `TranscodeOptions` that changes the output changes the key, and
+// anything that doesn't (the URL prefix, the output directory) doesn't.  Each entry is a directory
+// of outputs named after the key.  Hits get hardlinked into the output directory, so a cached
+// transcode costs no extra disk no matter how many channels it's served to.  The manifest isn't
+// cached, since it has the URL prefix in it; it's written fresh every time.
+
+use crate::manifest::MANIFEST_FILENAME;
+use crate::plan::TranscodePlan;
+use crate::probe_cache::{hex, quick_hash, CacheKey};
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory full of finished transcodes.
+#[derive(Debug, Clone)]
+pub struct TranscodeCache {
+    pub dir: PathBuf,
+    /// How the input's identified.  `CacheKey::Path` goes by the path, size and mtime.
+    pub key: CacheKey,
+}
+
+// every arg with `from` in it gets it swapped for `to`
+fn replace_in(arg: &OsString, from: &Path, to: &str) -> OsString {
+    let (arg, from) = (arg.to_string_lossy(), from.to_string_lossy());
+    if from.is_empty() {
+        return arg.into_owned().into();
+    }
+    arg.replace(&*from, to).into()
+}
+
+impl TranscodeCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        // reading 2 MB is nothing next to a transcode, and this way a copy of the file somewhere
+        // else still hits
+        TranscodeCache {dir: dir.into(), key: CacheKey::Content}
+    }
+
+    /// `$XDG_CACHE_HOME/cytube-generator/transcodes`, falling back on `~/.cache`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(dir.join("cytube-generator").join("transcodes"))
+    }
+
+    /// The key `plan` would be cached under.
+    pub fn key(&self, plan: &TranscodePlan) -> std::io::Result<String> {
+        let mut hasher = Sha256::new();
+        let metadata = plan.input.metadata()?;
+        match self.key {
+            CacheKey::Path => {
+                hasher.update(plan.input.canonicalize()?.as_os_str().as_encoded_bytes());
+                hasher.update(metadata.len().to_le_bytes());
+                let mtime = metadata.modified()?.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+                hasher.update(mtime.as_nanos().to_le_bytes());
+            },
+            CacheKey::Content => quick_hash(&mut hasher, &plan.input, metadata.len())?,
+        }
+        for command in plan.to_commands() {
+            for arg in command.to_args() {
+                let arg = replace_in(&arg, &plan.staging, "$OUT");
+                let arg = replace_in(&arg, &plan.input, "$IN");
+                hasher.update(arg.as_encoded_bytes());
+                hasher.update([0]);
+            }
+            hasher.update([1]);
+        }
+        Ok(hex(&hasher.finalize()))
+    }
+
+    /// Links everything cached under `key` into `dir`.  Returns whether there was anything.
+    pub fn fetch(&self, key: &str, dir: &Path) -> std::io::Result<bool> {
+        let entry = self.dir.join(key);
+        let files = match fs::read_dir(&entry) {
+            Ok(files) => files,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        fs::create_dir_all(dir)?;
+        for file in files {
+            let file = file?;
+            link_or_copy(&file.path(), &dir.join(file.file_name()))?;
+        }
+        Ok(true)
+    }
+
+    /// Caches the outputs in `dir` under `key`.  Subdirectories and the manifest are left out.
+    pub fn store(&self, key: &str, dir: &Path) -> std::io::Result<()> {
+        let entry = self.dir.join(key);
+        if entry.exists() {
+            return Ok(());
+        }
+        // fill in a temporary directory and rename it, so nobody ever fetches half an entry
+        let tmp = self.dir.join(format!("{}.tmp{}", key, std::process::id()));
+        fs::create_dir_all(&tmp)?;
+        for file in fs::read_dir(dir)? {
+            let file = file?;
+            if file.file_name() == MANIFEST_FILENAME || !file.file_type()?.is_file() {
+                continue;
+            }
+            link_or_copy(&file.path(), &tmp.join(file.file_name()))?;
+        }
+        if fs::rename(&tmp, &entry).is_err() {
+            // somebody else got there first
+            fs::remove_dir_all(&tmp)?;
+        }
+        Ok(())
+    }
+}
+
+// hardlinks `from` to `to`, or copies it if they're on different filesystems
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::remove_file(to) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    if fs::hard_link(from, to).is_err() {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+// Pushing finished outputs to wherever they're served from, for channels whose media lives on a
+// CDN or some storage box rather than on the machine doing the transcoding.
+//
+// An `Uploader` knows how to put a file at a path on its storage, and what public URL that path
+// ends up at, so the manifest's URL prefix comes from the uploader instead of being worked out by
+// hand.  Everything in the output directory gets uploaded, the manifest last, so it never points
+// at files that aren't there yet.
+//
+// The HTTP backends need the `upload` feature.  `Rclone` doesn't, and covers just about every
+// other kind of storage there is.  With the feature, uploads are checked afterwards by fetching
+// them back from their public URLs (see `verify_upload`).
+
+use crate::cytube_structs::CytubeVideo;
+use crate::manifest::{read_manifest, MANIFEST_FILENAME};
+use crate::signing::unsigned;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "upload")]
+mod cdn;
+mod pipeline;
+mod rclone;
+mod throttle;
+#[cfg(feature = "upload")]
+mod verify;
+#[cfg(feature = "upload")]
+mod webdav;
+#[cfg(feature = "upload")]
+pub use cdn::{Bunny, S3Upload};
+pub use pipeline::SegmentPipeline;
+pub use rclone::Rclone;
+pub use throttle::{Bandwidth, Throttle, Throttled, UploadWindow};
+#[cfg(feature = "upload")]
+pub use verify::verify_upload;
+#[cfg(feature = "upload")]
+pub use webdav::WebDav;
+
+/// Somewhere outputs can be uploaded to.
+pub trait Uploader: Send + Sync + std::fmt::Debug {
+    /// Uploads `file` to `remote_path`, a `/`-separated path relative to wherever the uploader
+    /// keeps things, replacing anything already there.  The upload has to be held to `throttle`'s
+    /// bandwidth limits; whoever calls this has already waited for its window.  If it's called
+    /// again after failing partway through, it should pick up where it left off if it can.
+    fn upload(&self, file: &Path, remote_path: &str, content_type: &str, throttle: &Throttle) -> std::io::Result<()>;
+
+    /// The public URL of what's uploaded to `remote_path`.
+    fn public_url(&self, remote_path: &str) -> String;
+
+    /// Uploads everything in `dir` into `remote_dir`.  See `upload_files`, which is what this does
+    /// unless the uploader has a better way.
+    fn upload_dir(&self, dir: &Path, remote_dir: &str, throttle: &Throttle) -> std::io::Result<()> {
+        upload_files(self, dir, remote_dir, throttle, &HashSet::new())
+    }
+
+    /// The URL prefix for the manifest of outputs uploaded into `remote_dir`.
+    fn url_prefix(&self, remote_dir: &str) -> String {
+        let remote_dir = remote_dir.trim_matches('/');
+        match remote_dir.is_empty() {
+            true => self.public_url(""),
+            false => self.public_url(&format!("{}/", remote_dir)),
+        }
+    }
+}
+
+/// An uploader, the directory one transcode's outputs go in, and the limits on uploading them.
+/// Goes in `TranscodeOptions::upload`.
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub uploader: Arc<dyn Uploader>,
+    pub remote_dir: String,
+    pub throttle: Throttle,
+}
+
+impl Upload {
+    /// What to pass to `plan` as the URL prefix.
+    pub fn url_prefix(&self) -> String {
+        self.uploader.url_prefix(&self.remote_dir)
+    }
+}
+
+/// Content types for what we write, going by the extension, for files the manifest doesn't list.
+const CONTENT_TYPES: [(&str, &str); 11] = [
+    ("jpg", "image/jpeg"),
+    ("json", "application/json"),
+    ("m3u8", "application/x-mpegURL"),
+    ("m4a", "audio/mp4"),
+    ("m4s", "video/iso.segment"),
+    ("mp4", "video/mp4"),
+    ("mpd", "application/dash+xml"),
+    ("ogg", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("vtt", "text/vtt"),
+    ("webm", "video/webm"),
+];
+
+/// The content type to upload `path` with, going by its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().map(|x| x.to_string_lossy().to_ascii_lowercase());
+    CONTENT_TYPES.iter()
+        .find(|(ext, _)| extension.as_deref() == Some(ext))
+        .map_or("application/octet-stream", |(_, content_type)| content_type)
+}
+
+// the content type the manifest gives each of its files, by filename.  an m4a is video/mp4 as a
+// source but audio/mp4 as an audio track, so the extension alone won't do
+fn manifest_content_types(manifest: &CytubeVideo) -> HashMap<String, String> {
+    let urls = manifest.sources.iter().map(|x| (&x.url, &x.content_type))
+        .chain(manifest.audio_tracks.iter().map(|x| (&x.url, &x.content_type)))
+        .chain(manifest.text_tracks.iter().map(|x| (&x.url, &x.content_type)));
+    urls.filter_map(|(url, content_type)| Some((unsigned(url).rsplit('/').next()?.to_owned(), content_type.clone())))
+        .collect()
+}
+
+/// Uploads everything in `dir` (except hidden files, like `.failed`) into `remote_dir` one file at
+/// a time, with the manifest last.  Each file waits for `throttle`'s window before it starts.
+/// Anything in `skip` (relative paths, with `/`s) has been uploaded already and is left out.
+/// Each file's tried again, with backoff, if it fails for reasons that might not last; see
+/// `retrying`.
+pub fn upload_files<U: Uploader + ?Sized>(uploader: &U, dir: &Path, remote_dir: &str, throttle: &Throttle, skip: &HashSet<String>) -> std::io::Result<()> {
+    let content_types = read_manifest(dir).map(|x| manifest_content_types(&x)).unwrap_or_default();
+    let remote_dir = remote_dir.trim_matches('/');
+    let remote_path = |relative: &str| match remote_dir.is_empty() {
+        true => relative.to_owned(),
+        false => format!("{}/{}", remote_dir, relative),
+    };
+
+    // (path, relative path with /s)
+    let mut files = Vec::new();
+    let mut dirs = vec![(dir.to_owned(), String::new())];
+    while let Some((dir, relative)) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = format!("{}{}", relative, name);
+            if entry.file_type()?.is_dir() {
+                dirs.push((entry.path(), format!("{}/", path)));
+            } else if path != MANIFEST_FILENAME && !skip.contains(&path) {
+                files.push((entry.path(), path));
+            }
+        }
+    }
+    files.sort();
+    files.push((dir.join(MANIFEST_FILENAME), MANIFEST_FILENAME.to_owned()));
+
+    for (path, relative) in files.iter().filter(|x| x.0.is_file()) {
+        let content_type = content_types.get(relative).map_or(content_type(path), |x| x.as_str());
+        throttle.wait_for_window();
+        retrying(relative, || uploader.upload(path, &remote_path(relative), content_type, throttle))?;
+    }
+    Ok(())
+}
+
+/// How many times an upload's tried before it's given up on.
+pub const UPLOAD_ATTEMPTS: u32 = 6;
+
+// whether trying again might work: the connection or the server having a bad moment, as opposed
+// to the file not being there or the server not liking the request.  see `http_error`
+fn is_transient(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), Other | TimedOut | Interrupted | UnexpectedEof | BrokenPipe
+        | ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected)
+}
+
+/// Runs `f` until it works, fails in a way that trying again won't fix, or has failed
+/// `UPLOAD_ATTEMPTS` times, waiting 2 seconds after the first failure and twice as long after
+/// each one after that, up to a minute.  `what` is for the messages about it.
+pub fn retrying<T>(what: &str, mut f: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    let mut wait = Duration::from_secs(2);
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if attempt < UPLOAD_ATTEMPTS && is_transient(&e) => {
+                eprintln!("uploading {} failed, trying again in {}s: {}", what, wait.as_secs(), e);
+                std::thread::sleep(wait);
+                wait = (wait * 2).min(Duration::from_secs(60));
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+// client errors other than timeouts and rate limiting won't go away by sending the same request
+// again, so they're kept apart from everything else, which might
+#[cfg(feature = "upload")]
+fn http_error(e: ureq::Error) -> std::io::Error {
+    let kind = match &e {
+        ureq::Error::Status(408 | 429, _) => std::io::ErrorKind::Other,
+        ureq::Error::Status(404, _) => std::io::ErrorKind::NotFound,
+        ureq::Error::Status(400..=499, _) => std::io::ErrorKind::InvalidInput,
+        _ => std::io::ErrorKind::Other,
+    };
+    std::io::Error::new(kind, e.to_string())
+}
+
+// sends `len` bytes of `body` with `request`.  S3 won't take a chunked upload, so the length goes
+// in up front
+#[cfg(feature = "upload")]
+fn send<R: std::io::Read>(request: ureq::Request, body: R, len: u64, throttle: &Throttle) -> std::io::Result<ureq::Response> {
+    request.set("Content-Length", &len.to_string()).send(throttle.reader(body)).map_err(http_error)
+}
+
+// PUTs `file` to `url` with `headers`
+#[cfg(feature = "upload")]
+fn put(url: &str, headers: &[(&str, &str)], file: &Path, throttle: &Throttle) -> std::io::Result<()> {
+    let f = std::fs::File::open(file)?;
+    let len = f.metadata()?.len();
+    let mut request = ureq::put(url);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    send(request, f, len, throttle)?;
+    Ok(())
+}
+
+/// The `[upload]` table in the config file: `kind = "rclone"`, or (with the `upload` feature)
+/// `"s3"`, `"bunny"` or `"webdav"`, the settings for that backend, and optionally a bandwidth
+/// limit for it.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct UploadConfig {
+    pub max_bytes_per_second: Option<u64>,
+    #[serde(flatten)]
+    pub backend: UploadBackend,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum UploadBackend {
+    Rclone(Rclone),
+    #[cfg(feature = "upload")]
+    S3(S3Upload),
+    #[cfg(feature = "upload")]
+    Bunny(Bunny),
+    #[cfg(feature = "upload")]
+    WebDav(WebDav),
+}
+
+impl UploadConfig {
+    pub fn uploader(&self) -> Arc<dyn Uploader> {
+        match &self.backend {
+            UploadBackend::Rclone(x) => Arc::new(x.clone()),
+            #[cfg(feature = "upload")]
+            UploadBackend::S3(x) => Arc::new(x.clone()),
+            #[cfg(feature = "upload")]
+            UploadBackend::Bunny(x) => Arc::new(x.clone()),
+            #[cfg(feature = "upload")]
+            UploadBackend::WebDav(x) => Arc::new(x.clone()),
+        }
+    }
+}
+
+/// The `[upload_limits]` table in the config file, for all uploads put together.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadLimits {
+    pub max_bytes_per_second: Option<u64>,
+    /// Like `"01:00-07:00"`.  See `UploadWindow`.
+    pub window: Option<UploadWindow>,
+}
@@ -0,0 +1,249 @@
+// Object storage over HTTP: anything with an S3-compatible API (Cloudflare R2, Backblaze B2, AWS
+// itself, MinIO, ...), and Bunny's storage zones, which have an API of their own.
+
+use super::{http_error, put, send, Throttle, Uploader};
+use crate::signing::{amz_timestamp, s3_scope, sigv4, uri_encode};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A bucket with an S3-compatible API.  Files are uploaded path-style (`endpoint/bucket/key`),
+/// which everything that isn't AWS expects, and AWS still takes.  Files over 64 MiB go up in
+/// parts, and an upload that's interrupted picks up from the last part that made it.
+///
+/// ```toml
+/// [upload]
+/// kind = "s3"
+/// endpoint = "https://<account id>.r2.cloudflarestorage.com"
+/// bucket = "media"
+/// region = "auto"
+/// access_key_id = "..."
+/// secret_access_key = "..."
+/// public_url = "https://media.example.com"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Upload {
+    /// e.g. `https://s3.us-west-004.backblazeb2.com`, or `https://s3.us-east-1.amazonaws.com`.
+    pub endpoint: String,
+    pub bucket: String,
+    /// `auto` for R2.
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Where the bucket's served to the public: R2's `r2.dev` URL or custom domain, a CDN in
+    /// front of the bucket, and so on.  Without one, URLs point at the bucket through
+    /// `endpoint`, which only works if the bucket's public or the URLs are signed (see
+    /// `signing::S3Presigner`).
+    pub public_url: Option<String>,
+}
+
+// files bigger than this go up in parts (of at least this size), so a failure only loses the part
+// it happened in, and the upload can pick up from there
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+
+// how far a multipart upload's got, kept next to the file being uploaded (hidden, so it isn't
+// uploaded itself) until it's done, so it can be picked up again even by another run
+#[derive(Debug, Serialize, Deserialize)]
+struct MultipartState {
+    remote_path: String,
+    // what the file was like when the upload started.  if it's changed since, the parts already
+    // uploaded are of something else, and it has to start over
+    size: u64,
+    modified: u64,
+    upload_id: String,
+    part_size: u64,
+    // (part number, ETag) of the parts that are up
+    parts: Vec<(u64, String)>,
+}
+
+impl MultipartState {
+    fn path(file: &Path) -> PathBuf {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        file.with_file_name(format!(".{}.multipart", name))
+    }
+
+    fn save(&self, file: &Path) -> std::io::Result<()> {
+        std::fs::write(Self::path(file), serde_json::to_vec(self)?)
+    }
+}
+
+// the text of the first `<tag>` in an XML response.  S3's responses are simple enough not to
+// need a real parser
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let len = xml[start..].find(&format!("</{}>", tag))?;
+    Some(&xml[start..start + len])
+}
+
+impl S3Upload {
+    // a request for `remote_path`, signed.  `query` has to be in canonical form already: sorted by
+    // name, with the values encoded
+    fn request(&self, method: &str, remote_path: &str, query: &str, content_type: Option<&str>) -> ureq::Request {
+        let endpoint = self.endpoint.trim_end_matches('/');
+        let host = endpoint.split_once("://").map_or(endpoint, |x| x.1);
+        let path = format!("/{}/{}", uri_encode(&self.bucket, ""), uri_encode(remote_path, "/"));
+        let timestamp = amz_timestamp(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+
+        // the body goes unhashed: it'd mean reading every file twice, and TLS covers it anyway
+        let (content_type_header, content_type_name) = match content_type {
+            Some(content_type) => (format!("content-type:{}\n", content_type), "content-type;"),
+            None => (String::new(), ""),
+        };
+        let signed_headers = format!("{}host;x-amz-content-sha256;x-amz-date", content_type_name);
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}host:{}\nx-amz-content-sha256:UNSIGNED-PAYLOAD\nx-amz-date:{}\n\n{}\nUNSIGNED-PAYLOAD",
+            method, path, query, content_type_header, host, timestamp, signed_headers);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, s3_scope(&timestamp, &self.region), signed_headers,
+            sigv4(&self.secret_access_key, &self.region, &timestamp, &canonical_request));
+
+        let url = match query.is_empty() {
+            true => format!("{}{}", endpoint, path),
+            false => format!("{}{}?{}", endpoint, path, query),
+        };
+        let mut request = ureq::request(method, &url)
+            .set("X-Amz-Content-Sha256", "UNSIGNED-PAYLOAD")
+            .set("X-Amz-Date", &timestamp)
+            .set("Authorization", &authorization);
+        if let Some(content_type) = content_type {
+            request = request.set("Content-Type", content_type);
+        }
+        request
+    }
+
+    // uploads `file` in parts, carrying on from wherever an earlier try got to.  an upload that's
+    // given up on is left for the bucket to clean up (R2 does after a week; AWS needs a lifecycle
+    // rule), or for the next try to finish
+    fn upload_multipart(&self, file: &Path, remote_path: &str, content_type: &str, throttle: &Throttle) -> std::io::Result<()> {
+        let metadata = std::fs::metadata(file)?;
+        let (size, modified) = (metadata.len(), metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+        let saved = std::fs::read(MultipartState::path(file)).ok()
+            .and_then(|x| serde_json::from_slice::<MultipartState>(&x).ok())
+            .filter(|x| x.remote_path == remote_path && x.size == size && x.modified == modified);
+        let mut state = match saved {
+            Some(state) => state,
+            None => {
+                let response = self.request("POST", remote_path, "uploads=", Some(content_type)).call().map_err(http_error)?;
+                let body = response.into_string()?;
+                let upload_id = xml_value(&body, "UploadId")
+                    .ok_or_else(|| std::io::Error::other(format!("no UploadId in {:?}", body)))?;
+                // S3 takes at most 10000 parts
+                let part_size = PART_SIZE.max(size.div_ceil(10000));
+                let state = MultipartState {remote_path: remote_path.to_owned(), size, modified, upload_id: upload_id.to_owned(), part_size, parts: Vec::new()};
+                state.save(file)?;
+                state
+            },
+        };
+        let upload_id = uri_encode(&state.upload_id, "");
+
+        // the upload having gone (expired, or aborted by someone else) means starting over, which
+        // the next try will
+        let gone = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                let _ = std::fs::remove_file(MultipartState::path(file));
+                std::io::Error::other(format!("the multipart upload of {} is gone", remote_path))
+            },
+            _ => e,
+        };
+
+        let mut f = std::fs::File::open(file)?;
+        for number in 1..=size.div_ceil(state.part_size) {
+            if state.parts.iter().any(|x| x.0 == number) {
+                continue;
+            }
+            let offset = (number - 1) * state.part_size;
+            let len = state.part_size.min(size - offset);
+            f.seek(SeekFrom::Start(offset))?;
+            let request = self.request("PUT", remote_path, &format!("partNumber={}&uploadId={}", number, upload_id), None);
+            let response = send(request, (&mut f).take(len), len, throttle).map_err(gone)?;
+            let etag = response.header("ETag").ok_or_else(|| std::io::Error::other("no ETag for an uploaded part"))?;
+            state.parts.push((number, etag.to_owned()));
+            state.save(file)?;
+        }
+
+        state.parts.sort();
+        let parts: String = state.parts.iter()
+            .map(|(number, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag))
+            .collect();
+        let response = self.request("POST", remote_path, &format!("uploadId={}", upload_id), Some("application/xml"))
+            .send_string(&format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts))
+            .map_err(http_error).map_err(gone)?;
+        // S3 can fail to put the parts together after it's already said 200, in which case the
+        // error's in the body instead
+        let body = response.into_string()?;
+        if let Some(code) = xml_value(&body, "Code") {
+            return Err(std::io::Error::other(format!("completing the multipart upload of {} failed: {}", remote_path, code)));
+        }
+        std::fs::remove_file(MultipartState::path(file))
+    }
+}
+
+impl Uploader for S3Upload {
+    fn upload(&self, file: &Path, remote_path: &str, content_type: &str, throttle: &Throttle) -> std::io::Result<()> {
+        let f = std::fs::File::open(file)?;
+        let len = f.metadata()?.len();
+        if len > PART_SIZE {
+            return self.upload_multipart(file, remote_path, content_type, throttle);
+        }
+        send(self.request("PUT", remote_path, "", Some(content_type)), f, len, throttle)?;
+        Ok(())
+    }
+
+    fn public_url(&self, remote_path: &str) -> String {
+        match &self.public_url {
+            Some(url) => format!("{}/{}", url.trim_end_matches('/'), uri_encode(remote_path, "/")),
+            None => format!("{}/{}/{}", self.endpoint.trim_end_matches('/'), uri_encode(&self.bucket, ""), uri_encode(remote_path, "/")),
+        }
+    }
+}
+
+/// A Bunny storage zone, served through a pull zone.
+///
+/// ```toml
+/// [upload]
+/// kind = "bunny"
+/// storage_zone = "my-media"
+/// password = "..."
+/// region = "ny"
+/// pull_zone = "my-media"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bunny {
+    pub storage_zone: String,
+    /// The storage zone's password (under FTP & API Access), not the account's API key.
+    pub password: String,
+    /// The storage zone's main region: `uk`, `ny`, `la`, `sg`, `se`, `br`, `jh` or `syd`, or
+    /// nothing for Falkenstein.
+    pub region: Option<String>,
+    /// The pull zone serving the storage zone, which is served from
+    /// `https://<pull_zone>.b-cdn.net`.  Defaults to the storage zone's name, which is what the
+    /// dashboard suggests.
+    pub pull_zone: Option<String>,
+    /// A custom hostname for the pull zone, like `https://media.example.com`.  Overrides
+    /// `pull_zone`.
+    pub public_url: Option<String>,
+}
+
+impl Uploader for Bunny {
+    fn upload(&self, file: &Path, remote_path: &str, _content_type: &str, throttle: &Throttle) -> std::io::Result<()> {
+        // bunny decides content types itself, by extension
+        let host = match self.region.as_deref().filter(|x| !x.is_empty() && *x != "de") {
+            Some(region) => format!("{}.storage.bunnycdn.com", region),
+            None => "storage.bunnycdn.com".to_string(),
+        };
+        let url = format!("https://{}/{}/{}", host, uri_encode(&self.storage_zone, ""), uri_encode(remote_path, "/"));
+        put(&url, &[("AccessKey", &self.password), ("Content-Type", "application/octet-stream")], file, throttle)
+    }
+
+    fn public_url(&self, remote_path: &str) -> String {
+        let base = match &self.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("https://{}.b-cdn.net", self.pull_zone.as_ref().unwrap_or(&self.storage_zone)),
+        };
+        format!("{}/{}", base, uri_encode(remote_path, "/"))
+    }
+}
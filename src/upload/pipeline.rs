@@ -0,0 +1,94 @@
+// Uploading a CMAF encode's segments while ffmpeg's still writing the rest, so the upload's
+// mostly done by the time the encode is.
+//
+// The dash muxer writes segments one after another, `chunk_<representation>_<number>.m4s`, so a
+// segment's finished once the next one for the same representation shows up.  Everything else
+// (the init segments, the playlists, which keep being rewritten, and the last segment of each
+// representation) goes up afterwards with the rest of the outputs, manifest last.
+
+use super::{content_type, Upload};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// (representation, number) of a CMAF media segment's filename
+fn parse_segment(name: &str) -> Option<(&str, u32)> {
+    let (representation, number) = name.strip_prefix("chunk_")?.strip_suffix(".m4s")?.rsplit_once('_')?;
+    Some((representation, number.parse().ok()?))
+}
+
+/// Uploads segments as the encode writing them into `dir` finishes them.  Call `run` on a thread
+/// of its own while the encode's going, and `finish` once it's done.
+#[derive(Debug)]
+pub struct SegmentPipeline<'a> {
+    upload: &'a Upload,
+    dir: PathBuf,
+    uploaded: Mutex<HashSet<String>>,
+    finished: AtomicBool,
+}
+
+impl<'a> SegmentPipeline<'a> {
+    pub fn new(upload: &'a Upload, dir: &Path) -> Self {
+        SegmentPipeline {upload, dir: dir.to_owned(), uploaded: Mutex::default(), finished: AtomicBool::new(false)}
+    }
+
+    // segments in `dir` that are finished and not uploaded yet
+    fn finished_segments(&self) -> std::io::Result<Vec<String>> {
+        let names: Vec<String> = std::fs::read_dir(&self.dir)?
+            .filter_map(|x| x.ok()?.file_name().into_string().ok())
+            .collect();
+        let mut latest = HashMap::new();
+        for (representation, number) in names.iter().filter_map(|x| parse_segment(x)) {
+            let latest = latest.entry(representation).or_insert(number);
+            *latest = number.max(*latest);
+        }
+        let uploaded = self.uploaded.lock().unwrap();
+        let mut finished: Vec<String> = names.iter()
+            .filter(|x| parse_segment(x).is_some_and(|(representation, number)| number < latest[representation]))
+            .filter(|x| !uploaded.contains(*x))
+            .cloned()
+            .collect();
+        finished.sort();
+        Ok(finished)
+    }
+
+    /// Keeps uploading finished segments until `finish` is called.  A segment that fails to upload
+    /// is left for the upload at the end to try again.  Nothing's started outside the upload
+    /// window, but nothing waits for it either, so the encode never has to wait on this.
+    pub fn run(&self) {
+        let remote_dir = self.upload.remote_dir.trim_matches('/');
+        while !self.finished.load(Ordering::Relaxed) {
+            let segments = match self.upload.throttle.in_window() {
+                true => self.finished_segments().unwrap_or_default(),
+                false => Vec::new(),
+            };
+            for name in segments {
+                if self.finished.load(Ordering::Relaxed) {
+                    break;
+                }
+                let path = self.dir.join(&name);
+                let remote_path = match remote_dir.is_empty() {
+                    true => name.clone(),
+                    false => format!("{}/{}", remote_dir, name),
+                };
+                match self.upload.uploader.upload(&path, &remote_path, content_type(&path), &self.upload.throttle) {
+                    Ok(()) => { self.uploaded.lock().unwrap().insert(name); },
+                    Err(e) => eprintln!("couldn't upload {}, trying again at the end: {}", name, e),
+                }
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+
+    /// Stops `run` once it's done with the segment it's on.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    /// The filenames of everything that got uploaded.
+    pub fn uploaded(&self) -> HashSet<String> {
+        self.uploaded.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,93 @@
+// Uploading with rclone, so anything rclone can write to (SFTP, Google Drive, B2's own API,
+// Dropbox, a mounted share...) works without us having to speak it, using remotes that are
+// already set up in rclone's config.
+
+use super::{retrying, Throttle, Uploader};
+use crate::manifest::MANIFEST_FILENAME;
+use crate::signing::uri_encode;
+use serde::Deserialize;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+/// An rclone remote (and path on it) to upload into, and the URL it's served from.
+///
+/// ```toml
+/// [upload]
+/// kind = "rclone"
+/// remote = "seedbox:www/media"
+/// public_url = "https://seedbox.example.com/media"
+/// args = ["--transfers", "8"]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rclone {
+    /// Where uploads go, as rclone takes it: `remote:path`.
+    pub remote: String,
+    /// The URL `remote` is served from.
+    pub public_url: String,
+    /// Extra arguments for every rclone command, like `--config` or `--bwlimit`.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl Rclone {
+    fn destination(&self, remote_path: &str) -> String {
+        let remote = self.remote.trim_end_matches('/');
+        match remote_path.trim_matches('/') {
+            "" => remote.to_string(),
+            // a bare "remote:" takes the path straight after the colon
+            path if remote.ends_with(':') => format!("{}{}", remote, path),
+            path => format!("{}/{}", remote, path),
+        }
+    }
+
+    fn run<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(&self, args: I, throttle: &Throttle) -> std::io::Result<()> {
+        let mut command = Command::new("rclone");
+        command.args(args).args(&self.args);
+        // rclone's limit is per command, so a limit shared with other uploads is only kept to
+        // as long as this is the only one going
+        if let Some(bytes_per_second) = throttle.bytes_per_second() {
+            command.arg("--bwlimit").arg(format!("{}B", bytes_per_second));
+        }
+        let status = command.status()?;
+        // rclone retries things itself, but when that's not enough, it might still be worth
+        // another go later, unless it's a usage error (1) or a fatal one, like bad credentials (7)
+        match status.code() {
+            Some(0) => Ok(()),
+            Some(1 | 7) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("rclone exited with {}", status))),
+            _ => Err(std::io::Error::other(format!("rclone exited with {}", status))),
+        }
+    }
+}
+
+impl Uploader for Rclone {
+    fn upload(&self, file: &Path, remote_path: &str, _content_type: &str, throttle: &Throttle) -> std::io::Result<()> {
+        // rclone works out content types from the extension by itself
+        self.run([OsStr::new("copyto"), file.as_os_str(), self.destination(remote_path).as_ref()], throttle)
+    }
+
+    // one `rclone copy` for everything but the manifest, which is a lot quicker than a command
+    // per file, since rclone does several transfers at once.  copy skips whatever's already there
+    // with the same size and modification time, so trying again only sends what didn't make it
+    fn upload_dir(&self, dir: &Path, remote_dir: &str, throttle: &Throttle) -> std::io::Result<()> {
+        throttle.wait_for_window();
+        let excluding_manifest = format!("/{}", MANIFEST_FILENAME);
+        retrying(&dir.display().to_string(), || self.run([
+            OsStr::new("copy"), dir.as_os_str(), self.destination(remote_dir).as_ref(),
+            "--exclude".as_ref(), excluding_manifest.as_ref(),
+            // hidden files and directories, like .failed
+            "--exclude".as_ref(), ".*".as_ref(),
+            "--exclude".as_ref(), ".*/**".as_ref(),
+        ], throttle))?;
+        let manifest = dir.join(MANIFEST_FILENAME);
+        match manifest.is_file() {
+            true => retrying(MANIFEST_FILENAME, || self.upload(&manifest, &format!("{}/{}", remote_dir, MANIFEST_FILENAME), "application/json", throttle)),
+            false => Ok(()),
+        }
+    }
+
+    fn public_url(&self, remote_path: &str) -> String {
+        format!("{}/{}", self.public_url.trim_end_matches('/'), uri_encode(remote_path, "/"))
+    }
+}
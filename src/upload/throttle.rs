@@ -0,0 +1,214 @@
+// Keeping uploads from hogging the line: bandwidth limits, and a time of day outside of which
+// nothing gets uploaded at all, so a big batch goes up overnight instead of while the channel's
+// watching something.
+
+use serde::Deserialize;
+use std::io::Read;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A bandwidth limit.  Everything reading through the same one gets `bytes_per_second` between
+/// them, so one can be shared by every upload (a global limit) or kept to one uploader.
+#[derive(Debug)]
+pub struct Bandwidth {
+    pub bytes_per_second: u64,
+    // when it was last topped up, and how many bytes can go right now (negative if something's
+    // gone over and the next reader has to wait it out)
+    state: Mutex<(Instant, f64)>,
+}
+
+impl Bandwidth {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        Bandwidth {bytes_per_second, state: Mutex::new((Instant::now(), bytes_per_second as f64))}
+    }
+
+    // blocks until `bytes` more can go
+    fn take(&self, bytes: usize) {
+        let rate = self.bytes_per_second as f64;
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let (last, available) = &mut *state;
+            // up to a second's worth saved up, so short bursts aren't slowed down
+            *available = (*available + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+            *last = now;
+            *available -= bytes as f64;
+            Duration::from_secs_f64((-*available / rate).max(0.0))
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+/// A time of day uploads are allowed in, like `"01:00-07:00"`, in local time.  It can go past
+/// midnight (`"22:00-06:00"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct UploadWindow {
+    /// Minutes since midnight.
+    pub start: u16,
+    pub end: u16,
+}
+
+impl TryFrom<String> for UploadWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        let time = |x: &str| {
+            let (hours, minutes) = x.trim().split_once(':')?;
+            let (hours, minutes) = (hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?);
+            (hours <= 24 && minutes < 60 && hours * 60 + minutes <= 24 * 60).then_some(hours * 60 + minutes)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(|| format!("{:?} should look like \"01:00-07:00\"", s))?;
+        match (time(start), time(end)) {
+            // "24:00-..." starts at midnight
+            (Some(start), Some(end)) if start % (24 * 60) != end => Ok(UploadWindow {start: start % (24 * 60), end}),
+            // it'd never be open, and uploads would wait forever
+            (Some(_), Some(_)) => Err(format!("{:?} is empty; \"00:00-24:00\" is all day", s)),
+            _ => Err(format!("{:?} should look like \"01:00-07:00\"", s)),
+        }
+    }
+}
+
+// the local timezone's offset from UTC, in minutes.  std doesn't know about timezones, but date
+// does.  UTC if it can't be found out
+fn utc_offset() -> i64 {
+    let output = Command::new("date").arg("+%z").output().ok();
+    let offset = output.as_ref().and_then(|x| std::str::from_utf8(&x.stdout).ok()).map(str::trim).unwrap_or("");
+    let (sign, digits) = match offset.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, offset.trim_start_matches('+')),
+    };
+    match (digits.get(..2).and_then(|x| x.parse::<i64>().ok()), digits.get(2..4).and_then(|x| x.parse::<i64>().ok())) {
+        (Some(hours), Some(minutes)) => sign * (hours * 60 + minutes),
+        _ => 0,
+    }
+}
+
+// minutes since midnight, local time
+fn local_minute() -> u16 {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    (now / 60 + utc_offset()).rem_euclid(24 * 60) as u16
+}
+
+impl UploadWindow {
+    /// Whether `minute` (since midnight) is in the window.
+    pub fn contains(&self, minute: u16) -> bool {
+        match self.start <= self.end {
+            true => (self.start..self.end).contains(&minute),
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+
+    // blocks until it's inside the window
+    fn wait(&self) {
+        while !self.contains(local_minute()) {
+            std::thread::sleep(Duration::from_secs(30));
+        }
+    }
+}
+
+/// The limits an upload's held to.  The default is none at all.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    /// Every limit that applies; the upload goes as fast as the tightest one lets it.
+    pub bandwidth: Vec<Arc<Bandwidth>>,
+    /// Files only start uploading in this window.  One that's already going when it closes
+    /// carries on.
+    pub window: Option<UploadWindow>,
+}
+
+impl Throttle {
+    /// Blocks until an upload's allowed to start.
+    pub fn wait_for_window(&self) {
+        if let Some(window) = &self.window {
+            window.wait();
+        }
+    }
+
+    /// Whether an upload's allowed to start right now.
+    pub fn in_window(&self) -> bool {
+        self.window.as_ref().is_none_or(|x| x.contains(local_minute()))
+    }
+
+    /// The tightest bandwidth limit, in bytes per second.
+    pub fn bytes_per_second(&self) -> Option<u64> {
+        self.bandwidth.iter().map(|x| x.bytes_per_second).min()
+    }
+
+    /// `reader`, slowed down to fit the bandwidth limits.
+    pub fn reader<R: Read>(&self, reader: R) -> Throttled<R> {
+        Throttled {inner: reader, bandwidth: self.bandwidth.clone()}
+    }
+}
+
+/// A reader held to some bandwidth limits.  See `Throttle::reader`.
+pub struct Throttled<R> {
+    inner: R,
+    bandwidth: Vec<Arc<Bandwidth>>,
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // small reads, so the limit's smooth rather than a burst and a long wait
+        let len = match self.bandwidth.is_empty() {
+            true => buf.len(),
+            false => buf.len().min(16 * 1024),
+        };
+        let n = self.inner.read(&mut buf[..len])?;
+        for bandwidth in &self.bandwidth {
+            bandwidth.take(n);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_windows() {
+        let cases = [
+            ("01:00-07:00", Ok((60, 420))),
+            (" 22:30 - 06:00 ", Ok((1350, 360))),
+            ("00:00-24:00", Ok((0, 1440))),
+            ("24:00-06:00", Ok((0, 360))),
+            ("00:00-00:00", Err(())),
+            ("12:00-12:00", Err(())),
+            ("24:00-00:00", Err(())),
+            ("01:00", Err(())),
+            ("25:00-07:00", Err(())),
+            ("24:01-07:00", Err(())),
+            ("01:60-07:00", Err(())),
+            ("1-7", Err(())),
+        ];
+        for (s, expected) in cases {
+            let window = UploadWindow::try_from(s.to_string()).map(|x| (x.start, x.end)).map_err(|_| ());
+            assert_eq!(window, expected, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn windows_contain() {
+        let window = |s: &str| UploadWindow::try_from(s.to_string()).unwrap();
+        let cases = [
+            ("01:00-07:00", 0, false),
+            ("01:00-07:00", 60, true),
+            ("01:00-07:00", 419, true),
+            ("01:00-07:00", 420, false),
+            ("22:00-06:00", 1320, true),
+            ("22:00-06:00", 1439, true),
+            ("22:00-06:00", 0, true),
+            ("22:00-06:00", 359, true),
+            ("22:00-06:00", 360, false),
+            ("22:00-06:00", 720, false),
+            ("00:00-24:00", 0, true),
+            ("00:00-24:00", 1439, true),
+        ];
+        for (s, minute, expected) in cases {
+            assert_eq!(window(s).contains(minute), expected, "{} at {}", s, minute);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// Checking that what got uploaded is actually served right.  An upload can go through fine and
+// the files still not play: a CDN serving .vtt as text/plain or .mp4 as application/octet-stream,
+// a bucket that isn't public, a public URL pointing at the wrong place.  Cytube can't tell anyone
+// why a video won't load, so it's better to find out here.
+
+use crate::cytube_structs::CytubeVideo;
+use crate::manifest::{local_file, MANIFEST_FILENAME};
+use crate::preflight::{fetch, same_type, DEFAULT_ORIGIN};
+use std::path::Path;
+
+// what's wrong with how `url` is served, if anything
+fn check(url: &str, content_type: &str, file: Option<&Path>) -> Option<String> {
+    let fetched = match fetch(url, DEFAULT_ORIGIN) {
+        Ok(fetched) => fetched,
+        Err(e) => return Some(format!("{} {}", url, e)),
+    };
+    let served = fetched.content_type.as_deref().unwrap_or("");
+    if !same_type(served, content_type) {
+        return Some(format!("{} is served as {:?}, not {}", url, served, content_type));
+    }
+    let expected = file.and_then(|x| std::fs::metadata(x).ok()).map(|x| x.len());
+    match (fetched.length, expected) {
+        (Some(length), Some(expected)) if length != expected => Some(format!("{} is {} bytes, not {}", url, length, expected)),
+        _ => None,
+    }
+}
+
+/// Fetches the start of everything the manifest in `dir` lists, and the manifest itself from
+/// `manifest_url`, and checks they're all there, served with the right content type, and the
+/// same size as the files in `dir`.  The error lists everything that isn't.  For the rest of
+/// what the player needs (HTTPS, CORS, range requests), see `preflight`.
+pub fn verify_upload(dir: &Path, manifest: &CytubeVideo, manifest_url: &str) -> std::io::Result<()> {
+    let urls = manifest.sources.iter().map(|x| (&x.url, &x.content_type))
+        .chain(manifest.audio_tracks.iter().map(|x| (&x.url, &x.content_type)))
+        .chain(manifest.text_tracks.iter().map(|x| (&x.url, &x.content_type)));
+    let mut problems: Vec<String> = urls
+        .filter_map(|(url, content_type)| check(url, content_type, local_file(dir, url).as_deref()))
+        .collect();
+    problems.extend(check(manifest_url, "application/json", Some(&dir.join(MANIFEST_FILENAME))));
+
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("the upload isn't being served right: {}", problems.join("; ")))),
+    }
+}
@@ -0,0 +1,93 @@
+// WebDAV, which Nextcloud, ownCloud and a lot of seedbox panels speak.  Directories have to be
+// made (MKCOL) one level at a time before anything can go in them.
+
+use super::{http_error, put, Throttle, Uploader};
+use crate::signing::{base64, uri_encode};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A WebDAV collection to upload into.
+///
+/// ```toml
+/// [upload]
+/// kind = "webdav"
+/// url = "https://cloud.example.com/remote.php/dav/files/me/media"
+/// username = "me"
+/// password = "an app password"
+/// public_url = "https://media.example.com"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebDav {
+    pub url: String,
+    /// For HTTP basic auth.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Where what's in `url` is served to the public.  Without one it's `url` itself, which is
+    /// only any good if the server lets anyone read it.
+    pub public_url: Option<String>,
+    // directories that are known to exist already
+    #[serde(skip)]
+    made: Arc<Mutex<HashSet<String>>>,
+}
+
+impl WebDav {
+    fn authorization(&self) -> Option<String> {
+        if self.username.is_none() && self.password.is_none() {
+            return None;
+        }
+        let credentials = format!("{}:{}", self.username.as_deref().unwrap_or(""), self.password.as_deref().unwrap_or(""));
+        Some(format!("Basic {}", base64(credentials.as_bytes())))
+    }
+
+    fn url(&self, remote_path: &str) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), uri_encode(remote_path, "/"))
+    }
+
+    // makes `dir` and everything above it, as far as they don't exist already
+    fn make_dirs(&self, dir: &str) -> std::io::Result<()> {
+        let mut path = String::new();
+        for name in dir.split('/').filter(|x| !x.is_empty()) {
+            path.push_str(name);
+            path.push('/');
+            if self.made.lock().unwrap().contains(&path) {
+                continue;
+            }
+            let mut request = ureq::request("MKCOL", &self.url(&path));
+            if let Some(authorization) = self.authorization() {
+                request = request.set("Authorization", &authorization);
+            }
+            match request.call() {
+                // 405 means there's something there already
+                Ok(_) | Err(ureq::Error::Status(405, _)) => {},
+                Err(e) => return Err(http_error(e)),
+            }
+            self.made.lock().unwrap().insert(path.clone());
+        }
+        Ok(())
+    }
+}
+
+impl Uploader for WebDav {
+    fn upload(&self, file: &Path, remote_path: &str, content_type: &str, throttle: &Throttle) -> std::io::Result<()> {
+        if let Some((dir, _)) = remote_path.rsplit_once('/') {
+            self.make_dirs(dir)?;
+        }
+        // most servers go by the extension whatever this says, but some do keep it
+        let authorization = self.authorization();
+        let mut headers = vec![("Content-Type", content_type)];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        put(&self.url(remote_path), &headers, file, throttle)
+    }
+
+    fn public_url(&self, remote_path: &str) -> String {
+        match &self.public_url {
+            Some(url) => format!("{}/{}", url.trim_end_matches('/'), uri_encode(remote_path, "/")),
+            None => self.url(remote_path),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// Waveform peaks for the audio outputs, in audiowaveform's JSON format (version 2), so a page
+// embedding the media can draw a waveform (peaks.js, wavesurfer.js and the like all read it)
+// without downloading and decoding the whole thing.
+//
+// Each peaks file is made from the audio output it's for, after it's been encoded, rather than
+// from the input, which could be a multi-gigabyte video.  It's `<output name>.peaks.json`, next to
+// the output: `main.peaks.json` for music, `audio_2_eng.peaks.json` for a film's audio tracks.
+
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// what everything's decoded to before it's measured
+const SAMPLE_RATE: u32 = 44100;
+
+/// How detailed to make the peaks.  The `[waveform]` table in the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Waveform {
+    /// How many samples (at 44.1 kHz) go into each min/max pair.  The default's about 86 pairs a
+    /// second, which is plenty to zoom into a song, and a few MB of JSON for a film.
+    pub samples_per_pixel: u32,
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        Waveform {samples_per_pixel: 512}
+    }
+}
+
+/// audiowaveform's JSON, with 8 bits a value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Peaks {
+    pub version: u32,
+    pub channels: u32,
+    pub sample_rate: u32,
+    pub samples_per_pixel: u32,
+    pub bits: u32,
+    /// How many min/max pairs there are.
+    pub length: usize,
+    /// min, max, min, max...
+    pub data: Vec<i8>,
+}
+
+/// Where the peaks of `output` go.
+pub fn peaks_filename(output: &str) -> String {
+    let stem = output.rsplit_once('.').map_or(output, |x| x.0);
+    format!("{}.peaks.json", stem)
+}
+
+impl Waveform {
+    /// Decodes `audio_file` (downmixed to mono) and measures its peaks.
+    pub fn peaks(&self, audio_file: &Path) -> std::io::Result<Peaks> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-hide_banner", "-v", "error", "-i"])
+            .arg(audio_file)
+            .args(["-vn", "-ac", "1", "-ar", SAMPLE_RATE.to_string().as_str(), "-f", "s16le", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdout = child.stdout.take().unwrap();
+
+        let samples_per_pixel = self.samples_per_pixel.max(1) as usize;
+        let mut data = Vec::new();
+        let (mut min, mut max, mut count) = (i16::MAX, i16::MIN, 0);
+        let mut buf = vec![0u8; 64 * 1024];
+        // a sample can be split across two reads
+        let mut leftover = None;
+        loop {
+            let n = stdout.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let mut bytes = &buf[..n];
+            let mut samples = Vec::with_capacity(n / 2 + 1);
+            if let Some(low) = leftover.take() {
+                samples.push(i16::from_le_bytes([low, bytes[0]]));
+                bytes = &bytes[1..];
+            }
+            let chunks = bytes.chunks_exact(2);
+            leftover = chunks.remainder().first().copied();
+            samples.extend(chunks.map(|x| i16::from_le_bytes([x[0], x[1]])));
+            for sample in samples {
+                min = min.min(sample);
+                max = max.max(sample);
+                count += 1;
+                if count == samples_per_pixel {
+                    data.extend([(min >> 8) as i8, (max >> 8) as i8]);
+                    (min, max, count) = (i16::MAX, i16::MIN, 0);
+                }
+            }
+        }
+        if count > 0 {
+            data.extend([(min >> 8) as i8, (max >> 8) as i8]);
+        }
+        if !child.wait()?.success() {
+            return Err(std::io::Error::other(format!("ffmpeg couldn't decode {}", audio_file.display())));
+        }
+        Ok(Peaks {version: 2, channels: 1, sample_rate: SAMPLE_RATE, samples_per_pixel: samples_per_pixel as u32, bits: 8, length: data.len() / 2, data})
+    }
+
+    /// Writes the peaks of each of `outputs` (filenames in `dir`) next to it.
+    pub fn write_peaks(&self, dir: &Path, outputs: &[String]) -> std::io::Result<()> {
+        for output in outputs {
+            let peaks = self.peaks(&dir.join(output))?;
+            std::fs::write(dir.join(peaks_filename(output)), serde_json::to_vec(&peaks)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+// Probe, plan, encode and check the results, against a real ffmpeg, on inputs made by
+// `testmedia`.  Each test skips itself if there's no ffmpeg to run.
+
+use cytube_generator::chapters::{plan_chapter, split_points};
+use cytube_generator::encoder::VideoEncoder;
+use cytube_generator::ffprobe::{ffprobe, TrackType};
+use cytube_generator::manifest::read_manifest;
+use cytube_generator::plan::CliRunner;
+use cytube_generator::testmedia::{ffmpeg_available, TestAudio, TestMedia, TestSubtitle, TestVideo};
+use cytube_generator::transcode::{explain, plan, TrackAction, TranscodeOptions};
+use std::path::PathBuf;
+
+// a fresh directory for one test, under the system's temp directory
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("cytube-generator-tests").join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+macro_rules! needs_ffmpeg {
+    () => {
+        if !ffmpeg_available() {
+            eprintln!("no ffmpeg, skipping");
+            return;
+        }
+    };
+}
+
+fn options() -> TranscodeOptions {
+    TranscodeOptions {
+        preferred_language: Some("eng".into()),
+        // x264 is everywhere, and fast
+        fallback_encoder: VideoEncoder::X264,
+        ..Default::default()
+    }
+}
+
+fn audio(encoder: &str, language: &str, frequency: u32) -> TestAudio {
+    TestAudio {encoder: encoder.to_string(), language: Some(language.to_string()), frequency, ..Default::default()}
+}
+
+#[test]
+fn copies_h264_with_one_audio_track() {
+    needs_ffmpeg!();
+    let dir = scratch("copies_h264_with_one_audio_track");
+    let input = dir.join("in.mkv");
+    TestMedia {duration: 4.0, video: Some(TestVideo::default()), audio: vec![audio("aac", "eng", 440)], ..Default::default()}
+        .generate(&input).unwrap();
+
+    let probe = ffprobe(&input).unwrap();
+    assert_eq!(probe.tracks.len(), 2);
+    assert!((probe.duration - 4.0).abs() < 0.5, "duration {}", probe.duration);
+    assert!(explain(&probe, &options()).iter().all(|x| x.action == TrackAction::Copy));
+
+    let out = dir.join("out");
+    let manifest = plan(&input, &probe, &out, "http://example.com/", &options()).execute(&CliRunner).unwrap();
+    assert_eq!(manifest.sources.len(), 1);
+    assert_eq!(manifest.sources[0].url, "http://example.com/main.mp4");
+    assert_eq!(manifest.sources[0].quality, 240);
+    assert!(manifest.audio_tracks.is_empty());
+    assert!((manifest.duration - 4.0).abs() < 0.5, "duration {}", manifest.duration);
+
+    let output = ffprobe(&out.join("main.mp4")).unwrap();
+    assert_eq!(output.tracks.iter().map(|x| x.codec.as_str()).collect::<Vec<_>>(), ["h264", "aac"]);
+    assert_eq!(read_manifest(&out).unwrap().title, manifest.title);
+}
+
+#[test]
+fn splits_out_audio_languages_and_subtitles() {
+    needs_ffmpeg!();
+    let dir = scratch("splits_out_audio_languages_and_subtitles");
+    let input = dir.join("in.mkv");
+    TestMedia {
+        duration: 4.0,
+        video: Some(TestVideo::default()),
+        audio: vec![audio("aac", "jpn", 440), TestAudio {channels: 6, ..audio("ac3", "eng", 660)}],
+        subtitles: vec![
+            TestSubtitle {language: Some("eng".to_string()), ..Default::default()},
+            TestSubtitle {encoder: "ass".to_string(), language: Some("eng".to_string()), title: Some("Signs".to_string()), disposition: vec!["forced".to_string()]},
+        ],
+        ..Default::default()
+    }.generate(&input).unwrap();
+
+    let probe = ffprobe(&input).unwrap();
+    assert_eq!(probe.tracks.iter().filter(|x| matches!(x.kind, TrackType::Audio)).count(), 2);
+    assert!(probe.tracks.iter().any(|x| x.disposition.iter().any(|x| x == "forced")));
+
+    let out = dir.join("out");
+    let manifest = plan(&input, &probe, &out, "http://example.com/", &options()).execute(&CliRunner).unwrap();
+    // the preferred language goes first, and the AC-3 one's transcoded to something browsers play
+    assert_eq!(manifest.audio_tracks.len(), 2);
+    assert_eq!(manifest.audio_tracks[0].language, "en");
+    for track in &manifest.audio_tracks {
+        let filename = track.url.rsplit('/').next().unwrap();
+        let probed = ffprobe(&out.join(filename)).unwrap();
+        assert!(probed.tracks.iter().all(|x| x.codec != "ac3"), "{} is still AC-3", filename);
+    }
+    assert_eq!(manifest.text_tracks.len(), 2);
+    for track in &manifest.text_tracks {
+        let vtt = std::fs::read_to_string(out.join(track.url.rsplit('/').next().unwrap())).unwrap();
+        assert!(vtt.starts_with("WEBVTT"));
+        assert!(vtt.contains("Line 1"));
+    }
+}
+
+#[test]
+fn transcodes_what_browsers_cant_play() {
+    needs_ffmpeg!();
+    let dir = scratch("transcodes_what_browsers_cant_play");
+    let input = dir.join("in.mkv");
+    // MS-MPEG4 v2, which nothing plays and no profile copies
+    let video = TestVideo {encoder: "msmpeg4v2".to_string(), pix_fmt: None, ..Default::default()};
+    TestMedia {duration: 2.0, video: Some(video), audio: vec![audio("mp2", "eng", 440)], ..Default::default()}
+        .generate(&input).unwrap();
+
+    let probe = ffprobe(&input).unwrap();
+    let verdicts = explain(&probe, &options());
+    assert_eq!(verdicts[0].action, TrackAction::Transcode, "{}", verdicts[0].reason);
+
+    let out = dir.join("out");
+    let manifest = plan(&input, &probe, &out, "http://example.com/", &options()).execute(&CliRunner).unwrap();
+    let output = ffprobe(&out.join(manifest.sources[0].url.rsplit('/').next().unwrap())).unwrap();
+    assert_eq!(output.tracks.iter().map(|x| x.codec.as_str()).collect::<Vec<_>>(), ["h264", "aac"]);
+}
+
+#[test]
+fn music_keeps_its_tags() {
+    needs_ffmpeg!();
+    let dir = scratch("music_keeps_its_tags");
+    let input = dir.join("in.flac");
+    TestMedia {duration: 3.0, audio: vec![TestAudio {encoder: "flac".to_string(), ..Default::default()}], title: Some("A Song".to_string()), ..Default::default()}
+        .generate(&input).unwrap();
+
+    let probe = ffprobe(&input).unwrap();
+    assert_eq!(probe.title.as_deref(), Some("A Song"));
+    let out = dir.join("out");
+    let manifest = plan(&input, &probe, &out, "http://example.com/", &options()).execute(&CliRunner).unwrap();
+    assert_eq!(manifest.title, "A Song");
+    assert_eq!(manifest.sources.len(), 1);
+    assert!(out.join(manifest.sources[0].url.rsplit('/').next().unwrap()).is_file());
+}
+
+#[test]
+fn splits_at_chapters() {
+    needs_ffmpeg!();
+    let dir = scratch("splits_at_chapters");
+    let input = dir.join("in.mkv");
+    TestMedia {
+        duration: 6.0,
+        video: Some(TestVideo::default()),
+        audio: vec![audio("aac", "eng", 440)],
+        chapters: vec![(0.0, 2.0, "First".to_string()), (2.0, 6.0, "Second".to_string())],
+        ..Default::default()
+    }.generate(&input).unwrap();
+
+    let probe = ffprobe(&input).unwrap();
+    let chapters = split_points(&probe);
+    assert_eq!(chapters.iter().map(|x| x.title.as_deref().unwrap()).collect::<Vec<_>>(), ["First", "Second"]);
+
+    for (i, chapter) in chapters.iter().enumerate() {
+        let out = dir.join(format!("{:02}", i + 1));
+        let manifest = plan_chapter(&input, chapter, &probe, &out, "http://example.com/", &options()).execute(&CliRunner).unwrap();
+        assert_eq!(Some(manifest.title.as_str()), chapter.title.as_deref());
+        // copied video can only be cut on a keyframe, which x264 makes every 250 frames, so
+        // it's only roughly the chapter's length
+        assert!(manifest.duration > 0.0 && manifest.duration <= 6.5, "duration {}", manifest.duration);
+    }
+}
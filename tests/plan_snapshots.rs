@@ -0,0 +1,112 @@
+// What gets planned for a handful of made-up inputs, compared against the commands and manifests
+// saved in `tests/snapshots`.  No ffmpeg needed: the probes are written out by hand, and nothing's
+// run.  A change in what's copied, what's transcoded, or how, shows up as a failure here.
+//
+// After a change that's meant to plan things differently, run with UPDATE_SNAPSHOTS=1 to write
+// the new snapshots, and check the diff.
+
+use cytube_generator::ffprobe::FFprobeResult;
+use cytube_generator::transcode::{plan, TranscodeOptions};
+use serde_json::{json, Value};
+use std::path::Path;
+
+fn probe(value: Value) -> FFprobeResult {
+    serde_json::from_value(value).unwrap()
+}
+
+fn check(name: &str, input: &str, probe: FFprobeResult, options: &TranscodeOptions) {
+    // absolute, since the staging directory next to it is made absolute
+    let plan = plan(Path::new(input), &probe, Path::new("/srv/media/out"), "https://example.com/", options);
+    let planned = json!({
+        "invocations": plan.invocations(),
+        "manifest": plan.manifest,
+    });
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots").join(format!("{}.json", name));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, serde_json::to_string_pretty(&planned).unwrap() + "\n").unwrap();
+        return;
+    }
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(saved == planned, "{} planned differently:\n{}", name, serde_json::to_string_pretty(&planned).unwrap());
+}
+
+fn options() -> TranscodeOptions {
+    TranscodeOptions {preferred_language: Some("eng".into()), ..Default::default()}
+}
+
+#[test]
+fn h264_with_one_audio_track() {
+    let probe = probe(json!({
+        "tracks": [
+            {"index": 0, "kind": "Video", "codec": "h264", "scanline_count": 1080, "profile": "High", "level": 41, "pix_fmt": "yuv420p", "frame_rate": 23.976},
+            {"index": 1, "kind": "Audio", "codec": "aac", "language": "eng", "channels": 2},
+        ],
+        "title": null, "duration": 1440.0, "bitrate": 5000,
+    }));
+    check("h264_with_one_audio_track", "Show.S01E01.1080p.mkv", probe, &options());
+}
+
+#[test]
+fn dual_audio_with_subtitles() {
+    let probe = probe(json!({
+        "tracks": [
+            {"index": 0, "kind": "Video", "codec": "hevc", "scanline_count": 1080, "profile": "Main 10", "level": 123, "pix_fmt": "yuv420p10le", "frame_rate": 23.976},
+            {"index": 1, "kind": "Audio", "codec": "flac", "language": "jpn", "channels": 2, "disposition": ["default"]},
+            {"index": 2, "kind": "Audio", "codec": "ac3", "language": "eng", "title": "Dub", "channels": 6},
+            {"index": 3, "kind": "Subtitle", "codec": "ass", "language": "eng", "title": "Signs & Songs", "disposition": ["forced"]},
+            {"index": 4, "kind": "Subtitle", "codec": "ass", "language": "eng", "title": "Full"},
+            {"index": 5, "kind": "Subtitle", "codec": "hdmv_pgs_subtitle", "language": "jpn"},
+        ],
+        "title": null, "duration": 1420.5, "bitrate": 8000,
+    }));
+    check("dual_audio_with_subtitles", "[Group] Show - 01 [BD 1080p].mkv", probe, &options());
+}
+
+#[test]
+fn old_codecs_are_transcoded() {
+    let probe = probe(json!({
+        "tracks": [
+            {"index": 0, "kind": "Video", "codec": "wmv3", "scanline_count": 480, "frame_rate": 29.97},
+            {"index": 1, "kind": "Audio", "codec": "wmav2", "channels": 2},
+        ],
+        "title": "Home Movie", "duration": 600.0, "bitrate": 1500,
+    }));
+    check("old_codecs_are_transcoded", "home movie.wmv", probe, &options());
+}
+
+#[test]
+fn music_with_cover_art() {
+    let probe = probe(json!({
+        "tracks": [
+            {"index": 0, "kind": "Audio", "codec": "flac", "channels": 2},
+            {"index": 1, "kind": "Video", "codec": "mjpeg", "scanline_count": 500, "disposition": ["attached_pic"]},
+        ],
+        "title": "Song", "duration": 215.0, "bitrate": 900, "artist": "Band", "album": "Album", "track_number": 3,
+        "tags": {"title": "Song", "artist": "Band", "album": "Album", "track": "3"},
+    }));
+    check("music_with_cover_art", "03 - Song.flac", probe, &options());
+}
+
+#[test]
+fn video_without_audio() {
+    let probe = probe(json!({
+        "tracks": [
+            {"index": 0, "kind": "Video", "codec": "vp9", "scanline_count": 720, "frame_rate": 60.0},
+        ],
+        "title": null, "duration": 95.0, "bitrate": 3000,
+    }));
+    check("video_without_audio", "screen recording.webm", probe, &options());
+}
+
+#[test]
+fn mp3_is_copied_into_mp4() {
+    let probe = probe(json!({
+        "tracks": [
+            {"index": 0, "kind": "Audio", "codec": "mp3", "channels": 2, "sample_rate": 44100},
+        ],
+        "title": "Song", "duration": 180.0, "bitrate": 320, "artist": "Band", "album": "Album",
+        "tags": {"title": "Song", "artist": "Band", "album": "Album"},
+    }));
+    check("mp3_is_copied_into_mp4", "Song.mp3", probe, &options());
+}